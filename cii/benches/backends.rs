@@ -0,0 +1,82 @@
+// Times the tree-walking interpreter against the bytecode VM on the same
+// scripts. Run with `cargo bench`; pass a name to run only matching ones,
+// as in `cargo bench -- calls`.
+use cii::{Engine, StractValue};
+use std::env;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+const BENCHMARKS: [(&str, &str); 4] = [
+	("loops", "var i::0;
+var sum::0;
+while (i < 200000) {
+	sum :: sum + i * 2!
+	i :: i + 1!
+}
+sum"),
+	("local loops", "func count(n) {
+	var i::0;
+	var sum::0;
+	while (i < n) {
+		sum :: sum + i * 2!
+		i :: i + 1!
+	}
+	return sum!
+}
+count(200000)"),
+	("calls", "func fib(n) {
+	if (n < 2) return n!
+	return fib(n - 1) + fib(n - 2)!
+}
+fib(20)"),
+	("string building", "func build(n) {
+	var text::\"\";
+	var i::0;
+	while (i < n) {
+		text :: text + \"{i},\"!
+		i :: i + 1!
+	}
+	return text!
+}
+build(5000)"),
+];
+
+/// The fastest of a few runs, which is the least disturbed by other work.
+fn fastest(mut run: impl FnMut() -> StractValue) -> (Duration, StractValue)
+{
+	let mut best = None;
+	let mut result = StractValue::Nil;
+	for _ in 0..RUNS
+	{
+		let start = Instant::now();
+		result = run();
+		let elapsed = start.elapsed();
+		if best.is_none_or(|best| elapsed < best)
+		{
+			best = Some(elapsed);
+		}
+	}
+	(best.unwrap(), result)
+}
+
+fn main()
+{
+	let filter = env::args().skip(1).find(|arg| !arg.starts_with('-'));
+	println!("{:<16} {:>12} {:>12} {:>8}", "benchmark", "tree-walker", "bytecode", "speedup");
+	for (name, source) in BENCHMARKS
+	{
+		if filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str()))
+		{
+			continue;
+		}
+		let (walked, expected) = fastest(|| Engine::new().eval(source).unwrap());
+		let function = Engine::new().compile(source).unwrap();
+		let (compiled, result) = fastest(|| Engine::new().run_bytecode(&function).unwrap());
+		assert_eq!(result, expected, "{} gave different results", name);
+		println!("{:<16} {:>10.2}ms {:>10.2}ms {:>7.1}x", name,
+			walked.as_secs_f64() * 1000.0,
+			compiled.as_secs_f64() * 1000.0,
+			walked.as_secs_f64() / compiled.as_secs_f64());
+	}
+}
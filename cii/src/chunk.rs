@@ -0,0 +1,179 @@
+// Bytecode: what the compiler turns statements into and the VM runs.
+//
+// Every function, including the top level of a script, compiles to its own
+// `Chunk`. Operands are indices into the chunk's constant pool, local slots
+// or absolute jump targets, all small enough to keep an `Op` at 4 bytes.
+use crate::expr::StractValue;
+use crate::span::Span;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+	/// Pushes a constant from the pool.
+	Constant(u16),
+	Nil,
+	True,
+	False,
+	Pop,
+	/// Pushes the local in a stack slot of the current call.
+	GetLocal(u16),
+	SetLocal(u16),
+	/// The operand is the constant holding the global's name.
+	GetGlobal(u16),
+	SetGlobal(u16),
+	DefineGlobal(u16),
+	/// Locals that a nested function captures live in cells rather than on
+	/// the stack, so closures keep them after the call returns.
+	NewCell(u16),
+	DefineCell(u16),
+	GetCell(u16),
+	SetCell(u16),
+	/// The captured cells of the running closure.
+	GetUpvalue(u16),
+	SetUpvalue(u16),
+	/// The operand is the constant holding the property's name.
+	GetProperty(u16),
+	SetProperty(u16),
+	/// Sets a property of the object below the value and pushes the object
+	/// back above the value: rebuilt when it is a vector and the property
+	/// one of its components, for the compiler to store where it came from.
+	SetComponent(u16),
+	Index,
+	Add,
+	Subtract,
+	Multiply,
+	Divide,
+	Greater,
+	GreaterEqual,
+	Less,
+	LessEqual,
+	Equal,
+	NotEqual,
+	Negate,
+	Not,
+	/// Jumps to an instruction index.
+	Jump(u16),
+	/// Jumps when the top of the stack is falsy, leaving it there.
+	JumpIfFalse(u16),
+	/// Calls the value below that many arguments.
+	Call(u8),
+	/// Makes a closure of one of the chunk's functions.
+	Closure(u16),
+	Return,
+	Print,
+	/// Collects that many values into a list.
+	List(u16),
+	/// Joins that many values into a string.
+	Interpolate(u16),
+}
+
+/// Where a closure finds a captured variable when it is made: a cell of the
+/// function making it, or one the maker captured itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+	Cell(u16),
+	Upvalue(u16),
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+	pub code: Vec<Op>,
+	/// The source of each instruction, for error messages.
+	pub spans: Vec<Span>,
+	/// Numbers, strings and names used by the code.
+	pub constants: Vec<StractValue>,
+	/// Functions declared inside this one.
+	pub functions: Vec<Rc<Function>>,
+}
+
+impl Chunk {
+	pub fn write(&mut self, op: Op, span: Span) -> usize
+	{
+		self.code.push(op);
+		self.spans.push(span);
+		self.code.len() - 1
+	}
+
+	/// Adds a constant, reusing an identical one already in the pool.
+	/// Numbers compare by their bits, so `0.0` and `-0.0` stay apart.
+	pub fn add_constant(&mut self, value: StractValue) -> Result<u16, String>
+	{
+		let same = |constant: &StractValue| match (constant, &value)
+		{
+			(StractValue::Number(a), StractValue::Number(b)) => a.to_bits() == b.to_bits(),
+			(a, b) => a == b,
+		};
+		if let Some(index) = self.constants.iter().position(same)
+		{
+			return Ok(index as u16);
+		}
+		self.constants.push(value);
+		u16::try_from(self.constants.len() - 1).map_err(|_| "Too many constants in one function".to_string())
+	}
+
+	/// One instruction per line, with constants written out:
+	///
+	/// ```text
+	/// 0000    1 Constant 0 '2'
+	/// 0001    | DefineGlobal 1 'x'
+	/// ```
+	pub fn disassemble(&self) -> String
+	{
+		let mut text = String::new();
+		for (index, op) in self.code.iter().enumerate()
+		{
+			let line = match index
+			{
+				0 => format!("{:4}", self.spans[0].line),
+				_ if self.spans[index].line == self.spans[index - 1].line => "   |".to_string(),
+				_ => format!("{:4}", self.spans[index].line),
+			};
+			let name = format!("{:?}", op).replace('(', " ").replace(')', "");
+			let detail = match op
+			{
+				Op::Constant(i) | Op::GetGlobal(i) | Op::SetGlobal(i) | Op::DefineGlobal(i)
+				| Op::GetProperty(i) | Op::SetProperty(i) | Op::SetComponent(i) => format!(" '{}'", self.constants[*i as usize]),
+				Op::Closure(i) => format!(" <func {}>", self.functions[*i as usize].name),
+				_ => String::new(),
+			};
+			text.push_str(&format!("{:04} {} {}{}\n", index, line, name, detail));
+		}
+		text
+	}
+}
+
+/// A compiled function, or the top level of a script.
+#[derive(Debug)]
+pub struct Function {
+	pub name: String,
+	pub arity: usize,
+	pub chunk: Chunk,
+	/// How many cells a call needs for its captured locals.
+	pub cell_count: usize,
+	/// What each closure of this function captures, in upvalue order.
+	pub captures: Vec<Capture>,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ops_stay_small()
+	{
+		assert_eq!(std::mem::size_of::<Op>(), 4);
+	}
+
+	#[test]
+	fn constants_are_shared()
+	{
+		let mut chunk = Chunk::default();
+		let a = chunk.add_constant(StractValue::StringValue("x".to_string())).unwrap();
+		let b = chunk.add_constant(StractValue::Number(1.0)).unwrap();
+		assert_eq!(chunk.add_constant(StractValue::StringValue("x".to_string())), Ok(a));
+		assert_ne!(a, b);
+		assert_eq!(chunk.constants.len(), 2);
+		let zero = chunk.add_constant(StractValue::Number(0.0)).unwrap();
+		assert_ne!(chunk.add_constant(StractValue::Number(-0.0)), Ok(zero));
+	}
+}
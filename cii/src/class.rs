@@ -0,0 +1,216 @@
+// Classes declared in scripts and the instances made by calling them.
+//
+//     class Player {
+//         var health::10;
+//         signal died(cause);
+//         func init(health) { self.health :: health! }
+//         func hurt(amount) { self.health :: self.health - amount! }
+//     }
+//
+// Field initializers run again for every instance. Methods see the instance
+// as `self`, and `init` runs with the arguments given to the class.
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::expr::{CallableImpl, StractFunctionImpl, StractValue};
+use crate::gc::{self, Collectable};
+use crate::signal::SignalImpl;
+use crate::statement::Statement;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub struct ClassImpl {
+	pub name: String,
+	/// The class body; its `var` statements initialize each new instance.
+	pub body: Rc<Vec<Statement>>,
+	pub methods: HashMap<String, StractFunctionImpl>,
+	/// Signal names with the number of arguments they are emitted with.
+	pub signals: Vec<(String, usize)>,
+	/// The scope the class was declared in.
+	pub closure: Rc<RefCell<Environment>>,
+}
+
+impl std::fmt::Debug for ClassImpl {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "<class {}>", self.name)
+	}
+}
+
+impl PartialEq for ClassImpl {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+impl ClassImpl {
+	/// Arguments the class takes when called, which are those of `init`.
+	pub fn arity(&self) -> usize {
+		self.methods.get("init").map_or(0, |init| init.params.len())
+	}
+
+	/// Makes an instance: fields first, then signals, then `init`.
+	pub fn instantiate(class: &Rc<ClassImpl>, arguments: &[StractValue]) -> Result<StractValue, RuntimeError> {
+		if arguments.len() != class.arity()
+		{
+			return Err(RuntimeError::arity(&class.name, class.arity(), arguments.len()));
+		}
+		let signals = class.signals.iter()
+			.map(|(name, arity)| (name.clone(), gc::alloc(SignalImpl::new(name, *arity))))
+			.collect();
+		let instance = gc::alloc(InstanceImpl {
+			class: class.clone(),
+			fields: RefCell::new(HashMap::new()),
+			signals,
+		});
+
+		let environment = gc::alloc(RefCell::new(Environment::new_enclosed(class.closure.clone())));
+		environment.borrow_mut().define("self".to_string(), StractValue::Instance(instance.clone()));
+		for statement in class.body.iter()
+		{
+			if let Statement::Var { name, initializer, .. } = statement
+			{
+				let value = initializer.evaluate(environment.clone())?;
+				instance.fields.borrow_mut().insert(name.panoll.clone(), value);
+			}
+		}
+
+		if let Some(init) = class.methods.get("init")
+		{
+			init.call_as(arguments, Some(&instance))?;
+		}
+		Ok(StractValue::Instance(instance))
+	}
+}
+
+pub struct InstanceImpl {
+	pub class: Rc<ClassImpl>,
+	pub fields: RefCell<HashMap<String, StractValue>>,
+	pub signals: HashMap<String, Rc<SignalImpl>>,
+}
+
+impl std::fmt::Debug for InstanceImpl {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "<{} instance>", self.class.name)
+	}
+}
+
+impl PartialEq for InstanceImpl {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+impl InstanceImpl {
+	/// Looks up a field, then a signal, then a method bound to `instance`.
+	pub fn get(instance: &Rc<InstanceImpl>, name: &str) -> Option<StractValue> {
+		if let Some(value) = instance.fields.borrow().get(name)
+		{
+			return Some(value.clone());
+		}
+		if let Some(signal) = instance.signals.get(name)
+		{
+			return Some(StractValue::Signal(signal.clone()));
+		}
+		instance.class.methods.get(name).map(|function| StractValue::Callable(CallableImpl::Method(MethodImpl {
+			receiver: instance.clone(),
+			function: function.clone(),
+		})))
+	}
+
+	pub fn set(&self, name: &str, value: StractValue) -> Result<(), RuntimeError> {
+		if self.signals.contains_key(name)
+		{
+			return Err(RuntimeError::type_mismatch(format!("Cannot assign to signal '{}'", name)));
+		}
+		self.fields.borrow_mut().insert(name.to_string(), value);
+		Ok(())
+	}
+}
+
+/// A class only holds its methods and the scope it was declared in, which
+/// are freed with it, so there is nothing to clear.
+impl Collectable for ClassImpl {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		visit(gc::address(&self.closure));
+		self.methods.values().for_each(|method| visit(gc::address(&method.closure)));
+		true
+	}
+
+	fn clear(&self) {}
+}
+
+impl Collectable for InstanceImpl {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		visit(gc::address(&self.class));
+		self.signals.values().for_each(|signal| visit(gc::address(signal)));
+		self.fields.trace(visit)
+	}
+
+	fn clear(&self) {
+		self.fields.borrow_mut().clear();
+	}
+}
+
+/// A method together with the instance it was read from, as in
+/// `var hurt::player.hurt;`.
+#[derive(Clone, Debug)]
+pub struct MethodImpl {
+	pub receiver: Rc<InstanceImpl>,
+	pub function: StractFunctionImpl,
+}
+
+impl PartialEq for MethodImpl {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.receiver, &other.receiver) && self.function == other.function
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::engine::Engine;
+	use crate::expr::StractValue::*;
+
+	const SHAPES: &str = "class Counter {
+	var count::0;
+	var step::1;
+	func init(step) { self.step :: step! }
+	func bump() { self.count :: self.count + self.step! return self! }
+}
+var a::Counter(2);
+var b::Counter(5);";
+
+	#[test]
+	fn instances_have_their_own_fields()
+	{
+		let mut engine = Engine::new();
+		engine.eval(SHAPES).unwrap();
+		engine.eval("a.bump().bump()!\nb.bump()!").unwrap();
+		assert_eq!(engine.eval("a.count").unwrap(), Number(4.0));
+		assert_eq!(engine.eval("b.count").unwrap(), Number(5.0));
+		assert_eq!(engine.eval("var f::a.bump;\nf().count").unwrap(), Number(6.0));
+		engine.eval("a.label :: \"extra\"!").unwrap();
+		assert_eq!(engine.eval("a.label").unwrap(), StringValue("extra".to_string()));
+	}
+
+	#[test]
+	fn identity_and_names()
+	{
+		let mut engine = Engine::new();
+		engine.eval(SHAPES).unwrap();
+		assert_eq!(engine.eval("a : a").unwrap(), True);
+		assert_eq!(engine.eval("a : b").unwrap(), False);
+		assert_eq!(engine.eval("\"{a} {Counter}\"").unwrap(), StringValue("<Counter instance> <class Counter>".to_string()));
+		assert_eq!(engine.get_global("a").unwrap().to_type(), "Counter");
+	}
+
+	#[test]
+	fn class_errors()
+	{
+		let mut engine = Engine::new();
+		engine.eval(SHAPES).unwrap();
+		assert_eq!(engine.eval("Counter()").unwrap_err().to_string(), "Counter() expects 1 arguments but got 0 at line 1, column 1");
+		assert!(engine.eval("a.missing").unwrap_err().to_string().contains("Counter has no property 'missing'"));
+		assert!(engine.eval("class Bad { print 1! }").unwrap_err().to_string().contains("Expected var, func or signal in class Bad"));
+		assert!(engine.eval("signal loose();").is_err());
+	}
+}
@@ -0,0 +1,499 @@
+// The catalogue of error codes. Every error the scanner, the parser and the
+// interpreter report carries one of these codes, and `cii explain PS0001`
+// prints the entry: what the error means and a short script that makes it.
+//
+// Codes are grouped by the stage that reports them: PS00xx while scanning,
+// PS01xx while parsing, PS02xx while running, PS03xx while compiling to
+// bytecode and PS04xx while the game hosting the engine loads scripts and
+// calls into them.
+
+/// An error code with its long-form explanation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+	pub code: &'static str,
+	/// A few words naming the error, such as "unterminated string".
+	pub title: &'static str,
+	pub explanation: &'static str,
+	/// A script that fails with this error.
+	pub example: &'static str,
+}
+
+impl ErrorCode {
+	/// The entry as `cii explain` prints it.
+	pub fn describe(&self) -> String {
+		let example: Vec<String> = self.example.lines().map(|line| format!("    {}", line)).collect();
+		format!("{}: {}\n\n{}\n\nFor example:\n\n{}\n", self.code, self.title, self.explanation, example.join("\n"))
+	}
+}
+
+pub const CATALOGUE: &[ErrorCode] = &[
+	ErrorCode {
+		code: "PS0001",
+		title: "unterminated string",
+		explanation: "A string literal reaches the end of the script without its closing quote. \
+Strings opened with \" close with \", strings opened with \"\"\" close with \"\"\", and raw \
+strings opened with r#\" close with \"# and as many # as they opened with.",
+		example: "var name::\"Gray;",
+	},
+	ErrorCode {
+		code: "PS0002",
+		title: "unterminated interpolation",
+		explanation: "A string holds a { that starts an interpolated expression, but the script \
+ends before the } that closes it. Write {{ for a literal brace.",
+		example: "print \"score: {points",
+	},
+	ErrorCode {
+		code: "PS0003",
+		title: "invalid escape sequence",
+		explanation: "A backslash in a string or character literal is followed by something that \
+is not an escape. The escapes are \\n, \\t, \\r, \\0, \\\", \\', \\\\ and \\u{...} with up to six \
+hex digits naming a character. Write \\\\ for a backslash, or use a raw string.",
+		example: "print \"C:\\games\"!",
+	},
+	ErrorCode {
+		code: "PS0004",
+		title: "single closing brace in a string",
+		explanation: "A } in a string closes an interpolated expression, so one that closes \
+nothing is an error. Write }} for a literal brace.",
+		example: "print \"a } b\"!",
+	},
+	ErrorCode {
+		code: "PS0005",
+		title: "unterminated character",
+		explanation: "A character literal opened with ' reaches the end of the line without its \
+closing '.",
+		example: "var c::'a;",
+	},
+	ErrorCode {
+		code: "PS0006",
+		title: "character literal is not one character",
+		explanation: "A character literal holds exactly one character. Use a string for none or \
+for more than one.",
+		example: "var c::'ab';",
+	},
+	ErrorCode {
+		code: "PS0007",
+		title: "invalid number",
+		explanation: "A number literal has a digit its base does not allow, no digits after a \
+0x, 0b or 0o prefix, or a trailing _ separator. Separators go between digits, as in 1_000.",
+		example: "var mask::0b102;",
+	},
+	ErrorCode {
+		code: "PS0008",
+		title: "number out of range",
+		explanation: "A number literal is too large. Decimal numbers must fit in a 64-bit float, \
+and 0x, 0b and 0o numbers can be at most 2^53 (0x20_0000_0000_0000), the largest whole number \
+a float holds without rounding.",
+		example: "var big::1e999;",
+	},
+	ErrorCode {
+		code: "PS0009",
+		title: "unrecognized character",
+		explanation: "The script holds a character that starts no token, outside of strings and \
+comments.",
+		example: "var price::$5;",
+	},
+	ErrorCode {
+		code: "PS0010",
+		title: "missing annotation name",
+		explanation: "An @ starts an annotation such as @reference or @lib_halcyon, so a name must \
+follow it directly.",
+		example: "@ reference(\"./lib\")",
+	},
+	ErrorCode {
+		code: "PS0011",
+		title: "raw string without a quote",
+		explanation: "An r followed by # starts a raw string, which needs a \" after the #s, as in \
+r#\"text\"#.",
+		example: "var path::r#C:\\games#;",
+	},
+	ErrorCode {
+		code: "PS0100",
+		title: "expected token",
+		explanation: "The parser needed a particular token, such as the ! that ends a statement, \
+the ; that ends a declaration or the bracket that closes an opened one, and found something \
+else. The message says what was expected; the labels show where it was needed and which \
+bracket it closes.",
+		example: "print (1 + 2!",
+	},
+	ErrorCode {
+		code: "PS0101",
+		title: "expected expression",
+		explanation: "The parser needed a value, such as a number, a name or a parenthesized \
+expression, and found something else, often a stray operator or the end of the script.",
+		example: "var x::1 + ;",
+	},
+	ErrorCode {
+		code: "PS0102",
+		title: "invalid assignment target",
+		explanation: "Only variables and properties can be assigned to with ::. The left side of \
+the assignment is some other expression, such as a call.",
+		example: "func f() { }\nf() :: 1!",
+	},
+	ErrorCode {
+		code: "PS0103",
+		title: "unknown hook",
+		explanation: "defi declares one of the hooks the engine calls, such as main or physic. \
+The name after defi is not one of them.",
+		example: "defi physics(delta) { }",
+	},
+	ErrorCode {
+		code: "PS0104",
+		title: "wrong hook parameters",
+		explanation: "The engine calls each hook with set arguments, such as physic(delta), so a \
+defi must declare the parameters the hook takes.",
+		example: "defi physic() { }",
+	},
+	ErrorCode {
+		code: "PS0105",
+		title: "unexpected class member",
+		explanation: "A class body holds only var, func and signal declarations. Statements \
+belong in its functions.",
+		example: "class Player {\n\tprint 1!\n}",
+	},
+	ErrorCode {
+		code: "PS0106",
+		title: "declaration outside the top level",
+		explanation: "@reference and defi can only appear at the top level of a script, not \
+inside functions or blocks.",
+		example: "func start() {\n\tdefi main() { }\n}",
+	},
+	ErrorCode {
+		code: "PS0107",
+		title: "invalid import",
+		explanation: "After ::, an @reference takes @--I++< to import everything a module exports, \
+or @--I++{...} with the names to import. After ;; inside @reference(...), it takes the name to \
+use the module under, as in @reference(\"./lib\";;lib), or a whole reference number, as in \
+@reference(\"./lib\";;1), which keeps the module's own name.",
+		example: "@reference @lib_halcyon::@--I++>",
+	},
+	ErrorCode {
+		code: "PS0108",
+		title: "try without catch or finally",
+		explanation: "A try block must be followed by at least one catch clause or a finally \
+block, or it would have no effect.",
+		example: "try { print 1! }\nprint 2!",
+	},
+	ErrorCode {
+		code: "PS0109",
+		title: "break outside a loop",
+		explanation: "break leaves the innermost loop, so it must be inside one. A function body \
+starts outside of any loop, even when the function is declared in one.",
+		example: "break!",
+	},
+	ErrorCode {
+		code: "PS0110",
+		title: "unclosed interpolated expression",
+		explanation: "A { in a string holds one expression and then a } to close it. Something \
+other than } follows the expression.",
+		example: "print \"{1 2}\"!",
+	},
+	ErrorCode {
+		code: "PS0200",
+		title: "runtime error",
+		explanation: "The script failed while running for a reason with no code of its own, such \
+as an error reported by a native function or the host.",
+		example: "print duplicate(\"ab\", 1e18)!",
+	},
+	ErrorCode {
+		code: "PS0201",
+		title: "type mismatch",
+		explanation: "An operator or function got a value of a type it does not work with, such \
+as adding nil to a number or calling a number.",
+		example: "print 1 + nil!",
+	},
+	ErrorCode {
+		code: "PS0202",
+		title: "undefined variable",
+		explanation: "The script reads or assigns a variable that was never declared with var, \
+func or class, or that was declared in a scope that has ended.",
+		example: "print score!",
+	},
+	ErrorCode {
+		code: "PS0203",
+		title: "wrong number of arguments",
+		explanation: "A function was called with more or fewer arguments than it declares \
+parameters.",
+		example: "func add(a, b) { return a + b! }\nprint add(1)!",
+	},
+	ErrorCode {
+		code: "PS0204",
+		title: "index out of range",
+		explanation: "A list or string was indexed at a position past its end. Positions start \
+at 0, so the last one is the length minus one.",
+		example: "var items::[1, 2];\nprint items[2]!",
+	},
+	ErrorCode {
+		code: "PS0205",
+		title: "missing key",
+		explanation: "A map from the host was indexed with a key it does not hold.",
+		example: "// config is a map the game passed in\nprint config[\"volume\"]!",
+	},
+	ErrorCode {
+		code: "PS0206",
+		title: "division by zero",
+		explanation: "A number was divided by zero. Check the divisor first when it can be zero.",
+		example: "var count::0;\nprint 10 / count!",
+	},
+	ErrorCode {
+		code: "PS0207",
+		title: "uncaught throw",
+		explanation: "A value was thrown and no enclosing try has a catch clause that handles \
+it.",
+		example: "throw \"out of fuel\"!",
+	},
+	ErrorCode {
+		code: "PS0208",
+		title: "module not found",
+		explanation: "An @reference names a file that is not there or cannot be read. Paths \
+starting with . or / are looked up from the referencing file; bare names are also looked up in \
+each search path. The message lists every place that was tried.",
+		example: "@reference(\"./no/such/module\")",
+	},
+	ErrorCode {
+		code: "PS0209",
+		title: "circular reference",
+		explanation: "A module references itself, directly or through the modules it references, \
+so it would have to finish running before it starts. Move what both need into a module of its \
+own.",
+		example: "// this file is cycle.pyx\n@reference(\"./cycle\")",
+	},
+	ErrorCode {
+		code: "PS0210",
+		title: "unknown library",
+		explanation: "An @reference names a built-in library that does not exist. The libraries \
+are @lib_halcyon and @lib_jellybeans.",
+		example: "@reference @lib_nope",
+	},
+	ErrorCode {
+		code: "PS0211",
+		title: "missing export",
+		explanation: "An @reference imports a name, with @--I++{...}, that the module or library \
+does not define at its top level.",
+		example: "@reference @lib_halcyon::@--I++{@teleport}",
+	},
+	ErrorCode {
+		code: "PS0212",
+		title: "stack overflow",
+		explanation: "Calls nested too deep, usually because a function calls itself with no case \
+that stops it. The trace shows the call that repeats.",
+		example: "func fall(n) { return fall(n + 1)! }\nfall(0)!",
+	},
+	ErrorCode {
+		code: "PS0213",
+		title: "expect failed",
+		explanation: "expect() got an error value, so it stopped the script with the message it \
+was given and the error's own. Use unwrap_or() or is_error() to carry on instead.",
+		example: "var save::error(\"no save file\");\nprint expect(save, \"need a save\")!",
+	},
+	ErrorCode {
+		code: "PS0214",
+		title: "return outside of a function",
+		explanation: "A return in the top-level code of a script has no function to return \
+from. End the script some other way, or move the code into a function.",
+		example: "return 1!",
+	},
+	ErrorCode {
+		code: "PS0300",
+		title: "not supported by the bytecode compiler",
+		explanation: "The script is valid, but it uses something the bytecode compiler cannot \
+compile yet: classes, signals, defi, @reference, try, throw and ?, assigning to a property of a \
+property, or a local declared after a nested function that uses it. `cii compile` writes no .pyxc for it, and running the .pyxc runs \
+the .pyx on the interpreter instead.",
+		example: "class Player { }",
+	},
+	ErrorCode {
+		code: "PS0301",
+		title: "compile error",
+		explanation: "The bytecode compiler could not compile or save the script for a reason \
+with no code of its own, such as a return outside of any function.",
+		example: "return 1!",
+	},
+	ErrorCode {
+		code: "PS0302",
+		title: "too large to compile",
+		explanation: "Part of the script is bigger than bytecode can hold: a call with more than \
+255 arguments, or a function with more than 65535 constants, local variables, captured \
+variables, nested functions, list items or bytes of code. Split it into smaller functions or \
+pass a list instead.",
+		example: "print max(0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,\n0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0)!",
+	},
+	ErrorCode {
+		code: "PS0400",
+		title: "file could not be loaded",
+		explanation: "The engine could not read a script, or could not write or load its \
+compiled .pyxc file: the file is missing or unreadable, or it is not a compiled PScript file \
+this cii can load. Compiling the .pyx again replaces a .pyxc from another version.",
+		example: "// the game loads this as a .pyxc file, but it was never compiled\nprint \"hi\"!",
+	},
+	ErrorCode {
+		code: "PS0401",
+		title: "undefined function called by the game",
+		explanation: "The game called a function of the script by name, such as a hook it runs \
+every frame, but the script declares no global by that name.",
+		example: "// the game calls update() every frame\nfunc tick() { }",
+	},
+	ErrorCode {
+		code: "PS0402",
+		title: "game called something that is not a function",
+		explanation: "The game called a function of the script by name, but the global by that \
+name holds some other value.",
+		example: "// the game calls update() every frame\nvar update::1;",
+	},
+	ErrorCode {
+		code: "PS0403",
+		title: "wrong return type",
+		explanation: "A function the game called returned a value the game cannot convert to the \
+type it asked for, such as a String where it wants a Number.",
+		example: "// the game calls update() and wants a Number back\nfunc update() { return \"fast\"! }",
+	},
+];
+
+/// The catalogue entry for `code`, such as `PS0001`.
+pub fn lookup(code: &str) -> Option<&'static ErrorCode> {
+	CATALOGUE.iter().find(|entry| entry.code.eq_ignore_ascii_case(code))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::convert::IntoValue;
+	use crate::engine::Engine;
+	use std::collections::HashMap;
+	use std::fs;
+
+	fn codes_of(source: &str) -> Vec<Option<&'static str>>
+	{
+		let mut engine = Engine::new();
+		engine.set_global("config", HashMap::<String, f64>::new().into_value());
+		match engine.eval(source)
+		{
+			Ok(_) => vec![],
+			Err(error) => error.diagnostics().iter().map(|diagnostic| diagnostic.code).collect(),
+		}
+	}
+
+	/// Like `codes_of`, for a script run as the file `name` in a directory
+	/// of its own.
+	fn file_codes_of(name: &str, source: &str) -> Vec<Option<&'static str>>
+	{
+		let dir = std::env::temp_dir().join(format!("cii_codes_{}_{}", name, std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join(name), source).unwrap();
+		let result = Engine::new().eval_file(dir.join(name));
+		fs::remove_dir_all(&dir).unwrap();
+		match result
+		{
+			Ok(_) => vec![],
+			Err(error) => error.diagnostics().iter().map(|diagnostic| diagnostic.code).collect(),
+		}
+	}
+
+	/// Like `codes_of`, for errors the bytecode compiler reports.
+	fn compile_codes_of(source: &str) -> Vec<Option<&'static str>>
+	{
+		match Engine::new().compile(source)
+		{
+			Ok(_) => vec![],
+			Err(error) => error.diagnostics().iter().map(|diagnostic| diagnostic.code).collect(),
+		}
+	}
+
+	/// Like `codes_of`, for errors the game hosting the engine gets: loading
+	/// the example as a `.pyxc` file for PS0400, and otherwise running it
+	/// and calling its `update` for a Number.
+	fn host_codes_of(code: &str, source: &str) -> Vec<Option<&'static str>>
+	{
+		let mut engine = Engine::new();
+		let result = match code
+		{
+			"PS0400" => {
+				let path = std::env::temp_dir().join(format!("cii_codes_{}.pyxc", std::process::id()));
+				fs::write(&path, source).unwrap();
+				let result = engine.eval_compiled(&path).map(|_| ());
+				fs::remove_file(&path).unwrap();
+				result
+			}
+			_ => engine.eval(source).and_then(|_| engine.call_function::<f64>("update", ()).map(|_| ())),
+		};
+		match result
+		{
+			Ok(_) => vec![],
+			Err(error) => error.diagnostics().iter().map(|diagnostic| diagnostic.code).collect(),
+		}
+	}
+
+	#[test]
+	fn codes_are_unique_and_in_order()
+	{
+		for pair in CATALOGUE.windows(2)
+		{
+			assert!(pair[0].code < pair[1].code, "{} comes before {}", pair[0].code, pair[1].code);
+		}
+		assert_eq!(lookup("ps0001").map(|entry| entry.title), Some("unterminated string"));
+		assert_eq!(lookup("PS9999"), None);
+	}
+
+	#[test]
+	fn every_example_fails_with_its_code()
+	{
+		for entry in CATALOGUE
+		{
+			let codes = match &entry.code[..4]
+			{
+				"PS03" => compile_codes_of(entry.example),
+				"PS04" => host_codes_of(entry.code, entry.example),
+				_ if entry.code == "PS0209" => file_codes_of("cycle.pyx", entry.example),
+				_ => codes_of(entry.example),
+			};
+			assert_eq!(codes.first(), Some(&Some(entry.code)), "{}: {}", entry.code, entry.example);
+		}
+	}
+
+	#[test]
+	fn every_error_has_a_registered_code()
+	{
+		let sources = [
+			"\"open", "\"\"\"open", "r\"open", "r#\"open\"", "\"a {", "\"\\q\"", "\"\\u{zz}\"", "\"\\u{110000}\"",
+			"'", "''", "'ab'", "0x", "1_", "12abc", "0x1_0000_0000_0000_0000", "~", "@", "r#x",
+			"var ::1;", "var x::;", "(1", "[1", "f(1", "x[1", "x.1", "1 :: 2", "if x { }", "while (x { }",
+			"func (", "func f(1) { }", "func f() {", "class { }", "class A { 1 }", "signal ;", "defi run() { }",
+			"defi main(x) { }", "{ @reference(\"./a\") }", "@reference(1)", "@reference(\"./a\"::@--I++{1})",
+			"@reference(\"./a\"::@x)", "try { }", "try { } catch x", "catch", "throw", "break!", "\"{1 2}\"",
+			"missing", "missing :: 1", "nil()", "-\"a\"", "[1][5]", "\"ab\"[2]", "1 / 0", "config[\"x\"]",
+			"throw 1!", "func f(a) { }\nf()", "return 1!", "func f() { return f()! }\nf()", "expect(error(\"gone\"), \"needed\")",
+			"@reference @lib_nope", "@reference(\"./no/such/module\")", "1 + 1\n2 +",
+		];
+		for source in sources
+		{
+			let codes = codes_of(source);
+			assert!(!codes.is_empty(), "Should have failed: {}", source);
+			for code in codes
+			{
+				assert!(code.and_then(lookup).is_some(), "{:?} has no registered code: {:?}", source, code);
+			}
+		}
+	}
+
+	#[test]
+	fn each_failure_has_its_own_code()
+	{
+		let cases = [
+			("print sqrt(1)!", "PS0202"),
+			("@reference(\"./no/such/module\")", "PS0208"),
+			("@reference @lib_nope", "PS0210"),
+			("@reference @lib_jellybeans::@--I++{@teleport}", "PS0211"),
+			("func f(n) { return f(n + 1)! }\nf(0)!", "PS0212"),
+			("expect(error(\"gone\"), \"needed\")", "PS0213"),
+			("var x::1;\nif (x : 1) { return x! }", "PS0214"),
+			("print join(1, \", \")!", "PS0200"),
+		];
+		for (source, code) in cases
+		{
+			assert_eq!(codes_of(source), vec![Some(code)], "{}", source);
+		}
+		assert_eq!(file_codes_of("self.pyx", "var x::1;\n@reference(\"./self\")"), vec![Some("PS0209")]);
+		let overflow = Engine::new().eval_bytecode("func f(n) { return f(n + 1)! }\nf(0)").unwrap_err();
+		assert_eq!(overflow.diagnostics()[0].code, Some("PS0212"));
+	}
+}
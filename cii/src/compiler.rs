@@ -0,0 +1,843 @@
+// Compiles statements to bytecode for the VM in `vm.rs`.
+//
+// Variables declared at the top level are globals, looked up by name as in
+// the tree-walker. Everything declared inside a block or function gets a
+// stack slot instead, or a cell when a nested function uses the name.
+use crate::chunk::{Capture, Chunk, Function, Op};
+use crate::diagnostic::Diagnostic;
+use crate::expr::{Expr, StractValue};
+use crate::scanner::{Token, TokenType};
+use crate::span::Span;
+use crate::statement::Statement;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+#[derive(Clone, Copy)]
+enum Slot {
+	Stack(u16),
+	Cell(u16),
+}
+
+struct Local {
+	name: String,
+	depth: usize,
+	slot: Slot,
+}
+
+/// A loop being compiled, with the jumps its `break`s leave by.
+struct Loop {
+	stack_slots: usize,
+	breaks: Vec<usize>,
+}
+
+/// The function being compiled, with the locals in scope at this point.
+struct State {
+	function: Function,
+	locals: Vec<Local>,
+	/// Names used inside nested functions; locals with these names go in
+	/// cells. This can include names a nested function only declares, which
+	/// costs a cell but is still correct.
+	captured: HashSet<String>,
+	upvalues: Vec<String>,
+	scope_depth: usize,
+	/// The ids of the open scopes, innermost last.
+	scopes: Vec<usize>,
+	/// Names nested functions took as globals, with where and the scopes
+	/// open here at the time. A local declared later in one of those scopes
+	/// is what the tree-walker would find instead.
+	assumed_globals: Vec<(String, Span, Vec<usize>)>,
+	stack_slots: usize,
+	loops: Vec<Loop>,
+}
+
+impl State {
+	fn new(name: &str, arity: usize, body: &[Statement]) -> Self
+	{
+		let mut captured = HashSet::new();
+		nested_names(body, &mut captured);
+		Self {
+			function: Function {
+				name: name.to_string(),
+				arity,
+				chunk: Chunk::default(),
+				cell_count: 0,
+				captures: vec![],
+			},
+			locals: vec![],
+			captured,
+			upvalues: vec![],
+			scope_depth: 0,
+			scopes: vec![],
+			assumed_globals: vec![],
+			stack_slots: 0,
+			loops: vec![],
+		}
+	}
+}
+
+/// The code of errors for scripts that are valid but use something the
+/// compiler cannot compile yet; the tree-walker runs them instead.
+pub const UNSUPPORTED: &str = "PS0300";
+
+/// The code of compile errors with no code of their own.
+pub const COMPILE_ERROR: &str = "PS0301";
+
+/// Compiles a script. Running the result gives the value of its final
+/// expression statement, or `Nil`, like [`crate::Engine::eval`].
+pub fn compile(statements: &[Statement]) -> Result<Function, Diagnostic>
+{
+	let mut compiler = Compiler {
+		states: vec![State::new("script", 0, statements)],
+		span: Span::default(),
+		scope_count: 0,
+	};
+	let (last, rest) = match statements.split_last()
+	{
+		Some((Statement::Expression { expression, .. }, rest)) => (Some(expression), rest),
+		_ => (None, statements),
+	};
+	for statement in rest
+	{
+		compiler.statement(statement)?;
+	}
+	match last
+	{
+		Some(expression) => compiler.expression(expression)?,
+		None => compiler.emit(Op::Nil),
+	}
+	compiler.emit(Op::Return);
+	Ok(compiler.states.pop().unwrap().function)
+}
+
+struct Compiler {
+	states: Vec<State>,
+	/// The source of the last instruction with one, for those without.
+	span: Span,
+	/// How many scopes have been opened, for their ids.
+	scope_count: usize,
+}
+
+impl Compiler {
+	fn state(&mut self) -> &mut State
+	{
+		self.states.last_mut().unwrap()
+	}
+
+	fn chunk(&mut self) -> &mut Chunk
+	{
+		&mut self.state().function.chunk
+	}
+
+	fn emit(&mut self, op: Op)
+	{
+		let span = self.span;
+		self.chunk().write(op, span);
+	}
+
+	/// Emits an instruction for the code at `span`, which errors it raises
+	/// are reported at.
+	fn emit_at(&mut self, op: Op, span: Span)
+	{
+		self.span = span;
+		self.emit(op);
+	}
+
+	fn constant(&mut self, value: StractValue) -> Result<u16, Diagnostic>
+	{
+		let span = self.span;
+		self.chunk().add_constant(value).map_err(|message| too_large(message, span))
+	}
+
+	fn name(&mut self, token: &Token) -> Result<u16, Diagnostic>
+	{
+		self.span = token.span;
+		self.constant(StractValue::StringValue(token.panoll.clone()))
+	}
+
+	/// Emits a jump whose target is filled in by `patch`.
+	fn jump(&mut self, op: fn(u16) -> Op) -> usize
+	{
+		let span = self.span;
+		self.chunk().write(op(u16::MAX), span)
+	}
+
+	fn patch(&mut self, jump: usize) -> Result<(), Diagnostic>
+	{
+		let target = self.target()?;
+		let code = &mut self.chunk().code;
+		code[jump] = match code[jump]
+		{
+			Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+			_ => Op::Jump(target),
+		};
+		Ok(())
+	}
+
+	/// The index of the next instruction, as a jump target.
+	fn target(&mut self) -> Result<u16, Diagnostic>
+	{
+		let (name, span) = (self.state().function.name.clone(), self.span);
+		u16::try_from(self.chunk().code.len()).map_err(|_| too_large(format!("{} is too long to compile", name), span))
+	}
+
+	fn begin_scope(&mut self)
+	{
+		self.scope_count += 1;
+		let id = self.scope_count;
+		let state = self.state();
+		state.scope_depth += 1;
+		state.scopes.push(id);
+	}
+
+	fn end_scope(&mut self)
+	{
+		let state = self.state();
+		state.scope_depth -= 1;
+		state.scopes.pop();
+		let mut pops = 0;
+		while let Some(local) = state.locals.last()
+		{
+			if local.depth <= state.scope_depth
+			{
+				break;
+			}
+			if let Slot::Stack(_) = local.slot
+			{
+				pops += 1;
+			}
+			state.locals.pop();
+		}
+		state.stack_slots -= pops;
+		for _ in 0..pops
+		{
+			self.emit(Op::Pop);
+		}
+	}
+
+	/// Fails when a function declared earlier in this scope took `name` as
+	/// a global. The tree-walker looks names up when the function runs, so it
+	/// would find this local instead.
+	fn check_declared_late(&mut self, name: &Token) -> Result<(), Diagnostic>
+	{
+		let state = self.state();
+		let scope = match state.scopes.last()
+		{
+			Some(scope) => *scope,
+			None => return Ok(()),
+		};
+		match state.assumed_globals.iter().find(|(used, _, scopes)| *used == name.panoll && scopes.contains(&scope))
+		{
+			Some((_, span, _)) => Err(Diagnostic::error(format!(
+				"'{}' is declared after a function that uses it, which is not supported by the bytecode compiler yet", name.panoll))
+				.with_code(UNSUPPORTED)
+				.with_label(name.span, "declared here")
+				.with_secondary(*span, "used here by a function declared before it")
+				.with_help("declare it before the function")),
+			None => Ok(()),
+		}
+	}
+
+	/// Records that the function being compiled takes `name` as a global,
+	/// for `check_declared_late` in each function around it.
+	fn assume_global(&mut self, name: &Token)
+	{
+		let depth = self.states.len() - 1;
+		for state in &mut self.states[..depth]
+		{
+			if !state.scopes.is_empty()
+			{
+				let scopes = state.scopes.clone();
+				state.assumed_globals.push((name.panoll.clone(), name.span, scopes));
+			}
+		}
+	}
+
+	/// Declares a local whose value is on top of the stack.
+	fn declare_local(&mut self, name: &Token) -> Result<(), Diagnostic>
+	{
+		self.check_declared_late(name)?;
+		let state = self.state();
+		let slot = if state.captured.contains(&name.panoll)
+		{
+			Slot::Cell(new_cell(state, name.span)?)
+		}
+		else
+		{
+			state.stack_slots += 1;
+			Slot::Stack(u16::try_from(state.stack_slots - 1).map_err(|_| too_large("Too many local variables", name.span))?)
+		};
+		state.locals.push(Local {
+			name: name.panoll.clone(),
+			depth: state.scope_depth,
+			slot,
+		});
+		if let Slot::Cell(cell) = slot
+		{
+			self.emit_at(Op::DefineCell(cell), name.span);
+		}
+		Ok(())
+	}
+
+	fn statement(&mut self, statement: &Statement) -> Result<(), Diagnostic>
+	{
+		match statement
+		{
+			Statement::Expression { expression, .. } => {
+				self.expression(expression)?;
+				self.emit(Op::Pop);
+			}
+			Statement::Print { expression, .. } => {
+				self.expression(expression)?;
+				self.emit(Op::Print);
+			}
+			Statement::Var { name, initializer, .. } => {
+				self.expression(initializer)?;
+				if self.state().scope_depth == 0 && self.states.len() == 1
+				{
+					let global = self.name(name)?;
+					self.emit(Op::DefineGlobal(global));
+				}
+				else
+				{
+					self.declare_local(name)?;
+				}
+			}
+			Statement::Block { statements, .. } => {
+				self.begin_scope();
+				for statement in statements
+				{
+					self.statement(statement)?;
+				}
+				self.end_scope();
+			}
+			Statement::If { condition, then_branch, else_branch, .. } => {
+				self.expression(condition)?;
+				let to_else = self.jump(Op::JumpIfFalse);
+				self.emit(Op::Pop);
+				self.statement(then_branch)?;
+				let to_end = self.jump(Op::Jump);
+				self.patch(to_else)?;
+				self.emit(Op::Pop);
+				if let Some(else_branch) = else_branch
+				{
+					self.statement(else_branch)?;
+				}
+				self.patch(to_end)?;
+			}
+			Statement::While { condition, body, .. } => {
+				let start = self.target()?;
+				self.expression(condition)?;
+				let to_exit = self.jump(Op::JumpIfFalse);
+				self.emit(Op::Pop);
+				let stack_slots = self.state().stack_slots;
+				self.state().loops.push(Loop {
+					stack_slots,
+					breaks: vec![],
+				});
+				self.statement(body)?;
+				self.emit(Op::Jump(start));
+				self.patch(to_exit)?;
+				self.emit(Op::Pop);
+				for jump in self.state().loops.pop().unwrap().breaks
+				{
+					self.patch(jump)?;
+				}
+			}
+			Statement::Break { keyword, .. } => {
+				// Drops the locals declared inside the loop on the way out.
+				self.span = keyword.span;
+				let state = self.state();
+				let pops = state.stack_slots - state.loops.last().unwrap().stack_slots;
+				for _ in 0..pops
+				{
+					self.emit(Op::Pop);
+				}
+				let jump = self.jump(Op::Jump);
+				self.state().loops.last_mut().unwrap().breaks.push(jump);
+			}
+			Statement::Throw { keyword, .. } => return Err(unsupported("throw", keyword)),
+			Statement::Try { keyword, .. } => return Err(unsupported("try", keyword)),
+			Statement::Function { name, params, body, .. } => self.function(name, params, body)?,
+			Statement::Return { keyword, value, .. } => {
+				if self.states.len() == 1
+				{
+					return Err(Diagnostic::error("Cannot return from top-level code").with_code(COMPILE_ERROR).with_label(keyword.span, ""));
+				}
+				self.expression(value)?;
+				self.emit_at(Op::Return, keyword.span);
+			}
+			Statement::Define { name, .. } => return Err(unsupported("defi", name)),
+			Statement::Class { name, .. } => return Err(unsupported("class", name)),
+			Statement::Signal { name, .. } => return Err(unsupported("signal", name)),
+			Statement::Reference { target, .. } => {
+				let token = match target
+				{
+					crate::statement::ReferenceTarget::Library(token) => token,
+					crate::statement::ReferenceTarget::File { path, .. } => path,
+				};
+				return Err(unsupported("@reference", token));
+			}
+		}
+		Ok(())
+	}
+
+	fn function(&mut self, name: &Token, params: &[Token], body: &Rc<Vec<Statement>>) -> Result<(), Diagnostic>
+	{
+		let global = self.states.len() == 1 && self.state().scope_depth == 0;
+		// A local function is declared first so its body can call it.
+		let cell = match (global, self.state().captured.contains(&name.panoll))
+		{
+			(true, _) => None,
+			(false, true) => {
+				self.check_declared_late(name)?;
+				let state = self.state();
+				let cell = new_cell(state, name.span)?;
+				state.locals.push(Local {
+					name: name.panoll.clone(),
+					depth: state.scope_depth,
+					slot: Slot::Cell(cell),
+				});
+				self.emit_at(Op::NewCell(cell), name.span);
+				Some(cell)
+			}
+			(false, false) => None,
+		};
+
+		self.states.push(State::new(&name.panoll, params.len(), body));
+		self.begin_scope();
+		for param in params
+		{
+			let state = self.state();
+			state.stack_slots += 1;
+			let slot = (state.stack_slots - 1) as u16;
+			if state.captured.contains(&param.panoll)
+			{
+				self.emit_at(Op::GetLocal(slot), param.span);
+				self.declare_local(param)?;
+			}
+			else
+			{
+				let depth = state.scope_depth;
+				state.locals.push(Local {
+					name: param.panoll.clone(),
+					depth,
+					slot: Slot::Stack(slot),
+				});
+			}
+		}
+		for statement in body.iter()
+		{
+			self.statement(statement)?;
+		}
+		self.emit(Op::Nil);
+		self.emit(Op::Return);
+		let state = self.states.pop().unwrap();
+
+		let chunk = self.chunk();
+		chunk.functions.push(Rc::new(state.function));
+		let index = u16::try_from(chunk.functions.len() - 1).map_err(|_| too_large("Too many functions in one function", name.span))?;
+		self.emit_at(Op::Closure(index), name.span);
+		match cell
+		{
+			Some(cell) => {
+				self.emit(Op::SetCell(cell));
+				self.emit(Op::Pop);
+			}
+			None if global => {
+				let global = self.name(name)?;
+				self.emit(Op::DefineGlobal(global));
+			}
+			None => self.declare_local(name)?,
+		}
+		Ok(())
+	}
+
+	fn expression(&mut self, expr: &Expr) -> Result<(), Diagnostic>
+	{
+		match expr
+		{
+			Expr::Lateral { value, span } => {
+				let op = match value
+				{
+					StractValue::Nil => Op::Nil,
+					StractValue::True => Op::True,
+					StractValue::False => Op::False,
+					other => Op::Constant(self.constant(other.clone())?),
+				};
+				self.emit_at(op, *span);
+			}
+			Expr::Grouping { expression, .. } => self.expression(expression)?,
+			Expr::Variable { name } => {
+				self.span = name.span;
+				let op = match self.resolve(self.states.len() - 1, &name.panoll)?
+				{
+					Some(Slot::Stack(slot)) => Op::GetLocal(slot),
+					Some(Slot::Cell(cell)) => Op::GetCell(cell),
+					None => match self.upvalue(self.states.len() - 1, &name.panoll)?
+					{
+						Some(upvalue) => Op::GetUpvalue(upvalue),
+						None => {
+							self.assume_global(name);
+							Op::GetGlobal(self.name(name)?)
+						}
+					},
+				};
+				self.emit_at(op, expr.span());
+			}
+			Expr::Assign { name, value } => {
+				self.expression(value)?;
+				self.span = name.span;
+				let op = self.assign(name)?;
+				self.emit_at(op, expr.span());
+			}
+			Expr::Logical { left, operator, right } => {
+				self.expression(left)?;
+				self.span = operator.span;
+				let to_end = if operator.token_type == TokenType::Or
+				{
+					let to_right = self.jump(Op::JumpIfFalse);
+					let to_end = self.jump(Op::Jump);
+					self.patch(to_right)?;
+					to_end
+				}
+				else
+				{
+					self.jump(Op::JumpIfFalse)
+				};
+				self.emit(Op::Pop);
+				self.expression(right)?;
+				self.patch(to_end)?;
+			}
+			Expr::Unary { operator, right } => {
+				self.expression(right)?;
+				let op = match operator.token_type
+				{
+					TokenType::Minus => Op::Negate,
+					TokenType::Bang => Op::Not,
+					other => return Err(Diagnostic::error(format!("{} is not a valid operator [TYPE UNARY]", other)).with_code(COMPILE_ERROR).with_label(operator.span, "")),
+				};
+				self.emit_at(op, expr.span());
+			}
+			Expr::Binary { left, operator, right } => {
+				self.expression(left)?;
+				self.expression(right)?;
+				let op = match operator.token_type
+				{
+					TokenType::Plus => Op::Add,
+					TokenType::Minus => Op::Subtract,
+					TokenType::Star => Op::Multiply,
+					TokenType::Slash => Op::Divide,
+					TokenType::Greater => Op::Greater,
+					TokenType::GreaterEqual => Op::GreaterEqual,
+					TokenType::Less => Op::Less,
+					TokenType::LessEqual => Op::LessEqual,
+					TokenType::EqualEqual => Op::Equal,
+					TokenType::BangEqual => Op::NotEqual,
+					other => return Err(Diagnostic::error(format!("{} is not a binary operator", other)).with_code(COMPILE_ERROR).with_label(operator.span, "")),
+				};
+				self.emit_at(op, expr.span());
+			}
+			Expr::Call { callee, paren, arguments } => {
+				self.expression(callee)?;
+				for argument in arguments
+				{
+					self.expression(argument)?;
+				}
+				let count = u8::try_from(arguments.len()).map_err(|_| too_large("Too many arguments", paren.span))?;
+				self.emit_at(Op::Call(count), expr.span());
+			}
+			Expr::Get { object, name } => {
+				self.expression(object)?;
+				let property = self.name(name)?;
+				self.emit_at(Op::GetProperty(property), expr.span());
+			}
+			// A vector in a variable is rebuilt with the component changed
+			// and stored back; one in a property needs the object holding
+			// the property as well, which the stack does not keep.
+			Expr::Set { object, name, value } => match &**object
+			{
+				Expr::Variable { name: variable } => {
+					self.expression(object)?;
+					self.expression(value)?;
+					let property = self.name(name)?;
+					self.emit_at(Op::SetComponent(property), expr.span());
+					let op = self.assign(variable)?;
+					self.emit_at(op, expr.span());
+					self.emit_at(Op::Pop, expr.span());
+				}
+				Expr::Get { .. } => return Err(unsupported("Assigning to a property of a property", name)),
+				_ => {
+					self.expression(object)?;
+					self.expression(value)?;
+					let property = self.name(name)?;
+					self.emit_at(Op::SetProperty(property), expr.span());
+				}
+			},
+			Expr::Index { object, index, .. } => {
+				self.expression(object)?;
+				self.expression(index)?;
+				self.emit_at(Op::Index, expr.span());
+			}
+			Expr::List { items, .. } => {
+				for item in items
+				{
+					self.expression(item)?;
+				}
+				let count = u16::try_from(items.len()).map_err(|_| too_large("Too many list items", expr.span()))?;
+				self.emit_at(Op::List(count), expr.span());
+			}
+			Expr::Propagate { question, .. } => return Err(unsupported("?", question)),
+			Expr::Interpolation { parts, .. } => {
+				for part in parts
+				{
+					self.expression(part)?;
+				}
+				let count = u16::try_from(parts.len()).map_err(|_| too_large("Too many interpolated parts", expr.span()))?;
+				self.emit_at(Op::Interpolate(count), expr.span());
+			}
+		}
+		Ok(())
+	}
+
+	/// The instruction that stores the top of the stack in the variable
+	/// `name`, leaving it there.
+	fn assign(&mut self, name: &Token) -> Result<Op, Diagnostic>
+	{
+		let op = match self.resolve(self.states.len() - 1, &name.panoll)?
+		{
+			Some(Slot::Stack(slot)) => Op::SetLocal(slot),
+			Some(Slot::Cell(cell)) => Op::SetCell(cell),
+			None => match self.upvalue(self.states.len() - 1, &name.panoll)?
+			{
+				Some(upvalue) => Op::SetUpvalue(upvalue),
+				None => {
+					self.assume_global(name);
+					Op::SetGlobal(self.name(name)?)
+				}
+			},
+		};
+		Ok(op)
+	}
+
+	/// Finds `name` among the locals of `self.states[depth]`.
+	fn resolve(&self, depth: usize, name: &str) -> Result<Option<Slot>, Diagnostic>
+	{
+		let state = &self.states[depth];
+		if depth == 0 && state.scope_depth == 0
+		{
+			return Ok(None);
+		}
+		Ok(state.locals.iter().rev().find(|local| local.name == name).map(|local| local.slot))
+	}
+
+	/// Finds `name` in the functions around `self.states[depth]`, capturing
+	/// it into every function in between.
+	fn upvalue(&mut self, depth: usize, name: &str) -> Result<Option<u16>, Diagnostic>
+	{
+		if depth == 0
+		{
+			return Ok(None);
+		}
+		if let Some(index) = self.states[depth].upvalues.iter().position(|upvalue| upvalue == name)
+		{
+			return Ok(Some(index as u16));
+		}
+		let capture = match self.resolve(depth - 1, name)?
+		{
+			Some(Slot::Cell(cell)) => Capture::Cell(cell),
+			Some(Slot::Stack(_)) => return Err(Diagnostic::error(format!("Cannot capture '{}', which is not in a cell", name)).with_code(COMPILE_ERROR).with_label(self.span, "")),
+			None => match self.upvalue(depth - 1, name)?
+			{
+				Some(upvalue) => Capture::Upvalue(upvalue),
+				None => return Ok(None),
+			},
+		};
+		let span = self.span;
+		let state = &mut self.states[depth];
+		state.upvalues.push(name.to_string());
+		state.function.captures.push(capture);
+		u16::try_from(state.upvalues.len() - 1).map(Some).map_err(|_| too_large(format!("{} captures too many variables", state.function.name), span))
+	}
+}
+
+fn new_cell(state: &mut State, span: Span) -> Result<u16, Diagnostic>
+{
+	state.function.cell_count += 1;
+	u16::try_from(state.function.cell_count - 1).map_err(|_| too_large(format!("{} has too many captured variables", state.function.name), span))
+}
+
+fn unsupported(what: &str, token: &Token) -> Diagnostic
+{
+	Diagnostic::error(format!("{} is not supported by the bytecode compiler yet", what))
+		.with_code(UNSUPPORTED)
+		.with_label(token.span, "")
+}
+
+fn too_large(message: impl Into<String>, span: Span) -> Diagnostic
+{
+	Diagnostic::error(message).with_code("PS0302").with_label(span, "")
+}
+
+/// Collects every name used in the functions declared among `statements`.
+fn nested_names(statements: &[Statement], names: &mut HashSet<String>)
+{
+	for statement in statements
+	{
+		match statement
+		{
+			Statement::Function { body, .. } | Statement::Define { body, .. } => all_names(body, names),
+			Statement::Block { statements, .. } => nested_names(statements, names),
+			Statement::If { then_branch, else_branch, .. } => {
+				nested_names(std::slice::from_ref(then_branch), names);
+				if let Some(else_branch) = else_branch
+				{
+					nested_names(std::slice::from_ref(else_branch), names);
+				}
+			}
+			Statement::While { body, .. } => nested_names(std::slice::from_ref(body), names),
+			_ => (),
+		}
+	}
+}
+
+fn all_names(statements: &[Statement], names: &mut HashSet<String>)
+{
+	for statement in statements
+	{
+		match statement
+		{
+			Statement::Expression { expression, .. } | Statement::Print { expression, .. } => expr_names(expression, names),
+			Statement::Var { initializer, .. } => expr_names(initializer, names),
+			Statement::Return { value, .. } | Statement::Throw { value, .. } => expr_names(value, names),
+			Statement::Block { statements, .. } => all_names(statements, names),
+			Statement::Function { body, .. } | Statement::Define { body, .. } => all_names(body, names),
+			Statement::If { condition, then_branch, else_branch, .. } => {
+				expr_names(condition, names);
+				all_names(std::slice::from_ref(then_branch), names);
+				if let Some(else_branch) = else_branch
+				{
+					all_names(std::slice::from_ref(else_branch), names);
+				}
+			}
+			Statement::While { condition, body, .. } => {
+				expr_names(condition, names);
+				all_names(std::slice::from_ref(body), names);
+			}
+			Statement::Try { body, handlers, finally, .. } => {
+				all_names(body, names);
+				handlers.iter().for_each(|handler| all_names(&handler.body, names));
+				if let Some(finally) = finally
+				{
+					all_names(finally, names);
+				}
+			}
+			Statement::Class { .. } | Statement::Signal { .. } | Statement::Reference { .. } | Statement::Break { .. } => (),
+		}
+	}
+}
+
+fn expr_names(expr: &Expr, names: &mut HashSet<String>)
+{
+	match expr
+	{
+		Expr::Variable { name } => { names.insert(name.panoll.clone()); }
+		Expr::Assign { name, value } => {
+			names.insert(name.panoll.clone());
+			expr_names(value, names);
+		}
+		Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+			expr_names(left, names);
+			expr_names(right, names);
+		}
+		Expr::Call { callee, arguments, .. } => {
+			expr_names(callee, names);
+			arguments.iter().for_each(|argument| expr_names(argument, names));
+		}
+		Expr::Get { object, .. } => expr_names(object, names),
+		Expr::Set { object, value, .. } => {
+			expr_names(object, names);
+			expr_names(value, names);
+		}
+		Expr::Index { object, index, .. } => {
+			expr_names(object, names);
+			expr_names(index, names);
+		}
+		Expr::Grouping { expression, .. } | Expr::Propagate { expression, .. } => expr_names(expression, names),
+		Expr::Unary { right, .. } => expr_names(right, names),
+		Expr::List { items: parts, .. } | Expr::Interpolation { parts, .. } => parts.iter().for_each(|part| expr_names(part, names)),
+		Expr::Lateral { .. } => (),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parser::Parser;
+	use crate::scanner::Scanner;
+
+	fn compile_source(source: &str) -> Result<Function, String>
+	{
+		let tokens = Scanner::new(source).scan_tokens().map_err(|errors| crate::diagnostic::join(&errors))?;
+		let statements = Parser::new(tokens).parse().map_err(|errors| crate::diagnostic::join(&errors))?;
+		compile(&statements).map_err(|error| error.to_string())
+	}
+
+	#[test]
+	fn globals_and_constants()
+	{
+		let function = compile_source("var x::2;\nx + 1").unwrap();
+		assert_eq!(function.chunk.disassemble(), "\
+0000    1 Constant 0 '2'
+0001    | DefineGlobal 1 'x'
+0002    2 GetGlobal 1 'x'
+0003    | Constant 2 '1'
+0004    | Add
+0005    | Return
+");
+	}
+
+	#[test]
+	fn locals_use_slots_and_captured_locals_use_cells()
+	{
+		let function = compile_source("func f(a) { var b::a; func g() { return b! } return g! }").unwrap();
+		let f = &function.chunk.functions[0];
+		assert_eq!(f.chunk.code[..3], [Op::GetLocal(0), Op::DefineCell(0), Op::Closure(0)]);
+		assert_eq!(f.cell_count, 1);
+		assert_eq!(f.chunk.functions[0].captures, vec![Capture::Cell(0)]);
+		assert_eq!(f.chunk.functions[0].chunk.code[0], Op::GetUpvalue(0));
+	}
+
+	#[test]
+	fn loops_jump_back()
+	{
+		let function = compile_source("{ var i::0; while (i < 3) i :: i + 1! }").unwrap();
+		let code = &function.chunk.code;
+		let exit = code.iter().position(|op| matches!(op, Op::JumpIfFalse(_))).unwrap();
+		assert_eq!(code[exit], Op::JumpIfFalse(12));
+		assert_eq!(code[11], Op::Jump(1));
+	}
+
+	#[test]
+	fn unsupported_statements()
+	{
+		assert_eq!(compile_source("class A { }").err(), Some("class is not supported by the bytecode compiler yet at line 1, column 7".to_string()));
+		assert_eq!(compile_source("\ntry { } catch (e) { }").err(), Some("try is not supported by the bytecode compiler yet at line 2, column 1".to_string()));
+		assert_eq!(compile_source("func f() { return g()?! }").err(), Some("? is not supported by the bytecode compiler yet at line 1, column 22".to_string()));
+		assert_eq!(compile_source("a.b.c :: 1").err(), Some("Assigning to a property of a property is not supported by the bytecode compiler yet at line 1, column 5".to_string()));
+		assert_eq!(compile_source("1!\nreturn 2!").err(), Some("Cannot return from top-level code at line 2, column 1".to_string()));
+	}
+
+	#[test]
+	fn locals_declared_after_a_function_that_uses_them()
+	{
+		let error = compile_source("func f() { func g() { return x! } var x::1; return g()! }").err().unwrap();
+		assert_eq!(error, "'x' is declared after a function that uses it, which is not supported by the bytecode compiler yet \
+at line 1, column 39\n  help: declare it before the function");
+		assert!(compile_source("{ func g() { x :: 2! } func x() { } }").is_err());
+		assert!(compile_source("func f() { { func g() { return x! } } var x::1; }").is_err());
+		// Declared first, or in a scope the function cannot see, it is fine.
+		assert!(compile_source("func f() { var x::1; func g() { return x! } return g()! }").is_ok());
+		assert!(compile_source("func f() { func g() { return x! } { var x::1; } }").is_ok());
+		assert!(compile_source("func g() { return x! }\nvar x::1;").is_ok());
+	}
+}
@@ -0,0 +1,431 @@
+// Conversions between script values and Rust types, used to unpack the
+// arguments of registered native functions and to pack their results, and
+// by the engine to pass arguments in and read results out.
+use crate::error::ErrorValue;
+use crate::expr::{StractValue, StractValue::*};
+use crate::gc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A Rust type that can be read out of a script value.
+pub trait FromValue: Sized {
+	fn from_value(value: &StractValue) -> Result<Self, String>;
+}
+
+/// A Rust type that can be handed to a script as a value.
+pub trait IntoValue {
+	fn into_value(self) -> StractValue;
+}
+
+fn mismatch(expected: &str, found: &StractValue) -> String
+{
+	format!("expected {}, got {}", expected, found.to_type())
+}
+
+impl FromValue for StractValue {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		Ok(value.clone())
+	}
+}
+
+impl FromValue for f64 {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			Number(x) => Ok(*x),
+			other => Err(mismatch("Number", other)),
+		}
+	}
+}
+
+impl FromValue for i64 {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			// i64::MAX as f64 rounds up to 2^63, which is already out of range.
+			Number(x) if x.fract() == 0.0 && *x >= i64::MIN as f64 && *x < i64::MAX as f64 => Ok(*x as i64),
+			Number(x) if x.fract() == 0.0 => Err(format!("expected a whole Number within 64 bits, got {}", x)),
+			Number(x) => Err(format!("expected a whole Number, got {}", x)),
+			other => Err(mismatch("Number", other)),
+		}
+	}
+}
+
+impl FromValue for bool {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			True => Ok(true),
+			False => Ok(false),
+			other => Err(mismatch("Boolean", other)),
+		}
+	}
+}
+
+impl FromValue for char {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			Char(c) => Ok(*c),
+			other => Err(mismatch("Char", other)),
+		}
+	}
+}
+
+impl FromValue for String {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			StringValue(s) => Ok(s.clone()),
+			Char(c) => Ok(c.to_string()),
+			other => Err(mismatch("String", other)),
+		}
+	}
+}
+
+impl FromValue for ErrorValue {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			Error(error) => Ok(error.as_ref().clone()),
+			other => Err(mismatch("Error", other)),
+		}
+	}
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			List(items) => items.borrow().iter().enumerate()
+				.map(|(i, item)| T::from_value(item).map_err(|e| format!("item {}: {}", i, e)))
+				.collect(),
+			other => Err(mismatch("List", other)),
+		}
+	}
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			Nil | Null => Ok(None),
+			other => T::from_value(other).map(Some),
+		}
+	}
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			Map(entries) => entries.borrow().iter()
+				.map(|(key, item)| T::from_value(item).map(|item| (key.clone(), item)).map_err(|e| format!("key '{}': {}", key, e)))
+				.collect(),
+			other => Err(mismatch("Map", other)),
+		}
+	}
+}
+
+impl IntoValue for StractValue {
+	fn into_value(self) -> StractValue {
+		self
+	}
+}
+
+impl IntoValue for () {
+	fn into_value(self) -> StractValue {
+		Nil
+	}
+}
+
+impl IntoValue for f64 {
+	fn into_value(self) -> StractValue {
+		Number(self)
+	}
+}
+
+impl IntoValue for i64 {
+	fn into_value(self) -> StractValue {
+		Number(self as f64)
+	}
+}
+
+impl IntoValue for bool {
+	fn into_value(self) -> StractValue {
+		StractValue::from_bool(self)
+	}
+}
+
+impl IntoValue for char {
+	fn into_value(self) -> StractValue {
+		Char(self)
+	}
+}
+
+impl IntoValue for String {
+	fn into_value(self) -> StractValue {
+		StringValue(self)
+	}
+}
+
+impl IntoValue for &str {
+	fn into_value(self) -> StractValue {
+		StringValue(self.to_string())
+	}
+}
+
+impl IntoValue for ErrorValue {
+	fn into_value(self) -> StractValue {
+		Error(Rc::new(self))
+	}
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+	fn into_value(self) -> StractValue {
+		List(gc::alloc(RefCell::new(self.into_iter().map(IntoValue::into_value).collect())))
+	}
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+	fn into_value(self) -> StractValue {
+		match self {
+			Some(value) => value.into_value(),
+			None => Nil,
+		}
+	}
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+	fn into_value(self) -> StractValue {
+		Map(gc::alloc(RefCell::new(self.into_iter().map(|(key, item)| (key, item.into_value())).collect())))
+	}
+}
+
+macro_rules! count {
+	() => { 0 };
+	($head:ident $($tail:ident)*) => { 1 + count!($($tail)*) };
+}
+
+// Tuples travel as fixed-length lists: `(1, "a")` becomes `[1, "a"]`.
+macro_rules! impl_tuple {
+	($($item:ident),+) => {
+		impl<$($item: FromValue),+> FromValue for ($($item,)+) {
+			#[allow(non_snake_case, unused_assignments)]
+			fn from_value(value: &StractValue) -> Result<Self, String> {
+				let items = match value {
+					List(items) => items.borrow().clone(),
+					other => return Err(mismatch("List", other)),
+				};
+				if items.len() != count!($($item)+)
+				{
+					return Err(format!("expected a List of {} items, got {}", count!($($item)+), items.len()));
+				}
+				let mut position = 0;
+				$(
+					let $item = $item::from_value(&items[position]).map_err(|e| format!("item {}: {}", position, e))?;
+					position += 1;
+				)+
+				Ok(($($item,)+))
+			}
+		}
+
+		impl<$($item: IntoValue),+> IntoValue for ($($item,)+) {
+			#[allow(non_snake_case)]
+			fn into_value(self) -> StractValue {
+				let ($($item,)+) = self;
+				vec![$($item.into_value()),+].into_value()
+			}
+		}
+
+		impl<$($item: IntoValue),+> IntoArgs for ($($item,)+) {
+			#[allow(non_snake_case)]
+			fn into_args(self) -> Vec<StractValue> {
+				let ($($item,)+) = self;
+				vec![$($item.into_value()),+]
+			}
+		}
+	};
+}
+
+/// The arguments of a call from the host into a script: a tuple of Rust
+/// values, or a ready-made list of script values.
+pub trait IntoArgs {
+	fn into_args(self) -> Vec<StractValue>;
+}
+
+impl IntoArgs for () {
+	fn into_args(self) -> Vec<StractValue> {
+		vec![]
+	}
+}
+
+impl IntoArgs for &[StractValue] {
+	fn into_args(self) -> Vec<StractValue> {
+		self.to_vec()
+	}
+}
+
+impl<const N: usize> IntoArgs for &[StractValue; N] {
+	fn into_args(self) -> Vec<StractValue> {
+		self.to_vec()
+	}
+}
+
+impl IntoArgs for Vec<StractValue> {
+	fn into_args(self) -> Vec<StractValue> {
+		self
+	}
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, G);
+
+/// Maps a Rust struct to and from a script `Map` one field at a time, for
+/// types that want `FromValue`/`IntoValue` without a derive.
+///
+/// ```
+/// use cii::{FromValue, IntoValue, Record, StractValue};
+///
+/// struct Stats { hp: i64, name: String }
+///
+/// impl IntoValue for Stats {
+///     fn into_value(self) -> StractValue {
+///         Record::new().with("hp", self.hp).with("name", self.name).into_value()
+///     }
+/// }
+///
+/// impl FromValue for Stats {
+///     fn from_value(value: &StractValue) -> Result<Self, String> {
+///         let record = Record::from_value(value)?;
+///         Ok(Stats { hp: record.field("hp")?, name: record.field("name")? })
+///     }
+/// }
+///
+/// let stats = Stats::from_value(&Stats { hp: 3, name: "Ann".to_string() }.into_value()).unwrap();
+/// assert_eq!(stats.hp, 3);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+	fields: HashMap<String, StractValue>,
+}
+
+impl Record {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with(mut self, name: &str, value: impl IntoValue) -> Self {
+		self.fields.insert(name.to_string(), value.into_value());
+		self
+	}
+
+	/// Reads a field, naming it in the error when it is missing or has the
+	/// wrong type. Use an `Option` type for fields that may be absent.
+	pub fn field<T: FromValue>(&self, name: &str) -> Result<T, String> {
+		let value = self.fields.get(name).unwrap_or(&Nil);
+		T::from_value(value).map_err(|e| match self.fields.contains_key(name) {
+			true => format!("field '{}': {}", name, e),
+			false => format!("missing field '{}'", name),
+		})
+	}
+}
+
+impl FromValue for Record {
+	fn from_value(value: &StractValue) -> Result<Self, String> {
+		match value {
+			Map(entries) => Ok(Record { fields: entries.borrow().clone() }),
+			other => Err(mismatch("Map", other)),
+		}
+	}
+}
+
+impl IntoValue for Record {
+	fn into_value(self) -> StractValue {
+		self.fields.into_value()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn list(items: Vec<StractValue>) -> StractValue
+	{
+		List(gc::alloc(RefCell::new(items)))
+	}
+
+	#[test]
+	fn whole_numbers_within_64_bits()
+	{
+		assert_eq!(i64::from_value(&Number(-9223372036854775808.0)), Ok(i64::MIN));
+		assert_eq!(i64::from_value(&Number(9223372036854774784.0)), Ok(9223372036854774784));
+		assert_eq!(i64::from_value(&Number(9223372036854775808.0)), Err("expected a whole Number within 64 bits, got 9223372036854776000".to_string()));
+		assert_eq!(i64::from_value(&Number(-1e19)), Err("expected a whole Number within 64 bits, got -10000000000000000000".to_string()));
+		assert_eq!(i64::from_value(&Number(1.5)), Err("expected a whole Number, got 1.5".to_string()));
+	}
+
+	#[test]
+	fn options()
+	{
+		assert_eq!(Option::<f64>::from_value(&Nil), Ok(None));
+		assert_eq!(Option::<f64>::from_value(&Number(2.0)), Ok(Some(2.0)));
+		assert_eq!(Option::<f64>::from_value(&True), Err("expected Number, got Boolean".to_string()));
+		assert_eq!(Some("a").into_value(), StringValue("a".to_string()));
+		assert_eq!(None::<i64>.into_value(), Nil);
+	}
+
+	#[test]
+	fn maps()
+	{
+		let mut scores = HashMap::new();
+		scores.insert("ann".to_string(), 3_i64);
+		let value = scores.clone().into_value();
+		assert_eq!(value.to_string(), "{ann: 3}");
+		assert_eq!(HashMap::<String, i64>::from_value(&value), Ok(scores));
+		let bad = HashMap::from([("bo".to_string(), True)]).into_value();
+		assert_eq!(HashMap::<String, i64>::from_value(&bad), Err("key 'bo': expected Number, got Boolean".to_string()));
+	}
+
+	#[test]
+	fn tuples()
+	{
+		let value = (1_i64, "a", true).into_value();
+		assert_eq!(value, list(vec![Number(1.0), StringValue("a".to_string()), True]));
+		assert_eq!(<(i64, String, bool)>::from_value(&value), Ok((1, "a".to_string(), true)));
+		assert_eq!(<(i64, i64)>::from_value(&value), Err("expected a List of 2 items, got 3".to_string()));
+		assert_eq!(<(i64, i64, i64)>::from_value(&value), Err("item 1: expected Number, got String".to_string()));
+		assert_eq!((2.5, 'x').into_args(), vec![Number(2.5), Char('x')]);
+	}
+
+	#[derive(Debug, PartialEq)]
+	struct Stats {
+		hp: i64,
+		tags: Vec<String>,
+		owner: Option<String>,
+	}
+
+	impl FromValue for Stats {
+		fn from_value(value: &StractValue) -> Result<Self, String> {
+			let record = Record::from_value(value)?;
+			Ok(Stats { hp: record.field("hp")?, tags: record.field("tags")?, owner: record.field("owner")? })
+		}
+	}
+
+	impl IntoValue for Stats {
+		fn into_value(self) -> StractValue {
+			Record::new().with("hp", self.hp).with("tags", self.tags).with("owner", self.owner).into_value()
+		}
+	}
+
+	#[test]
+	fn records_map_struct_fields()
+	{
+		let stats = Stats { hp: 7, tags: vec!["boss".to_string()], owner: None };
+		let value = Stats { hp: 7, tags: vec!["boss".to_string()], owner: None }.into_value();
+		assert_eq!(value.to_string(), "{hp: 7, owner: nil, tags: [boss]}");
+		assert_eq!(Stats::from_value(&value), Ok(stats));
+
+		let partial = Record::new().with("hp", 1_i64).into_value();
+		assert_eq!(Stats::from_value(&partial), Err("missing field 'tags'".to_string()));
+		let wrong = Record::new().with("hp", "full").into_value();
+		assert_eq!(Stats::from_value(&wrong), Err("field 'hp': expected Number, got String".to_string()));
+		assert_eq!(Stats::from_value(&Number(1.0)), Err("expected Map, got Number".to_string()));
+	}
+}
@@ -0,0 +1,406 @@
+// Problems found in a script, as the `cii` tool reports them: a message
+// with an optional code, labels pointing into the source, and notes and
+// help below it. `render` draws them over the source the way a compiler
+// would,
+//
+//     error[PS0001]: Unterminated NONULL ATString <j
+//      --> game.pyx:3:8
+//       |
+//     3 | var x::"abc
+//       |        ^ the string starts here
+//       |
+//       = help: close it with '"'
+//
+// and `to_json` writes them for editors and other tools. Codes are listed
+// in `crate::codes`.
+use crate::sources;
+use crate::span::Span;
+use std::fmt;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+	Error,
+	Warning,
+	Note,
+}
+
+impl Severity {
+	pub fn name(&self) -> &'static str {
+		match self
+		{
+			Severity::Error => "error",
+			Severity::Warning => "warning",
+			Severity::Note => "note",
+		}
+	}
+
+	fn color(&self) -> &'static str {
+		match self
+		{
+			Severity::Error => "\x1b[1;31m",
+			Severity::Warning => "\x1b[1;33m",
+			Severity::Note => "\x1b[1;36m",
+		}
+	}
+}
+
+/// Source text a diagnostic points at. The primary label marks what is
+/// wrong; secondary ones mark related code, such as an unclosed bracket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+	pub span: Span,
+	pub message: String,
+	pub primary: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub code: Option<&'static str>,
+	pub message: String,
+	pub labels: Vec<Label>,
+	pub notes: Vec<String>,
+	pub help: Vec<String>,
+}
+
+impl Diagnostic {
+	pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+		Self {
+			severity,
+			code: None,
+			message: message.into(),
+			labels: vec![],
+			notes: vec![],
+			help: vec![],
+		}
+	}
+
+	pub fn error(message: impl Into<String>) -> Self {
+		Self::new(Severity::Error, message)
+	}
+
+	pub fn warning(message: impl Into<String>) -> Self {
+		Self::new(Severity::Warning, message)
+	}
+
+	pub fn with_code(mut self, code: &'static str) -> Self {
+		self.code = Some(code);
+		self
+	}
+
+	/// Adds the primary label, which says where the problem is.
+	pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+		self.labels.push(Label {
+			span,
+			message: message.into(),
+			primary: true,
+		});
+		self
+	}
+
+	pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+		self.labels.push(Label {
+			span,
+			message: message.into(),
+			primary: false,
+		});
+		self
+	}
+
+	pub fn with_note(mut self, note: impl Into<String>) -> Self {
+		self.notes.push(note.into());
+		self
+	}
+
+	pub fn with_help(mut self, help: impl Into<String>) -> Self {
+		self.help.push(help.into());
+		self
+	}
+
+	/// Where the problem is: the span of the first primary label, or an
+	/// unknown span when there is none.
+	pub fn span(&self) -> Span {
+		self.labels.iter()
+			.find(|label| label.primary)
+			.map(|label| label.span)
+			.unwrap_or_default()
+	}
+
+	/// Draws the diagnostic over `source`, the text of the file at `path`,
+	/// with ANSI colors when `color` is set. Labels in another file, such
+	/// as a module the error came out of, are drawn over that file's text.
+	/// An empty `source` draws no lines, for files whose text is not at hand.
+	pub fn render(&self, source: &str, path: &str, color: bool) -> String {
+		let paint = |style: &str, text: &str| match color
+		{
+			true => format!("{}{}{}", style, text, RESET),
+			false => text.to_string(),
+		};
+		let span = self.span();
+		// The labels by file, the one the problem is in first.
+		let mut files: Vec<(usize, Vec<&Label>)> = vec![];
+		for label in self.labels.iter().filter(|label| label.span.is_known())
+		{
+			match files.iter_mut().find(|(file, _)| *file == label.span.file)
+			{
+				Some((_, labels)) => labels.push(label),
+				None => files.push((label.span.file, vec![label])),
+			}
+		}
+		files.sort_by_key(|(file, _)| *file != span.file);
+		let files: Vec<(String, String, Vec<&Label>)> = files.into_iter()
+			.map(|(file, mut labels)|
+			{
+				let (name, text) = open(file, source, path);
+				let count = text.split('\n').count();
+				labels.retain(|label| label.span.line <= count && !text.is_empty());
+				labels.sort_by_key(|label| (label.span.line, label.span.column));
+				(name, text, labels)
+			})
+			.filter(|(_, _, labels)| !labels.is_empty())
+			.collect();
+		let width = files.iter().flat_map(|(_, _, labels)| labels).map(|label| label.span.line.to_string().len()).max().unwrap_or(1);
+		let pad = " ".repeat(width);
+		let bar = paint(BLUE, "|");
+
+		let mut out = match &self.code
+		{
+			Some(code) => paint(self.severity.color(), &format!("{}[{}]", self.severity.name(), code)),
+			None => paint(self.severity.color(), self.severity.name()),
+		};
+		out.push_str(&paint(BOLD, &format!(": {}", self.message)));
+		match span.is_known()
+		{
+			true => out.push_str(&format!("\n{}{} {}:{}:{}", pad, paint(BLUE, "-->"), name_of(span.file, path), span.line, span.column)),
+			false if !path.is_empty() => out.push_str(&format!("\n{}{} {}", pad, paint(BLUE, "-->"), path)),
+			false => (),
+		}
+
+		for (index, (name, text, labels)) in files.iter().enumerate()
+		{
+			if index > 0 || !span.is_known()
+			{
+				let first = labels[0].span;
+				if index > 0
+				{
+					out.push_str(&format!("\n{} {}", pad, bar));
+				}
+				out.push_str(&format!("\n{}{} {}:{}:{}", pad, paint(BLUE, ":::"), name, first.line, first.column));
+			}
+			out.push_str(&format!("\n{} {}", pad, bar));
+			let lines: Vec<&str> = text.split('\n').collect();
+			let mut previous = None;
+			for label in labels
+			{
+				let number = label.span.line;
+				let line = lines[number - 1].trim_end_matches('\r');
+				if previous != Some(number)
+				{
+					if previous.is_some_and(|previous| number > previous + 1)
+					{
+						out.push_str(&format!("\n{}", paint(BLUE, "...")));
+					}
+					out.push_str(&format!("\n{} {} {}", paint(BLUE, &format!("{:>width$}", number, width = width)), bar, expand_tabs(line)));
+					previous = Some(number);
+				}
+				let before: String = line.chars().take(label.span.column.saturating_sub(1)).collect();
+				let marked: String = label.span.text(text).split('\n').next().unwrap_or("").to_string();
+				let (mark, style) = match label.primary
+				{
+					true => ("^", self.severity.color()),
+					false => ("-", BLUE),
+				};
+				let marks = mark.repeat(expand_tabs(&marked).chars().count().max(1));
+				let mut underline = format!("{}{}", " ".repeat(expand_tabs(&before).chars().count()), paint(style, &marks));
+				if !label.message.is_empty()
+				{
+					underline.push(' ');
+					underline.push_str(&paint(style, &label.message));
+				}
+				out.push_str(&format!("\n{} {} {}", pad, bar, underline));
+			}
+		}
+
+		if !files.is_empty() && (!self.notes.is_empty() || !self.help.is_empty())
+		{
+			out.push_str(&format!("\n{} {}", pad, bar));
+		}
+		for note in &self.notes
+		{
+			out.push_str(&format!("\n{} {} {}", pad, paint(BLUE, "="), paint(BOLD, "note:")));
+			out.push(' ');
+			out.push_str(note);
+		}
+		for help in &self.help
+		{
+			out.push_str(&format!("\n{} {} {}", pad, paint(BLUE, "="), paint(BOLD, "help:")));
+			out.push(' ');
+			out.push_str(help);
+		}
+		out
+	}
+
+	/// The diagnostic as one line of JSON, for tools that read `cii --json`.
+	/// `file` is the file the problem is in, and each label has its own.
+	pub fn to_json(&self, path: &str) -> String {
+		let labels: Vec<String> = self.labels.iter()
+			.filter(|label| label.span.is_known())
+			.map(|label| format!(
+				"{{\"file\":{},\"line\":{},\"column\":{},\"start\":{},\"end\":{},\"primary\":{},\"message\":{}}}",
+				json_string(&name_of(label.span.file, path)), label.span.line, label.span.column, label.span.start, label.span.end, label.primary, json_string(&label.message)))
+			.collect();
+		let strings = |items: &[String]| items.iter().map(|item| json_string(item)).collect::<Vec<String>>().join(",");
+		format!(
+			"{{\"severity\":\"{}\",\"code\":{},\"message\":{},\"file\":{},\"labels\":[{}],\"notes\":[{}],\"help\":[{}]}}",
+			self.severity.name(),
+			self.code.map(json_string).unwrap_or_else(|| "null".to_string()),
+			json_string(&self.message),
+			json_string(&name_of(self.span().file, path)),
+			labels.join(","),
+			strings(&self.notes),
+			strings(&self.help))
+	}
+}
+
+/// The message, where it points and any notes and help, one per line.
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message)?;
+		let span = self.span();
+		if span.is_known()
+		{
+			write!(f, " at {}", span)?;
+		}
+		for note in &self.notes
+		{
+			write!(f, "\n  note: {}", note)?;
+		}
+		for help in &self.help
+		{
+			write!(f, "\n  help: {}", help)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Several diagnostics as text, one after another.
+pub fn join(diagnostics: &[Diagnostic]) -> String {
+	diagnostics.iter().map(Diagnostic::to_string).collect::<Vec<String>>().join("\n")
+}
+
+/// The name and text of the file numbered `file`, which is `path` with
+/// `source` for file 0 or a file [`crate::sources`] does not have.
+fn open(file: usize, source: &str, path: &str) -> (String, String) {
+	match sources::get(file)
+	{
+		Some(found) => (found.name(), found.text.clone()),
+		None => (path.to_string(), source.to_string()),
+	}
+}
+
+fn name_of(file: usize, path: &str) -> String {
+	sources::get(file).map_or_else(|| path.to_string(), |found| found.name())
+}
+
+/// Tabs become four spaces so carets line up under them.
+fn expand_tabs(text: &str) -> String {
+	text.replace('\t', "    ")
+}
+
+fn json_string(text: &str) -> String {
+	let mut out = String::from("\"");
+	for c in text.chars()
+	{
+		match c
+		{
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rendering_points_at_the_source()
+	{
+		let source = "var speed::1;\n\tspeed :: (speed + \"fast\"!";
+		let diagnostic = Diagnostic::error("Expected ')'")
+			.with_code("PS0100")
+			.with_label(Span::new(39, 40, 2, 26), "expected ')' here")
+			.with_secondary(Span::new(24, 25, 2, 11), "to close this")
+			.with_help("add ')' before '!'");
+		assert_eq!(diagnostic.render(source, "game.pyx", false), "\
+error[PS0100]: Expected ')'
+ --> game.pyx:2:26
+  |
+2 |     speed :: (speed + \"fast\"!
+  |              - to close this
+  |                             ^ expected ')' here
+  |
+  = help: add ')' before '!'");
+		assert_eq!(diagnostic.to_string(), "Expected ')' at line 2, column 26\n  help: add ')' before '!'");
+	}
+
+	#[test]
+	fn labels_in_other_files_are_drawn_over_their_text()
+	{
+		let module = sources::add(std::path::Path::new("mods/shapes.pyx"), "func area(w) {\n\treturn w * \"h\"!\n}");
+		let diagnostic = Diagnostic::error("Star is not definied for string")
+			.with_label(Span::new(23, 30, 2, 9).in_file(module), "")
+			.with_secondary(Span::new(21, 36, 2, 12), "in the module referenced here");
+		assert_eq!(diagnostic.render("var x::1;\n@reference(\"./mods/shapes\")", "main.pyx", false), "\
+error: Star is not definied for string
+ --> mods/shapes.pyx:2:9
+  |
+2 |     return w * \"h\"!
+  |            ^^^^^^^
+  |
+ ::: main.pyx:2:12
+  |
+2 | @reference(\"./mods/shapes\")
+  |            --------------- in the module referenced here");
+		let json = diagnostic.to_json("main.pyx");
+		assert!(json.contains("\"file\":\"mods/shapes.pyx\",\"labels\":[{\"file\":\"mods/shapes.pyx\""), "{}", json);
+		assert!(json.contains("{\"file\":\"main.pyx\",\"line\":2"), "{}", json);
+	}
+
+	#[test]
+	fn rendering_without_a_location()
+	{
+		let diagnostic = Diagnostic::warning("Script has no main").with_note("main runs first");
+		assert_eq!(diagnostic.render("", "game.pyx", false), "warning: Script has no main\n --> game.pyx\n  = note: main runs first");
+		let unseen = Diagnostic::error("Boom").with_label(Span::new(0, 4, 1, 1), "here");
+		assert_eq!(unseen.render("", "game.pyxc", false), "error: Boom\n --> game.pyxc:1:1");
+		let colored = Diagnostic::error("Boom").with_label(Span::new(0, 4, 1, 1), "").render("boom", "a.pyx", true);
+		assert!(colored.starts_with("\x1b[1;31merror\x1b[0m"), "{:?}", colored);
+		assert!(colored.contains("\x1b[1;31m^^^^\x1b[0m"), "{:?}", colored);
+	}
+
+	#[test]
+	fn json_output()
+	{
+		let diagnostic = Diagnostic::error("Unterminated \"string\"")
+			.with_label(Span::new(7, 11, 1, 8), "starts here")
+			.with_note("strings end at a quote");
+		assert_eq!(diagnostic.to_json("dir\\game.pyx"), concat!(
+			r#"{"severity":"error","code":null,"message":"Unterminated \"string\"","file":"dir\\game.pyx","#,
+			r#""labels":[{"file":"dir\\game.pyx","line":1,"column":8,"start":7,"end":11,"primary":true,"message":"starts here"}],"#,
+			r#""notes":["strings end at a quote"],"help":[]}"#));
+	}
+}
@@ -0,0 +1,747 @@
+use crate::chunk::Function;
+use crate::compiler;
+use crate::convert::{FromValue, IntoArgs};
+use crate::diagnostic::{self, Diagnostic};
+use crate::error::RuntimeError;
+use crate::expr::{CallableImpl, StractValue};
+use crate::gc::{self, GcStats};
+use crate::hooks::Hook;
+use crate::interpreter::Interpreter;
+use crate::modules;
+use crate::native::{IntoNative, IntoNativeResult, NativeModule};
+use crate::parser::Parser;
+use crate::pyxc;
+use crate::scanner::Scanner;
+use crate::sources;
+use crate::statement::Statement;
+use crate::vm::Vm;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Why a call into the [`Engine`] failed, split by the stage that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+	/// The script file could not be read.
+	Io { path: String, message: String },
+	/// The source could not be split into tokens.
+	Scan(Vec<Diagnostic>),
+	/// The tokens do not form valid statements.
+	Parse(Vec<Diagnostic>),
+	/// The bytecode compiler could not compile the statements.
+	Compile(Box<Diagnostic>),
+	/// The statements use something the bytecode compiler does not support
+	/// yet, such as classes or `defi`. `eval` can still run them.
+	Unsupported(Box<Diagnostic>),
+	/// The script failed while running.
+	Runtime(RuntimeError),
+	/// `call_function` named a global that does not exist.
+	UndefinedFunction(String),
+	/// `call_function` named a global that holds something other than a function.
+	NotCallable { name: String, found: String },
+	/// `call_function` got a result it could not convert to the requested type.
+	ReturnType { name: String, message: String },
+}
+
+impl fmt::Display for EngineError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			EngineError::Io { path, message } => write!(f, "Could not read {}: {}", path, message),
+			EngineError::Scan(errors) | EngineError::Parse(errors) => write!(f, "{}", diagnostic::join(errors)),
+			EngineError::Compile(error) | EngineError::Unsupported(error) => write!(f, "{}", error),
+			EngineError::Runtime(msg) => write!(f, "{}", msg),
+			EngineError::UndefinedFunction(name) => write!(f, "Undefined<j function '{}'", name),
+			EngineError::NotCallable { name, found } => write!(f, "'{}' is a {}, not a function", name, found),
+			EngineError::ReturnType { name, message } => write!(f, "{}() returned the wrong type: {}", name, message),
+		}
+	}
+}
+
+impl std::error::Error for EngineError {}
+
+impl EngineError {
+	/// The error as diagnostics for `cii` to show over the source: one for
+	/// each problem a scan or parse found, or a single one otherwise.
+	/// Every diagnostic has a code from the catalogue.
+	pub fn diagnostics(&self) -> Vec<Diagnostic> {
+		let code = match self {
+			EngineError::Scan(errors) | EngineError::Parse(errors) => return errors.clone(),
+			EngineError::Compile(error) | EngineError::Unsupported(error) => return vec![(**error).clone()],
+			EngineError::Runtime(error) => return error.diagnostics(),
+			EngineError::Io { .. } => "PS0400",
+			EngineError::UndefinedFunction(_) => "PS0401",
+			EngineError::NotCallable { .. } => "PS0402",
+			EngineError::ReturnType { .. } => "PS0403",
+		};
+		vec![Diagnostic::error(self.to_string()).with_code(code)]
+	}
+}
+
+/// How `load_compiled` can run a script.
+#[derive(Debug, Clone)]
+pub enum Compiled {
+	/// Bytecode for `run_bytecode`.
+	Bytecode(Rc<Function>),
+	/// The script at `source` uses something the compiler does not support
+	/// yet, as `reason` says, so it runs on the tree-walker with `eval_file`.
+	Interpreted { source: PathBuf, reason: Diagnostic },
+}
+
+fn read(path: &Path) -> Result<String, EngineError> {
+	fs::read_to_string(path).map_err(|err| EngineError::Io {
+		path: path.display().to_string(),
+		message: err.to_string(),
+	})
+}
+
+/// Returns `result` after collecting garbage if enough was allocated. Every
+/// call into a script ends here, so scripts never pause mid-statement.
+fn safe_point<T>(result: T) -> T {
+	gc::collect_if_due();
+	result
+}
+
+fn invoke<R: FromValue>(name: &str, function: &CallableImpl, args: impl IntoArgs) -> Result<R, EngineError> {
+	let result = safe_point(function.call(&args.into_args())).map_err(EngineError::Runtime)?;
+	R::from_value(&result).map_err(|message| EngineError::ReturnType {
+		name: name.to_string(),
+		message,
+	})
+}
+
+/// Runs PScript source and trades values with it. Globals persist across
+/// calls, so a script can be loaded once and its functions called later.
+///
+/// ```
+/// use cii::{Engine, StractValue};
+///
+/// let mut engine = Engine::new();
+/// engine.eval("func double(x) { return x * 2! }").unwrap();
+/// let result = engine.call_function::<f64>("double", (21,)).unwrap();
+/// assert_eq!(result, 42.0);
+/// let raw = engine.call_function::<StractValue>("double", &[StractValue::Number(1.5)]).unwrap();
+/// assert_eq!(raw, StractValue::Number(3.0));
+/// ```
+pub struct Engine {
+	interpreter: Interpreter,
+}
+
+impl Default for Engine {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Engine {
+	pub fn new() -> Self {
+		Self {
+			interpreter: Interpreter::new(),
+		}
+	}
+
+	fn parse(source: &str, file: usize) -> Result<Vec<Statement>, EngineError> {
+		let mut scanner = Scanner::new(source).in_file(file);
+		let tokens = scanner.scan_tokens().map_err(EngineError::Scan)?;
+		let mut parser = Parser::new(tokens);
+		parser.parse().map_err(EngineError::Parse)
+	}
+
+	/// Runs `source` and returns the value of its final expression
+	/// statement, or `Nil` when it ends with any other kind of statement.
+	pub fn eval(&mut self, source: &str) -> Result<StractValue, EngineError> {
+		self.eval_in(source, 0)
+	}
+
+	/// Runs `source` as the file numbered `file` by [`crate::sources`].
+	fn eval_in(&mut self, source: &str, file: usize) -> Result<StractValue, EngineError> {
+		let mut statements = Self::parse(source, file)?;
+
+		let last = match statements.last() {
+			Some(Statement::Expression { .. }) => statements.pop(),
+			_ => None,
+		};
+		let result = self.interpreter.interpret(statements).and_then(|_| match last {
+			Some(Statement::Expression { expression, .. }) => self.interpreter.interpret_expr(expression),
+			_ => Ok(StractValue::Nil),
+		});
+		safe_point(result).map_err(|e| EngineError::Runtime(e.in_script()))
+	}
+
+	/// Compiles `source` to bytecode without running it.
+	pub fn compile(&self, source: &str) -> Result<Rc<Function>, EngineError> {
+		let statements = Self::parse(source, 0)?;
+		compiler::compile(&statements).map(Rc::new).map_err(|error| match error.code
+		{
+			Some(compiler::UNSUPPORTED) => EngineError::Unsupported(Box::new(error)),
+			_ => EngineError::Compile(Box::new(error)),
+		})
+	}
+
+	/// Runs compiled code on the bytecode VM, sharing this engine's globals
+	/// with scripts run by `eval`.
+	pub fn run_bytecode(&mut self, function: &Rc<Function>) -> Result<StractValue, EngineError> {
+		safe_point(Vm::new(self.interpreter.globals()).run(function.clone())).map_err(EngineError::Runtime)
+	}
+
+	/// Like `eval`, but compiles `source` and runs it on the bytecode VM,
+	/// which is faster for loops and calls. Classes, `defi`, `@reference`
+	/// and exceptions still need `eval`, and fail here as `Unsupported`.
+	pub fn eval_bytecode(&mut self, source: &str) -> Result<StractValue, EngineError> {
+		let function = self.compile(source)?;
+		self.run_bytecode(&function)
+	}
+
+	/// Compiles the script at `source` and saves the bytecode to `output`,
+	/// usually a `.pyxc` file next to it.
+	pub fn compile_file(&self, source: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<(), EngineError> {
+		let text = read(source.as_ref())?;
+		let function = self.compile(&text)?;
+		let bytes = pyxc::serialize(&function, pyxc::source_hash(&text)).map_err(|message| EngineError::Compile(Box::new(Diagnostic::error(message).with_code(compiler::COMPILE_ERROR))))?;
+		fs::write(output.as_ref(), bytes).map_err(|err| EngineError::Io {
+			path: output.as_ref().display().to_string(),
+			message: err.to_string(),
+		})
+	}
+
+	/// Runs a script on the bytecode VM through its compiled file, as
+	/// `load_compiled` finds it, or on the tree-walker when the script uses
+	/// something the compiler does not support yet.
+	pub fn eval_compiled(&mut self, path: impl AsRef<Path>) -> Result<StractValue, EngineError> {
+		match self.load_compiled(path)? {
+			Compiled::Bytecode(function) => self.run_bytecode(&function),
+			Compiled::Interpreted { source, .. } => self.eval_file(source),
+		}
+	}
+
+	/// Loads the bytecode of a script through its compiled file. Given
+	/// `foo.pyx` or `foo.pyxc`, this loads `foo.pyxc` unless it is missing,
+	/// from another format version, or compiled from a different `foo.pyx`;
+	/// then it compiles `foo.pyx` again and tries to save the result. With
+	/// no `foo.pyx`, the compiled file is used as it is. A `foo.pyx` the
+	/// compiler does not support comes back as `Compiled::Interpreted`.
+	pub fn load_compiled(&self, path: impl AsRef<Path>) -> Result<Compiled, EngineError> {
+		let path = path.as_ref();
+		let source = path.with_extension("pyx");
+		let compiled = path.with_extension("pyxc");
+		let text = match source.exists() {
+			true => Some(read(&source)?),
+			false => None,
+		};
+
+		let loaded = fs::read(&compiled)
+			.map_err(|err| err.to_string())
+			.and_then(|bytes| pyxc::deserialize(&bytes));
+		let function = match (loaded, text) {
+			(Ok((hash, function)), Some(text)) if hash == pyxc::source_hash(&text) => Rc::new(function),
+			(Ok((_, function)), None) => Rc::new(function),
+			(Err(message), None) => return Err(EngineError::Io {
+				path: compiled.display().to_string(),
+				message,
+			}),
+			(_, Some(text)) => {
+				let function = match self.compile(&text) {
+					Err(EngineError::Unsupported(reason)) => return Ok(Compiled::Interpreted {
+						source,
+						reason: *reason,
+					}),
+					other => other?,
+				};
+				// A stale file that cannot be replaced only costs a recompile.
+				if let Ok(bytes) = pyxc::serialize(&function, pyxc::source_hash(&text)) {
+					let _ = fs::write(&compiled, bytes);
+				}
+				function
+			}
+		};
+		Ok(Compiled::Bytecode(function))
+	}
+
+	/// Runs a script file. `@reference("./other")` inside it resolves from
+	/// the file's own directory.
+	pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<StractValue, EngineError> {
+		let path = path.as_ref();
+		let contents = read(path)?;
+		let file = sources::add(path, &contents);
+		let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+		let previous = self.interpreter.enter_file(&path).map_err(EngineError::Runtime)?;
+		let result = self.eval_in(&contents, file);
+		self.interpreter.leave_file(previous);
+		result
+	}
+
+	/// Calls the global function `name`, which may be declared by a script
+	/// or be one of the native library functions. Arguments are a tuple of
+	/// Rust values or a slice of script values; the result is converted to
+	/// `R`, so ask for `StractValue` to take it as it is.
+	pub fn call_function<R: FromValue>(&mut self, name: &str, args: impl IntoArgs) -> Result<R, EngineError> {
+		let function = match self.get_global(name) {
+			Some(StractValue::Callable(callable)) => callable,
+			Some(other) => return Err(EngineError::NotCallable {
+				name: name.to_string(),
+				found: other.to_type().to_string(),
+			}),
+			None => return Err(EngineError::UndefinedFunction(name.to_string())),
+		};
+		invoke(name, &function, args)
+	}
+
+	/// Emits the signal `signal` of a script object, such as one read with
+	/// `get_global("player")`, calling its listeners before returning.
+	pub fn emit_signal(&mut self, object: &StractValue, signal: &str, args: impl IntoArgs) -> Result<(), EngineError> {
+		let found = match object {
+			StractValue::Instance(instance) => instance.signals.get(signal).cloned(),
+			StractValue::Signal(own) if own.name == signal => Some(own.clone()),
+			_ => None,
+		};
+		match found {
+			Some(found) => safe_point(found.emit(&args.into_args())).map_err(EngineError::Runtime),
+			None => Err(EngineError::Runtime(format!("{} has no signal '{}'", object.to_type(), signal).into())),
+		}
+	}
+
+	/// Whether the script declared `hook` with `defi`.
+	pub fn has_hook(&self, hook: Hook) -> bool {
+		self.interpreter.hook(hook).is_some()
+	}
+
+	/// The hooks the script declared, in the order of [`Hook::ALL`].
+	pub fn hooks(&self) -> Vec<Hook> {
+		Hook::ALL.into_iter().filter(|hook| self.has_hook(*hook)).collect()
+	}
+
+	/// Calls a hook the script declared with `defi`, such as
+	/// `engine.call_hook::<StractValue>(Hook::Physic, (delta,))` every frame.
+	/// Fails with `UndefinedFunction` when the script did not declare it.
+	pub fn call_hook<R: FromValue>(&mut self, hook: Hook, args: impl IntoArgs) -> Result<R, EngineError> {
+		match self.interpreter.hook(hook) {
+			Some(function) => invoke(hook.name(), &function, args),
+			None => Err(EngineError::UndefinedFunction(hook.name().to_string())),
+		}
+	}
+
+	/// Runs the script's entry points the way `cii` does: `main`, then
+	/// `imide`, skipping any the script does not declare.
+	pub fn run_main(&mut self) -> Result<(), EngineError> {
+		for hook in [Hook::Main, Hook::Imide] {
+			if self.has_hook(hook) {
+				self.call_hook::<StractValue>(hook, ())?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Frees objects that only reference cycles keep alive, such as a
+	/// closure stored in the scope it closes over, and returns how many
+	/// were freed. This also happens on its own after enough allocations;
+	/// a game can call it between frames to keep pauses out of them.
+	///
+	/// The heap is shared by every engine on the thread, so this collects
+	/// for all of them. Values the host holds are never freed.
+	pub fn collect_garbage(&mut self) -> usize {
+		gc::collect()
+	}
+
+	/// How many objects are allocated and freed, and how long the last
+	/// collection took. The stats are per thread: they count the objects
+	/// of every engine on it, not just this one.
+	pub fn gc_stats(&self) -> GcStats {
+		gc::stats()
+	}
+
+	/// Sets how many allocations make the next call into a script collect
+	/// garbage when it returns. Defaults to [`gc::DEFAULT_THRESHOLD`];
+	/// `usize::MAX` leaves collecting to `collect_garbage`. The heap is per
+	/// thread, so this sets it for every engine on the thread.
+	pub fn set_gc_threshold(&mut self, threshold: usize) {
+		gc::set_threshold(threshold);
+	}
+
+	/// Caps how long collecting may pause a frame. With a budget, calls
+	/// into scripts stop collecting on their own and `gc_frame` collects in
+	/// steps that stop when the budget is spent. Like the threshold, this
+	/// is per thread and applies to every engine on it.
+	pub fn set_gc_budget(&mut self, budget: Option<Duration>) {
+		gc::set_budget(budget);
+	}
+
+	/// The garbage collection for the end of a frame, which
+	/// [`Scheduler`](crate::Scheduler) runs after every tick. Returns how
+	/// many objects were freed.
+	pub fn gc_frame(&mut self) -> usize {
+		gc::frame()
+	}
+
+	pub fn get_global(&self, name: &str) -> Option<StractValue> {
+		self.interpreter.globals().borrow().get(name)
+	}
+
+	/// Defines or overwrites a global visible to every script run afterwards.
+	pub fn set_global(&mut self, name: &str, value: StractValue) {
+		self.interpreter.globals().borrow_mut().define(name.to_string(), value);
+	}
+
+	/// Adds a directory to search for modules referenced by bare name, as
+	/// in `@reference("collections")`. Directories are tried in the order
+	/// they were added, after the importing file's own directory.
+	pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+		self.interpreter.add_search_path(path.into());
+	}
+
+	/// Adds the search paths listed in the `PYXIS_PATH` environment variable.
+	pub fn add_search_paths_from_env(&mut self) {
+		for path in modules::paths_from_env() {
+			self.interpreter.add_search_path(path);
+		}
+	}
+
+	pub fn search_paths(&self) -> Vec<PathBuf> {
+		self.interpreter.search_paths()
+	}
+
+	/// Reseeds the generator behind `randf` so runs can be replayed.
+	pub fn seed_random(&mut self, seed: u64) {
+		self.interpreter.seed_random(seed);
+	}
+
+	/// See [`Interpreter::register_fn`].
+	pub fn register_fn<Args>(&mut self, name: &str, fun: impl IntoNative<Args>) {
+		self.interpreter.register_fn(name, fun);
+	}
+
+	/// See [`Interpreter::register_variadic`].
+	pub fn register_variadic<F, R>(&mut self, name: &str, fun: F)
+	where
+		F: Fn(&[StractValue]) -> R + 'static,
+		R: IntoNativeResult,
+	{
+		self.interpreter.register_variadic(name, fun);
+	}
+
+	/// See [`Interpreter::register_module`].
+	pub fn register_module(&mut self, module: NativeModule) {
+		self.interpreter.register_module(module);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::StractValue::*;
+
+	/// A fresh directory under the system temp dir, unique to this test run.
+	fn temp_dir(name: &str) -> PathBuf
+	{
+		let dir = std::env::temp_dir().join(format!("cii_engine_{}_{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn eval_returns_last_expression()
+	{
+		let mut engine = Engine::new();
+		assert_eq!(engine.eval("1 + 2").unwrap(), Number(3.0));
+		assert_eq!(engine.eval("var x::4;\nx * 2!").unwrap(), Number(8.0));
+		assert_eq!(engine.eval("var y::1;").unwrap(), Nil);
+	}
+
+	#[test]
+	fn globals_persist_between_evals()
+	{
+		let mut engine = Engine::new();
+		engine.eval("var score::10;").unwrap();
+		engine.eval("score :: score + 5!").unwrap();
+		assert_eq!(engine.get_global("score"), Some(Number(15.0)));
+		assert_eq!(engine.get_global("missing"), None);
+	}
+
+	#[test]
+	fn set_global_is_visible_to_scripts()
+	{
+		let mut engine = Engine::new();
+		engine.set_global("name", StringValue("Ann".to_string()));
+		assert_eq!(engine.eval(r#""Hi {name}""#).unwrap(), StringValue("Hi Ann".to_string()));
+	}
+
+	#[test]
+	fn call_script_and_native_functions()
+	{
+		let mut engine = Engine::new();
+		engine.eval("var calls::0;\nfunc add(a, b) { calls :: calls + 1! return a + b! }").unwrap();
+		assert_eq!(engine.call_function::<StractValue>("add", &[Number(2.0), Number(3.0)]).unwrap(), Number(5.0));
+		assert_eq!(engine.get_global("calls"), Some(Number(1.0)));
+		assert_eq!(engine.call_function::<StractValue>("caseupper", &[StringValue("ok".to_string())]).unwrap(), StringValue("OK".to_string()));
+	}
+
+	#[test]
+	fn closures_keep_their_scope()
+	{
+		let mut engine = Engine::new();
+		engine.eval("func counter() {\n\tvar n::0;\n\tfunc next() { n :: n + 1! return n! }\n\treturn next!\n}\nvar tick::counter();").unwrap();
+		engine.call_function::<StractValue>("tick", &[]).unwrap();
+		assert_eq!(engine.call_function::<StractValue>("tick", &[]).unwrap(), Number(2.0));
+		assert_eq!(engine.get_global("n"), None);
+	}
+
+	#[test]
+	fn errors_are_split_by_stage()
+	{
+		let mut engine = Engine::new();
+		assert!(matches!(engine.eval("\"open"), Err(EngineError::Scan(_))));
+		assert!(matches!(engine.eval("var ::"), Err(EngineError::Parse(_))));
+		assert!(matches!(engine.eval("missing + 1"), Err(EngineError::Runtime(_))));
+		assert!(matches!(engine.eval("return 1!"), Err(EngineError::Runtime(_))));
+		assert!(matches!(engine.eval_file("/no/such/file.pyx"), Err(EngineError::Io { .. })));
+	}
+
+	#[test]
+	fn errors_become_diagnostics()
+	{
+		let mut engine = Engine::new();
+		let errors = engine.eval("var a::'ab';\nvar b::0x;").unwrap_err().diagnostics();
+		let spans: Vec<(usize, usize)> = errors.iter().map(|error| (error.span().line, error.span().column)).collect();
+		assert_eq!(spans, vec![(1, 8), (2, 8)]);
+
+		let source = "func half(n) {\n\treturn n / 0!\n}\nhalf(4)";
+		let error = &engine.eval(source).unwrap_err().diagnostics()[0];
+		assert_eq!(error.message, "Division by zero");
+		assert_eq!(error.span().text(source), "n / 0");
+		assert_eq!(error.notes, vec!["in half() at line 2", "in script at line 4"]);
+
+		let error = &engine.call_function::<StractValue>("nope", ()).unwrap_err().diagnostics()[0];
+		assert_eq!((error.message.as_str(), error.labels.len()), ("Undefined<j function 'nope'", 0));
+	}
+
+	#[test]
+	fn every_error_has_a_catalogued_code()
+	{
+		let dir = temp_dir("codes");
+		let mut engine = Engine::new();
+		engine.eval("var x::1;\nfunc name() { return \"Ann\"! }").unwrap();
+		let errors = [
+			engine.eval_file(dir.join("missing.pyx")).unwrap_err(),
+			engine.eval("\"open").unwrap_err(),
+			engine.eval("(1").unwrap_err(),
+			engine.compile("var a::1;\nreturn a!").unwrap_err(),
+			engine.compile("class A { }").unwrap_err(),
+			engine.eval("1 / 0").unwrap_err(),
+			engine.call_function::<StractValue>("nope", ()).unwrap_err(),
+			engine.call_function::<StractValue>("x", ()).unwrap_err(),
+			engine.call_function::<f64>("name", ()).unwrap_err(),
+		];
+		let mut seen = vec![];
+		for error in &errors
+		{
+			// Listing every variant makes a new one fail to build until it is added here.
+			seen.push(match error
+			{
+				EngineError::Io { .. } => "Io",
+				EngineError::Scan(_) => "Scan",
+				EngineError::Parse(_) => "Parse",
+				EngineError::Compile(_) => "Compile",
+				EngineError::Unsupported(_) => "Unsupported",
+				EngineError::Runtime(_) => "Runtime",
+				EngineError::UndefinedFunction(_) => "UndefinedFunction",
+				EngineError::NotCallable { .. } => "NotCallable",
+				EngineError::ReturnType { .. } => "ReturnType",
+			});
+			for diagnostic in error.diagnostics()
+			{
+				assert!(diagnostic.code.and_then(crate::codes::lookup).is_some(), "{:?} has no catalogued code", error);
+			}
+		}
+		assert_eq!(seen, ["Io", "Scan", "Parse", "Compile", "Unsupported", "Runtime", "UndefinedFunction", "NotCallable", "ReturnType"]);
+		assert_eq!(errors[3].diagnostics()[0].span().line, 2);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn call_function_errors()
+	{
+		let mut engine = Engine::new();
+		engine.eval("var x::1;\nfunc one(a) { return a! }").unwrap();
+		assert_eq!(engine.call_function::<StractValue>("nope", &[]), Err(EngineError::UndefinedFunction("nope".to_string())));
+		assert_eq!(engine.call_function::<StractValue>("x", &[]), Err(EngineError::NotCallable { name: "x".to_string(), found: "Number".to_string() }));
+		assert!(matches!(engine.call_function::<StractValue>("one", &[]), Err(EngineError::Runtime(_))));
+	}
+
+	#[test]
+	fn eval_file_runs_a_script()
+	{
+		let dir = temp_dir("eval_file");
+		let path = dir.join("area.pyx");
+		fs::write(&path, "func area(w, h) { return w * h! }\narea(3, 4)").unwrap();
+		let mut engine = Engine::new();
+		assert_eq!(engine.eval_file(&path).unwrap(), Number(12.0));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn compiled_files_are_reused_until_the_source_changes()
+	{
+		let dir = temp_dir("compiled");
+		let source = dir.join("game.pyx");
+		let compiled = dir.join("game.pyxc");
+		fs::write(&source, "func speed() { return 3! }\nspeed() * 2").unwrap();
+
+		let mut engine = Engine::new();
+		assert_eq!(engine.eval_compiled(&source).unwrap(), Number(6.0));
+		let (hash, _) = pyxc::deserialize(&fs::read(&compiled).unwrap()).unwrap();
+		assert_eq!(hash, pyxc::source_hash(&fs::read_to_string(&source).unwrap()));
+
+		fs::write(&source, "func speed() { return 5! }\nspeed() * 2").unwrap();
+		assert_eq!(engine.eval_compiled(&compiled).unwrap(), Number(10.0));
+		let (hash, _) = pyxc::deserialize(&fs::read(&compiled).unwrap()).unwrap();
+		assert_eq!(hash, pyxc::source_hash("func speed() { return 5! }\nspeed() * 2"));
+
+		fs::remove_file(&source).unwrap();
+		assert_eq!(engine.eval_compiled(&compiled).unwrap(), Number(10.0));
+		fs::write(&compiled, b"PYXC\x09\x00").unwrap();
+		let err = engine.eval_compiled(&compiled).unwrap_err().to_string();
+		assert!(err.ends_with("compiled with format version 9, but this cii reads version 3"), "{}", err);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn unsupported_scripts_fall_back_to_the_interpreter()
+	{
+		let dir = temp_dir("fallback");
+		let source = dir.join("game.pyx");
+		fs::write(&source, "class Ship { var speed::2; }\nfunc f() { func g() { return x! } var x::3; return g()! }\nShip().speed * f()").unwrap();
+		let mut engine = Engine::new();
+		match engine.load_compiled(&source).unwrap()
+		{
+			Compiled::Interpreted { source: path, reason } => {
+				assert_eq!(path, source);
+				assert_eq!(reason.code, Some(compiler::UNSUPPORTED));
+				assert_eq!(reason.span().line, 1);
+			}
+			Compiled::Bytecode(_) => panic!("classes should not compile"),
+		}
+		assert_eq!(engine.eval_compiled(dir.join("game.pyxc")).unwrap(), Number(6.0));
+		assert!(!dir.join("game.pyxc").exists());
+
+		fs::write(&source, "func f() { func g() { return x! } var x::3; return g()! }\nf()").unwrap();
+		assert!(matches!(engine.compile(&fs::read_to_string(&source).unwrap()), Err(EngineError::Unsupported(_))));
+		assert_eq!(engine.eval_compiled(&source).unwrap(), engine.eval_file(&source).unwrap());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn compile_file_writes_bytecode()
+	{
+		let dir = temp_dir("compile_file");
+		fs::write(dir.join("a.pyx"), "var x::1;\nx + 1").unwrap();
+		let engine = Engine::new();
+		engine.compile_file(dir.join("a.pyx"), dir.join("out.pyxc")).unwrap();
+		let (_, function) = pyxc::deserialize(&fs::read(dir.join("out.pyxc")).unwrap()).unwrap();
+		assert_eq!(Engine::new().run_bytecode(&Rc::new(function)), Ok(Number(2.0)));
+		fs::write(dir.join("b.pyx"), "class A { }").unwrap();
+		assert!(matches!(engine.compile_file(dir.join("b.pyx"), dir.join("b.pyxc")), Err(EngineError::Unsupported(_))));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn call_function_converts_arguments_and_result()
+	{
+		let mut engine = Engine::new();
+		engine.eval("func score(hits, bonus) { return hits * 10 + bonus! }\nfunc greet(name) { return \"hi \" + name! }").unwrap();
+		assert_eq!(engine.call_function::<f64>("score", (3, 2.5)), Ok(32.5));
+		assert_eq!(engine.call_function::<i64>("score", (1_i64, 0_i64)), Ok(10));
+		assert_eq!(engine.call_function::<String>("greet", ("Ann",)), Ok("hi Ann".to_string()));
+		assert_eq!(engine.call_function::<Option<String>>("greet", ("Bo",)), Ok(Some("hi Bo".to_string())));
+		let err = engine.call_function::<bool>("greet", ("Cy",)).unwrap_err();
+		assert_eq!(err.to_string(), "greet() returned the wrong type: expected Boolean, got String");
+		assert!(matches!(engine.call_function::<i64>("score", (1, 0.5)), Err(EngineError::ReturnType { .. })));
+	}
+
+	#[test]
+	fn maps_from_the_host()
+	{
+		let mut engine = Engine::new();
+		let config: std::collections::HashMap<String, f64> = [("speed".to_string(), 2.0)].into_iter().collect();
+		engine.set_global("config", crate::convert::IntoValue::into_value(config));
+		assert_eq!(engine.eval("config.speed * config[\"speed\"]").unwrap(), Number(4.0));
+		engine.eval("config.jump :: 3!").unwrap();
+		assert_eq!(engine.eval("config").unwrap().to_string(), "{jump: 3, speed: 2}");
+		assert!(engine.eval("config[\"fly\"]").is_err());
+	}
+
+	#[test]
+	fn hooks_are_declared_with_defi()
+	{
+		let mut engine = Engine::new();
+		engine.eval("var y::0;\ndefi physic(delta) { y :: y + delta * 10! return y! }\ndefi init() { y :: 1! }\nfunc proc() { }").unwrap();
+		assert_eq!(engine.hooks(), vec![Hook::Physic, Hook::Init]);
+		assert!(!engine.has_hook(Hook::Proc));
+		engine.call_hook::<StractValue>(Hook::Init, ()).unwrap();
+		assert_eq!(engine.call_hook::<f64>(Hook::Physic, (0.5,)), Ok(6.0));
+		assert_eq!(engine.call_function::<f64>("physic", (0.1,)), Ok(7.0));
+		assert_eq!(engine.call_hook::<StractValue>(Hook::Proc, ()), Err(EngineError::UndefinedFunction("proc".to_string())));
+	}
+
+	#[test]
+	fn run_main_calls_main_then_imide()
+	{
+		let order = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+		let log = order.clone();
+		let mut engine = Engine::new();
+		engine.register_fn("log", move |name: String| log.borrow_mut().push(name));
+		engine.eval("defi imide() { log(\"imide\")! }\nlog(\"top\")!\ndefi main() { log(\"main\")! }").unwrap();
+		engine.run_main().unwrap();
+		assert_eq!(*order.borrow(), vec!["top", "main", "imide"]);
+
+		let mut engine = Engine::new();
+		engine.eval("var x::1;").unwrap();
+		assert_eq!(engine.run_main(), Ok(()));
+	}
+
+	#[test]
+	fn hook_signatures_are_checked_when_loading()
+	{
+		let mut engine = Engine::new();
+		let err = engine.eval("defi physic() { }").unwrap_err();
+		assert_eq!(err.to_string(), "defi physic takes 0 parameters, expected physic(delta) at line 1, column 6");
+		let err = engine.eval("defi physics(delta) { }").unwrap_err().to_string();
+		assert!(err.starts_with("Unknown<j define 'physics' at line 1, column 6\n  help: expected one of main, physic"), "{}", err);
+		assert!(engine.eval("defi input(event) { }\ndefi notify(what) { }").is_ok());
+		let err = engine.eval("func outer() {\n\tdefi main() { }\n}").unwrap_err().to_string();
+		assert!(err.starts_with("defi must appear at the top level at line 2, column 2"), "{}", err);
+	}
+
+	#[test]
+	fn module_errors_point_into_the_module()
+	{
+		let dir = temp_dir("module_errors");
+		fs::create_dir_all(dir.join("mods")).unwrap();
+		let main = dir.join("main.pyx");
+		fs::write(&main, "var a::1;\n@reference(\"./mods/m\")\nprint a!").unwrap();
+		fs::write(dir.join("mods/m.pyx"), "func boom(x) {\n\treturn x * \"a\"!\n}\nboom(1)!").unwrap();
+
+		let mut engine = Engine::new();
+		let diagnostics = engine.eval_file(&main).unwrap_err().diagnostics();
+		let module = sources::get(diagnostics[0].span().file).unwrap();
+		assert!(module.path.ends_with("mods/m.pyx"), "{}", module.path.display());
+		assert_eq!(diagnostics[0].code, Some("PS0201"));
+		assert_eq!(diagnostics[0].span().line, 2);
+		let reference = &diagnostics[0].labels[1];
+		assert!(!reference.primary);
+		assert_eq!((reference.span.line, reference.span.column), (2, 12));
+		assert_eq!(sources::get(reference.span.file).unwrap().path, main);
+		assert_eq!(diagnostics[0].notes[..2], ["in boom() at line 2".to_string(), "in script at line 4".to_string()]);
+		assert!(diagnostics[0].notes[2].starts_with("in script at ") && diagnostics[0].notes[2].ends_with("main.pyx:2"), "{:?}", diagnostics[0].notes);
+
+		fs::write(dir.join("mods/m.pyx"), "var x::1;\nvar y::\"abc").unwrap();
+		let diagnostics = Engine::new().eval_file(&main).unwrap_err().diagnostics();
+		assert_eq!(diagnostics.len(), 1);
+		assert_eq!(diagnostics[0].code, Some("PS0001"));
+		assert_eq!(diagnostics[0].message, "Unterminated NONULL ATString <j");
+		assert_eq!((diagnostics[0].span().line, diagnostics[0].span().column), (2, 8));
+		assert!(sources::get(diagnostics[0].span().file).unwrap().path.ends_with("mods/m.pyx"));
+		assert_eq!(diagnostics[0].labels[1].span.line, 2);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
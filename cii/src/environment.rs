@@ -1,14 +1,33 @@
 use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
 use crate::expr::StractValue;
+use crate::gc::{self, Collectable};
 
 pub struct Environment {
 	values: HashMap<String, StractValue>,
+	enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Default for Environment {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl Environment {
 	pub fn new() -> Self {
 		Self {
-			values: HashMap::new()
+			values: HashMap::new(),
+			enclosing: None,
+		}
+	}
+
+	/// A scope nested inside `enclosing`, such as a block or a function body.
+	pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+		Self {
+			values: HashMap::new(),
+			enclosing: Some(enclosing),
 		}
 	}
 
@@ -17,7 +36,52 @@ impl Environment {
 		self.values.insert(name, value);
 	}
 
-	pub fn get(&self, name: String) -> Option<&StractValue> {
-		self.values.get(name)
+	pub fn get(&self, name: &str) -> Option<StractValue> {
+		match (self.values.get(name), &self.enclosing) {
+			(Some(value), _) => Some(value.clone()),
+			(None, Some(enclosing)) => enclosing.borrow().get(name),
+			(None, None) => None,
+		}
 	}
-}
\ No newline at end of file
+
+	/// The variables declared directly in this scope.
+	pub fn values(&self) -> &HashMap<String, StractValue> {
+		&self.values
+	}
+
+	/// Overwrites an existing variable in the nearest scope that declares it.
+	/// Returns false when no scope does.
+	pub fn assign(&mut self, name: &str, value: StractValue) -> bool {
+		if let Some(slot) = self.values.get_mut(name) {
+			*slot = value;
+			return true;
+		}
+		match &self.enclosing {
+			Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+			None => false,
+		}
+	}
+}
+
+impl Collectable for RefCell<Environment> {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		match self.try_borrow()
+		{
+			Ok(environment) => {
+				environment.values.values().for_each(|value| gc::trace_value(value, visit));
+				if let Some(enclosing) = &environment.enclosing
+				{
+					visit(gc::address(enclosing));
+				}
+				true
+			}
+			Err(_) => false,
+		}
+	}
+
+	fn clear(&self) {
+		let mut environment = self.borrow_mut();
+		environment.values.clear();
+		environment.enclosing = None;
+	}
+}
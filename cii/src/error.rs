@@ -0,0 +1,568 @@
+// Errors raised while a script runs.
+//
+// An error starts without a location. The first expression it passes
+// through as it travels out stamps its span with `at`, and each function it
+// escapes adds a frame to the trace with `traced`, so by the time it reaches
+// the host it reads
+//
+//     Index 4 out of range for length 1 at line 2, column 9
+//       in get() at line 2
+//       in script at line 5
+use crate::diagnostic::Diagnostic;
+use crate::expr::StractValue;
+use crate::sources;
+use crate::span::Span;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+	/// An operator or function got a value of the wrong type.
+	TypeMismatch(String),
+	UndefinedVariable(String),
+	/// A function was called with the wrong number of arguments.
+	Arity { function: String, expected: usize, found: usize },
+	IndexOutOfRange { index: usize, length: usize },
+	MissingKey(String),
+	DivisionByZero,
+	/// A value the script threw, boxed to keep errors small.
+	Thrown(Box<StractValue>),
+	/// An `@reference`d file that could not be found or read.
+	ModuleNotFound(String),
+	/// A module that references itself through the modules it references.
+	CircularReference(String),
+	UnknownLibrary(String),
+	/// An `@reference` imports a name the module does not define.
+	MissingExport(String),
+	/// Calls nested deeper than the engine allows, in the named function.
+	StackOverflow(String),
+	/// `expect()` got an error, with the message it was given and the
+	/// error's own.
+	ExpectFailed(String),
+	/// A `return` in the top-level code of a script.
+	ReturnOutsideFunction,
+	/// Anything else, such as the errors natives and the host report.
+	Other(String),
+	/// What is wrong with the text of a module, as its scan or parse found.
+	Syntax(Vec<Diagnostic>),
+	/// An error `?` is carrying out of the function it was raised in, to
+	/// be returned to the caller as a value.
+	Propagated(Box<RuntimeError>),
+}
+
+impl ErrorKind {
+	/// The name a `catch (e: Name)` clause uses for errors of this kind.
+	pub fn name(&self) -> &'static str {
+		match self
+		{
+			ErrorKind::TypeMismatch(_) => "TypeMismatch",
+			ErrorKind::UndefinedVariable(_) => "UndefinedVariable",
+			ErrorKind::Arity { .. } => "Arity",
+			ErrorKind::IndexOutOfRange { .. } => "IndexOutOfRange",
+			ErrorKind::MissingKey(_) => "MissingKey",
+			ErrorKind::DivisionByZero => "DivisionByZero",
+			ErrorKind::Thrown(_) => "Thrown",
+			ErrorKind::ModuleNotFound(_) => "ModuleNotFound",
+			ErrorKind::CircularReference(_) => "CircularReference",
+			ErrorKind::UnknownLibrary(_) => "UnknownLibrary",
+			ErrorKind::MissingExport(_) => "MissingExport",
+			ErrorKind::StackOverflow(_) => "StackOverflow",
+			ErrorKind::ExpectFailed(_) => "ExpectFailed",
+			ErrorKind::ReturnOutsideFunction => "ReturnOutsideFunction",
+			ErrorKind::Other(_) => "Error",
+			ErrorKind::Syntax(_) => "Syntax",
+			ErrorKind::Propagated(error) => error.kind.name(),
+		}
+	}
+
+	/// The error code for this kind, listed in [`crate::codes`].
+	pub fn code(&self) -> &'static str {
+		match self
+		{
+			ErrorKind::Other(_) => "PS0200",
+			ErrorKind::TypeMismatch(_) => "PS0201",
+			ErrorKind::UndefinedVariable(_) => "PS0202",
+			ErrorKind::Arity { .. } => "PS0203",
+			ErrorKind::IndexOutOfRange { .. } => "PS0204",
+			ErrorKind::MissingKey(_) => "PS0205",
+			ErrorKind::DivisionByZero => "PS0206",
+			ErrorKind::Thrown(_) => "PS0207",
+			ErrorKind::ModuleNotFound(_) => "PS0208",
+			ErrorKind::CircularReference(_) => "PS0209",
+			ErrorKind::UnknownLibrary(_) => "PS0210",
+			ErrorKind::MissingExport(_) => "PS0211",
+			ErrorKind::StackOverflow(_) => "PS0212",
+			ErrorKind::ExpectFailed(_) => "PS0213",
+			ErrorKind::ReturnOutsideFunction => "PS0214",
+			ErrorKind::Syntax(errors) => errors.first().and_then(|error| error.code).unwrap_or("PS0200"),
+			ErrorKind::Propagated(error) => error.kind.code(),
+		}
+	}
+}
+
+/// An error as a value a script can inspect: `is_error(result)`,
+/// `result.message` and `result.code`. A native function registered from
+/// Rust returns one with `Err(ErrorValue::new(...))`, and the script gets
+/// the value instead of stopping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorValue {
+	pub message: String,
+	/// A short name scripts can check, such as `"NotFound"`.
+	pub code: Option<String>,
+}
+
+impl ErrorValue {
+	pub fn new(message: impl Into<String>) -> Self {
+		Self {
+			message: message.into(),
+			code: None,
+		}
+	}
+
+	pub fn with_code(mut self, code: impl Into<String>) -> Self {
+		self.code = Some(code.into());
+		self
+	}
+
+	pub fn get(&self, name: &str) -> Option<StractValue> {
+		match name
+		{
+			"message" => Some(StractValue::StringValue(self.message.clone())),
+			"code" => Some(self.code.clone().map_or(StractValue::Nil, StractValue::StringValue)),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for ErrorValue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+/// A call an error escaped, and the line the error left it from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+	pub function: String,
+	pub line: usize,
+	/// The file of that line, numbered by [`crate::sources`].
+	pub file: usize,
+	/// For the top-level code of a module, the `@reference` that ran it.
+	pub reference: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+	pub kind: ErrorKind,
+	/// Where the error was raised.
+	pub span: Span,
+	/// The calls the error escaped, innermost first.
+	pub trace: Vec<TraceFrame>,
+	/// The line the error has reached in the function it is leaving, and
+	/// its file.
+	line: usize,
+	file: usize,
+}
+
+impl RuntimeError {
+	pub fn new(kind: ErrorKind) -> Self {
+		Self {
+			kind,
+			span: Span::default(),
+			trace: vec![],
+			line: 0,
+			file: 0,
+		}
+	}
+
+	pub fn type_mismatch(message: impl Into<String>) -> Self {
+		Self::new(ErrorKind::TypeMismatch(message.into()))
+	}
+
+	/// The scan or parse errors of a module, raised where the first is.
+	pub fn syntax(errors: Vec<Diagnostic>) -> Self {
+		let span = errors.first().map(Diagnostic::span).unwrap_or_default();
+		let mut error = Self::new(ErrorKind::Syntax(errors));
+		error.span = span;
+		error
+	}
+
+	pub fn arity(function: &str, expected: usize, found: usize) -> Self {
+		Self::new(ErrorKind::Arity {
+			function: function.to_string(),
+			expected,
+			found,
+		})
+	}
+
+	/// Records that the error passed through the code at `span`, unless it
+	/// already passed through code of the current function.
+	pub fn at(mut self, span: Span) -> Self {
+		if self.line == 0
+		{
+			self.line = span.line;
+			self.file = span.file;
+			if !self.span.is_known()
+			{
+				self.span = span;
+			}
+		}
+		self
+	}
+
+	/// Records that the error escaped a call to `function`.
+	pub fn traced(mut self, function: &str) -> Self {
+		self.trace.push(TraceFrame {
+			function: function.to_string(),
+			line: self.line,
+			file: self.file,
+			reference: None,
+		});
+		self.line = 0;
+		self
+	}
+
+	/// Records that the error left the module run by the `@reference` at
+	/// `span`, from its top-level code or before that code ran.
+	pub fn referenced_at(mut self, span: Span) -> Self {
+		self = self.traced("script");
+		if let Some(frame) = self.trace.last_mut()
+		{
+			frame.reference = Some(span);
+		}
+		self.at(span)
+	}
+
+	/// Takes back the last frame `traced` added, as if the error had not
+	/// left that call yet.
+	pub fn untraced(mut self) -> Self {
+		if let Some(frame) = self.trace.pop()
+		{
+			self.line = frame.line;
+			self.file = frame.file;
+		}
+		self
+	}
+
+	/// Records the script's top-level code as the outermost frame, when the
+	/// error came out of a function; otherwise the span says it all.
+	pub fn in_script(self) -> Self {
+		match self.trace.is_empty()
+		{
+			true => self,
+			false => self.traced("script"),
+		}
+	}
+
+	/// Whether a `catch (e: name)` clause handles the error: thrown values
+	/// match their type, such as `String` or a class name, and other errors
+	/// match their kind's name or `Error`.
+	pub fn is_a(&self, name: &str) -> bool {
+		match &self.kind
+		{
+			ErrorKind::Thrown(value) => value.to_type() == name,
+			ErrorKind::Propagated(_) => false,
+			kind => name == kind.name() || name == "Error",
+		}
+	}
+
+	/// The value a `catch` binds, and `?` returns: what was thrown, or an
+	/// error value with the message of any other error and its kind's name
+	/// as the code.
+	pub fn value(&self) -> StractValue {
+		let error = match &self.kind
+		{
+			ErrorKind::Thrown(value) => return (**value).clone(),
+			ErrorKind::Propagated(error) => return error.value(),
+			ErrorKind::Other(message) => ErrorValue::new(message.clone()),
+			kind => ErrorValue::new(self.message()).with_code(kind.name()),
+		};
+		StractValue::Error(Rc::new(error))
+	}
+
+	/// Wraps the error for `?` to carry to the enclosing call.
+	pub fn propagated(self) -> Self {
+		match self.kind
+		{
+			ErrorKind::Propagated(_) => self,
+			_ => Self::new(ErrorKind::Propagated(Box::new(self))),
+		}
+	}
+
+	/// The error a `?` in top-level code was carrying, which has no caller
+	/// to be returned to.
+	pub fn unpropagated(self) -> Self {
+		match self.kind
+		{
+			ErrorKind::Propagated(error) => *error,
+			_ => self,
+		}
+	}
+
+	/// What went wrong, without where.
+	pub fn message(&self) -> String {
+		match &self.kind
+		{
+			ErrorKind::TypeMismatch(message) | ErrorKind::Other(message) | ErrorKind::ModuleNotFound(message)
+				| ErrorKind::CircularReference(message) | ErrorKind::MissingExport(message) | ErrorKind::ExpectFailed(message) => message.clone(),
+			ErrorKind::UndefinedVariable(name) => format!("Undefined<j variable '{}'", name),
+			ErrorKind::Arity { function, expected, found } => format!("{}() expects {} arguments but got {}", function, expected, found),
+			ErrorKind::IndexOutOfRange { index, length } => format!("Index {} out of range for length {}", index, length),
+			ErrorKind::MissingKey(key) => format!("Key \"{}\" not found", key),
+			ErrorKind::DivisionByZero => "Division by zero".to_string(),
+			ErrorKind::UnknownLibrary(library) => format!("Unknown<j library {}", library),
+			ErrorKind::StackOverflow(function) => format!("Stack overflow in {}()", function),
+			ErrorKind::ReturnOutsideFunction => "Cannot return from top-level code".to_string(),
+			ErrorKind::Thrown(value) => match &**value
+			{
+				StractValue::Error(error) => error.message.clone(),
+				value => format!("Uncaught {}", value),
+			},
+			ErrorKind::Syntax(errors) => errors.first().map_or_else(String::new, |error| error.message.clone()),
+			ErrorKind::Propagated(error) => error.message(),
+		}
+	}
+
+	/// The calls the error escaped, one line each, such as `in get() at
+	/// line 2`.
+	pub fn trace_lines(&self) -> Vec<String> {
+		let mut lines = vec![];
+		// Deep recursion repeats one frame thousands of times.
+		let mut frames = self.trace.iter().peekable();
+		while let Some(frame) = frames.next()
+		{
+			// A module whose text could not be read has no line to show.
+			if frame.function == "script" && frame.line == 0
+			{
+				continue;
+			}
+			let mut repeats = 0;
+			while frames.peek() == Some(&frame)
+			{
+				frames.next();
+				repeats += 1;
+			}
+			// Lines in another file than the error's say which file.
+			let at = match sources::get(frame.file)
+			{
+				Some(file) if frame.file != self.span.file => format!("{}:{}", file.name(), frame.line),
+				_ => format!("line {}", frame.line),
+			};
+			lines.push(match (frame.function.as_str(), frame.line)
+			{
+				("script", _) => format!("in script at {}", at),
+				(function, 0) => format!("in {}()", function),
+				(function, _) => format!("in {}() at {}", function, at),
+			});
+			if repeats > 0
+			{
+				lines.push(format!("... {} more times", repeats));
+			}
+		}
+		lines
+	}
+
+	/// The error as a diagnostic pointing at where it was raised, and at
+	/// the references of the modules it came out of, with the trace as
+	/// notes.
+	pub fn diagnostic(&self) -> Diagnostic {
+		let mut diagnostic = Diagnostic::error(self.message()).with_code(self.kind.code());
+		if self.span.is_known()
+		{
+			diagnostic = diagnostic.with_label(self.span, "");
+		}
+		self.locate(diagnostic)
+	}
+
+	/// The error as diagnostics: one for each problem in a module's text,
+	/// or the single one `diagnostic` gives.
+	pub fn diagnostics(&self) -> Vec<Diagnostic> {
+		match &self.kind
+		{
+			ErrorKind::Syntax(errors) => errors.iter().map(|error| self.locate(error.clone())).collect(),
+			ErrorKind::Propagated(error) => error.diagnostics(),
+			_ => vec![self.diagnostic()],
+		}
+	}
+
+	/// Adds the references and the trace to `diagnostic`.
+	fn locate(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+		for span in self.trace.iter().filter_map(|frame| frame.reference)
+		{
+			diagnostic = diagnostic.with_secondary(span, "in the module referenced here");
+		}
+		for line in self.trace_lines()
+		{
+			diagnostic = diagnostic.with_note(line);
+		}
+		diagnostic
+	}
+}
+
+impl From<String> for RuntimeError {
+	fn from(message: String) -> Self {
+		Self::new(ErrorKind::Other(message))
+	}
+}
+
+impl From<&str> for RuntimeError {
+	fn from(message: &str) -> Self {
+		Self::new(ErrorKind::Other(message.to_string()))
+	}
+}
+
+impl fmt::Display for RuntimeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message())?;
+		if self.span.is_known()
+		{
+			write!(f, " at {}", self.span)?;
+		}
+		for line in self.trace_lines()
+		{
+			write!(f, "\n  {}", line)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::engine::{Engine, EngineError};
+
+	fn runtime_error(source: &str) -> RuntimeError
+	{
+		match Engine::new().eval(source)
+		{
+			Err(EngineError::Runtime(error)) => error,
+			other => panic!("expected a runtime error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn kinds_and_spans()
+	{
+		let cases = [
+			("1 + nil", ErrorKind::TypeMismatch("Plus isn't implemented for operands Number(1.0) and Nil".to_string()), 1),
+			("\nmissing", ErrorKind::UndefinedVariable("missing".to_string()), 2),
+			("func f(a) { }\n\n\nf()", ErrorKind::Arity { function: "f".to_string(), expected: 1, found: 0 }, 4),
+			("[1, 2][2]", ErrorKind::IndexOutOfRange { index: 2, length: 2 }, 1),
+			("\n\n1 / 0", ErrorKind::DivisionByZero, 3),
+		];
+		for (source, kind, line) in cases
+		{
+			let error = runtime_error(source);
+			assert_eq!((error.kind, error.span.line), (kind, line), "{}", source);
+		}
+	}
+
+	#[test]
+	fn traces_name_each_function_escaped()
+	{
+		let error = runtime_error("func inner(list) {\n\treturn list[3]!\n}\nfunc outer() {\n\tvar list::[1];\n\treturn inner(list)!\n}\nprint outer()!");
+		assert_eq!(error.span.line, 2);
+		let trace: Vec<(&str, usize)> = error.trace.iter().map(|frame| (frame.function.as_str(), frame.line)).collect();
+		assert_eq!(trace, vec![("inner", 2), ("outer", 6), ("script", 8)]);
+		assert_eq!(error.to_string(), "Index 3 out of range for length 1 at line 2, column 9\n  in inner() at line 2\n  in outer() at line 6\n  in script at line 8");
+	}
+
+	#[test]
+	fn runaway_recursion_is_a_stack_overflow()
+	{
+		let error = runtime_error("func f(n) { return f(n + 1)! }\nf(0)");
+		assert_eq!(error.message(), "Stack overflow in f()");
+		assert_eq!((error.span.line, error.span.column), (1, 20));
+		assert_eq!(error.trace.first().map(|frame| frame.function.as_str()), Some("f"));
+		assert_eq!(error.trace.last().map(|frame| (frame.function.as_str(), frame.line)), Some(("script", 2)));
+		let nested = runtime_error("func g(n) {\n\twhile (true) { if (n > -1) { { var k::[1, 2 * (g(n + 1) + 1)]; return k[1]! } } }\n}\ng(0)");
+		assert_eq!(nested.message(), "Stack overflow in g()");
+		let mut engine = Engine::new();
+		assert!(engine.eval("func f(n) { return f(n + 1)! }\nf(0)").is_err());
+		assert_eq!(engine.eval("func down(n) { if (n : 0) return 0! return down(n - 1)! }\ndown(20)"), Ok(StractValue::Number(0.0)));
+	}
+
+	#[test]
+	fn recursion_is_folded()
+	{
+		let error = runtime_error("func down(n) {\n\tif (n : 0) return nope!\n\treturn down(n - 1)!\n}\ndown(3)");
+		assert_eq!(error.to_string(), "Undefined<j variable 'nope' at line 2, column 20\n  in down() at line 2\n  in down() at line 3\n  ... 2 more times\n  in script at line 5");
+	}
+
+	#[test]
+	fn errors_from_the_host_keep_their_message()
+	{
+		let mut engine = Engine::new();
+		engine.register_fn("fail", || -> Result<f64, String> { Err("no fuel".to_string()) });
+		match engine.eval("func burn() { return fail()! }\n\nburn()")
+		{
+			Err(EngineError::Runtime(error)) => {
+				assert_eq!(error.kind, ErrorKind::Other("no fuel".to_string()));
+				assert_eq!(error.to_string(), "no fuel at line 1, column 22\n  in burn() at line 1\n  in script at line 3");
+			}
+			other => panic!("expected a runtime error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn thrown_values_are_caught()
+	{
+		let mut engine = Engine::new();
+		assert_eq!(engine.eval("var got::nil;\ntry { throw 42! } catch (e) { got :: e! }\ngot").unwrap(), StractValue::Number(42.0));
+		let error = runtime_error("func f() {\n\tthrow \"boom\"!\n}\nf()");
+		assert_eq!(error.kind, ErrorKind::Thrown(Box::new(StractValue::StringValue("boom".to_string()))));
+		assert_eq!(error.to_string(), "Uncaught boom at line 2, column 2\n  in f() at line 2\n  in script at line 4");
+	}
+
+	#[test]
+	fn handlers_match_types_and_kinds()
+	{
+		let mut engine = Engine::new();
+		engine.eval("class Oops { var why::nil; }\nfunc kind(f) {\n\ttry { f()! } catch (e: String) { return \"string\"! } catch (e: Oops) { return \"oops\"! } catch (e: DivisionByZero) { return \"{e.code}: {e.message}\"! } catch (e) { return \"other\"! }\n}").unwrap();
+		let cases = [
+			("throw \"x\"!", "string"),
+			("throw Oops()!", "oops"),
+			("return 1 / 0!", "DivisionByZero: Division by zero"),
+			("throw 3!", "other"),
+			("return nope!", "other"),
+		];
+		for (body, expected) in cases
+		{
+			let source = format!("func g() {{ {} }}\nkind(g)", body);
+			assert_eq!(engine.eval(&source).unwrap(), StractValue::StringValue(expected.to_string()), "{}", body);
+		}
+		let error = runtime_error("try { [1][2]! } catch (e: String) { }");
+		assert_eq!(error.kind, ErrorKind::IndexOutOfRange { index: 2, length: 1 });
+	}
+
+	#[test]
+	fn finally_runs_on_every_exit()
+	{
+		let mut engine = Engine::new();
+		engine.eval("var log::\"\";\nfunc note(s) { log :: \"{log}{s}\"! }").unwrap();
+		engine.eval("func ret() {\n\ttry { return 1! } finally { note(\"r\")! }\n}\nret()!").unwrap();
+		engine.eval("var i::0;\nwhile (true) {\n\ttry { break! } finally { note(\"b\")! }\n}").unwrap();
+		engine.eval("while (true) {\n\ttry { break? } finally { note(\"b\")! }\n}").unwrap();
+		engine.eval("try { note(\"n\")! } finally { note(\"f\")! }").unwrap();
+		engine.eval("try { throw 1! } catch (e) { note(\"c\")! } finally { note(\"f\")! }").unwrap();
+		assert!(engine.eval("try { throw 1! } finally { note(\"u\")! }").is_err());
+		assert_eq!(engine.eval("log").unwrap(), StractValue::StringValue("rbbnfcfu".to_string()));
+		assert_eq!(engine.eval("func over() {\n\ttry { throw 1! } finally { return 2! }\n}\nover()").unwrap(), StractValue::Number(2.0));
+	}
+
+	#[test]
+	fn question_mark_returns_the_error_to_the_caller()
+	{
+		let mut engine = Engine::new();
+		engine.eval("var after::false;\nfunc risky() { throw \"bad\"! }\nfunc outer() {\n\tvar x::risky()?;\n\tafter :: true!\n\treturn x!\n}").unwrap();
+		assert_eq!(engine.eval("outer()").unwrap(), StractValue::StringValue("bad".to_string()));
+		assert_eq!(engine.eval("after").unwrap(), StractValue::False);
+		assert_eq!(engine.eval("func fine() { return 1! }\nfunc g() { return fine()? + 1! }\ng()").unwrap(), StractValue::Number(2.0));
+		// No catch stops it, and at the top level it is the error it carries.
+		assert_eq!(engine.eval("func h() {\n\ttry { risky()?! } catch (e) { return \"caught\"! }\n\treturn \"after\"!\n}\nh()").unwrap(), StractValue::StringValue("bad".to_string()));
+		let error = runtime_error("func risky() {\n\tthrow \"bad\"!\n}\nrisky()?");
+		assert_eq!(error.to_string(), "Uncaught bad at line 2, column 2\n  in risky() at line 2\n  in script at line 4");
+	}
+}
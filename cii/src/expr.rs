@@ -1,10 +1,55 @@
 use crate::scanner::{Token, TokenType};
+use crate::span::Span;
 use crate::scanner;
+use crate::environment::Environment;
+use crate::vector;
+use crate::host::HostHandle;
+use crate::class::{ClassImpl, InstanceImpl, MethodImpl};
+use crate::signal::{self, SignalImpl};
+use crate::interpreter::{Interpreter, Flow};
+use crate::vm::Closure;
+use crate::statement::Statement;
+use crate::error::{ErrorKind, ErrorValue, RuntimeError};
+use crate::gc::{self, Collectable};
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+/// Calls nested deeper than this fail as a stack overflow, as in the VM.
+const MAX_DEPTH: usize = 10_000;
+
+/// How much of the thread's stack nested calls may use before they fail as
+/// a stack overflow. Threads get 2 MiB by default, and one call can take
+/// tens of kilobytes of it in a debug build, so depth alone is no guard.
+const STACK_BUDGET: usize = 1 << 20;
+
+thread_local! {
+	/// How deep the calls running on this thread are, and where the stack
+	/// was when the outermost one started.
+	static CALLS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum StractValue {
-	Number(f32),
+	Number(f64),
 	StringValue(String),
+	Char(char),
+	List(Rc<RefCell<Vec<StractValue>>>),
+	Map(Rc<RefCell<HashMap<String, StractValue>>>),
+	Callable(CallableImpl),
+	Module(Rc<ModuleImpl>),
+	Object(HostHandle),
+	Class(Rc<ClassImpl>),
+	Instance(Rc<InstanceImpl>),
+	Signal(Rc<SignalImpl>),
+	/// A failure handed to the script as a value, for it to inspect. It is
+	/// falsy, like `nil`.
+	Error(Rc<ErrorValue>),
+	Vector2(f64, f64),
+	Vector3(f64, f64, f64),
+	Color(f64, f64, f64, f64),
+	/// A 3D basis: its x, y and z axes, which rotate and scale a Vector3.
+	Basis(Rc<[[f64; 3]; 3]>),
 	True,
 	False,
 	Nil,
@@ -12,13 +57,211 @@ pub enum StractValue {
 }
 use StractValue::*;
 
-fn unwrap_as_f32(stract: Option<scanner::StractValue>)->f32
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallableImpl {
+	NativeFunction(NativeFunctionImpl),
+	StractFunction(StractFunctionImpl),
+	Method(MethodImpl),
+	/// A function compiled to bytecode.
+	Compiled(Rc<Closure>),
+}
+
+pub type NativeFn = Rc<dyn Fn(&[StractValue]) -> Result<StractValue, RuntimeError>>;
+
+/// A function implemented in Rust and exposed to scripts under `name`.
+/// An `arity` of `None` accepts any number of arguments.
+#[derive(Clone)]
+pub struct NativeFunctionImpl {
+	pub name: String,
+	pub arity: Option<usize>,
+	pub fun: NativeFn,
+}
+
+impl NativeFunctionImpl {
+	pub fn new(name: &str, arity: usize, fun: NativeFn) -> Self {
+		Self {
+			name: name.to_string(),
+			arity: Some(arity),
+			fun,
+		}
+	}
+
+	pub fn variadic(name: &str, fun: NativeFn) -> Self {
+		Self {
+			name: name.to_string(),
+			arity: None,
+			fun,
+		}
+	}
+}
+
+impl std::fmt::Debug for NativeFunctionImpl {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "<native func {}>", self.name)
+	}
+}
+
+impl PartialEq for NativeFunctionImpl {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.fun, &other.fun)
+	}
+}
+
+/// A namespace of values reached with `name.member`, such as a group of
+/// native functions registered by the host.
+pub struct ModuleImpl {
+	pub name: String,
+	pub members: RefCell<HashMap<String, StractValue>>,
+}
+
+impl std::fmt::Debug for ModuleImpl {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "<module {}>", self.name)
+	}
+}
+
+impl PartialEq for ModuleImpl {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+impl Collectable for ModuleImpl {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		self.members.trace(visit)
+	}
+
+	fn clear(&self) {
+		self.members.clear();
+	}
+}
+
+/// A function declared in a script with `func`, closing over the scope it
+/// was declared in.
+#[derive(Clone)]
+pub struct StractFunctionImpl {
+	pub name: String,
+	pub params: Vec<Token>,
+	pub body: Rc<Vec<Statement>>,
+	pub closure: Rc<RefCell<Environment>>,
+}
+
+impl std::fmt::Debug for StractFunctionImpl {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "<func {}>", self.name)
+	}
+}
+
+impl PartialEq for StractFunctionImpl {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.body, &other.body) && Rc::ptr_eq(&self.closure, &other.closure)
+	}
+}
+
+impl StractFunctionImpl {
+	/// Runs the body with `arguments`, and with `self` bound to `receiver`
+	/// when called as a method.
+	pub fn call_as(&self, arguments: &[StractValue], receiver: Option<&Rc<InstanceImpl>>) -> Result<StractValue, RuntimeError> {
+		let mut environment = Environment::new_enclosed(self.closure.clone());
+		let here = &environment as *const Environment as usize;
+		let (depth, base) = match CALLS.get()
+		{
+			(0, _) => (0, here),
+			calls => calls,
+		};
+		if depth == MAX_DEPTH || base.abs_diff(here) > STACK_BUDGET
+		{
+			return Err(RuntimeError::new(ErrorKind::StackOverflow(self.name.clone())).traced(&self.name));
+		}
+		if let Some(receiver) = receiver
+		{
+			environment.define("self".to_string(), Instance(receiver.clone()));
+		}
+		for (param, argument) in self.params.iter().zip(arguments)
+		{
+			environment.define(param.panoll.clone(), argument.clone());
+		}
+		let mut interpreter = Interpreter::for_closure(gc::alloc(RefCell::new(environment)));
+		CALLS.set((depth + 1, base));
+		let result = interpreter.execute_statements(&self.body);
+		CALLS.set((depth, base));
+		match result
+		{
+			Ok(Flow::Return(value)) => Ok(value),
+			Ok(Flow::Normal | Flow::Break) => Ok(Nil),
+			Err(RuntimeError { kind: ErrorKind::Propagated(error), .. }) => Ok(error.value()),
+			Err(error) => Err(error.traced(&self.name)),
+		}
+	}
+}
+
+impl CallableImpl {
+	pub fn name(&self) -> &str {
+		match self {
+			CallableImpl::NativeFunction(native) => &native.name,
+			CallableImpl::StractFunction(function) => &function.name,
+			CallableImpl::Method(method) => &method.function.name,
+			CallableImpl::Compiled(closure) => &closure.function.name,
+		}
+	}
+
+	pub fn arity(&self) -> Option<usize> {
+		match self {
+			CallableImpl::NativeFunction(native) => native.arity,
+			CallableImpl::StractFunction(function) => Some(function.params.len()),
+			CallableImpl::Method(method) => Some(method.function.params.len()),
+			CallableImpl::Compiled(closure) => Some(closure.function.arity),
+		}
+	}
+
+	pub fn call(&self, arguments: &[StractValue]) -> Result<StractValue, RuntimeError> {
+		if let Some(arity) = self.arity()
+		{
+			if arguments.len() != arity
+			{
+				return Err(RuntimeError::arity(self.name(), arity, arguments.len()));
+			}
+		}
+		match self {
+			CallableImpl::NativeFunction(native) => (native.fun)(arguments),
+			CallableImpl::StractFunction(function) => function.call_as(arguments, None),
+			CallableImpl::Method(method) => method.function.call_as(arguments, Some(&method.receiver)),
+			CallableImpl::Compiled(closure) => closure.call(arguments),
+		}
+	}
+}
+
+fn unwrap_as_f64(stract: Option<scanner::StractValue>)->f64
+{
+	match stract
+	{
+		Some(scanner::StractValue::IntValue(x))=>x as f64,
+		Some(scanner::StractValue::FloatValue(x))=>x,
+		_=>panic!("Could not unwrap<j Float [64bit]")
+	}
+}
+
+fn unwrap_as_char(stract: Option<scanner::StractValue>)->char
 {
 	match stract
 	{
-		Some(scanner::StractValue::IntValue(x))=>x as f32,
-		Some(scanner::StractValue::FloatValue(x))=>x as f32,
-		_=>panic!("Could not unwrap<j Float [32bit]")
+		Some(scanner::StractValue::CharValue(c))=>c,
+		_=>panic!("Could not unwrap<j Char")
+	}
+}
+
+/// Shifts a character by a number of code points, as in `'a' + 1`.
+fn shift_char(c: char, offset: f64)->Result<StractValue, RuntimeError>
+{
+	if offset.fract() != 0.0
+	{
+		return Err(RuntimeError::type_mismatch(format!("Cannot shift Char by non-integral amount {}", offset)));
+	}
+	let code = c as i64 + offset as i64;
+	match u32::try_from(code).ok().and_then(char::from_u32)
+	{
+		Some(shifted) => Ok(Char(shifted)),
+		None => Err(format!("Code point {} is not a valid Char", code).into()),
 	}
 }
 
@@ -32,22 +275,65 @@ fn unwrap_as_string(stract: Option<scanner::StractValue>)->String
 	}
 }
 
-impl StractValue {
-	pub fn to_string(&self) -> String {
-		match self {
+impl std::fmt::Display for StractValue {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let text = match self {
 			StractValue::Number(x) => x.to_string(),
 			StractValue::StringValue(x) => x.clone(),
+			StractValue::Char(c) => c.to_string(),
+			StractValue::List(items) => {
+				let items: Vec<String> = items.borrow().iter().map(|item| item.to_string()).collect();
+				format!("[{}]", items.join(", "))
+			}
+			StractValue::Map(entries) => {
+				let entries = entries.borrow();
+				let mut keys: Vec<&String> = entries.keys().collect();
+				keys.sort();
+				let entries: Vec<String> = keys.iter().map(|key| format!("{}: {}", key, entries[*key])).collect();
+				format!("{{{}}}", entries.join(", "))
+			}
+			StractValue::Callable(callable) => format!("<func {}>", callable.name()),
+			StractValue::Module(module) => format!("<module {}>", module.name),
+			StractValue::Object(object) => format!("<{}>", object.type_name()),
+			StractValue::Class(class) => format!("<class {}>", class.name),
+			StractValue::Instance(instance) => format!("<{} instance>", instance.class.name),
+			StractValue::Signal(signal) => format!("<signal {}>", signal.name),
+			StractValue::Error(error) => format!("<error: {}>", error.message),
+			StractValue::Vector2(x, y) => format!("({}, {})", x, y),
+			StractValue::Vector3(x, y, z) => format!("({}, {}, {})", x, y, z),
+			StractValue::Color(r, g, b, a) => format!("({}, {}, {}, {})", r, g, b, a),
+			StractValue::Basis(axes) => {
+				let [x, y, z] = axes.map(|[a, b, c]| format!("({}, {}, {})", a, b, c));
+				format!("[X: {}, Y: {}, Z: {}]", x, y, z)
+			}
 			StractValue::True => "true".to_string(),
 			StractValue::False => "false".to_string(),
 			StractValue::Nil => "nil".to_string(),
 			StractValue::Null => "null".to_string(),
-		}
+		};
+		write!(f, "{}", text)
 	}
+}
 
+impl StractValue {
 	pub fn to_type(&self) -> &str {
 		match self {
 			StractValue::Number(_) => "Number",
 			StractValue::StringValue(_) => "String",
+			StractValue::Char(_) => "Char",
+			StractValue::List(_) => "List",
+			StractValue::Map(_) => "Map",
+			StractValue::Callable(_) => "Callable",
+			StractValue::Module(_) => "Module",
+			StractValue::Object(object) => object.type_name(),
+			StractValue::Class(_) => "Class",
+			StractValue::Instance(instance) => &instance.class.name,
+			StractValue::Signal(_) => "Signal",
+			StractValue::Error(_) => "Error",
+			StractValue::Vector2(..) => "Vector2",
+			StractValue::Vector3(..) => "Vector3",
+			StractValue::Color(..) => "Color",
+			StractValue::Basis(_) => "Basis",
 			StractValue::True => "Boolean",
 			StractValue::False => "Boolean",
 			StractValue::Nil => "nil",
@@ -59,8 +345,9 @@ impl StractValue {
 	{
 		match token.token_type
 		{
-			TokenType::Number => Self::Number(unwrap_as_f32(token.stract)),
+			TokenType::Number => Self::Number(unwrap_as_f64(token.stract)),
 			TokenType::StringLat => Self::StringValue(unwrap_as_string(token.stract)),
+			TokenType::CharLat => Self::Char(unwrap_as_char(token.stract)),
 			TokenType::False => Self::False,
 			TokenType::True => Self::True,
 			TokenType::Nil => Self::Nil,
@@ -81,8 +368,17 @@ impl StractValue {
 
 	pub fn is_falsy(&self) -> StractValue {
 		match self {
-			Number(x) => {if *x == 0.0 as f32 {True} else {False}},
-			StringValue(s) => {if s.len() == 0 {True} else {False}},
+			Number(x) => {if *x == 0.0 {True} else {False}},
+			StringValue(s) => {if s.is_empty() {True} else {False}},
+			Char(c) => {if *c == '\0' {True} else {False}},
+			List(items) => {if items.borrow().is_empty() {True} else {False}},
+			Map(entries) => {if entries.borrow().is_empty() {True} else {False}},
+			Callable(_) => False,
+			Module(_) => False,
+			Object(_) => False,
+			Class(_) | Instance(_) | Signal(_) => False,
+			Error(_) => True,
+			Vector2(..) | Vector3(..) | Color(..) | Basis(_) => False,
 			True => False,
 			False => True,
 			Nil => True,
@@ -91,88 +387,362 @@ impl StractValue {
 	}
 }
 
+#[derive(Clone)]
 pub enum Expr {
+	Assign { name: Token, value: Box<Expr> },
 	Binary { left: Box<Expr>, operator: Token, right:Box<Expr>},
-	Grouping { expression: Box<Expr> },
-	Lateral { value: StractValue },
-	Unary { operator: Token, right: Box<Expr> }
+	Call { callee: Box<Expr>, paren: Token, arguments: Vec<Expr> },
+	Get { object: Box<Expr>, name: Token },
+	Grouping { expression: Box<Expr>, span: Span },
+	Index { object: Box<Expr>, bracket: Token, index: Box<Expr> },
+	Interpolation { parts: Vec<Expr>, span: Span },
+	Lateral { value: StractValue, span: Span },
+	List { items: Vec<Expr>, span: Span },
+	Logical { left: Box<Expr>, operator: Token, right: Box<Expr> },
+	/// `expression?`: an error from `expression` is returned to the
+	/// caller of the enclosing function as a value.
+	Propagate { expression: Box<Expr>, question: Token },
+	Set { object: Box<Expr>, name: Token, value: Box<Expr> },
+	Unary { operator: Token, right: Box<Expr> },
 	Variable { name: Token, },
 }
 
-impl Expr {
-	pub fn to_string(&self) -> String {
-		match self {
-			Expr::Binary { left, operator, right, } => format!("({} {} {})", operator.panoll, left.to_string(), right.to_string()),
-			Expr::Grouping { expression } => format!("(group: {})", (*expression).to_string()),
-			Expr::Lateral { value } => format!("{}", value.to_string()),
+impl std::fmt::Display for Expr {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let text = match self {
+			Expr::Assign { name, value } => format!("(= {} {})", name.panoll, value),
+			Expr::Binary { left, operator, right, } => format!("({} {} {})", operator.panoll, left, right),
+			Expr::Call { callee, paren: _, arguments } => {
+				let arguments: Vec<String> = arguments.iter().map(|argument| argument.to_string()).collect();
+				format!("(call {} {})", callee, arguments.join(" "))
+			}
+			Expr::Get { object, name } => format!("(get {} {})", object, name.panoll),
+			Expr::Grouping { expression, .. } => format!("(group: {})", (*expression)),
+			Expr::Index { object, bracket: _, index } => format!("(index {} {})", object, index),
+			Expr::List { items, .. } => {
+				let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+				format!("[{}]", items.join(" "))
+			}
+			Expr::Interpolation { parts, .. } => {
+				let parts: Vec<String> = parts.iter().map(|part| part.to_string()).collect();
+				format!("(interpolate {})", parts.join(" "))
+			}
+			Expr::Lateral { value, .. } => value.to_string(),
+			Expr::Logical { left, operator, right } => format!("({} {} {})", operator.panoll, left, right),
+			Expr::Propagate { expression, question: _ } => format!("(? {})", expression),
+			Expr::Set { object, name, value } => format!("(set {} {} {})", object, name.panoll, value),
 			Expr::Unary { operator, right } => {
 				let operator_str = operator.panoll.clone();
 				let right_str = (*right).to_string();
 				format!("({} {})", operator_str, right_str)
 			}
 			Expr::Variable { name } => format!("(var {})", name.panoll),
+		};
+		write!(f, "{}", text)
+	}
+}
+
+impl Expr {
+	/// The source the expression was parsed from. Only the expressions
+	/// whose first and last tokens are not kept store it; the rest join the
+	/// spans of their parts.
+	pub fn span(&self) -> Span
+	{
+		match self
+		{
+			Expr::Variable {name} => name.span,
+			Expr::Assign {name, value} => name.span.to(value.span()),
+			Expr::Set {object, value, ..} => object.span().to(value.span()),
+			Expr::Get {object, name} => object.span().to(name.span),
+			Expr::Call {callee, paren, ..} => callee.span().to(paren.span),
+			Expr::Index {object, bracket, ..} => object.span().to(bracket.span),
+			Expr::Propagate {expression, question} => expression.span().to(question.span),
+			Expr::Logical {left, right, ..} | Expr::Binary {left, right, ..} => left.span().to(right.span()),
+			Expr::Unary {operator, right} => operator.span.to(right.span()),
+			Expr::Lateral {span, ..} | Expr::Grouping {span, ..} | Expr::List {span, ..} | Expr::Interpolation {span, ..} => *span,
 		}
 	}
 
-	pub fn evaluate(&self) -> Result<StractValue, String>
+	pub fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Result<StractValue, RuntimeError>
+	{
+		self.evaluate_here(environment).map_err(|e| e.at(self.span()))
+	}
+
+	fn evaluate_here(&self, environment: Rc<RefCell<Environment>>) -> Result<StractValue, RuntimeError>
 	{
 		match self
 		{
-			Expr::Variable {name} => todo!(),
-			Expr::Lateral {value} => Ok((*value).clone()),
-			Expr::Grouping {expression} => expression.evaluate(),
-			Expr::Unary {operator, right} =>
+			Expr::Variable {name} => match environment.borrow().get(&name.panoll)
+			{
+				Some(value) => Ok(value),
+				None => Err(RuntimeError::new(ErrorKind::UndefinedVariable(name.panoll.clone()))),
+			},
+			Expr::Assign {name, value} =>
 			{
-				let right = right.evaluate()?;
-				match (&right, operator.token_type)
+				let value = value.evaluate(environment.clone())?;
+				if environment.borrow_mut().assign(&name.panoll, value.clone())
 				{
-					(Number(x), TokenType::Minus) => Ok(Number(-x)),
-					(_, TokenType::Minus) => { return Err(format!("Minus not implemented in {}", right.to_type())) },
-					(any, TokenType::Bang) => Ok(any.is_falsy()),
-					(_, ttype) => Err(format!("{} is not a valid operator [TYPE UNARY]", ttype)),
+					Ok(value)
+				}
+				else
+				{
+					Err(RuntimeError::new(ErrorKind::UndefinedVariable(name.panoll.clone())))
 				}
 			}
-			Expr::Binary{ left, operator, right, } => {
-				let left = left.evaluate()?;
-				let right = right.evaluate()?;
-
-				match (&left, operator.token_type, &right)
+			Expr::Lateral { value, .. } => Ok((*value).clone()),
+			Expr::Get {object, name} =>
+			{
+				let object = object.evaluate(environment)?;
+				get_property(&object, &name.panoll)
+			}
+			Expr::Set {object, name, value} =>
+			{
+				let target = object.evaluate(environment.clone())?;
+				let value = value.evaluate(environment.clone())?;
+				match vector::with_component(&target, &name.panoll, &value)
+				{
+					Some(rebuilt) => store(object, rebuilt.map_err(RuntimeError::type_mismatch)?, environment)?,
+					None => set_property(&target, &name.panoll, value.clone())?,
+				}
+				Ok(value)
+			}
+			Expr::Grouping { expression, .. } => expression.evaluate(environment),
+			Expr::Propagate {expression, ..} => match expression.evaluate(environment)
+			{
+				Ok(Error(error)) => Err(RuntimeError::new(ErrorKind::Thrown(Box::new(Error(error)))).propagated()),
+				result => result.map_err(RuntimeError::propagated),
+			},
+			Expr::Call {callee, arguments, ..} =>
+			{
+				let callable = callee.evaluate(environment.clone())?;
+				let mut values = vec![];
+				for argument in arguments
+				{
+					values.push(argument.evaluate(environment.clone())?);
+				}
+				call_value(callable, &values)
+			}
+			Expr::Index {object, index, ..} =>
+			{
+				let object = object.evaluate(environment.clone())?;
+				let index = index.evaluate(environment)?;
+				index_value(&object, &index)
+			}
+			Expr::List { items, .. } =>
+			{
+				let mut values = vec![];
+				for item in items
+				{
+					values.push(item.evaluate(environment.clone())?);
+				}
+				Ok(List(gc::alloc(RefCell::new(values))))
+			}
+			Expr::Interpolation { parts, .. } =>
+			{
+				let mut text = String::new();
+				for part in parts
+				{
+					text.push_str(&part.evaluate(environment.clone())?.to_string());
+				}
+				Ok(StringValue(text))
+			}
+			Expr::Logical {left, operator, right} =>
+			{
+				let left = left.evaluate(environment.clone())?;
+				let decided = match operator.token_type
+				{
+					TokenType::Or => left.is_falsy() == False,
+					_ => left.is_falsy() == True,
+				};
+				if decided
+				{
+					Ok(left)
+				}
+				else
 				{
-					(Number(x), TokenType::Plus, Number(y)) => Ok(Number(x + y)),
-					(Number(x), TokenType::Minus, Number(y)) => Ok(Number(x - y)),
-					(Number(x), TokenType::Star, Number(y)) => Ok(Number(x * y)),
-					(Number(x), TokenType::Slash, Number(y)) => Ok(Number(x / y)),
-
-					//(Number(x), TokenType::BangEqual, Number(y)) => Ok(StractValue::from_bool(x != y)),
-					//(Number(x), TokenType::EqualEqual, Number(y)) => Ok(StractValue::from_bool(x == y)),
-
-					(Number(x), TokenType::Greater, Number(y)) => Ok(StractValue::from_bool(x > y)),
-					(Number(x), TokenType::GreaterEqual, Number(y)) => Ok(StractValue::from_bool(x >= y)),
-					(Number(x), TokenType::Less, Number(y)) => Ok(StractValue::from_bool(x < y)),
-					(Number(x), TokenType::LessEqual, Number(y)) => Ok(StractValue::from_bool(x <= y)),
-
-					(StringValue(_), op, Number(_)) => Err(format!("{} is not definied for string", op)),
-					(Number(_), op, StringValue(_)) => Err(format!("{} is not definied for number", op)),
-
-					(StringValue(s1), TokenType::Plus, StringValue(s2)) => { Ok(StringValue(format!("{}{}", s1, s2))) },
-					//(StringValue(s1), TokenType::EqualEqual, StringValue(s2)) => { Ok(StractValue::from_bool(s1 == s2)) },
-					//(StringValue(s1), TokenType::BangEqual, StringValue(s2)) => { Ok(StractValue::from_bool(s1 != s2)) },
-					(x, TokenType::BangEqual, y) => Ok(StractValue::from_bool(x != y)),
-					(x, TokenType::EqualEqual, y) => Ok(StractValue::from_bool(x == y)),
-
-					(StringValue(s1), TokenType::Greater, StringValue(s2)) => Ok(StractValue::from_bool(s1 > s2)),
-					(StringValue(s1), TokenType::GreaterEqual, StringValue(s2)) => Ok(StractValue::from_bool(s1 >= s2)),
-					(StringValue(s1), TokenType::Less, StringValue(s2)) => Ok(StractValue::from_bool(s1 < s2)),
-					(StringValue(s1), TokenType::LessEqual, StringValue(s2)) => Ok(StractValue::from_bool(s1 <= s2)),
-
-					(x, ttype, y) => Err(format!("{} isn't implemented for operands {:?} and {:?}", ttype, x, y)),
+					right.evaluate(environment)
 				}
 			}
+			Expr::Unary {operator, right} =>
+			{
+				let right = right.evaluate(environment)?;
+				unary(operator.token_type, &right)
+			}
+			Expr::Binary{ left, operator, right, } => {
+				let left = left.evaluate(environment.clone())?;
+				let right = right.evaluate(environment)?;
+				binary(&left, operator.token_type, &right)
+			}
 		}
 	}
 
 	pub fn print(&self) {
-		println!("{}", self.to_string());
+		println!("{}", self);
+	}
+}
+
+/// `operator right`, for both the tree-walker and the bytecode VM.
+pub fn unary(operator: TokenType, right: &StractValue) -> Result<StractValue, RuntimeError>
+{
+	match (right, operator)
+	{
+		(Number(x), TokenType::Minus) => Ok(Number(-x)),
+		(Vector2(..) | Vector3(..) | Color(..), TokenType::Minus) => Ok(vector::negate(right).unwrap()),
+		(_, TokenType::Minus) => { Err(RuntimeError::type_mismatch(format!("Minus not implemented in {}", right.to_type()))) },
+		(any, TokenType::Bang) => Ok(any.is_falsy()),
+		(_, ttype) => Err(format!("{} is not a valid operator [TYPE UNARY]", ttype).into()),
+	}
+}
+
+/// `left operator right`, for both the tree-walker and the bytecode VM.
+pub fn binary(left: &StractValue, operator: TokenType, right: &StractValue) -> Result<StractValue, RuntimeError>
+{
+	if let Some(value) = vector::arithmetic(left, operator, right)
+	{
+		return Ok(value);
+	}
+
+	match (left, operator, right)
+	{
+		(Number(x), TokenType::Plus, Number(y)) => Ok(Number(x + y)),
+		(Number(x), TokenType::Minus, Number(y)) => Ok(Number(x - y)),
+		(Number(x), TokenType::Star, Number(y)) => Ok(Number(x * y)),
+		(Number(x), TokenType::Slash, Number(y)) => divide(*x, *y),
+
+		//(Number(x), TokenType::BangEqual, Number(y)) => Ok(StractValue::from_bool(x != y)),
+		//(Number(x), TokenType::EqualEqual, Number(y)) => Ok(StractValue::from_bool(x == y)),
+
+		(Number(x), TokenType::Greater, Number(y)) => Ok(StractValue::from_bool(x > y)),
+		(Number(x), TokenType::GreaterEqual, Number(y)) => Ok(StractValue::from_bool(x >= y)),
+		(Number(x), TokenType::Less, Number(y)) => Ok(StractValue::from_bool(x < y)),
+		(Number(x), TokenType::LessEqual, Number(y)) => Ok(StractValue::from_bool(x <= y)),
+
+		(StringValue(_), op, Number(_)) => Err(RuntimeError::type_mismatch(format!("{} is not definied for string", op))),
+		(Number(_), op, StringValue(_)) => Err(RuntimeError::type_mismatch(format!("{} is not definied for number", op))),
+
+		(Char(c), TokenType::Plus, Number(y)) => shift_char(*c, *y),
+		(Number(x), TokenType::Plus, Char(c)) => shift_char(*c, *x),
+		(Char(c), TokenType::Minus, Number(y)) => shift_char(*c, -y),
+		(Char(c1), TokenType::Minus, Char(c2)) => Ok(Number(*c1 as u32 as f64 - *c2 as u32 as f64)),
+
+		(Char(c1), TokenType::Greater, Char(c2)) => Ok(StractValue::from_bool(c1 > c2)),
+		(Char(c1), TokenType::GreaterEqual, Char(c2)) => Ok(StractValue::from_bool(c1 >= c2)),
+		(Char(c1), TokenType::Less, Char(c2)) => Ok(StractValue::from_bool(c1 < c2)),
+		(Char(c1), TokenType::LessEqual, Char(c2)) => Ok(StractValue::from_bool(c1 <= c2)),
+
+		(StringValue(s1), TokenType::Plus, StringValue(s2)) => { Ok(StringValue(format!("{}{}", s1, s2))) },
+		(StringValue(s), TokenType::Plus, Char(c)) => { Ok(StringValue(format!("{}{}", s, c))) },
+		(Char(c), TokenType::Plus, StringValue(s)) => { Ok(StringValue(format!("{}{}", c, s))) },
+		//(StringValue(s1), TokenType::EqualEqual, StringValue(s2)) => { Ok(StractValue::from_bool(s1 == s2)) },
+		//(StringValue(s1), TokenType::BangEqual, StringValue(s2)) => { Ok(StractValue::from_bool(s1 != s2)) },
+		(x, TokenType::BangEqual, y) => Ok(StractValue::from_bool(x != y)),
+		(x, TokenType::EqualEqual, y) => Ok(StractValue::from_bool(x == y)),
+
+		(StringValue(s1), TokenType::Greater, StringValue(s2)) => Ok(StractValue::from_bool(s1 > s2)),
+		(StringValue(s1), TokenType::GreaterEqual, StringValue(s2)) => Ok(StractValue::from_bool(s1 >= s2)),
+		(StringValue(s1), TokenType::Less, StringValue(s2)) => Ok(StractValue::from_bool(s1 < s2)),
+		(StringValue(s1), TokenType::LessEqual, StringValue(s2)) => Ok(StractValue::from_bool(s1 <= s2)),
+
+		(x, ttype, y) => Err(RuntimeError::type_mismatch(format!("{} isn't implemented for operands {:?} and {:?}", ttype, x, y))),
+	}
+}
+
+/// `x / y`, which fails rather than give infinity or NaN.
+pub fn divide(x: f64, y: f64) -> Result<StractValue, RuntimeError>
+{
+	match y == 0.0
+	{
+		true => Err(RuntimeError::new(ErrorKind::DivisionByZero)),
+		false => Ok(Number(x / y)),
+	}
+}
+
+/// Reads `object.name`.
+pub fn get_property(object: &StractValue, name: &str) -> Result<StractValue, RuntimeError>
+{
+	let found = match object
+	{
+		Module(module) => module.members.borrow().get(name).cloned(),
+		Object(object) => object.get(name),
+		Map(entries) => entries.borrow().get(name).cloned(),
+		Instance(instance) => InstanceImpl::get(instance, name),
+		Signal(signal) => signal::get(signal, name),
+		Error(error) => error.get(name),
+		_ => vector::get(object, name),
+	};
+	match found
+	{
+		Some(value) => Ok(value),
+		None => Err(format!("{} has no property '{}'", object.to_type(), name).into()),
+	}
+}
+
+/// Runs `object.name :: value`.
+pub fn set_property(object: &StractValue, name: &str, value: StractValue) -> Result<(), RuntimeError>
+{
+	match object
+	{
+		Object(handle) => Ok(handle.set(name, value)?),
+		Map(entries) => { entries.borrow_mut().insert(name.to_string(), value); Ok(()) }
+		Instance(instance) => instance.set(name, value),
+		other => Err(RuntimeError::type_mismatch(format!("Cannot set property '{}' on {}", name, other.to_type()))),
+	}
+}
+
+/// Stores a vector with a component changed back where `place` read it
+/// from: a variable or a property.
+fn store(place: &Expr, value: StractValue, environment: Rc<RefCell<Environment>>) -> Result<(), RuntimeError>
+{
+	match place
+	{
+		Expr::Variable { name } => match environment.borrow_mut().assign(&name.panoll, value)
+		{
+			true => Ok(()),
+			false => Err(RuntimeError::new(ErrorKind::UndefinedVariable(name.panoll.clone()))),
+		},
+		Expr::Get { object, name } => set_property(&object.evaluate(environment)?, &name.panoll, value),
+		Expr::Grouping { expression, .. } => store(expression, value, environment),
+		_ => Err(RuntimeError::type_mismatch(format!("Cannot set a component of a {} that is not in a variable or property", value.to_type()))),
+	}
+}
+
+/// Reads `object[index]` from a string, list or map.
+pub fn index_value(object: &StractValue, index: &StractValue) -> Result<StractValue, RuntimeError>
+{
+	if let (Map(entries), StringValue(key)) = (object, index)
+	{
+		return match entries.borrow().get(key)
+		{
+			Some(value) => Ok(value.clone()),
+			None => Err(RuntimeError::new(ErrorKind::MissingKey(key.clone()))),
+		};
+	}
+	let position = match index
+	{
+		Number(x) if *x >= 0.0 && x.fract() == 0.0 => *x as usize,
+		other => return Err(RuntimeError::type_mismatch(format!("Cannot index with {}", other))),
+	};
+	let (found, length) = match object
+	{
+		StringValue(s) => (s.chars().nth(position).map(Char), s.chars().count()),
+		List(items) => (items.borrow().get(position).cloned(), items.borrow().len()),
+		other => return Err(RuntimeError::type_mismatch(format!("{} cannot be indexed", other.to_type()))),
+	};
+	match found
+	{
+		Some(value) => Ok(value),
+		None => Err(RuntimeError::new(ErrorKind::IndexOutOfRange { index: position, length })),
+	}
+}
+
+/// Calls a function, or makes an instance when `callee` is a class.
+pub fn call_value(callee: StractValue, arguments: &[StractValue]) -> Result<StractValue, RuntimeError>
+{
+	match callee
+	{
+		Callable(callable) => callable.call(arguments),
+		Class(class) => ClassImpl::instantiate(&class, arguments),
+		other => Err(RuntimeError::type_mismatch(format!("{} is not callable", other.to_type()))),
 	}
 }
 
@@ -180,7 +750,6 @@ impl Expr {
 mod tests {
 	use super::*;
 	use super::Expr::*;
-	use super::StractValue::*;
 
 	#[test]
 	fn pretty_print_ast()
@@ -189,21 +758,71 @@ mod tests {
 			token_type: TokenType::Minus, 
 			panoll: "-".to_string(), 
 			stract: None, 
-			line_number: 0,
+			span: Span::default(),
 		};
 		let onetwothree = Lateral {
-			value: Number(123.0) };
+			value: Number(123.0), span: Span::default() };
 		let group = Grouping { 
-			expression: Box::from(Lateral {value: Number(45.67)})};
+			expression: Box::from(Lateral {value: Number(45.67), span: Span::default()}), span: Span::default()};
 		let multi = Token { 
 			token_type: TokenType::Star,
 			panoll: "*".to_string(), 
 			stract: None,
-			line_number: 0};
-		let ast = Binary { left: Box::from(Unary {operator:&minus_token, right: Box::from(onetwothree),}),
-			operator:&multi,
+			span: Span::default()};
+		let ast = Binary { left: Box::from(Unary {operator: minus_token, right: Box::from(onetwothree),}),
+			operator: multi,
 			right: Box::from(group)};
 		let result = ast.to_string();
 		assert_eq!(result, "(* (- 123) (group: 45.67))");
 	}
-}
\ No newline at end of file
+
+	fn evaluate_source(source: &str) -> Result<StractValue, String>
+	{
+		let mut scanner = crate::scanner::Scanner::new(source);
+		let tokens = scanner.scan_tokens().map_err(|errors| crate::diagnostic::join(&errors))?;
+		let mut parser = crate::parser::Parser::new(tokens);
+		let expr = parser.expression().map_err(|error| error.to_string())?;
+		expr.evaluate(Rc::new(RefCell::new(Environment::new()))).map_err(|e| e.to_string())
+	}
+
+	#[test]
+	fn char_arithmetic()
+	{
+		assert_eq!(evaluate_source("'a' + 2").unwrap(), Char('c'));
+		assert_eq!(evaluate_source("1 + 'a'").unwrap(), Char('b'));
+		assert_eq!(evaluate_source("'Z' - 1").unwrap(), Char('Y'));
+		assert_eq!(evaluate_source("'Z' - 'A'").unwrap(), Number(25.0));
+		assert_eq!(evaluate_source("'A' - '\\0'").unwrap(), Number(65.0));
+		assert_eq!(evaluate_source("'\\0' + 65").unwrap(), Char('A'));
+		assert!(evaluate_source("'a' + 0.5").is_err());
+		assert!(evaluate_source("'\\0' - 1").is_err());
+	}
+
+	#[test]
+	fn char_comparison_and_concat()
+	{
+		assert_eq!(evaluate_source("'B' >: 'A'").unwrap(), True);
+		assert_eq!(evaluate_source("'B' < 'A'").unwrap(), False);
+		assert_eq!(evaluate_source("'x' : 'x'").unwrap(), True);
+		assert_eq!(evaluate_source("\"ab\" + 'c'").unwrap(), StringValue("abc".to_string()));
+		assert_eq!(evaluate_source("'c' + \"ab\"").unwrap(), StringValue("cab".to_string()));
+	}
+
+	#[test]
+	fn interpolation()
+	{
+		assert_eq!(evaluate_source("\"n={1 + 2}, c={'a' + 1}, {true}{nil}\"").unwrap(), StringValue("n=3, c=b, truenil".to_string()));
+		assert_eq!(evaluate_source("\"{{{\"x\"}}}\"").unwrap(), StringValue("{x}".to_string()));
+		assert!(evaluate_source("\"{1 +}\"").is_err());
+	}
+
+	#[test]
+	fn indexing()
+	{
+		assert_eq!(evaluate_source("\"h\\u{e9}llo\"[1]").unwrap(), Char('\u{e9}'));
+		assert_eq!(evaluate_source("[1, \"a\"][1]").unwrap(), StringValue("a".to_string()));
+		assert!(evaluate_source("[1][1]").is_err());
+		assert!(evaluate_source("[1][0.5]").is_err());
+		assert!(evaluate_source("3[0]").is_err());
+	}
+}
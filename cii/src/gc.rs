@@ -0,0 +1,855 @@
+// The garbage collector.
+//
+// Values are reference counted, which frees most objects as soon as the
+// last reference goes, but not cycles: a closure stored in the scope it
+// closes over, or an instance whose field points back at itself. So every
+// list, map, scope, instance, signal, class, module and compiled closure is
+// also registered in a heap, and `collect` finds and frees the cycles.
+//
+// Collection is mark-and-sweep. The roots are the objects referenced from
+// outside the heap: the interpreter's globals and scopes in use, the VM
+// stack, values the host holds, and natives and host objects, which the
+// collector cannot see into. They are found by subtracting the references
+// heap objects hold to each other from each object's reference count; what
+// is left over comes from outside. Everything reachable from a root is
+// marked, and the rest is garbage that only cycles keep alive. Sweeping
+// clears the contents of the garbage, which breaks the cycles and lets
+// reference counting free them.
+//
+// The heap is per thread and shared by every `Engine` on it. Collections
+// run when the host asks, or automatically when an engine call returns
+// after enough allocations.
+//
+// Games cannot stop for a whole collection, so a host can set a budget
+// instead and call `frame` once per frame, which collects in steps. Each
+// step collects slices of the heap until the budget is spent: first the
+// young generation, the objects allocated since the last step, then the
+// survivors in the old generation, a slice per step. A slice needs no
+// write barriers, because references from the rest of the heap show up in
+// the reference counts and keep what they point to alive.
+//
+// Slices miss cycles that reach past them, so once the steps have gone
+// round the old generation, they escalate to a full pass over it, spread
+// across as many steps as it takes. The pass can be fooled by scripts
+// changing references between steps, so what it finds is only a guess at
+// the garbage; each group it finds is collected again on its own before
+// anything is freed, which is safe for the same reason slices are. A group
+// too big for a step is counted and cleared a window at a time instead.
+// Its members are held meanwhile, so clearing one cannot free the rest at
+// once, and before each window every member left must be referenced only
+// from inside the group, or the group is let go.
+//
+// Budgets are spent in work, the number of objects a step looks at, which
+// is worked out from the time allowed and how long objects took so far.
+use crate::expr::{CallableImpl, StractValue};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+/// An object the heap tracks. `trace` and `clear` only look at the
+/// object's own references, not at the objects they point to.
+pub trait Collectable {
+	/// Calls `visit` with the address of every heap object this one holds
+	/// a strong reference to, once per reference. Returns false when the
+	/// object is borrowed and cannot be looked into, which keeps it alive.
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool;
+
+	/// Drops the object's references so the cycle it is in can be freed.
+	fn clear(&self);
+}
+
+/// What the collector has done so far on this thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+	/// Objects registered with the heap since the thread started.
+	pub allocated: usize,
+	/// Registered objects that are still alive.
+	pub live: usize,
+	/// Live objects that no collection has looked at yet.
+	pub young: usize,
+	/// Full collections and budgeted steps.
+	pub collections: usize,
+	/// Objects freed by collections, as opposed to by reference counting.
+	pub freed: usize,
+	/// How long the last collection or step took.
+	pub last_pause: Duration,
+	/// The longest any collection or step took.
+	pub max_pause: Duration,
+	/// The most objects a budgeted step looked at.
+	pub max_work: usize,
+	/// Budgeted steps that looked at more objects than their budget
+	/// allowed. Big garbage cycles are cleared over several steps, so this
+	/// stays at 0.
+	pub over_budget: usize,
+}
+
+/// Allocations between automatic collections, unless changed with
+/// `set_threshold`.
+pub const DEFAULT_THRESHOLD: usize = 10_000;
+
+/// The fewest objects a budgeted step collects at once. Below this, the
+/// fixed cost of a collection outweighs what it frees.
+const MIN_WINDOW: usize = 32;
+
+/// Where a full pass over the old generation has got to.
+enum Phase {
+	/// Counting references from outside, up to the object at this index.
+	Count(usize),
+	/// Marking what the objects still to visit can reach.
+	Mark(Vec<usize>),
+	/// Collecting the unmarked objects, a group of connected ones at a
+	/// time, along with the group being collected in windows, if any.
+	Sweep(Vec<Vec<usize>>, Option<Condemned>),
+}
+
+/// A group of garbage too big for one step, collected over several.
+struct Condemned {
+	group: Vec<usize>,
+	/// The members counted and not yet cleared, by their index in the pass.
+	members: VecDeque<(usize, Rc<dyn Collectable>)>,
+	/// References to each unmarked object from the members left.
+	inside: HashMap<usize, usize>,
+	counted: usize,
+}
+
+/// A full collection of the old generation, spread over budgeted steps.
+struct Pass {
+	objects: Vec<Weak<dyn Collectable>>,
+	index: HashMap<usize, usize>,
+	outside: Vec<isize>,
+	busy: Vec<bool>,
+	marked: Vec<bool>,
+	/// The objects each one referred to when it was counted.
+	refs: Vec<Vec<usize>>,
+	phase: Phase,
+}
+
+struct Heap {
+	/// Objects allocated since the last collection that looked at them.
+	young: Vec<Weak<dyn Collectable>>,
+	/// Objects that survived a collection, in the order budgeted steps
+	/// visit them.
+	old: VecDeque<Weak<dyn Collectable>>,
+	/// Dead young entries are dropped once the list reaches this length,
+	/// so objects freed by reference counting do not pile up between
+	/// collections.
+	prune_at: usize,
+	since_collection: usize,
+	threshold: usize,
+	budget: Option<Duration>,
+	/// How long collecting one object takes, measured as steps run.
+	cost: Duration,
+	/// Old objects budgeted steps visited since the last full pass.
+	round: usize,
+	pass: Option<Pass>,
+	stats: GcStats,
+}
+
+thread_local! {
+	static HEAP: RefCell<Heap> = RefCell::new(Heap {
+		young: vec![],
+		old: VecDeque::new(),
+		prune_at: 1024,
+		since_collection: 0,
+		threshold: DEFAULT_THRESHOLD,
+		budget: None,
+		cost: Duration::from_micros(10),
+		round: 0,
+		pass: None,
+		stats: GcStats::default(),
+	});
+}
+
+/// The address identifying a heap object.
+pub fn address<T: ?Sized>(object: &Rc<T>) -> usize {
+	Rc::as_ptr(object) as *const () as usize
+}
+
+/// Registers an object with the heap.
+pub fn track<T: Collectable + 'static>(object: &Rc<T>) {
+	let weak: Weak<dyn Collectable> = Rc::downgrade(object) as Weak<dyn Collectable>;
+	HEAP.with(|heap| {
+		let mut heap = heap.borrow_mut();
+		if heap.young.len() >= heap.prune_at
+		{
+			heap.young.retain(|object| object.strong_count() > 0);
+			heap.prune_at = (heap.young.len() * 2).max(1024);
+		}
+		heap.young.push(weak);
+		heap.since_collection += 1;
+		heap.stats.allocated += 1;
+	});
+}
+
+/// Allocates an object in the heap.
+pub fn alloc<T: Collectable + 'static>(value: T) -> Rc<T> {
+	let object = Rc::new(value);
+	track(&object);
+	object
+}
+
+/// Visits the heap objects a value refers to directly.
+pub fn trace_value(value: &StractValue, visit: &mut dyn FnMut(usize)) {
+	match value
+	{
+		StractValue::List(items) => visit(address(items)),
+		StractValue::Map(entries) => visit(address(entries)),
+		StractValue::Callable(callable) => trace_callable(callable, visit),
+		StractValue::Module(module) => visit(address(module)),
+		StractValue::Class(class) => visit(address(class)),
+		StractValue::Instance(instance) => visit(address(instance)),
+		StractValue::Signal(signal) => visit(address(signal)),
+		_ => (),
+	}
+}
+
+pub fn trace_callable(callable: &CallableImpl, visit: &mut dyn FnMut(usize)) {
+	match callable
+	{
+		CallableImpl::StractFunction(function) => visit(address(&function.closure)),
+		CallableImpl::Method(method) => {
+			visit(address(&method.receiver));
+			visit(address(&method.function.closure));
+		}
+		CallableImpl::Compiled(closure) => visit(address(closure)),
+		CallableImpl::NativeFunction(_) => (),
+	}
+}
+
+impl Collectable for RefCell<Vec<StractValue>> {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		match self.try_borrow()
+		{
+			Ok(items) => { items.iter().for_each(|item| trace_value(item, visit)); true }
+			Err(_) => false,
+		}
+	}
+
+	fn clear(&self) {
+		self.borrow_mut().clear();
+	}
+}
+
+impl Collectable for RefCell<HashMap<String, StractValue>> {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		match self.try_borrow()
+		{
+			Ok(entries) => { entries.values().for_each(|value| trace_value(value, visit)); true }
+			Err(_) => false,
+		}
+	}
+
+	fn clear(&self) {
+		self.borrow_mut().clear();
+	}
+}
+
+/// A variable a compiled closure captured.
+impl Collectable for RefCell<StractValue> {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		match self.try_borrow()
+		{
+			Ok(value) => { trace_value(&value, visit); true }
+			Err(_) => false,
+		}
+	}
+
+	fn clear(&self) {
+		*self.borrow_mut() = StractValue::Nil;
+	}
+}
+
+/// Frees the garbage among `objects` and returns how many were freed,
+/// along with the survivors. References from objects left out count as
+/// coming from outside, so collecting part of the heap is safe; it only
+/// misses cycles that reach past the part.
+fn collect_objects(objects: Vec<Rc<dyn Collectable>>) -> (usize, Vec<Weak<dyn Collectable>>) {
+	let index: HashMap<usize, usize> = objects.iter().enumerate()
+		.map(|(i, object)| (address(object), i))
+		.collect();
+
+	// References from outside, not counting `objects` itself.
+	let mut outside: Vec<isize> = objects.iter().map(|object| Rc::strong_count(object) as isize - 1).collect();
+	let mut busy = vec![false; objects.len()];
+	for (i, object) in objects.iter().enumerate()
+	{
+		busy[i] = !object.trace(&mut |target| {
+			if let Some(&j) = index.get(&target)
+			{
+				outside[j] -= 1;
+			}
+		});
+	}
+
+	let mut marked = vec![false; objects.len()];
+	let mut pending: Vec<usize> = (0..objects.len()).filter(|&i| outside[i] > 0 || busy[i]).collect();
+	while let Some(i) = pending.pop()
+	{
+		if marked[i]
+		{
+			continue;
+		}
+		marked[i] = true;
+		objects[i].trace(&mut |target| {
+			if let Some(&j) = index.get(&target)
+			{
+				if !marked[j]
+				{
+					pending.push(j);
+				}
+			}
+		});
+	}
+
+	let mut freed = 0;
+	let mut survivors = vec![];
+	for (object, marked) in objects.iter().zip(marked)
+	{
+		if marked
+		{
+			survivors.push(Rc::downgrade(object));
+		}
+		else
+		{
+			object.clear();
+			freed += 1;
+		}
+	}
+	(freed, survivors)
+}
+
+fn upgrade<'a>(objects: impl IntoIterator<Item = &'a Weak<dyn Collectable>>) -> Vec<Rc<dyn Collectable>> {
+	objects.into_iter().filter_map(Weak::upgrade).collect()
+}
+
+fn record(freed: usize, pause: Duration) {
+	HEAP.with(|heap| {
+		let stats = &mut heap.borrow_mut().stats;
+		stats.collections += 1;
+		stats.freed += freed;
+		stats.last_pause = pause;
+		stats.max_pause = stats.max_pause.max(pause);
+	});
+}
+
+/// Frees the cycles nothing outside the heap can reach. Returns how many
+/// objects were freed.
+pub fn collect() -> usize {
+	let start = Instant::now();
+	let objects = HEAP.with(|heap| {
+		let mut heap = heap.borrow_mut();
+		heap.since_collection = 0;
+		heap.round = 0;
+		heap.pass = None;
+		let young = std::mem::take(&mut heap.young);
+		let old = std::mem::take(&mut heap.old);
+		upgrade(old.iter().chain(&young))
+	});
+	let (freed, survivors) = collect_objects(objects);
+	HEAP.with(|heap| heap.borrow_mut().old.extend(survivors));
+	record(freed, start.elapsed());
+	freed
+}
+
+fn find(links: &mut [usize], mut i: usize) -> usize {
+	while links[i] != i
+	{
+		links[i] = links[links[i]];
+		i = links[i];
+	}
+	i
+}
+
+/// Splits the unmarked objects into groups that do not refer to each
+/// other, so each can be collected on its own.
+fn groups(objects: &[Weak<dyn Collectable>], marked: &[bool], refs: &[Vec<usize>]) -> Vec<Vec<usize>> {
+	let mut links: Vec<usize> = (0..objects.len()).collect();
+	for (i, targets) in refs.iter().enumerate().filter(|&(i, _)| !marked[i])
+	{
+		for &j in targets.iter().filter(|&&j| !marked[j])
+		{
+			let (a, b) = (find(&mut links, i), find(&mut links, j));
+			links[a] = b;
+		}
+	}
+	let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+	for i in (0..objects.len()).filter(|&i| !marked[i] && objects[i].strong_count() > 0)
+	{
+		groups.entry(find(&mut links, i)).or_default().push(i);
+	}
+	groups.into_values().collect()
+}
+
+impl Pass {
+	fn new(objects: Vec<Weak<dyn Collectable>>) -> Pass {
+		let index = objects.iter().enumerate()
+			.map(|(i, object)| (object.as_ptr() as *const () as usize, i))
+			.collect();
+		let count = objects.len();
+		Pass {
+			objects,
+			index,
+			outside: vec![0; count],
+			busy: vec![false; count],
+			marked: vec![false; count],
+			refs: vec![vec![]; count],
+			phase: Phase::Count(0),
+		}
+	}
+
+	/// Does up to `window` objects of counting or marking, or collects
+	/// groups while they fit in `left`; a group bigger than `window` is
+	/// collected a part at a time. Returns the objects looked at and freed,
+	/// and whether the pass is done.
+	fn advance(&mut self, window: usize, left: usize) -> (usize, usize, bool) {
+		let Pass { objects, index, outside, busy, marked, refs, phase } = self;
+		match phase
+		{
+			Phase::Count(next) => {
+				let end = (*next + window).min(objects.len());
+				for i in *next..end
+				{
+					if let Some(object) = objects[i].upgrade()
+					{
+						// Less the reference upgrading just made.
+						outside[i] += Rc::strong_count(&object) as isize - 1;
+						busy[i] = !object.trace(&mut |target| {
+							if let Some(&j) = index.get(&target)
+							{
+								outside[j] -= 1;
+								refs[i].push(j);
+							}
+						});
+					}
+				}
+				let work = end - *next;
+				*next = end;
+				if end == objects.len()
+				{
+					let roots = (0..objects.len()).filter(|&i| outside[i] > 0 || busy[i]).collect();
+					*phase = Phase::Mark(roots);
+				}
+				(work, 0, false)
+			}
+			Phase::Mark(pending) => {
+				let mut work = 0;
+				while work < window
+				{
+					let i = match pending.pop()
+					{
+						Some(i) => i,
+						None => break,
+					};
+					work += 1;
+					if marked[i]
+					{
+						continue;
+					}
+					marked[i] = true;
+					pending.extend(refs[i].iter().filter(|&&j| !marked[j]));
+				}
+				if pending.is_empty()
+				{
+					*phase = Phase::Sweep(groups(objects, marked, refs), None);
+				}
+				(work, 0, false)
+			}
+			Phase::Sweep(groups, condemned) => {
+				let (mut work, mut freed) = (0, 0);
+				while work < left
+				{
+					if let Some(group) = condemned
+					{
+						let (looked, cleared, finished) = group.advance(objects, index, marked, left - work);
+						work += looked;
+						freed += cleared;
+						if !finished
+						{
+							break;
+						}
+						*condemned = None;
+						continue;
+					}
+					let group = match groups.last()
+					{
+						Some(group) => group,
+						None => break,
+					};
+					if group.len() > window
+					{
+						*condemned = groups.pop().map(Condemned::new);
+					}
+					else if work + group.len() <= left
+					{
+						let group = groups.pop().unwrap_or_default();
+						work += group.len();
+						freed += collect_objects(upgrade(group.iter().map(|&i| &objects[i]))).0;
+					}
+					else
+					{
+						break;
+					}
+				}
+				(work, freed, groups.is_empty() && condemned.is_none())
+			}
+		}
+	}
+}
+
+impl Condemned {
+	fn new(group: Vec<usize>) -> Condemned {
+		Condemned { group, members: VecDeque::new(), inside: HashMap::new(), counted: 0 }
+	}
+
+	/// Counts, then clears, up to `budget` members. Returns the objects
+	/// looked at and freed, and whether the group is done with, either
+	/// because it is all cleared or because it turned out to be alive.
+	fn advance(&mut self, objects: &[Weak<dyn Collectable>], index: &HashMap<usize, usize>, marked: &[bool], budget: usize) -> (usize, usize, bool) {
+		let Condemned { group, members, inside, counted } = self;
+		let mut work = 0;
+		while *counted < group.len() && work < budget
+		{
+			let i = group[*counted];
+			*counted += 1;
+			work += 1;
+			if let Some(object) = objects[i].upgrade()
+			{
+				let free = object.trace(&mut |target| {
+					if let Some(&j) = index.get(&target).filter(|&&j| !marked[j])
+					{
+						*inside.entry(j).or_default() += 1;
+					}
+				});
+				if !free
+				{
+					return (work, 0, true);
+				}
+				members.push_back((i, object));
+			}
+		}
+		if *counted < group.len() || work == budget
+		{
+			return (work, 0, false);
+		}
+
+		// Reading a count is far cheaper than tracing, so checking every
+		// member left is not counted as work. Each is held once here.
+		let alive = members.iter()
+			.any(|(i, object)| Rc::strong_count(object) != inside.get(i).copied().unwrap_or(0) + 1);
+		if alive
+		{
+			return (work, 0, true);
+		}
+		let mut freed = 0;
+		while work < budget
+		{
+			let (_, object) = match members.pop_front()
+			{
+				Some(member) => member,
+				None => break,
+			};
+			object.trace(&mut |target| {
+				if let Some(count) = index.get(&target).and_then(|j| inside.get_mut(j))
+				{
+					*count -= 1;
+				}
+			});
+			object.clear();
+			work += 1;
+			freed += 1;
+		}
+		(work, freed, members.is_empty())
+	}
+}
+
+/// Collects for at most about `budget`, a slice of the heap at a time:
+/// young objects first, since most garbage is young, then old ones, going
+/// round the old generation over successive steps. After each round, the
+/// steps make a full pass over the old generation to free the cycles that
+/// spanned slices. Returns how many objects were freed.
+pub fn step(budget: Duration) -> usize {
+	let start = Instant::now();
+	let (old_len, limit) = HEAP.with(|heap| {
+		let mut heap = heap.borrow_mut();
+		heap.since_collection = 0;
+		// Three quarters of the budget are kept back for objects slower than
+		// the estimate and for the thread being descheduled mid-step.
+		let limit = (budget / 4).as_nanos() / heap.cost.as_nanos().max(1);
+		(heap.old.len(), (limit.min(usize::MAX as u128) as usize).max(MIN_WINDOW))
+	});
+	let (mut freed, mut done, mut visited_old, mut passed) = (0, 0, 0, false);
+	while limit.saturating_sub(done) >= MIN_WINDOW && (done == 0 || start.elapsed() < budget / 4)
+	{
+		let left = limit - done;
+		let window = left.min((limit / 4).max(MIN_WINDOW));
+		let began = Instant::now();
+		let slice = HEAP.with(|heap| {
+			let mut heap = heap.borrow_mut();
+			// A running pass goes first, so young objects cannot starve it.
+			if heap.pass.is_some() && !passed
+			{
+				Some(None)
+			}
+			else if !heap.young.is_empty()
+			{
+				let from = heap.young.len().saturating_sub(window);
+				Some(Some(upgrade(&heap.young.split_off(from))))
+			}
+			else if heap.pass.is_some()
+			{
+				Some(None)
+			}
+			else if visited_old < old_len && heap.round < heap.old.len()
+			{
+				let count = window.min(old_len - visited_old);
+				visited_old += count;
+				heap.round += count;
+				Some(Some(upgrade(&heap.old.drain(..count).collect::<Vec<_>>())))
+			}
+			else if heap.round >= heap.old.len() && !heap.old.is_empty()
+			{
+				heap.pass = Some(Pass::new(heap.old.iter().cloned().collect()));
+				Some(None)
+			}
+			else
+			{
+				None
+			}
+		});
+		let (work, survivors) = match slice
+		{
+			Some(Some(objects)) => {
+				let count = objects.len().max(1);
+				let (freed_now, survivors) = collect_objects(objects);
+				freed += freed_now;
+				(count, survivors)
+			}
+			Some(None) => {
+				passed = true;
+				let mut pass = HEAP.with(|heap| heap.borrow_mut().pass.take());
+				let (work, freed_now, finished) = match &mut pass
+				{
+					Some(pass) => pass.advance(window, left),
+					None => (0, 0, true),
+				};
+				freed += freed_now;
+				HEAP.with(|heap| {
+					let mut heap = heap.borrow_mut();
+					match finished
+					{
+						true => heap.round = 0,
+						false => heap.pass = pass,
+					}
+				});
+				(work.max(1), vec![])
+			}
+			None => break,
+		};
+		let took = began.elapsed();
+		done += work;
+		HEAP.with(|heap| {
+			let mut heap = heap.borrow_mut();
+			// Slow work counts in full at once; fast work eases the estimate down.
+			let measured = took / work as u32;
+			heap.cost = measured.max((heap.cost * 3 + measured) / 4);
+			heap.old.extend(survivors);
+		});
+	}
+	HEAP.with(|heap| {
+		let stats = &mut heap.borrow_mut().stats;
+		stats.max_work = stats.max_work.max(done);
+		stats.over_budget += (done > limit) as usize;
+	});
+	record(freed, start.elapsed());
+	freed
+}
+
+/// Collects if enough objects were allocated since the last collection.
+/// With a budget set, this waits for `frame` instead.
+pub fn collect_if_due() -> bool {
+	let due = HEAP.with(|heap| {
+		let heap = heap.borrow();
+		heap.budget.is_none() && heap.since_collection >= heap.threshold
+	});
+	if due
+	{
+		collect();
+	}
+	due
+}
+
+/// The collection to run once per frame: a step within the budget if one
+/// is set, otherwise a full collection if one is due.
+pub fn frame() -> usize {
+	match HEAP.with(|heap| heap.borrow().budget)
+	{
+		Some(budget) => step(budget),
+		None => {
+			let before = stats().freed;
+			collect_if_due();
+			stats().freed - before
+		}
+	}
+}
+
+/// Sets how many allocations trigger an automatic collection, for every
+/// engine on the thread. Use `usize::MAX` to collect only when asked.
+pub fn set_threshold(threshold: usize) {
+	HEAP.with(|heap| heap.borrow_mut().threshold = threshold.max(1));
+}
+
+/// Limits the time collecting takes per frame, for every engine on the
+/// thread. With a budget, automatic collections stop and `frame` collects
+/// in steps instead.
+pub fn set_budget(budget: Option<Duration>) {
+	HEAP.with(|heap| heap.borrow_mut().budget = budget);
+}
+
+pub fn stats() -> GcStats {
+	HEAP.with(|heap| {
+		let heap = heap.borrow();
+		let young = heap.young.iter().filter(|object| object.strong_count() > 0).count();
+		GcStats {
+			live: young + heap.old.iter().filter(|object| object.strong_count() > 0).count(),
+			young,
+			..heap.stats
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::engine::Engine;
+	use crate::expr::StractValue::*;
+
+	fn weak_global(engine: &Engine, name: &str) -> Weak<dyn Collectable>
+	{
+		match engine.get_global(name).unwrap()
+		{
+			List(items) => Rc::downgrade(&items) as Weak<dyn Collectable>,
+			Instance(instance) => Rc::downgrade(&instance) as Weak<dyn Collectable>,
+			Callable(CallableImpl::StractFunction(function)) => Rc::downgrade(&function.closure) as Weak<dyn Collectable>,
+			Callable(CallableImpl::Compiled(closure)) => Rc::downgrade(&closure) as Weak<dyn Collectable>,
+			other => panic!("{} is not a heap object", other.to_type()),
+		}
+	}
+
+	#[test]
+	fn cycles_are_freed()
+	{
+		let mut engine = Engine::new();
+		engine.eval("class Node { var next::nil; }\nvar a::Node();\nvar b::Node();\na.next :: b!\nb.next :: a!").unwrap();
+		let a = weak_global(&engine, "a");
+		engine.eval("a :: nil!\nb :: nil!").unwrap();
+		assert!(a.upgrade().is_some(), "a cycle outlives reference counting");
+		assert!(collect() >= 2);
+		assert!(a.upgrade().is_none());
+	}
+
+	#[test]
+	fn closures_in_their_own_scope_are_freed()
+	{
+		let mut engine = Engine::new();
+		let source = "func counter() {\n\tvar n::0;\n\tfunc next() { n :: n + 1! return n! }\n\treturn next!\n}\nvar f::counter();";
+		engine.eval(source).unwrap();
+		let scope = weak_global(&engine, "f");
+		engine.eval_bytecode(source).unwrap();
+		let compiled = weak_global(&engine, "f");
+		assert_eq!(engine.call_function::<f64>("f", ()), Ok(1.0));
+		engine.eval("f :: nil!").unwrap();
+		collect();
+		assert!(scope.upgrade().is_none());
+		assert!(compiled.upgrade().is_none());
+	}
+
+	#[test]
+	fn reachable_objects_survive()
+	{
+		let mut engine = Engine::new();
+		engine.eval("class Node { var next::nil; var name::\"n\"; }\nvar kept::Node();\nkept.next :: kept!\nvar held::Node();\nheld.next :: held!\nvar list::[[1], kept];").unwrap();
+		let held = engine.get_global("held").unwrap();
+		engine.eval("held :: nil!").unwrap();
+		collect();
+		assert_eq!(engine.eval("list[1].next.next.name + \"{list[0]}\"").unwrap(), StringValue("n[1]".to_string()));
+		engine.set_global("back", held);
+		assert_eq!(engine.eval("back.next.name").unwrap(), StringValue("n".to_string()));
+	}
+
+	#[test]
+	fn stats_and_thresholds()
+	{
+		let mut engine = Engine::new();
+		let mut other = Engine::new();
+		engine.set_gc_threshold(50);
+		let before = engine.gc_stats();
+		engine.eval("class Node { var next::nil; }\nvar i::0;\nwhile (i < 100) { var n::Node(); n.next :: n! i :: i + 1! }").unwrap();
+		let after = engine.gc_stats();
+		assert!(after.allocated >= before.allocated + 100);
+		assert!(after.collections > before.collections);
+		assert!(after.freed >= before.freed + 100);
+		// The heap belongs to the thread, so another engine on it shares
+		// the threshold and sees the same stats.
+		assert_eq!(other.gc_stats(), after);
+		other.set_gc_threshold(DEFAULT_THRESHOLD);
+	}
+
+	#[test]
+	fn cycles_spanning_slices_are_freed_by_later_steps()
+	{
+		let mut engine = Engine::new();
+		engine.eval("class Node { var next::nil; }\nvar first::Node();\nvar last::first;\nvar i::0;\nwhile (i < 500) { var n::Node(); last.next :: n! last :: n! i :: i + 1! }\nlast.next :: first!").unwrap();
+		let first = weak_global(&engine, "first");
+		engine.eval("first :: nil!\nlast :: nil!").unwrap();
+		set_budget(Some(Duration::from_nanos(1)));
+		let mut steps = 0;
+		while first.upgrade().is_some() && steps < 10_000
+		{
+			frame();
+			steps += 1;
+		}
+		assert!(first.upgrade().is_none(), "the ring outlived {} steps", steps);
+		// The ring is one group bigger than a whole step, so it is cleared
+		// over several, each within the budget.
+		assert_eq!(stats().over_budget, 0);
+		set_budget(None);
+	}
+
+	#[test]
+	fn steps_stay_within_the_frame_budget()
+	{
+		let budget = Duration::from_millis(8);
+		let mut engine = Engine::new();
+		set_budget(Some(budget));
+		engine.eval("class Node { var next::nil; var kept::nil; var items::[]; }
+var kept::nil;
+var frame::0;
+defi proc() {
+	var i::0;
+	while (i < 300) {
+		var a::Node();
+		var b::Node();
+		a.next :: b!
+		b.next :: a!
+		if (i : 0) { a.kept :: kept! kept :: a! }
+		i :: i + 1!
+	}
+	frame :: frame + 1!
+}").unwrap();
+		let mut scheduler = crate::Scheduler::new(1.0);
+		let id = scheduler.add("stress", engine);
+		for _ in 0..100
+		{
+			assert!(scheduler.tick(1.0 / 60.0).errors.is_empty());
+		}
+		let stats = scheduler.engine(id).gc_stats();
+		assert!(stats.collections >= 100);
+		assert!(stats.max_work > 0);
+		assert_eq!(stats.over_budget, 0, "a step looked at up to {} objects", stats.max_work);
+		assert!(stats.max_pause <= budget, "a step took {:?}", stats.max_pause);
+		assert!(stats.freed > 50_000, "only {} objects were freed", stats.freed);
+		assert_eq!(scheduler.engine_mut(id).eval("frame"), Ok(Number(100.0)));
+		assert_eq!(scheduler.engine_mut(id).eval("kept.kept.next.next : kept.kept"), Ok(True));
+	}
+}
@@ -0,0 +1,445 @@
+// HALCYON: The Standard Programming Library
+//
+// Every function works on characters rather than bytes, so `length`,
+// `search` and friends count "h\u{e9}llo" as five characters.
+use crate::environment::Environment;
+use crate::error::{ErrorKind, ErrorValue, RuntimeError};
+use crate::expr::{CallableImpl, NativeFunctionImpl, StractValue, StractValue::*};
+use crate::gc;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Native = fn(&[StractValue]) -> Result<StractValue, String>;
+
+fn define(environment: &mut Environment, name: &str, arity: usize, fun: Native)
+{
+	environment.define(name.to_string(), Callable(CallableImpl::NativeFunction(NativeFunctionImpl::new(name, arity, Rc::new(move |args: &[StractValue]| fun(args).map_err(RuntimeError::from))))));
+}
+
+pub fn define_halcyon(environment: &mut Environment)
+{
+	define(environment, "alphabetic", 1, alphabetic);
+	define(environment, "alphanumeric", 1, alphanumeric);
+	define(environment, "numeric", 1, numeric);
+	define(environment, "length", 1, length);
+	define(environment, "copy", 1, copy);
+	define(environment, "cat", 2, cat);
+	define(environment, "caseupper", 1, caseupper);
+	define(environment, "caselower", 1, caselower);
+	define(environment, "search", 2, search);
+	define(environment, "searchrfs", 2, searchrfs);
+	define(environment, "compare", 2, compare);
+	define(environment, "instringfs", 2, instringfs);
+	define(environment, "atoi", 1, atoi);
+	define(environment, "duplicate", 2, duplicate);
+	define(environment, "join", 2, join);
+	define(environment, "trimfs", 1, trimfs);
+	define(environment, "splitterfs", 2, splitterfs);
+	define(environment, "is_error", 1, is_error);
+	define(environment, "unwrap_or", 2, unwrap_or);
+	environment.define("error".to_string(), Callable(CallableImpl::NativeFunction(NativeFunctionImpl::variadic("error", Rc::new(|args: &[StractValue]| error(args).map_err(RuntimeError::from))))));
+	environment.define("expect".to_string(), Callable(CallableImpl::NativeFunction(NativeFunctionImpl::new("expect", 2, Rc::new(expect)))));
+	// Errors from the applied function pass through as they are.
+	environment.define("apply".to_string(), Callable(CallableImpl::NativeFunction(NativeFunctionImpl::new("apply", 2, Rc::new(apply)))));
+}
+
+fn expect_text(function: &str, value: &StractValue) -> Result<String, String>
+{
+	match value
+	{
+		StringValue(s) => Ok(s.clone()),
+		Char(c) => Ok(c.to_string()),
+		other => Err(format!("{}() expects a String, got {}", function, other.to_type())),
+	}
+}
+
+fn expect_count(function: &str, value: &StractValue) -> Result<usize, String>
+{
+	match value
+	{
+		Number(x) if *x >= 0.0 && x.fract() == 0.0 => Ok(*x as usize),
+		other => Err(format!("{}() expects a non-negative whole Number, got {}", function, other)),
+	}
+}
+
+fn all_chars(function: &str, value: &StractValue, test: fn(char) -> bool) -> Result<StractValue, String>
+{
+	let text = expect_text(function, value)?;
+	Ok(StractValue::from_bool(!text.is_empty() && text.chars().all(test)))
+}
+
+/// Converts a byte offset into `text` to a character offset.
+fn char_position(text: &str, byte: Option<usize>) -> StractValue
+{
+	match byte
+	{
+		Some(byte) => Number(text[..byte].chars().count() as f64),
+		None => Number(-1.0),
+	}
+}
+
+fn alphabetic(args: &[StractValue]) -> Result<StractValue, String>
+{
+	all_chars("alphabetic", &args[0], char::is_alphabetic)
+}
+
+fn alphanumeric(args: &[StractValue]) -> Result<StractValue, String>
+{
+	all_chars("alphanumeric", &args[0], char::is_alphanumeric)
+}
+
+fn numeric(args: &[StractValue]) -> Result<StractValue, String>
+{
+	all_chars("numeric", &args[0], char::is_numeric)
+}
+
+fn length(args: &[StractValue]) -> Result<StractValue, String>
+{
+	match &args[0]
+	{
+		List(items) => Ok(Number(items.borrow().len() as f64)),
+		other => Ok(Number(expect_text("length", other)?.chars().count() as f64)),
+	}
+}
+
+fn copy(args: &[StractValue]) -> Result<StractValue, String>
+{
+	match &args[0]
+	{
+		List(items) => Ok(List(gc::alloc(RefCell::new(items.borrow().clone())))),
+		other => Ok(StringValue(expect_text("copy", other)?)),
+	}
+}
+
+fn cat(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let mut text = expect_text("cat", &args[0])?;
+	text.push_str(&expect_text("cat", &args[1])?);
+	Ok(StringValue(text))
+}
+
+fn caseupper(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(StringValue(expect_text("caseupper", &args[0])?.to_uppercase()))
+}
+
+fn caselower(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(StringValue(expect_text("caselower", &args[0])?.to_lowercase()))
+}
+
+fn search(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let text = expect_text("search", &args[0])?;
+	let needle = expect_text("search", &args[1])?;
+	Ok(char_position(&text, text.find(&needle)))
+}
+
+fn searchrfs(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let text = expect_text("searchrfs", &args[0])?;
+	let needle = expect_text("searchrfs", &args[1])?;
+	Ok(char_position(&text, text.rfind(&needle)))
+}
+
+fn compare(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let first = expect_text("compare", &args[0])?;
+	let second = expect_text("compare", &args[1])?;
+	Ok(Number(first.cmp(&second) as i32 as f64))
+}
+
+fn instringfs(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let text = expect_text("instringfs", &args[0])?;
+	let needle = expect_text("instringfs", &args[1])?;
+	Ok(StractValue::from_bool(text.contains(&needle)))
+}
+
+/// Reads an optionally signed run of decimal digits after any leading
+/// whitespace, ignoring whatever follows. Yields 0 when there are no digits.
+fn atoi(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let text = expect_text("atoi", &args[0])?;
+	let mut chars = text.trim_start().chars().peekable();
+	let mut sign = 1.0;
+	if let Some(&c) = chars.peek()
+	{
+		if c == '-' || c == '+'
+		{
+			sign = if c == '-' { -1.0 } else { 1.0 };
+			chars.next();
+		}
+	}
+	let mut value = 0.0;
+	while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10))
+	{
+		value = value * 10.0 + digit as f64;
+		chars.next();
+	}
+	Ok(Number(sign * value))
+}
+
+/// The longest string duplicate() builds, in bytes.
+const MAX_DUPLICATE: usize = 1 << 28;
+
+fn duplicate(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let text = expect_text("duplicate", &args[0])?;
+	let count = expect_count("duplicate", &args[1])?;
+	match text.len().checked_mul(count)
+	{
+		Some(length) if length <= MAX_DUPLICATE => Ok(StringValue(text.repeat(count))),
+		_ => Err(format!("duplicate() would build a string longer than {} bytes", MAX_DUPLICATE)),
+	}
+}
+
+fn join(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let separator = expect_text("join", &args[1])?;
+	match &args[0]
+	{
+		List(items) => {
+			let items: Vec<String> = items.borrow().iter().map(|item| item.to_string()).collect();
+			Ok(StringValue(items.join(&separator)))
+		}
+		other => Err(format!("join() expects a List, got {}", other.to_type())),
+	}
+}
+
+fn trimfs(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(StringValue(expect_text("trimfs", &args[0])?.trim().to_string()))
+}
+
+/// Splits on every occurrence of the separator, or into single characters
+/// when the separator is empty.
+fn splitterfs(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let text = expect_text("splitterfs", &args[0])?;
+	let separator = expect_text("splitterfs", &args[1])?;
+	let parts: Vec<StractValue> = if separator.is_empty()
+	{
+		text.chars().map(|c| StringValue(c.to_string())).collect()
+	}
+	else
+	{
+		text.split(separator.as_str()).map(|part| StringValue(part.to_string())).collect()
+	};
+	Ok(List(gc::alloc(RefCell::new(parts))))
+}
+
+fn is_error(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(StractValue::from_bool(matches!(args[0], Error(_))))
+}
+
+/// `error(message)` or `error(message, code)` makes an error value.
+fn error(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let error = match args
+	{
+		[message] => ErrorValue::new(expect_text("error", message)?),
+		[message, code] => ErrorValue::new(expect_text("error", message)?).with_code(expect_text("error", code)?),
+		_ => return Err(format!("error() expects 1 or 2 arguments but got {}", args.len())),
+	};
+	Ok(Error(Rc::new(error)))
+}
+
+/// The value, or the fallback when the value is an error.
+fn unwrap_or(args: &[StractValue]) -> Result<StractValue, String>
+{
+	match &args[0]
+	{
+		Error(_) => Ok(args[1].clone()),
+		value => Ok(value.clone()),
+	}
+}
+
+/// The value, stopping the script with the message when it is an error.
+fn expect(args: &[StractValue]) -> Result<StractValue, RuntimeError>
+{
+	match &args[0]
+	{
+		Error(error) => Err(RuntimeError::new(ErrorKind::ExpectFailed(format!("{}: {}", expect_text("expect", &args[1])?, error.message)))),
+		value => Ok(value.clone()),
+	}
+}
+
+/// Calls the function on every character of a String, concatenating the
+/// results, or on every item of a List, collecting the results.
+fn apply(args: &[StractValue]) -> Result<StractValue, RuntimeError>
+{
+	let function = match &args[1]
+	{
+		Callable(callable) => callable,
+		other => return Err(RuntimeError::type_mismatch(format!("apply() expects a Callable, got {}", other.to_type()))),
+	};
+	match &args[0]
+	{
+		List(items) => {
+			let items = items.borrow().clone();
+			let mut mapped = vec![];
+			for item in items
+			{
+				mapped.push(function.call(&[item])?);
+			}
+			Ok(List(gc::alloc(RefCell::new(mapped))))
+		}
+		other => {
+			let mut text = String::new();
+			for c in expect_text("apply", other)?.chars()
+			{
+				text.push_str(&function.call(&[Char(c)])?.to_string());
+			}
+			Ok(StringValue(text))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::interpreter::Interpreter;
+	use crate::parser::Parser;
+	use crate::scanner::Scanner;
+
+	fn evaluate(source: &str) -> Result<StractValue, String>
+	{
+		let mut scanner = Scanner::new(source);
+		let tokens = scanner.scan_tokens().map_err(|errors| crate::diagnostic::join(&errors))?;
+		let mut parser = Parser::new(tokens);
+		let expr = parser.expression().map_err(|error| error.to_string())?;
+		Interpreter::new().interpret_expr(expr).map_err(|e| e.to_string())
+	}
+
+	fn text(value: &str) -> StractValue
+	{
+		StringValue(value.to_string())
+	}
+
+	#[test]
+	fn character_classes()
+	{
+		assert_eq!(evaluate(r#"alphabetic("h\u{e9}llo")"#).unwrap(), True);
+		assert_eq!(evaluate(r#"alphabetic("ab1")"#).unwrap(), False);
+		assert_eq!(evaluate(r#"alphabetic("")"#).unwrap(), False);
+		assert_eq!(evaluate(r#"alphanumeric("ab1")"#).unwrap(), True);
+		assert_eq!(evaluate(r#"alphanumeric("a b")"#).unwrap(), False);
+		assert_eq!(evaluate(r#"numeric("0123")"#).unwrap(), True);
+		assert_eq!(evaluate("numeric('x')").unwrap(), False);
+		assert!(evaluate("numeric(12)").is_err());
+	}
+
+	#[test]
+	fn length_counts_characters()
+	{
+		assert_eq!(evaluate(r#"length("h\u{e9}llo")"#).unwrap(), Number(5.0));
+		assert_eq!(evaluate(r#"length("")"#).unwrap(), Number(0.0));
+		assert_eq!(evaluate("length([1, 2, 3])").unwrap(), Number(3.0));
+		assert!(evaluate("length(nil)").is_err());
+	}
+
+	#[test]
+	fn error_values()
+	{
+		assert_eq!(evaluate(r#"is_error(error("gone"))"#).unwrap(), True);
+		assert_eq!(evaluate("is_error(nil)").unwrap(), False);
+		assert_eq!(evaluate(r#"error("gone", "NotFound").code"#).unwrap(), text("NotFound"));
+		assert_eq!(evaluate(r#"error("gone").code"#).unwrap(), Nil);
+		assert_eq!(evaluate(r#""{error("gone")}""#).unwrap(), text("<error: gone>"));
+		assert_eq!(evaluate(r#"unwrap_or(error("gone"), 7)"#).unwrap(), Number(7.0));
+		assert_eq!(evaluate("unwrap_or(3, 7)").unwrap(), Number(3.0));
+		assert_eq!(evaluate(r#"expect(3, "need a number")"#).unwrap(), Number(3.0));
+		assert_eq!(evaluate(r#"expect(error("gone"), "need a number")"#).unwrap_err(), "need a number: gone at line 1, column 1");
+		assert!(evaluate("error()").is_err());
+	}
+
+	#[test]
+	fn copy_and_cat()
+	{
+		assert_eq!(evaluate(r#"copy("abc")"#).unwrap(), text("abc"));
+		assert_eq!(evaluate(r#"cat("ab", 'c')"#).unwrap(), text("abc"));
+		assert!(evaluate(r#"cat("ab")"#).is_err());
+	}
+
+	#[test]
+	fn copy_list_is_shallow()
+	{
+		let mut interpreter = Interpreter::new();
+		let mut scanner = Scanner::new("var a::[1];\nvar b::copy(a);\nvar c::b : a;");
+		let statements = Parser::new(scanner.scan_tokens().unwrap()).parse().unwrap();
+		interpreter.interpret(statements).unwrap();
+		let mut scanner = Scanner::new("c");
+		let expr = Parser::new(scanner.scan_tokens().unwrap()).expression().unwrap();
+		assert_eq!(interpreter.interpret_expr(expr).unwrap(), True);
+	}
+
+	#[test]
+	fn case_conversion()
+	{
+		assert_eq!(evaluate(r#"caseupper("stra\u{df}e")"#).unwrap(), text("STRASSE"));
+		assert_eq!(evaluate(r#"caselower("\u{c9}T\u{c9}")"#).unwrap(), text("\u{e9}t\u{e9}"));
+	}
+
+	#[test]
+	fn searching()
+	{
+		assert_eq!(evaluate(r#"search("\u{e9}abcab", "ab")"#).unwrap(), Number(1.0));
+		assert_eq!(evaluate(r#"searchrfs("\u{e9}abcab", "ab")"#).unwrap(), Number(4.0));
+		assert_eq!(evaluate(r#"search("abc", "z")"#).unwrap(), Number(-1.0));
+		assert_eq!(evaluate(r#"instringfs("abc", "bc")"#).unwrap(), True);
+		assert_eq!(evaluate(r#"instringfs("abc", "cb")"#).unwrap(), False);
+	}
+
+	#[test]
+	fn comparing()
+	{
+		assert_eq!(evaluate(r#"compare("a", "b")"#).unwrap(), Number(-1.0));
+		assert_eq!(evaluate(r#"compare("b", "b")"#).unwrap(), Number(0.0));
+		assert_eq!(evaluate(r#"compare("c", "b")"#).unwrap(), Number(1.0));
+	}
+
+	#[test]
+	fn parsing_integers()
+	{
+		assert_eq!(evaluate(r#"atoi("  -42abc")"#).unwrap(), Number(-42.0));
+		assert_eq!(evaluate(r#"atoi("+7")"#).unwrap(), Number(7.0));
+		assert_eq!(evaluate(r#"atoi("abc")"#).unwrap(), Number(0.0));
+	}
+
+	#[test]
+	fn duplicating()
+	{
+		assert_eq!(evaluate(r#"duplicate("ab", 3)"#).unwrap(), text("ababab"));
+		assert_eq!(evaluate(r#"duplicate("ab", 0)"#).unwrap(), text(""));
+		assert!(evaluate(r#"duplicate("ab", -1)"#).is_err());
+		assert!(evaluate(r#"duplicate("ab", 1e18)"#).is_err());
+		assert!(evaluate(r#"duplicate("ab", 1e300)"#).is_err());
+		assert_eq!(evaluate(r#"duplicate("", 1e18)"#).unwrap(), text(""));
+	}
+
+	#[test]
+	fn joining_and_splitting()
+	{
+		assert_eq!(evaluate(r#"join(["a", 1, 'c'], ", ")"#).unwrap(), text("a, 1, c"));
+		assert_eq!(evaluate(r#"splitterfs("a,b,,c", ",")"#).unwrap().to_string(), "[a, b, , c]");
+		assert_eq!(evaluate(r#"splitterfs("\u{e9}t\u{e9}", "")"#).unwrap().to_string(), "[\u{e9}, t, \u{e9}]");
+		assert_eq!(evaluate(r#"join(splitterfs("a b c", " "), "-")"#).unwrap(), text("a-b-c"));
+		assert!(evaluate(r#"join("abc", ",")"#).is_err());
+	}
+
+	#[test]
+	fn trimming()
+	{
+		assert_eq!(evaluate(r#"trimfs("  \t padded \n")"#).unwrap(), text("padded"));
+	}
+
+	#[test]
+	fn applying()
+	{
+		assert_eq!(evaluate(r#"apply("h\u{e9}llo", caseupper)"#).unwrap(), text("H\u{c9}LLO"));
+		assert_eq!(evaluate(r#"apply(["a", "bb"], length)"#).unwrap().to_string(), "[1, 2]");
+		assert!(evaluate(r#"apply("abc", "abc")"#).is_err());
+	}
+}
@@ -0,0 +1,100 @@
+// Lifecycle hooks: the special functions a script declares with `defi`
+// instead of `func`, which the host calls at fixed points such as start-up
+// or every frame.
+
+/// A function a script can declare with `defi`, such as `defi physic(delta)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+	/// Run by `cii` once the top-level code has finished.
+	Main,
+	/// Run by the host at a fixed timestep with the step length in seconds.
+	Physic,
+	/// Run by the host once per frame.
+	Proc,
+	/// Run by the host once, before the first frame.
+	Init,
+	/// Run by `cii` right after `main`.
+	Imide,
+	/// Run by the host with a notification sent to the script.
+	Notify,
+	/// Run by the host when input arrives, with or without the event.
+	Input,
+	/// Run by the host when the script is unloaded.
+	Exput,
+}
+
+impl Hook {
+	pub const ALL: [Hook; 8] = [Hook::Main, Hook::Physic, Hook::Proc, Hook::Init, Hook::Imide, Hook::Notify, Hook::Input, Hook::Exput];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Hook::Main => "main",
+			Hook::Physic => "physic",
+			Hook::Proc => "proc",
+			Hook::Init => "init",
+			Hook::Imide => "imide",
+			Hook::Notify => "notify",
+			Hook::Input => "input",
+			Hook::Exput => "exput",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Hook> {
+		Hook::ALL.into_iter().find(|hook| hook.name() == name)
+	}
+
+	/// The parameter lists the hook may be declared with. The names only
+	/// document what the host passes; a script may name them as it likes.
+	pub fn signatures(self) -> &'static [&'static [&'static str]] {
+		match self {
+			Hook::Physic => &[&["delta"]],
+			Hook::Notify => &[&["what"]],
+			Hook::Input => &[&[], &["event"]],
+			_ => &[&[]],
+		}
+	}
+
+	/// Checks the number of parameters a `defi` declares.
+	pub fn check_params(self, count: usize) -> Result<(), String> {
+		if self.signatures().iter().any(|params| params.len() == count)
+		{
+			return Ok(());
+		}
+		let expected: Vec<String> = self.signatures().iter()
+			.map(|params| format!("{}({})", self.name(), params.join(", ")))
+			.collect();
+		Err(format!("defi {} takes {} parameters, expected {}", self.name(), count, expected.join(" or ")))
+	}
+}
+
+impl std::fmt::Display for Hook {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn names_round_trip()
+	{
+		for hook in Hook::ALL
+		{
+			assert_eq!(Hook::from_name(hook.name()), Some(hook));
+		}
+		assert_eq!(Hook::from_name("physics"), None);
+	}
+
+	#[test]
+	fn parameter_conventions()
+	{
+		assert!(Hook::Main.check_params(0).is_ok());
+		assert!(Hook::Physic.check_params(1).is_ok());
+		assert!(Hook::Input.check_params(0).is_ok());
+		assert!(Hook::Input.check_params(1).is_ok());
+		assert_eq!(Hook::Physic.check_params(0), Err("defi physic takes 0 parameters, expected physic(delta)".to_string()));
+		assert_eq!(Hook::Input.check_params(2), Err("defi input takes 2 parameters, expected input() or input(event)".to_string()));
+	}
+}
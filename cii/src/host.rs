@@ -0,0 +1,253 @@
+// Rust-owned objects handed to scripts. The script holds a shared handle, so
+// `player.health :: 10!` writes straight into the host's struct and the host
+// sees the change without copying anything back.
+use crate::convert::IntoValue;
+use crate::error::RuntimeError;
+use crate::expr::{CallableImpl, NativeFunctionImpl, StractValue};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A Rust type whose properties and methods scripts can use.
+///
+/// ```
+/// use cii::{Engine, HostObject, StractValue};
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// struct Button { pressed: bool }
+///
+/// impl HostObject for Button {
+///     fn type_name(&self) -> &str { "Button" }
+///
+///     fn get(&self, name: &str) -> Option<StractValue> {
+///         match name {
+///             "pressed" => Some(StractValue::from_bool(self.pressed)),
+///             _ => None,
+///         }
+///     }
+///
+///     fn set(&mut self, name: &str, value: StractValue) -> Result<(), String> {
+///         match name {
+///             "pressed" => { self.pressed = cii::FromValue::from_value(&value)?; Ok(()) }
+///             _ => Err(format!("Button has no property '{}'", name)),
+///         }
+///     }
+/// }
+///
+/// let button = Rc::new(RefCell::new(Button { pressed: false }));
+/// let mut engine = Engine::new();
+/// engine.set_global("button", StractValue::object(button.clone()));
+/// engine.eval("button.pressed :: true!").unwrap();
+/// assert!(button.borrow().pressed);
+/// ```
+pub trait HostObject {
+	/// The name scripts see from `to_type`, such as "Player".
+	fn type_name(&self) -> &str;
+
+	/// Reads a property, or `None` if the object has no such property.
+	fn get(&self, name: &str) -> Option<StractValue>;
+
+	/// Writes a property. Read-only objects keep the default.
+	fn set(&mut self, name: &str, _value: StractValue) -> Result<(), String> {
+		Err(format!("{} property '{}' is read-only", self.type_name(), name))
+	}
+
+	/// Whether `name` is a method, reached from scripts as `object.name(...)`.
+	fn has_method(&self, _name: &str) -> bool {
+		false
+	}
+
+	/// Runs a method that `has_method` reported. Methods check their own
+	/// arguments, for instance with `FromValue`.
+	fn call_method(&mut self, name: &str, _args: &[StractValue]) -> Result<StractValue, String> {
+		Err(format!("{} has no method '{}'", self.type_name(), name))
+	}
+}
+
+/// A shared handle to a host object. Two handles are equal only when they
+/// point at the same object.
+#[derive(Clone)]
+pub struct HostHandle {
+	pub object: Rc<RefCell<dyn HostObject>>,
+	type_name: Rc<str>,
+}
+
+impl HostHandle {
+	pub fn new(object: Rc<RefCell<dyn HostObject>>) -> Self {
+		let type_name = Rc::from(object.borrow().type_name());
+		Self {
+			object,
+			type_name,
+		}
+	}
+
+	/// The type name, read once when the handle was made.
+	pub fn type_name(&self) -> &str {
+		&self.type_name
+	}
+
+	/// Looks up a property, falling back to a method bound to this object.
+	pub fn get(&self, name: &str) -> Option<StractValue> {
+		let object = self.object.borrow();
+		if let Some(value) = object.get(name)
+		{
+			return Some(value);
+		}
+		if !object.has_method(name)
+		{
+			return None;
+		}
+		let handle = self.object.clone();
+		let method = name.to_string();
+		let label = format!("{}.{}", self.type_name, name);
+		let busy = label.clone();
+		let fun = Rc::new(move |args: &[StractValue]| {
+			let mut object = handle.try_borrow_mut()
+				.map_err(|_| format!("{}() called while the object is in use", busy))?;
+			object.call_method(&method, args).map_err(RuntimeError::from)
+		});
+		Some(StractValue::Callable(CallableImpl::NativeFunction(NativeFunctionImpl::variadic(&label, fun))))
+	}
+
+	pub fn set(&self, name: &str, value: StractValue) -> Result<(), String> {
+		match self.object.try_borrow_mut()
+		{
+			Ok(mut object) => object.set(name, value),
+			Err(_) => Err(format!("Cannot set '{}' while the {} is in use", name, self.type_name())),
+		}
+	}
+}
+
+impl std::fmt::Debug for HostHandle {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "<{}>", self.type_name())
+	}
+}
+
+impl PartialEq for HostHandle {
+	fn eq(&self, other: &Self) -> bool {
+		Rc::ptr_eq(&self.object, &other.object)
+	}
+}
+
+impl StractValue {
+	/// Wraps a host object so scripts can reach it; the host keeps its own
+	/// `Rc` and sees every change the script makes.
+	pub fn object<T: HostObject + 'static>(object: Rc<RefCell<T>>) -> Self {
+		StractValue::Object(HostHandle::new(object))
+	}
+}
+
+impl<T: HostObject + 'static> IntoValue for Rc<RefCell<T>> {
+	fn into_value(self) -> StractValue {
+		StractValue::object(self)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::convert::FromValue;
+	use crate::engine::Engine;
+	use crate::expr::StractValue::*;
+
+	struct Player {
+		name: String,
+		position: (f64, f64),
+		health: i64,
+	}
+
+	impl HostObject for Player {
+		fn type_name(&self) -> &str {
+			"Player"
+		}
+
+		fn get(&self, name: &str) -> Option<StractValue> {
+			match name {
+				"name" => Some(StringValue(self.name.clone())),
+				"position" => Some(Vector2(self.position.0, self.position.1)),
+				"health" => Some(Number(self.health as f64)),
+				_ => None,
+			}
+		}
+
+		fn set(&mut self, name: &str, value: StractValue) -> Result<(), String> {
+			match (name, value) {
+				("position", Vector2(x, y)) => self.position = (x, y),
+				("position", other) => return Err(format!("position must be a Vector2, got {}", other.to_type())),
+				("health", value) => self.health = i64::from_value(&value)?,
+				("name", _) => return Err("name is read-only".to_string()),
+				_ => return Err(format!("Player has no property '{}'", name)),
+			}
+			Ok(())
+		}
+
+		fn has_method(&self, name: &str) -> bool {
+			name == "hurt"
+		}
+
+		fn call_method(&mut self, name: &str, args: &[StractValue]) -> Result<StractValue, String> {
+			match (name, args) {
+				("hurt", [amount]) => {
+					self.health -= i64::from_value(amount)?;
+					Ok(Number(self.health as f64))
+				}
+				_ => Err(format!("Player.{}() got {} arguments", name, args.len())),
+			}
+		}
+	}
+
+	fn player() -> Rc<RefCell<Player>>
+	{
+		Rc::new(RefCell::new(Player { name: "Ann".to_string(), position: (0.0, 0.0), health: 10 }))
+	}
+
+	#[test]
+	fn properties_read_and_write_host_state()
+	{
+		let player = player();
+		let mut engine = Engine::new();
+		engine.set_global("player", StractValue::object(player.clone()));
+		assert_eq!(engine.eval("player.name").unwrap(), StringValue("Ann".to_string()));
+		engine.eval("player.position :: player.position + Vector2(1, 2)!").unwrap();
+		assert_eq!(player.borrow().position, (1.0, 2.0));
+		assert_eq!(engine.eval("player.health :: 4").unwrap(), Number(4.0));
+		assert_eq!(player.borrow().health, 4);
+		player.borrow_mut().health = 7;
+		assert_eq!(engine.eval("player.health").unwrap(), Number(7.0));
+	}
+
+	#[test]
+	fn methods_mutate_the_object()
+	{
+		let player = player();
+		let mut engine = Engine::new();
+		engine.set_global("player", player.clone().into_value());
+		engine.eval("var hurt::player.hurt;\nhurt(3)!").unwrap();
+		assert_eq!(player.borrow().health, 7);
+		assert!(engine.eval("player.hurt()").unwrap_err().to_string().contains("got 0 arguments"));
+	}
+
+	#[test]
+	fn errors_from_the_host_reach_the_script()
+	{
+		let mut engine = Engine::new();
+		engine.set_global("player", StractValue::object(player()));
+		assert!(engine.eval("player.speed").unwrap_err().to_string().contains("Player has no property 'speed'"));
+		assert!(engine.eval("player.name :: \"Bo\"!").unwrap_err().to_string().contains("read-only"));
+		assert!(engine.eval("player.position :: 1!").unwrap_err().to_string().contains("must be a Vector2"));
+		assert!(engine.eval("Vector2(1, 2).x :: 3!").is_err());
+	}
+
+	#[test]
+	fn equality_is_by_identity()
+	{
+		let first = player();
+		let mut engine = Engine::new();
+		engine.set_global("a", StractValue::object(first.clone()));
+		engine.set_global("b", StractValue::object(first));
+		engine.set_global("c", StractValue::object(player()));
+		assert_eq!(engine.eval("a : b").unwrap(), True);
+		assert_eq!(engine.eval("a : c").unwrap(), False);
+		assert_eq!(engine.eval("\"{a}\"").unwrap(), StringValue("<Player>".to_string()));
+	}
+}
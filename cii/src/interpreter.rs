@@ -1,30 +1,396 @@
-use crate::expr::{Expr, StractValue};
-use crate::statement::Statement;
+use crate::expr::{Expr, StractValue, CallableImpl, ModuleImpl, StractFunctionImpl};
+use crate::statement::{Handler, Import, ReferenceTarget, Statement};
+use crate::environment::Environment;
+use crate::halcyon::define_halcyon;
+use crate::jellybeans::{define_jellybeans, Random};
+use crate::vector::define_vectors;
+use crate::native::{IntoNative, IntoNativeResult, NativeModule, variadic};
+use crate::modules::{self, Modules};
+use crate::hooks::Hook;
+use crate::class::ClassImpl;
+use crate::error::{ErrorKind, RuntimeError};
+use crate::gc;
+use crate::parser::Parser;
+use crate::scanner::{self, Scanner, Token};
+use crate::sources;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::cell::RefCell;
 
 pub struct Interpreter {
-	//Pass
+	environment: Rc<RefCell<Environment>>,
+	random: Rc<RefCell<Random>>,
+	modules: Rc<RefCell<Modules>>,
+	/// The script being run, which relative `@reference` paths start from.
+	file: Option<PathBuf>,
+	/// Library members brought into scope, which a module does not export.
+	library_imports: HashMap<String, StractValue>,
+	/// The functions declared with `defi`, by the hook they implement.
+	hooks: HashMap<Hook, CallableImpl>,
+}
+
+/// How a statement finished: by running off its end, by a `return` that
+/// has to unwind to the enclosing function call, or by a `break` that has
+/// to unwind to the enclosing loop.
+pub enum Flow {
+	Normal,
+	Return(StractValue),
+	Break,
+}
+
+impl Default for Interpreter {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 impl Interpreter {
-	pub fn new() -> Self { Self {} }
+	pub fn new() -> Self {
+		let mut globals = Environment::new();
+		define_halcyon(&mut globals);
+		define_vectors(&mut globals);
+		let globals = gc::alloc(RefCell::new(globals));
+		Self {
+			environment: globals.clone(),
+			random: Rc::new(RefCell::new(Random::from_time())),
+			modules: Rc::new(RefCell::new(Modules::new(globals))),
+			file: None,
+			library_imports: HashMap::new(),
+			hooks: HashMap::new(),
+		}
+	}
+
+	/// An interpreter for a function body running in `environment`.
+	/// `@reference` is only allowed at the top level, so it never needs the
+	/// random generator or modules of the interpreter that made the call.
+	pub fn for_closure(environment: Rc<RefCell<Environment>>) -> Self {
+		Self {
+			environment: environment.clone(),
+			random: Rc::new(RefCell::new(Random::new(0))),
+			modules: Rc::new(RefCell::new(Modules::new(environment))),
+			file: None,
+			library_imports: HashMap::new(),
+			hooks: HashMap::new(),
+		}
+	}
 
-	pub fn interpret_expr(&mut self, expr: Expr) -> Result<StractValue, String> { expr.evaluate() }
+	pub fn globals(&self) -> Rc<RefCell<Environment>> {
+		self.environment.clone()
+	}
 
-	pub fn interpret(&mut self, statements:Vec<Statement>) -> Result<(), String> {
-		for statement in statements {
-			match statement {
-				Statement::Expression{expression} => {expression.evaluate()?;},
-				Statement::Print{expression} => {
-					let value = expression.evaluate()?;
-					println!("{value:?}");
+	/// Reseeds the generator behind `randf` so runs can be replayed.
+	pub fn seed_random(&mut self, seed: u64) {
+		self.random.borrow_mut().seed(seed);
+	}
+
+	/// Exposes a Rust closure to scripts as the global function `name`.
+	/// Its arity is the closure's number of parameters.
+	pub fn register_fn<Args>(&mut self, name: &str, fun: impl IntoNative<Args>) {
+		let native = fun.into_native(name);
+		self.environment.borrow_mut().define(name.to_string(), StractValue::Callable(CallableImpl::NativeFunction(native)));
+	}
+
+	/// Exposes a Rust closure that takes any number of arguments.
+	pub fn register_variadic<F, R>(&mut self, name: &str, fun: F)
+	where
+		F: Fn(&[StractValue]) -> R + 'static,
+		R: IntoNativeResult,
+	{
+		let native = variadic(name, fun);
+		self.environment.borrow_mut().define(name.to_string(), StractValue::Callable(CallableImpl::NativeFunction(native)));
+	}
+
+	/// Defines a group of native functions as a global named after the module.
+	pub fn register_module(&mut self, module: NativeModule) {
+		let name = module.name().to_string();
+		self.environment.borrow_mut().define(name, module.into_value());
+	}
+
+	/// Runs the code that follows as the file `path`: relative references
+	/// resolve from its directory, and a module referencing it back is a
+	/// cycle. Returns the file to restore with `leave_file`.
+	pub fn enter_file(&mut self, path: &Path) -> Result<Option<PathBuf>, RuntimeError> {
+		self.modules.borrow_mut().enter(path).map_err(|e| RuntimeError::new(ErrorKind::CircularReference(e)))?;
+		Ok(self.file.replace(path.to_path_buf()))
+	}
+
+	pub fn leave_file(&mut self, previous: Option<PathBuf>) {
+		self.modules.borrow_mut().leave();
+		self.file = previous;
+	}
+
+	/// The function the script declared for `hook` with `defi`, if any.
+	pub fn hook(&self, hook: Hook) -> Option<CallableImpl> {
+		self.hooks.get(&hook).cloned()
+	}
+
+	/// Adds a directory to search for modules referenced by bare name, after
+	/// the ones added before it.
+	pub fn add_search_path(&mut self, path: PathBuf) {
+		self.modules.borrow_mut().search_paths.push(path);
+	}
+
+	pub fn search_paths(&self) -> Vec<PathBuf> {
+		self.modules.borrow().search_paths.clone()
+	}
+
+	fn reference(&mut self, target: &ReferenceTarget, import: &Import) -> Result<(), RuntimeError> {
+		let (name, members) = match target {
+			ReferenceTarget::Library(library) => (library.panoll.clone(), self.library(library)?),
+			ReferenceTarget::File { path, alias } => {
+				let module = self.load_module(path)?;
+				if let Import::Namespace = import {
+					let name = alias.as_ref().map_or(module.name.clone(), |alias| alias.panoll.clone());
+					self.environment.borrow_mut().define(name, StractValue::Module(module));
+					return Ok(());
+				}
+				let members = module.members.borrow().clone();
+				(module.name.clone(), members)
+			}
+		};
+
+		let imported: HashMap<String, StractValue> = match import {
+			Import::Namespace | Import::All => members,
+			Import::Only(names) => {
+				let mut selected = HashMap::new();
+				for token in names {
+					let member = token.panoll.trim_start_matches('@');
+					match members.get(member) {
+						Some(value) => selected.insert(member.to_string(), value.clone()),
+						None => return Err(RuntimeError::new(ErrorKind::MissingExport(format!("{} has no export '{}'", name, member))).at(token.span)),
+					};
 				}
-				Statement::Var { name, initializer } => {
-					let value = initializer()?;
+				selected
+			}
+		};
+		if let ReferenceTarget::Library(_) = target {
+			self.library_imports.extend(imported.clone());
+		}
+		let mut environment = self.environment.borrow_mut();
+		for (member, value) in imported {
+			environment.define(member, value);
+		}
+		Ok(())
+	}
+
+	/// The members of a built-in library.
+	fn library(&mut self, library: &Token) -> Result<HashMap<String, StractValue>, RuntimeError> {
+		let mut members = Environment::new();
+		match library.panoll.as_str() {
+			"@lib_halcyon" => define_halcyon(&mut members),
+			"@lib_jellybeans" => define_jellybeans(&mut members, self.random.clone()),
+			other => return Err(RuntimeError::new(ErrorKind::UnknownLibrary(other.to_string())).at(library.span)),
+		}
+		Ok(members.values().clone())
+	}
+
+	/// Loads the module at `path`, running it the first time only. What goes
+	/// wrong inside the module is reported in the module's own file, with
+	/// the reference as where it was loaded from.
+	fn load_module(&mut self, path: &Token) -> Result<Rc<ModuleImpl>, RuntimeError> {
+		let raw = match &path.stract {
+			Some(scanner::StractValue::StringValue(raw)) => raw.clone(),
+			_ => path.panoll.clone(),
+		};
+		let search_paths = self.modules.borrow().search_paths.clone();
+		let resolved = modules::resolve(self.file.as_deref(), &raw, &search_paths)
+			.map_err(|e| RuntimeError::new(ErrorKind::ModuleNotFound(e)).at(path.span))?;
+		if let Some(module) = self.modules.borrow().cached(&resolved) {
+			return Ok(module);
+		}
+
+		self.modules.borrow_mut().enter(&resolved).map_err(|e| RuntimeError::new(ErrorKind::CircularReference(e)).at(path.span))?;
+		let result = self.run_module(&resolved, modules::module_name(&raw));
+		self.modules.borrow_mut().leave();
+		let module = result.map_err(|e| e.referenced_at(path.span))?;
+		self.modules.borrow_mut().insert(resolved, module.clone());
+		Ok(module)
+	}
+
+	/// Runs a module file in a scope of its own and collects what it
+	/// defines at the top level: its variables and functions, and what it
+	/// references from other files, but not built-in library members.
+	fn run_module(&mut self, path: &Path, name: String) -> Result<Rc<ModuleImpl>, RuntimeError> {
+		let source = fs::read_to_string(path)
+			.map_err(|e| RuntimeError::new(ErrorKind::ModuleNotFound(format!("Could not read {}: {}", path.display(), e))))?;
+		let file = sources::add(path, &source);
+		let tokens = Scanner::new(&source).in_file(file).scan_tokens().map_err(RuntimeError::syntax)?;
+		let statements = Parser::new(tokens).parse().map_err(RuntimeError::syntax)?;
+		let root = self.modules.borrow().root.clone();
+		let environment = gc::alloc(RefCell::new(Environment::new_enclosed(root)));
+		let mut interpreter = Interpreter {
+			environment: environment.clone(),
+			random: self.random.clone(),
+			modules: self.modules.clone(),
+			file: Some(path.to_path_buf()),
+			library_imports: HashMap::new(),
+			hooks: HashMap::new(),
+		};
+		interpreter.interpret(statements)?;
 
-					self.environment.define(name.panoll, value);
-				},
-			};
+		let members = environment.borrow().values().iter()
+			.filter(|(name, value)| interpreter.library_imports.get(*name) != Some(value))
+			.map(|(name, value)| (name.clone(), value.clone()))
+			.collect();
+		Ok(gc::alloc(ModuleImpl {
+			name,
+			members: RefCell::new(members),
+		}))
+	}
+
+	pub fn interpret_expr(&mut self, expr: Expr) -> Result<StractValue, RuntimeError> { expr.evaluate(self.environment.clone()).map_err(RuntimeError::unpropagated) }
+
+	pub fn interpret(&mut self, statements:Vec<Statement>) -> Result<(), RuntimeError> {
+		for statement in &statements {
+			match self.execute(statement).map_err(RuntimeError::unpropagated)? {
+				Flow::Normal => (),
+				Flow::Break => break,
+				Flow::Return(_) => return Err(RuntimeError::new(ErrorKind::ReturnOutsideFunction).at(statement.span())),
+			}
 		}
 		Ok(())
 	}
+
+	pub fn execute_statements(&mut self, statements: &[Statement]) -> Result<Flow, RuntimeError> {
+		for statement in statements {
+			match self.execute(statement)? {
+				Flow::Normal => (),
+				flow => return Ok(flow),
+			}
+		}
+		Ok(Flow::Normal)
+	}
+
+	fn execute_block(&mut self, statements: &[Statement], environment: Rc<RefCell<Environment>>) -> Result<Flow, RuntimeError> {
+		let previous = std::mem::replace(&mut self.environment, environment);
+		let result = self.execute_statements(statements);
+		self.environment = previous;
+		result
+	}
+
+	fn execute_scoped(&mut self, statements: &[Statement]) -> Result<Flow, RuntimeError> {
+		let environment = Environment::new_enclosed(self.environment.clone());
+		self.execute_block(statements, gc::alloc(RefCell::new(environment)))
+	}
+
+	/// Runs the first handler that catches `error`, with the error's value
+	/// bound to its name. `?` is on its way out of the function, so no
+	/// handler stops it.
+	fn catch(&mut self, error: RuntimeError, handlers: &[Handler]) -> Result<Flow, RuntimeError> {
+		if let ErrorKind::Propagated(_) = error.kind {
+			return Err(error);
+		}
+		let handler = handlers.iter().find(|handler| match &handler.error_type {
+			Some(error_type) => error.is_a(&error_type.panoll),
+			None => true,
+		});
+		match handler {
+			Some(handler) => {
+				let mut environment = Environment::new_enclosed(self.environment.clone());
+				environment.define(handler.name.panoll.clone(), error.value());
+				self.execute_block(&handler.body, gc::alloc(RefCell::new(environment)))
+			}
+			None => Err(error),
+		}
+	}
+
+	fn function(&self, name: &Token, params: &[Token], body: &Rc<Vec<Statement>>) -> StractFunctionImpl {
+		StractFunctionImpl {
+			name: name.panoll.clone(),
+			params: params.to_vec(),
+			body: body.clone(),
+			closure: self.environment.clone(),
+		}
+	}
+
+	fn execute(&mut self, statement: &Statement) -> Result<Flow, RuntimeError> {
+		match statement {
+			Statement::Block { statements, .. } => return self.execute_scoped(statements),
+			Statement::Expression { expression, .. } => {expression.evaluate(self.environment.clone())?;},
+			Statement::Function { name, params, body, .. } => {
+				let callable = CallableImpl::StractFunction(self.function(name, params, body));
+				self.environment.borrow_mut().define(name.panoll.clone(), StractValue::Callable(callable));
+			}
+			Statement::Define { hook, name, params, body, .. } => {
+				let callable = CallableImpl::StractFunction(self.function(name, params, body));
+				self.environment.borrow_mut().define(name.panoll.clone(), StractValue::Callable(callable.clone()));
+				self.hooks.insert(*hook, callable);
+			}
+			Statement::If { condition, then_branch, else_branch, .. } => {
+				if condition.evaluate(self.environment.clone())?.is_falsy() == StractValue::False {
+					return self.execute(then_branch);
+				}
+				if let Some(else_branch) = else_branch {
+					return self.execute(else_branch);
+				}
+			}
+			Statement::While { condition, body, .. } => {
+				while condition.evaluate(self.environment.clone())?.is_falsy() == StractValue::False {
+					match self.execute(body)? {
+						Flow::Normal => (),
+						Flow::Break => break,
+						flow => return Ok(flow),
+					}
+				}
+			}
+			Statement::Print { expression, .. } => {
+				let value = expression.evaluate(self.environment.clone())?;
+				println!("{}", value);
+			}
+			Statement::Return { keyword: _, value, .. } => {
+				let value = value.evaluate(self.environment.clone())?;
+				return Ok(Flow::Return(value));
+			}
+			Statement::Var { name, initializer, .. } => {
+				let value = initializer.evaluate(self.environment.clone())?;
+
+				self.environment.borrow_mut().define(name.panoll.clone(), value);
+			},
+			Statement::Reference { target, import, span } => self.reference(target, import).map_err(|e| e.at(*span))?,
+			Statement::Class { name, body, .. } => {
+				let mut methods = HashMap::new();
+				let mut signals = vec![];
+				for member in body.iter() {
+					match member {
+						Statement::Function { name, params, body, .. } => {
+							methods.insert(name.panoll.clone(), self.function(name, params, body));
+						}
+						Statement::Signal { name, params, .. } => signals.push((name.panoll.clone(), params.len())),
+						_ => (),
+					}
+				}
+				let class = ClassImpl {
+					name: name.panoll.clone(),
+					body: body.clone(),
+					methods,
+					signals,
+					closure: self.environment.clone(),
+				};
+				self.environment.borrow_mut().define(name.panoll.clone(), StractValue::Class(gc::alloc(class)));
+			}
+			Statement::Throw { value, span, .. } => {
+				let value = value.evaluate(self.environment.clone())?;
+				return Err(RuntimeError::new(ErrorKind::Thrown(Box::new(value))).at(*span));
+			}
+			Statement::Try { body, handlers, finally, .. } => {
+				let result = match self.execute_scoped(body) {
+					Err(error) => self.catch(error, handlers),
+					result => result,
+				};
+				// A `finally` that returns, breaks or raises replaces the outcome.
+				if let Some(finally) = finally {
+					match self.execute_scoped(finally)? {
+						Flow::Normal => (),
+						flow => return Ok(flow),
+					}
+				}
+				return result;
+			}
+			Statement::Break { .. } => return Ok(Flow::Break),
+			Statement::Signal { name, span, .. } => return Err(RuntimeError::from(format!("signal '{}' must be declared in a class", name.panoll)).at(*span)),
+		};
+		Ok(Flow::Normal)
+	}
 }
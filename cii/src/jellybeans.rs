@@ -0,0 +1,422 @@
+// JELLYBEANS: The Standard Game Development Library
+//
+// Numbers are all f64. The plain and `f` variants of a function (`clamp`,
+// `clampf`) behave the same on any Number, while the integer variants
+// (`absi`, `wrap`) reject Numbers with a fractional part or outside the
+// 64-bit integer range instead of silently truncating them.
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::expr::{CallableImpl, NativeFn, NativeFunctionImpl, StractValue, StractValue::*};
+use std::cell::RefCell;
+use std::f64::consts::TAU;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// SplitMix64 generator backing `randf` and `randf_range`. The same seed
+/// always yields the same sequence, which keeps test runs reproducible.
+pub struct Random {
+	state: u64,
+}
+
+impl Random {
+	pub fn new(seed: u64) -> Self {
+		Self { state: seed }
+	}
+
+	pub fn from_time() -> Self {
+		let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+		Self::new(nanos)
+	}
+
+	pub fn seed(&mut self, seed: u64) {
+		self.state = seed;
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+
+	/// Uniform float in `[0, 1)`.
+	pub fn next_f64(&mut self) -> f64 {
+		(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+	}
+}
+
+type Native = fn(&[StractValue]) -> Result<StractValue, String>;
+
+fn define(environment: &mut Environment, name: &str, arity: usize, fun: Native)
+{
+	define_closure(environment, name, arity, Rc::new(move |args: &[StractValue]| fun(args).map_err(RuntimeError::from)));
+}
+
+fn define_closure(environment: &mut Environment, name: &str, arity: usize, fun: NativeFn)
+{
+	environment.define(name.to_string(), Callable(CallableImpl::NativeFunction(NativeFunctionImpl::new(name, arity, fun))));
+}
+
+pub fn define_jellybeans(environment: &mut Environment, random: Rc<RefCell<Random>>)
+{
+	define(environment, "abs", 1, abs);
+	define(environment, "absf", 1, abs);
+	define(environment, "absi", 1, absi);
+	define(environment, "acos", 1, acos);
+	define(environment, "clamp", 3, clamp);
+	define(environment, "clampf", 3, clamp);
+	define(environment, "deg_to_rad", 1, deg_to_rad);
+	define(environment, "floor", 1, floor);
+	define(environment, "lerp", 3, lerp);
+	define(environment, "lerpf", 3, lerp);
+	define(environment, "lerp_with_angle", 3, lerp_with_angle);
+	define(environment, "max", 2, max);
+	define(environment, "maxf", 2, max);
+	define(environment, "min", 2, min);
+	define(environment, "minf", 2, min);
+	define(environment, "roundup", 1, roundup);
+	define(environment, "sin", 1, sin);
+	define(environment, "sign", 1, sign);
+	define(environment, "smooth", 3, smooth);
+	define(environment, "snap_up", 2, snap_up);
+	define(environment, "snap_fac", 2, snap_fac);
+	define(environment, "sqrt", 1, sqrt);
+	define(environment, "wrap", 3, wrap);
+	define(environment, "wrapf", 3, wrapf);
+
+	let generator = random.clone();
+	define_closure(environment, "randf", 0, Rc::new(move |_args: &[StractValue]| {
+		Ok(Number(generator.borrow_mut().next_f64()))
+	}));
+	let generator = random.clone();
+	define_closure(environment, "randf_range", 2, Rc::new(move |args: &[StractValue]| {
+		let from = expect_number("randf_range", &args[0])?;
+		let to = expect_number("randf_range", &args[1])?;
+		Ok(Number(from + (to - from) * generator.borrow_mut().next_f64()))
+	}));
+	define_closure(environment, "seed", 1, Rc::new(move |args: &[StractValue]| {
+		let seed = expect_whole("seed", &args[0])?;
+		random.borrow_mut().seed(seed as u64);
+		Ok(Nil)
+	}));
+}
+
+fn expect_number(function: &str, value: &StractValue) -> Result<f64, String>
+{
+	match value
+	{
+		Number(x) => Ok(*x),
+		other => Err(format!("{}() expects a Number, got {}", function, other.to_type())),
+	}
+}
+
+fn expect_whole(function: &str, value: &StractValue) -> Result<i64, String>
+{
+	match value
+	{
+		// i64::MIN is -2^63 exactly; i64::MAX rounds up to 2^63 as a float.
+		Number(x) if x.fract() == 0.0 && *x >= i64::MIN as f64 && *x < i64::MAX as f64 => Ok(*x as i64),
+		Number(x) if x.fract() == 0.0 => Err(format!("{}() expects a whole Number within 64 bits, got {}", function, x)),
+		other => Err(format!("{}() expects a whole Number, got {}", function, other)),
+	}
+}
+
+fn numbers<const N: usize>(function: &str, args: &[StractValue]) -> Result<[f64; N], String>
+{
+	let mut values = [0.0; N];
+	for (value, arg) in values.iter_mut().zip(args)
+	{
+		*value = expect_number(function, arg)?;
+	}
+	Ok(values)
+}
+
+fn abs(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Number(expect_number("abs", &args[0])?.abs()))
+}
+
+fn absi(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Number(expect_whole("absi", &args[0])?.unsigned_abs() as f64))
+}
+
+fn acos(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let x = expect_number("acos", &args[0])?;
+	Ok(Number(x.clamp(-1.0, 1.0).acos()))
+}
+
+fn clamp(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [value, low, high] = numbers("clamp", args)?;
+	if low > high
+	{
+		return Err(format!("clamp() lower bound {} is above upper bound {}", low, high));
+	}
+	Ok(Number(value.clamp(low, high)))
+}
+
+fn deg_to_rad(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Number(expect_number("deg_to_rad", &args[0])?.to_radians()))
+}
+
+fn floor(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Number(expect_number("floor", &args[0])?.floor()))
+}
+
+fn lerp(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [from, to, weight] = numbers("lerp", args)?;
+	Ok(Number(from + (to - from) * weight))
+}
+
+/// Interpolates between two angles in radians along the shorter arc.
+fn lerp_with_angle(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [from, to, weight] = numbers("lerp_with_angle", args)?;
+	let difference = (to - from) % TAU;
+	let distance = (2.0 * difference) % TAU - difference;
+	Ok(Number(from + distance * weight))
+}
+
+fn max(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [a, b] = numbers("max", args)?;
+	Ok(Number(a.max(b)))
+}
+
+fn min(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [a, b] = numbers("min", args)?;
+	Ok(Number(a.min(b)))
+}
+
+/// Rounds towards positive infinity.
+fn roundup(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Number(expect_number("roundup", &args[0])?.ceil()))
+}
+
+fn sin(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Number(expect_number("sin", &args[0])?.sin()))
+}
+
+fn sign(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let x = expect_number("sign", &args[0])?;
+	Ok(Number(if x > 0.0 { 1.0 } else if x < 0.0 { -1.0 } else { 0.0 }))
+}
+
+/// Hermite smoothstep of `x` between the `from` and `to` edges.
+fn smooth(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [from, to, x] = numbers("smooth", args)?;
+	if from == to
+	{
+		return Ok(Number(if x < from { 0.0 } else { 1.0 }));
+	}
+	let t = ((x - from) / (to - from)).clamp(0.0, 1.0);
+	Ok(Number(t * t * (3.0 - 2.0 * t)))
+}
+
+/// Smallest multiple of `step` that is not below `x`.
+fn snap_up(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [x, step] = numbers("snap_up", args)?;
+	if step == 0.0
+	{
+		return Ok(Number(x));
+	}
+	Ok(Number((x / step).ceil() * step))
+}
+
+/// Nearest multiple of `step` to `x`.
+fn snap_fac(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [x, step] = numbers("snap_fac", args)?;
+	if step == 0.0
+	{
+		return Ok(Number(x));
+	}
+	Ok(Number((x / step).round() * step))
+}
+
+fn sqrt(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let x = expect_number("sqrt", &args[0])?;
+	if x < 0.0
+	{
+		return Err(format!("sqrt() of negative Number {}", x));
+	}
+	Ok(Number(x.sqrt()))
+}
+
+/// Wraps a whole Number into `[min, max)`.
+fn wrap(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let value = expect_whole("wrap", &args[0])? as i128;
+	let low = expect_whole("wrap", &args[1])? as i128;
+	let high = expect_whole("wrap", &args[2])? as i128;
+	let range = high - low;
+	if range == 0
+	{
+		return Ok(Number(low as f64));
+	}
+	match i64::try_from(low + (value - low).rem_euclid(range))
+	{
+		Ok(wrapped) => Ok(Number(wrapped as f64)),
+		Err(_) => Err(format!("wrap() of {} between {} and {} is outside 64 bits", value, low, high)),
+	}
+}
+
+/// Wraps a Number into `[min, max)`.
+fn wrapf(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let [value, low, high] = numbers("wrapf", args)?;
+	let range = high - low;
+	if range == 0.0
+	{
+		return Ok(Number(low));
+	}
+	Ok(Number(low + (value - low).rem_euclid(range)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::interpreter::Interpreter;
+	use crate::parser::Parser;
+	use crate::scanner::Scanner;
+	use std::f64::consts::PI;
+
+	fn run(interpreter: &mut Interpreter, source: &str) -> Result<StractValue, String>
+	{
+		let mut scanner = Scanner::new(source);
+		let tokens = scanner.scan_tokens().map_err(|errors| crate::diagnostic::join(&errors))?;
+		let expr = Parser::new(tokens).expression().map_err(|error| error.to_string())?;
+		interpreter.interpret_expr(expr).map_err(|e| e.to_string())
+	}
+
+	fn referenced() -> Interpreter
+	{
+		let mut interpreter = Interpreter::new();
+		let mut scanner = Scanner::new("@reference @lib_jellybeans");
+		let statements = Parser::new(scanner.scan_tokens().unwrap()).parse().unwrap();
+		interpreter.interpret(statements).unwrap();
+		interpreter
+	}
+
+	fn evaluate(source: &str) -> Result<StractValue, String>
+	{
+		run(&mut referenced(), source)
+	}
+
+	fn number(source: &str) -> f64
+	{
+		match evaluate(source).unwrap()
+		{
+			Number(x) => x,
+			other => panic!("Expected a Number, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn needs_reference()
+	{
+		assert!(run(&mut Interpreter::new(), "abs(1)").is_err());
+		assert_eq!(evaluate("abs(-1.5)").unwrap(), Number(1.5));
+	}
+
+	#[test]
+	fn absolute_values()
+	{
+		assert_eq!(number("absf(-2.25)"), 2.25);
+		assert_eq!(number("absi(-3)"), 3.0);
+		assert!(evaluate("absi(-3.5)").is_err());
+		assert_eq!(number("absi(-9223372036854775808)"), 9223372036854775808.0);
+		assert!(evaluate("absi(9223372036854775808)").is_err());
+		assert!(evaluate("absi(-1e300)").is_err());
+		assert!(evaluate("abs(\"x\")").is_err());
+	}
+
+	#[test]
+	fn clamping_and_extremes()
+	{
+		assert_eq!(number("clamp(5, 0, 3)"), 3.0);
+		assert_eq!(number("clampf(-0.5, 0, 1)"), 0.0);
+		assert!(evaluate("clamp(1, 3, 0)").is_err());
+		assert_eq!(number("max(2, 7)"), 7.0);
+		assert_eq!(number("minf(2.5, 7)"), 2.5);
+	}
+
+	#[test]
+	fn rounding()
+	{
+		assert_eq!(number("floor(-1.5)"), -2.0);
+		assert_eq!(number("roundup(1.1)"), 2.0);
+		assert_eq!(number("snap_up(7, 5)"), 10.0);
+		assert_eq!(number("snap_fac(7, 5)"), 5.0);
+		assert_eq!(number("snap_up(7, 0)"), 7.0);
+	}
+
+	#[test]
+	fn trigonometry()
+	{
+		assert!((number("deg_to_rad(180)") - PI).abs() < 1e-12);
+		assert!((number("sin(deg_to_rad(90))") - 1.0).abs() < 1e-12);
+		assert!((number("acos(1)")).abs() < 1e-12);
+		assert_eq!(number("sqrt(9)"), 3.0);
+		assert!(evaluate("sqrt(-1)").is_err());
+	}
+
+	#[test]
+	fn interpolation()
+	{
+		assert_eq!(number("lerp(0, 10, 0.25)"), 2.5);
+		assert_eq!(number("lerpf(10, 0, 0.5)"), 5.0);
+		assert_eq!(number("smooth(0, 1, 0.5)"), 0.5);
+		assert_eq!(number("smooth(0, 1, 2)"), 1.0);
+		let halfway = number("lerp_with_angle(deg_to_rad(350), deg_to_rad(10), 0.5)");
+		assert!((halfway - TAU).abs() < 1e-9, "{}", halfway);
+	}
+
+	#[test]
+	fn signs_and_wrapping()
+	{
+		assert_eq!(number("sign(-4)"), -1.0);
+		assert_eq!(number("sign(0)"), 0.0);
+		assert_eq!(number("wrap(-1, 0, 4)"), 3.0);
+		assert_eq!(number("wrap(9, 2, 5)"), 3.0);
+		assert!(evaluate("wrap(1.5, 0, 4)").is_err());
+		assert_eq!(number("wrap(9000000000000000000, -9000000000000000000, 0)"), -9000000000000000000.0);
+		assert_eq!(number("wrap(-9223372036854775808, 0, 9000000000000000000)"), 8776627963145224192.0);
+		assert!(evaluate("wrap(0, 9000000000000000000, -9000000000000000000)").is_err());
+		assert!(evaluate("wrap(1e300, 0, 4)").is_err());
+		assert!((number("wrapf(-0.5, 0, 2)") - 1.5).abs() < 1e-12);
+	}
+
+	#[test]
+	fn seeded_random_is_reproducible()
+	{
+		let mut first = referenced();
+		let mut second = referenced();
+		run(&mut first, "seed(42)").unwrap();
+		run(&mut second, "seed(42)").unwrap();
+		for _ in 0..10
+		{
+			let a = run(&mut first, "randf()").unwrap();
+			assert_eq!(a, run(&mut second, "randf()").unwrap());
+			match run(&mut first, "randf_range(-2, 3)").unwrap()
+			{
+				Number(x) => assert!((-2.0..3.0).contains(&x)),
+				other => panic!("Expected a Number, got {:?}", other),
+			}
+			run(&mut second, "randf_range(-2, 3)").unwrap();
+		}
+	}
+}
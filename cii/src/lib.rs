@@ -0,0 +1,45 @@
+//! PScript embedded in a Rust application. [`Engine`] runs scripts and
+//! exchanges values with them; the modules below are the interpreter
+//! pipeline it drives.
+
+pub mod scanner;
+pub mod span;
+pub mod sources;
+pub mod diagnostic;
+pub mod codes;
+pub mod expr;
+pub mod error;
+pub mod parser;
+pub mod interpreter;
+pub mod statement;
+pub mod environment;
+pub mod halcyon;
+pub mod jellybeans;
+pub mod vector;
+pub mod convert;
+pub mod native;
+pub mod host;
+pub mod class;
+pub mod signal;
+pub mod modules;
+pub mod hooks;
+pub mod scheduler;
+pub mod chunk;
+pub mod compiler;
+pub mod vm;
+pub mod pyxc;
+pub mod gc;
+pub mod engine;
+
+pub use crate::engine::{Compiled, Engine, EngineError};
+pub use crate::error::{ErrorKind, ErrorValue, RuntimeError, TraceFrame};
+pub use crate::span::Span;
+pub use crate::diagnostic::{Diagnostic, Label, Severity};
+pub use crate::expr::StractValue;
+pub use crate::convert::{FromValue, IntoArgs, IntoValue, Record};
+pub use crate::native::NativeModule;
+pub use crate::host::{HostHandle, HostObject};
+pub use crate::hooks::Hook;
+pub use crate::signal::SignalImpl;
+pub use crate::gc::GcStats;
+pub use crate::scheduler::{Scheduler, ScriptError, ScriptId, TickReport};
@@ -1,42 +1,207 @@
-mod scanner;
-mod expr;
-mod parser;
-mod interpreter;
-mod statement;
-use crate::scanner::*;
-use crate::parser::*;
-use crate::interpreter::*;
-use crate::statement::{Statement::*};
+use cii::{codes, Compiled, Diagnostic, Engine, EngineError, Scheduler, Severity};
 
 use std::env;
 use std::fs;
+use std::path::Path;
 use std::process::exit;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead, IsTerminal, Write};
 
 
-fn run_file(path: &str)->Result<(), String> 
+const USAGE: &str = "USAGE: j< pscript [--json] [-I search_path]... [--frames N [--delta seconds]] [Script]
+       j< pscript [--json] compile Script [-o Output]
+       j< pscript explain Code";
+
+/// How errors are shown: drawn over the source, in color on a terminal,
+/// or as one JSON object per line for editors and other tools.
+#[derive(Clone, Copy)]
+struct Output
+{
+	json: bool,
+	color: bool,
+}
+
+impl Output
+{
+	fn report(&self, diagnostics: Vec<Diagnostic>, source: &str, path: &str)
+	{
+		for diagnostic in diagnostics
+		{
+			match self.json
+			{
+				true => println!("{}", diagnostic.to_json(path)),
+				false => println!("{}\n", diagnostic.render(source, path, self.color)),
+			}
+			if let (false, Some(code)) = (self.json, diagnostic.code)
+			{
+				println!("For more about this {}, run `cii explain {}`.\n", diagnostic.severity.name(), code);
+			}
+		}
+	}
+
+	/// Reports an error from the script at `path`, whose source is read
+	/// again to show the lines it points at.
+	fn report_file(&self, error: &EngineError, path: &str)
+	{
+		self.report(error.diagnostics(), &source_of(path), path);
+	}
+}
+
+/// The source of the script at `path`. A `.pyxc` file holds bytecode, so
+/// its `.pyx` is read instead, or nothing when that is gone, which leaves
+/// the source lines out of reports.
+fn source_of(path: &str) -> String
 {
-	let mut interpreter = Interpreter::new();
-	match fs::read_to_string(path) {
-		Err(msg) => return Err(msg.to_string()),
-		Ok(contents)=>return run(&mut interpreter, &contents),
+	let path = Path::new(path);
+	match path.extension()
+	{
+		Some(extension) if extension == "pyxc" => fs::read_to_string(path.with_extension("pyx")).unwrap_or_default(),
+		_ => fs::read_to_string(path).unwrap_or_default(),
 	}
 }
 
-fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), String>
+/// Warns that the script at `path` runs on the interpreter, because the
+/// bytecode compiler does not support what `reason` points at.
+fn report_interpreted(output: Output, mut reason: Diagnostic, note: String, path: &str)
 {
-	let mut scanner = Scanner::new(contents);
-	let tokens = scanner.scan_tokens()?;
-	let mut parser = Parser::new(tokens);
-	
-	let statement = parser.parse()?;
-	interpreter.interpret(statement);
-	return Ok(());
+	reason.severity = Severity::Warning;
+	output.report(vec![reason.with_note(note)], &source_of(path), path);
 }
 
-fn run_prompt()->Result<(), String>
+/// Reads the value after a flag, or stops with the usage.
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str, what: &str) -> String
+{
+	match args.next()
+	{
+		Some(value) => value,
+		None => {
+			println!("{}\n{} needs {}", USAGE, flag, what);
+			exit(64);
+		}
+	}
+}
+
+fn parse_flag<T: std::str::FromStr>(value: String, flag: &str) -> T
+{
+	match value.parse()
+	{
+		Ok(parsed) => parsed,
+		Err(_) => {
+			println!("{}\n{} cannot use '{}'", USAGE, flag, value);
+			exit(64);
+		}
+	}
+}
+
+/// Runs `frames` frames of `delta` seconds through a scheduler with no
+/// window, as a game would, reporting every hook that fails.
+fn run_headless(engine: Engine, name: &str, frames: usize, delta: f64, output: Output) -> Result<(), String>
+{
+	let mut scheduler = Scheduler::new(delta);
+	scheduler.add(name, engine);
+	let mut failures = 0;
+	for frame in 1..=frames
+	{
+		for error in scheduler.tick(delta).errors
+		{
+			let source = source_of(name);
+			let diagnostics = error.error.diagnostics().into_iter()
+				.map(|diagnostic| diagnostic.with_note(format!("in {} during frame {}", error.hook, frame)))
+				.collect();
+			output.report(diagnostics, &source, name);
+			failures += 1;
+		}
+	}
+	match failures
+	{
+		0 => Ok(()),
+		n => Err(format!("{} hook calls failed over {} frames", n, frames)),
+	}
+}
+
+/// `cii compile foo.pyx [-o foo.pyxc]`: saves the script's bytecode so it
+/// can be run later without parsing it again.
+fn run_compile(engine: &Engine, mut args: impl Iterator<Item = String>, output: Output) -> Result<(), ()>
+{
+	let mut script = None;
+	let mut compiled = None;
+	while let Some(arg) = args.next()
+	{
+		if arg == "-o"
+		{
+			compiled = Some(flag_value(&mut args, "-o", "a file to write"));
+		}
+		else if script.is_none()
+		{
+			script = Some(arg);
+		}
+		else
+		{
+			println!("{}\nOnly one script can be compiled at a time", USAGE);
+			exit(64);
+		}
+	}
+	let script = match script
+	{
+		Some(script) => script,
+		None => {
+			println!("{}\ncompile needs a script", USAGE);
+			exit(64);
+		}
+	};
+	let compiled = compiled.unwrap_or_else(|| Path::new(&script).with_extension("pyxc").display().to_string());
+	match engine.compile_file(&script, &compiled)
+	{
+		Ok(()) => Ok(()),
+		Err(EngineError::Unsupported(reason)) => {
+			let note = format!("{} was not written; running {} or {} uses the interpreter", compiled, script, compiled);
+			report_interpreted(output, *reason, note, &script);
+			Ok(())
+		}
+		Err(err) => {
+			output.report_file(&err, &script);
+			Err(())
+		}
+	}
+}
+
+/// Runs a `.pyxc` script on the VM, or its `.pyx` on the interpreter when
+/// the bytecode compiler does not support it.
+fn run_compiled(engine: &mut Engine, script: &str, output: Output) -> Result<cii::StractValue, EngineError>
+{
+	match engine.load_compiled(script)?
+	{
+		Compiled::Bytecode(function) => engine.run_bytecode(&function),
+		Compiled::Interpreted { source, reason } => {
+			let path = source.display().to_string();
+			report_interpreted(output, reason, format!("running {} on the interpreter instead", path), &path);
+			engine.eval_file(&source)
+		}
+	}
+}
+
+/// `cii explain PS0001`: prints what an error code means, with an example.
+fn run_explain(mut args: impl Iterator<Item = String>) -> Result<(), String>
+{
+	let code = match (args.next(), args.next())
+	{
+		(Some(code), None) => code,
+		_ => {
+			println!("{}\nexplain needs one error code, such as PS0001", USAGE);
+			exit(64);
+		}
+	};
+	match codes::lookup(&code)
+	{
+		Some(entry) => {
+			print!("{}", entry.describe());
+			Ok(())
+		}
+		None => Err(format!("{} is not an error code, they look like PS0001", code)),
+	}
+}
+
+fn run_prompt(mut engine: Engine, output: Output)->Result<(), String>
 {
-	let mut interpreter = Interpreter::new();
 	loop
 	{
 		print!("> ");
@@ -62,39 +227,111 @@ fn run_prompt()->Result<(), String>
 		}
 
 		println!("{}", buffer);
-		match run(&mut interpreter, &buffer)
+		match engine.eval(&buffer)
 		{
 			Ok(_) => (),
-			Err(msg) => { 
-				println!("{}", msg);
-				return Err(msg);
+			Err(err) => { 
+				output.report(err.diagnostics(), &buffer, "<prompt>");
+				return Err(err.to_string());
 			}
 		}
 	}
 }
 
 fn main() {
-	let args: Vec<String> = env::args().collect();
-	
-	if args.len() > 2 {
-		println!("USAGE: j< pscript[Script] - Script cannot be over argumentS > 2");
-		exit(64);
+	let mut engine = Engine::new();
+	let mut script = None;
+	let mut frames = None;
+	let mut delta = 1.0 / 60.0;
+	let mut args: Vec<String> = env::args().skip(1).collect();
+	let json = args.iter().any(|arg| arg == "--json");
+	args.retain(|arg| arg != "--json");
+	let mut args = args.into_iter().peekable();
+	let output = Output {
+		json,
+		color: !json && io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+	};
+	if args.peek().map(String::as_str) == Some("explain")
+	{
+		args.next();
+		if let Err(msg) = run_explain(args)
+		{
+			println!("ERROR:\n{}", msg);
+			exit(1);
+		}
+		exit(0);
 	}
-	else if args.len() == 2
+	if args.peek().map(String::as_str) == Some("compile")
 	{
-		match run_file(&args[1])
+		args.next();
+		if run_compile(&engine, args, output).is_err()
 		{
-			Ok(_) => exit(0),
-			Err(msg) =>
+			exit(1);
+		}
+		exit(0);
+	}
+	while let Some(arg) = args.next()
+	{
+		if arg == "-I"
+		{
+			engine.add_search_path(flag_value(&mut args, "-I", "a directory"));
+		}
+		else if arg == "--frames"
+		{
+			frames = Some(parse_flag::<usize>(flag_value(&mut args, "--frames", "a frame count"), "--frames"));
+		}
+		else if arg == "--delta"
+		{
+			delta = parse_flag::<f64>(flag_value(&mut args, "--delta", "a frame time in seconds"), "--delta");
+			if delta.is_nan() || delta <= 0.0
+			{
+				println!("{}\n--delta must be positive", USAGE);
+				exit(64);
+			}
+		}
+		else if script.is_none()
+		{
+			script = Some(arg);
+		}
+		else
+		{
+			println!("{}\nOnly one script can be run at a time", USAGE);
+			exit(64);
+		}
+	}
+	// Paths given with -I come before the ones from the environment.
+	engine.add_search_paths_from_env();
+
+	if let Some(script) = script
+	{
+		let loaded = match Path::new(&script).extension()
+		{
+			Some(extension) if extension == "pyxc" => run_compiled(&mut engine, &script, output),
+			_ => engine.eval_file(&script),
+		};
+		if let Err(err) = loaded.and_then(|_| engine.run_main())
+		{
+			output.report_file(&err, &script);
+			exit(1);
+		}
+		if let Some(frames) = frames
+		{
+			if let Err(msg) = run_headless(engine, &script, frames, delta, output)
 			{
 				println!("ERROR:\n{}", msg);
 				exit(1);
 			}
 		}
+		exit(0);
+	}
+	else if frames.is_some()
+	{
+		println!("{}\n--frames needs a script", USAGE);
+		exit(64);
 	}
 	else
 	{
-		match run_prompt()
+		match run_prompt(engine, output)
 		{
 			Ok(_) => exit(0),
 			Err(msg) => {
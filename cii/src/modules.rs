@@ -0,0 +1,303 @@
+// File modules loaded with `@reference("./path")`. Each file runs once in
+// its own scope; later references get the cached module. The stack of files
+// being loaded turns an import cycle into an error instead of a hang.
+//
+// A module is either a file, `name.pyx`, or a package: a directory `name/`
+// whose entry module is `name/lib.pyx`. Paths starting with `.` or `/` are
+// looked up from the importing file only; bare names such as
+// `@reference("collections")` are tried next to the importing file and then
+// in each search path, in order.
+use crate::environment::Environment;
+use crate::expr::ModuleImpl;
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The environment variable holding extra search paths, separated like
+/// `PATH` on the host platform.
+pub const SEARCH_PATH_VAR: &str = "PYXIS_PATH";
+
+/// The entry module of a package directory.
+pub const PACKAGE_ENTRY: &str = "lib.pyx";
+
+pub struct Modules {
+	/// The scope every module encloses: the built-in and host globals.
+	pub root: Rc<RefCell<Environment>>,
+	pub search_paths: Vec<PathBuf>,
+	cache: HashMap<PathBuf, Rc<ModuleImpl>>,
+	loading: Vec<PathBuf>,
+}
+
+impl Modules {
+	pub fn new(root: Rc<RefCell<Environment>>) -> Self {
+		Self {
+			root,
+			search_paths: vec![],
+			cache: HashMap::new(),
+			loading: vec![],
+		}
+	}
+
+	pub fn cached(&self, path: &Path) -> Option<Rc<ModuleImpl>> {
+		self.cache.get(path).cloned()
+	}
+
+	pub fn insert(&mut self, path: PathBuf, module: Rc<ModuleImpl>) {
+		self.cache.insert(path, module);
+	}
+
+	/// Marks `path` as being loaded, or fails with the chain of files that
+	/// leads back to it.
+	pub fn enter(&mut self, path: &Path) -> Result<(), String> {
+		if let Some(start) = self.loading.iter().position(|loading| loading == path)
+		{
+			let chain: Vec<String> = self.loading[start..].iter().chain([&path.to_path_buf()])
+				.map(|file| file.display().to_string())
+				.collect();
+			return Err(format!("Circular<j reference: {}", chain.join(" -> ")));
+		}
+		self.loading.push(path.to_path_buf());
+		Ok(())
+	}
+
+	pub fn leave(&mut self) {
+		self.loading.pop();
+	}
+}
+
+/// Reads the search paths listed in `PYXIS_PATH`, if it is set.
+pub fn paths_from_env() -> Vec<PathBuf>
+{
+	match std::env::var_os(SEARCH_PATH_VAR)
+	{
+		Some(paths) => std::env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()).collect(),
+		None => vec![],
+	}
+}
+
+/// The files `path` may name inside `directory`: the file itself, with
+/// `.pyx` added when it has no extension, or a package's entry module.
+fn candidates(directory: &Path, path: &str) -> Vec<PathBuf>
+{
+	let target = directory.join(path);
+	if target.extension().is_some()
+	{
+		return vec![target];
+	}
+	vec![target.with_extension("pyx"), target.join(PACKAGE_ENTRY)]
+}
+
+/// Finds the file `path` names when referenced from `from`, the importing
+/// file, or from the working directory when there is none. Fails with
+/// every location tried.
+pub fn resolve(from: Option<&Path>, path: &str, search_paths: &[PathBuf]) -> Result<PathBuf, String>
+{
+	let base = from.and_then(Path::parent).unwrap_or(Path::new("."));
+	let mut directories = vec![base];
+	if !path.starts_with('.') && !Path::new(path).is_absolute()
+	{
+		directories.extend(search_paths.iter().map(PathBuf::as_path));
+	}
+
+	let mut tried = vec![];
+	for directory in directories
+	{
+		for candidate in candidates(directory, path)
+		{
+			if candidate.is_file()
+			{
+				return candidate.canonicalize().map_err(|e| format!("Cannot open module {}: {}", candidate.display(), e));
+			}
+			tried.push(format!("\n  {}", candidate.display()));
+		}
+	}
+	Err(format!("Cannot find module \"{}\", looked in:{}", path, tried.concat()))
+}
+
+/// The name a module is bound to by default: the last part of the path it
+/// was referenced by, without `.pyx`, so `"./lib/shapes"` gives `shapes`
+/// and the package `"collections"` gives `collections`.
+pub fn module_name(path: &str) -> String
+{
+	Path::new(path).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::engine::Engine;
+	use crate::expr::StractValue::*;
+	use crate::sources;
+	use std::cell::Cell;
+	use std::fs;
+	use std::path::PathBuf;
+	use std::rc::Rc;
+
+	/// Writes `files` into a fresh directory under the system temp dir.
+	fn project(name: &str, files: &[(&str, &str)]) -> PathBuf
+	{
+		let root = std::env::temp_dir().join(format!("cii_modules_{}_{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&root);
+		for (path, source) in files
+		{
+			let path = root.join(path);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, source).unwrap();
+		}
+		root
+	}
+
+	#[test]
+	fn namespaced_modules_resolve_from_the_importing_file()
+	{
+		let root = project("namespace", &[
+			("main.pyx", "@reference(\"./lib/shapes\";;1)\nshapes.area(2, 3) + shapes.unit"),
+			("lib/shapes.pyx", "@reference(\"./scale\")\nvar unit::scale.factor;\nfunc area(w, h) { return w * h * unit! }"),
+			("lib/scale.pyx", "@reference @lib_jellybeans\nvar factor::sqrt(100);"),
+		]);
+		let mut engine = Engine::new();
+		assert_eq!(engine.eval_file(root.join("main.pyx")).unwrap(), Number(70.0));
+		assert_eq!(engine.eval("shapes.scale.factor").unwrap(), Number(10.0));
+		assert!(engine.eval("shapes.scale.sqrt").is_err());
+		assert_eq!(engine.get_global("unit"), None);
+		assert_eq!(engine.get_global("scale"), None);
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn modules_run_once()
+	{
+		let root = project("once", &[
+			("main.pyx", "@reference(\"./a\")\n@reference(\"./b\")\n@reference(\"./counter\";;c)\nc : a.counter"),
+			("a.pyx", "@reference(\"./counter\")\nvar counter::counter;"),
+			("b.pyx", "@reference(\"counter.pyx\")"),
+			("counter.pyx", "loaded()!\nvar value::1;"),
+		]);
+		let loads = Rc::new(Cell::new(0));
+		let seen = loads.clone();
+		let mut engine = Engine::new();
+		engine.register_fn("loaded", move || seen.set(seen.get() + 1));
+		assert_eq!(engine.eval_file(root.join("main.pyx")).unwrap(), True);
+		assert_eq!(loads.get(), 1);
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn selective_and_full_imports()
+	{
+		let root = project("imports", &[
+			("math.pyx", "func add(a, b) { return a + b! }\nfunc sub(a, b) { return a - b! }\nvar pi::3;"),
+		]);
+		let file = root.join("math.pyx");
+		let path = file.display().to_string();
+
+		let mut engine = Engine::new();
+		engine.eval(&format!("@reference(\"{}\")::@--I++{{@add, pi}}", path)).unwrap();
+		assert_eq!(engine.eval("add(pi, 1)").unwrap(), Number(4.0));
+		assert_eq!(engine.get_global("sub"), None);
+		assert_eq!(engine.get_global("math"), None);
+
+		let mut engine = Engine::new();
+		engine.eval(&format!("@reference(\"{}\")::@--I++<", path)).unwrap();
+		assert_eq!(engine.eval("sub(add(1, 2), pi)").unwrap(), Number(0.0));
+
+		let mut engine = Engine::new();
+		let err = engine.eval(&format!("@reference(\"{}\")::@--I++{{@mul}}", path)).unwrap_err();
+		let column = path.chars().count() + 24;
+		assert_eq!(err.to_string(), format!("math has no export 'mul' at line 1, column {}", column));
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn selective_library_imports()
+	{
+		let mut engine = Engine::new();
+		engine.eval("@reference @lib_jellybeans::@--I++{@sqrt}").unwrap();
+		assert_eq!(engine.eval("sqrt(9)").unwrap(), Number(3.0));
+		assert_eq!(engine.get_global("lerp"), None);
+		assert!(engine.eval("@reference @lib_halcyon::@--I++{@memcpy}").is_err());
+		assert!(engine.eval("@reference @lib_halcyon::@--I+<").is_err());
+	}
+
+	#[test]
+	fn cycles_report_the_chain()
+	{
+		let root = project("cycle", &[
+			("main.pyx", "@reference(\"./a\")"),
+			("a.pyx", "@reference(\"./b\")"),
+			("b.pyx", "@reference(\"./a\")"),
+		]);
+		let mut engine = Engine::new();
+		let err = engine.eval_file(root.join("main.pyx")).unwrap_err().to_string();
+		let root = root.canonicalize().unwrap();
+		let (a, b) = (root.join("a.pyx").display().to_string(), root.join("b.pyx").display().to_string());
+		assert!(err.contains(&format!("Circular<j reference: {} -> {} -> {}", a, b, a)), "{}", err);
+		let diagnostics = Engine::new().eval_file(root.join("main.pyx")).unwrap_err().diagnostics();
+		assert_eq!(sources::get(diagnostics[0].span().file).unwrap().path, root.join("b.pyx"));
+		assert_eq!(diagnostics[0].labels.len(), 3);
+
+		fs::write(root.join("main.pyx"), "@reference(\"./main\")").unwrap();
+		let err = engine.eval_file(root.join("main.pyx")).unwrap_err().to_string();
+		assert!(err.contains("Circular<j reference"), "{}", err);
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn missing_modules_and_module_errors()
+	{
+		let root = project("errors", &[
+			("main.pyx", "@reference(\"./broken\")"),
+			("broken.pyx", "var x::missing;"),
+		]);
+		let mut engine = Engine::new();
+		let err = engine.eval_file(root.join("main.pyx")).unwrap_err();
+		assert!(err.to_string().starts_with("Undefined<j variable 'missing' at line 1, column 8"), "{}", err);
+		let diagnostic = &err.diagnostics()[0];
+		assert_eq!(sources::get(diagnostic.span().file).unwrap().path, root.canonicalize().unwrap().join("broken.pyx"));
+		assert_eq!(diagnostic.labels[1].message, "in the module referenced here");
+		let err = engine.eval_file(root.join("absent.pyx")).unwrap_err().to_string();
+		assert!(err.starts_with("Could not read"), "{}", err);
+		fs::write(root.join("main.pyx"), "@reference(\"./nowhere\")").unwrap();
+		let err = engine.eval_file(root.join("main.pyx")).unwrap_err().to_string();
+		assert!(err.starts_with("Cannot find module \"./nowhere\", looked in:"), "{}", err);
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn search_paths_and_packages()
+	{
+		let root = project("search", &[
+			("game/main.pyx", "@reference(\"collections\")\n@reference(\"strings\")\n@reference(\"local\")\ncollections.first([4, 5]) + strings.tag + local.value"),
+			("game/local.pyx", "var value::100;"),
+			("shared/strings.pyx", "var tag::1;"),
+			("vendor/strings.pyx", "var tag::2;"),
+			("vendor/local.pyx", "var value::200;"),
+			("vendor/collections/lib.pyx", "@reference(\"./lists\")::@--I++<"),
+			("vendor/collections/lists.pyx", "func first(items) { return items[0]! }"),
+		]);
+		let mut engine = Engine::new();
+		engine.add_search_path(root.join("shared"));
+		engine.add_search_path(root.join("vendor"));
+		assert_eq!(engine.search_paths(), vec![root.join("shared"), root.join("vendor")]);
+		assert_eq!(engine.eval_file(root.join("game/main.pyx")).unwrap(), Number(105.0));
+		fs::remove_dir_all(root).unwrap();
+	}
+
+	#[test]
+	fn missing_modules_list_every_location()
+	{
+		let root = project("tried", &[("main.pyx", "\n@reference(\"physics\")")]);
+		let mut engine = Engine::new();
+		engine.add_search_path(root.join("libs"));
+		let err = engine.eval_file(root.join("main.pyx")).unwrap_err().to_string();
+		let root = root.canonicalize().unwrap();
+		let expected = format!("Cannot find module \"physics\", looked in:\n  {}\n  {}\n  {}\n  {} at line 2, column 12",
+			root.join("physics.pyx").display(), root.join("physics").join("lib.pyx").display(),
+			root.join("libs").join("physics.pyx").display(), root.join("libs").join("physics").join("lib.pyx").display());
+		assert_eq!(err, expected);
+
+		let err = engine.eval("@reference(\"./physics\")").unwrap_err().to_string();
+		assert_eq!(err.matches("\n  ").count(), 2, "{}", err);
+		fs::remove_dir_all(root).unwrap();
+	}
+}
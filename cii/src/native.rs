@@ -0,0 +1,276 @@
+// Exposing Rust closures to scripts. Any `Fn` whose arguments implement
+// `FromValue` and whose result implements `IntoValue` (or is a `Result` of
+// one) can be registered; arguments are converted and counted before the
+// closure runs, and an `Err` becomes a runtime error in the calling script,
+// except an `ErrorValue`, which the script gets back as a value.
+use crate::convert::{FromValue, IntoValue};
+use crate::error::{ErrorValue, RuntimeError};
+use crate::expr::{CallableImpl, ModuleImpl, NativeFunctionImpl, StractValue};
+use crate::gc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::any::Any;
+use std::fmt::Display;
+use std::rc::Rc;
+
+/// The result of a native function: a plain value, or a `Result` whose
+/// error is reported to the script. A `RuntimeError`, such as one from
+/// calling a script function, passes through unchanged, an `ErrorValue` is
+/// returned to the script for it to handle, and any other error becomes its
+/// message.
+pub trait IntoNativeResult {
+	fn into_native_result(self) -> Result<StractValue, RuntimeError>;
+}
+
+impl<T: IntoValue> IntoNativeResult for T {
+	fn into_native_result(self) -> Result<StractValue, RuntimeError> {
+		Ok(self.into_value())
+	}
+}
+
+impl<T: IntoValue, E: Display + 'static> IntoNativeResult for Result<T, E> {
+	fn into_native_result(self) -> Result<StractValue, RuntimeError> {
+		let error = match self
+		{
+			Ok(value) => return Ok(value.into_value()),
+			Err(error) => Box::new(error) as Box<dyn Any>,
+		};
+		let error = match error.downcast::<ErrorValue>()
+		{
+			Ok(error) => return Ok((*error).into_value()),
+			Err(error) => error,
+		};
+		match error.downcast::<RuntimeError>()
+		{
+			Ok(error) => Err(*error),
+			Err(other) => Err(RuntimeError::from(other.downcast_ref::<E>().map(E::to_string).unwrap_or_default())),
+		}
+	}
+}
+
+/// A Rust closure that can become a script function. `Args` is the tuple of
+/// its argument types and only serves to tell the implementations apart.
+pub trait IntoNative<Args> {
+	fn into_native(self, name: &str) -> NativeFunctionImpl;
+}
+
+macro_rules! count {
+	() => { 0 };
+	($head:ident $($tail:ident)*) => { 1 + count!($($tail)*) };
+}
+
+macro_rules! impl_into_native {
+	($($arg:ident),*) => {
+		impl<F, R, $($arg,)*> IntoNative<($($arg,)*)> for F
+		where
+			F: Fn($($arg),*) -> R + 'static,
+			R: IntoNativeResult,
+			$($arg: FromValue,)*
+		{
+			#[allow(non_snake_case, unused_mut, unused_variables, unused_assignments)]
+			fn into_native(self, name: &str) -> NativeFunctionImpl {
+				let label = name.to_string();
+				NativeFunctionImpl::new(name, count!($($arg)*), Rc::new(move |args: &[StractValue]| {
+					let mut position = 0;
+					$(
+						let $arg = <$arg as FromValue>::from_value(&args[position])
+							.map_err(|e| RuntimeError::type_mismatch(format!("{}() argument {}: {}", label, position + 1, e)))?;
+						position += 1;
+					)*
+					(self)($($arg),*).into_native_result()
+				}))
+			}
+		}
+	};
+}
+
+impl_into_native!();
+impl_into_native!(A);
+impl_into_native!(A, B);
+impl_into_native!(A, B, C);
+impl_into_native!(A, B, C, D);
+impl_into_native!(A, B, C, D, E);
+impl_into_native!(A, B, C, D, E, G);
+
+/// Wraps a closure taking the raw argument list, for functions that accept
+/// any number of arguments.
+pub fn variadic<F, R>(name: &str, fun: F) -> NativeFunctionImpl
+where
+	F: Fn(&[StractValue]) -> R + 'static,
+	R: IntoNativeResult,
+{
+	NativeFunctionImpl::variadic(name, Rc::new(move |args: &[StractValue]| fun(args).into_native_result()))
+}
+
+/// A named group of native functions and constants, reachable from scripts
+/// as `name.member`, such as `keyboard.press("a")`.
+pub struct NativeModule {
+	name: String,
+	members: HashMap<String, StractValue>,
+}
+
+impl NativeModule {
+	pub fn new(name: &str) -> Self {
+		Self {
+			name: name.to_string(),
+			members: HashMap::new(),
+		}
+	}
+
+	pub fn function<Args>(mut self, name: &str, fun: impl IntoNative<Args>) -> Self {
+		let native = fun.into_native(&format!("{}.{}", self.name, name));
+		self.members.insert(name.to_string(), StractValue::Callable(CallableImpl::NativeFunction(native)));
+		self
+	}
+
+	pub fn variadic<F, R>(mut self, name: &str, fun: F) -> Self
+	where
+		F: Fn(&[StractValue]) -> R + 'static,
+		R: IntoNativeResult,
+	{
+		let native = variadic(&format!("{}.{}", self.name, name), fun);
+		self.members.insert(name.to_string(), StractValue::Callable(CallableImpl::NativeFunction(native)));
+		self
+	}
+
+	pub fn constant(mut self, name: &str, value: impl IntoValue) -> Self {
+		self.members.insert(name.to_string(), value.into_value());
+		self
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn into_value(self) -> StractValue {
+		StractValue::Module(gc::alloc(ModuleImpl {
+			name: self.name,
+			members: RefCell::new(self.members),
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::engine::{Engine, EngineError};
+	use crate::expr::StractValue::*;
+
+	fn runtime_error(result: Result<StractValue, EngineError>) -> String
+	{
+		match result
+		{
+			Err(EngineError::Runtime(error)) => error.to_string(),
+			other => panic!("Expected a runtime error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn closures_capture_host_state()
+	{
+		let played = Rc::new(RefCell::new(vec![]));
+		let log = played.clone();
+		let mut engine = Engine::new();
+		engine.register_fn("play", move |sound: String| log.borrow_mut().push(sound));
+		engine.eval(r#"play("idle.ogg")!
+play("walking.ogg")"#).unwrap();
+		assert_eq!(*played.borrow(), vec!["idle.ogg", "walking.ogg"]);
+	}
+
+	#[test]
+	fn arguments_are_converted()
+	{
+		let mut engine = Engine::new();
+		engine.register_fn("scale", |x: f64, times: i64| x * times as f64);
+		engine.register_fn("pick", |flag: bool, yes: String, no: String| if flag { yes } else { no });
+		engine.register_fn("total", |items: Vec<i64>| items.iter().sum::<i64>());
+		engine.register_fn("shout", |c: char| c.to_ascii_uppercase());
+		engine.register_fn("tag", |value: StractValue| value.to_type().to_string());
+		engine.register_fn("nothing", || ());
+		assert_eq!(engine.eval("scale(1.5, 4)").unwrap(), Number(6.0));
+		assert_eq!(engine.eval(r#"pick(false, "a", "b")"#).unwrap(), StringValue("b".to_string()));
+		assert_eq!(engine.eval("total([1, 2, 3])").unwrap(), Number(6.0));
+		assert_eq!(engine.eval("shout('q')").unwrap(), Char('Q'));
+		assert_eq!(engine.eval("tag(Vector2(1, 2))").unwrap(), StringValue("Vector2".to_string()));
+		assert_eq!(engine.eval("nothing()").unwrap(), Nil);
+	}
+
+	#[test]
+	fn bad_arguments_are_runtime_errors()
+	{
+		let mut engine = Engine::new();
+		engine.register_fn("scale", |x: f64, times: i64| x * times as f64);
+		engine.register_fn("total", |items: Vec<i64>| items.iter().sum::<i64>());
+		assert!(runtime_error(engine.eval("scale(1)")).contains("expects 2 arguments but got 1"));
+		let msg = runtime_error(engine.eval(r#"scale("1", 2)"#));
+		assert!(msg.contains("scale() argument 1: expected Number, got String"), "{}", msg);
+		let msg = runtime_error(engine.eval("scale(1, 2.5)"));
+		assert!(msg.contains("argument 2: expected a whole Number"), "{}", msg);
+		let msg = runtime_error(engine.eval("total([1, nil])"));
+		assert!(msg.contains("item 1: expected Number, got nil"), "{}", msg);
+	}
+
+	#[test]
+	fn errors_propagate_into_the_script()
+	{
+		let mut engine = Engine::new();
+		engine.register_fn("load", |path: String| -> Result<String, String> {
+			if path.ends_with(".ogg") { Ok(path) } else { Err(format!("cannot load {}", path)) }
+		});
+		assert_eq!(engine.eval(r#"load("a.ogg")"#).unwrap(), StringValue("a.ogg".to_string()));
+		assert_eq!(runtime_error(engine.eval(r#"var x::load("a.txt");"#)), "cannot load a.txt at line 1, column 8");
+		assert_eq!(engine.get_global("x"), None);
+	}
+
+	#[test]
+	fn error_values_are_returned_to_the_script()
+	{
+		let mut engine = Engine::new();
+		engine.register_fn("read", |path: String| -> Result<String, ErrorValue> {
+			match path.as_str()
+			{
+				"save.txt" => Ok("level 3".to_string()),
+				_ => Err(ErrorValue::new(format!("{} not found", path)).with_code("NotFound")),
+			}
+		});
+		assert_eq!(engine.eval(r#"read("save.txt")"#).unwrap(), StringValue("level 3".to_string()));
+		engine.eval(r#"var missing::read("other.txt");"#).unwrap();
+		assert_eq!(engine.eval("is_error(missing)").unwrap(), True);
+		assert_eq!(engine.eval(r#""{missing.code}: {missing.message}""#).unwrap(), StringValue("NotFound: other.txt not found".to_string()));
+		assert_eq!(engine.eval(r#"unwrap_or(read("other.txt"), "level 1")"#).unwrap(), StringValue("level 1".to_string()));
+
+		// `?` hands the error value on to the caller.
+		engine.eval("func load(path) {\n\tvar text::read(path)?;\n\treturn \"loaded {text}\"!\n}").unwrap();
+		assert_eq!(engine.eval(r#"load("save.txt")"#).unwrap(), StringValue("loaded level 3".to_string()));
+		assert_eq!(engine.eval(r#"is_error(load("other.txt"))"#).unwrap(), True);
+		assert_eq!(runtime_error(engine.eval(r#"expect(load("other.txt"), "no save")"#)), "no save: other.txt not found at line 1, column 1");
+	}
+
+	#[test]
+	fn variadic_functions()
+	{
+		let mut engine = Engine::new();
+		engine.register_variadic("count", |args: &[StractValue]| args.len() as i64);
+		assert_eq!(engine.eval("count()").unwrap(), Number(0.0));
+		assert_eq!(engine.eval("count(1, \"two\", [3])").unwrap(), Number(3.0));
+	}
+
+	#[test]
+	fn native_modules()
+	{
+		let pressed = Rc::new(RefCell::new(String::from("a")));
+		let key = pressed.clone();
+		let mut engine = Engine::new();
+		engine.register_module(NativeModule::new("keyboard")
+			.function("press", move |name: String| *key.borrow() == name)
+			.variadic("any", |args: &[StractValue]| !args.is_empty())
+			.constant("layout", "qwerty"));
+		assert_eq!(engine.eval(r#"keyboard.press("a")"#).unwrap(), True);
+		assert_eq!(engine.eval(r#"keyboard.press("d")"#).unwrap(), False);
+		assert_eq!(engine.eval("keyboard.any(1)").unwrap(), True);
+		assert_eq!(engine.eval("keyboard.layout").unwrap(), StringValue("qwerty".to_string()));
+		let msg = runtime_error(engine.eval("keyboard.press(1)"));
+		assert!(msg.starts_with("keyboard.press() argument 1"), "{}", msg);
+		assert!(engine.eval("keyboard.release").is_err());
+	}
+}
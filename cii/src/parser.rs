@@ -1,11 +1,18 @@
+use crate::diagnostic::Diagnostic;
 use crate::expr::{Expr::*, Expr, StractValue};
 use crate::scanner::{Token, TokenType::*, TokenType};
-use crate::statement::Statement;
+use crate::span::Span;
+use crate::statement::{Handler, Import, ReferenceTarget, Statement};
+use crate::hooks::Hook;
+use std::rc::Rc;
 
 pub struct Parser
 {
 	tokens: Vec<Token>,
 	current: usize,
+	/// How many loops enclose the statement being parsed, within the
+	/// current function, so `break` can be checked.
+	loops: usize,
 }
 
 impl Parser
@@ -14,12 +21,14 @@ impl Parser
 	{
 		Self
 		{
-			tokens: tokens,
+			tokens,
 			current: 0,
+			loops: 0,
 		}
 	}
 
-	pub fn parse(&mut self) -> Result<Vec<Statement>, String> {
+	/// The statements of the script, or everything wrong with it.
+	pub fn parse(&mut self) -> Result<Vec<Statement>, Vec<Diagnostic>> {
 		let mut statements = vec![];
 		let mut errs = vec![];
 
@@ -33,58 +42,343 @@ impl Parser
 			}
 		}
 		
-		if errs.len() == 0 {
+		if errs.is_empty() {
 			Ok(statements)
 		}
 		else
 		{
-			Err(errs.join("\n"))
+			Err(errs)
 		}
 	}
 
-	fn declaration(&mut self) -> Result<Statement, String>
+	fn declaration(&mut self) -> Result<Statement, Diagnostic>
 	{
-		if self.match_token(Var)
+		let result = if self.match_token(&Var)
 		{
-			match self.var_declaration() {
-				Ok(statement) => Ok(statement),
-				Err(msg) => {
-					self.synchronize();
-					Err(msg)
+			self.var_declaration()
+		}
+		else if self.match_token(&Func)
+		{
+			self.function_declaration()
+		}
+		else if self.match_token(&Defi)
+		{
+			self.define_declaration()
+		}
+		else if self.match_token(&Class)
+		{
+			self.class_declaration()
+		}
+		else if self.check_annotation("@reference")
+		{
+			self.advance();
+			self.reference_declaration()
+		}
+		else
+		{
+			self.statement()
+		};
+		if result.is_err()
+		{
+			self.synchronize();
+		}
+		result
+	}
+
+	fn check_annotation(&self, name: &str) -> bool
+	{
+		let token = self.peek();
+		token.token_type == Annotation && token.panoll == name
+	}
+
+	fn function_declaration(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let start = self.previous().span;
+		let name = self.consume(Identifier, "Expected function name after 'func'")?;
+		let (params, body) = self.function_rest()?;
+		Ok(Statement::Function {
+			name,
+			params,
+			body,
+			span: self.span_from(start),
+		})
+	}
+
+	/// Parses `defi hook(params) { ... }`, checking that the hook exists and
+	/// takes the parameters its host passes.
+	fn define_declaration(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let start = self.previous().span;
+		let name = self.consume(Identifier, "Expected a hook name such as main after 'defi'")?;
+		let hook = match Hook::from_name(&name.panoll)
+		{
+			Some(hook) => hook,
+			None => {
+				let names: Vec<&str> = Hook::ALL.iter().map(|hook| hook.name()).collect();
+				return Err(Diagnostic::error(format!("Unknown<j define '{}'", name.panoll)).with_code("PS0103")
+					.with_label(name.span, "")
+					.with_help(format!("expected one of {}", names.join(", "))));
+			}
+		};
+		let (params, body) = self.function_rest()?;
+		hook.check_params(params.len()).map_err(|e| Diagnostic::error(e).with_code("PS0104").with_label(name.span, ""))?;
+		Ok(Statement::Define {
+			hook,
+			name,
+			params,
+			body,
+			span: self.span_from(start),
+		})
+	}
+
+	/// Parses `class Name { ... }`, whose body holds fields, methods and
+	/// signals only.
+	fn class_declaration(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let start = self.previous().span;
+		let name = self.consume(Identifier, "Expected class name after 'class'")?;
+		self.consume(LeftBrace, "Expected '{' before class body")?;
+		let mut body = vec![];
+		while self.peek().token_type != RightBrace && !self.is_at_end()
+		{
+			let member = if self.match_token(&Var)
+			{
+				self.var_declaration()?
+			}
+			else if self.match_token(&Func)
+			{
+				self.function_declaration()?
+			}
+			else if self.match_token(&Signal)
+			{
+				self.signal_declaration()?
+			}
+			else
+			{
+				return Err(Diagnostic::error(format!("Expected var, func or signal in class {}", name.panoll)).with_code("PS0105")
+					.with_label(self.peek().span, "")
+					.with_secondary(name.span, "in this class"));
+			};
+			body.push(member);
+		}
+		self.consume(RightBrace, "Expected '}' after class body")?;
+		Ok(Statement::Class {
+			name,
+			body: Rc::new(body),
+			span: self.span_from(start),
+		})
+	}
+
+	/// Parses `signal name(params);` inside a class body.
+	fn signal_declaration(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let start = self.previous().span;
+		let name = self.consume(Identifier, "Expected signal name after 'signal'")?;
+		let params = self.parameters()?;
+		self.consume(Semicolon, "Expect end of line declaration [!]")?;
+		Ok(Statement::Signal {
+			name,
+			params,
+			span: self.span_from(start),
+		})
+	}
+
+	/// Parses the parameter list and body that follow a function's name.
+	fn function_rest(&mut self) -> Result<(Vec<Token>, Rc<Vec<Statement>>), Diagnostic>
+	{
+		let params = self.parameters()?;
+		self.consume(LeftBrace, "Expected '{' before function body")?;
+		let loops = std::mem::replace(&mut self.loops, 0);
+		let body = self.block();
+		self.loops = loops;
+		Ok((params, Rc::new(body?)))
+	}
+
+	fn parameters(&mut self) -> Result<Vec<Token>, Diagnostic>
+	{
+		self.consume(LeftParen, "Expected '(' after function name")?;
+		let mut params = vec![];
+		if self.peek().token_type != RightParen
+		{
+			loop
+			{
+				params.push(self.consume(Identifier, "Expected parameter name")?);
+				if !self.match_token(&Comma)
+				{
+					break;
+				}
+			}
+		}
+		self.consume(RightParen, "Expected ')' after parameters")?;
+		Ok(params)
+	}
+
+	/// Parses the declarations of a block whose `{` was already consumed.
+	fn block(&mut self) -> Result<Vec<Statement>, Diagnostic>
+	{
+		let opener = self.previous().span;
+		let mut statements = vec![];
+		while self.peek().token_type != RightBrace && !self.is_at_end()
+		{
+			if self.check_annotation("@reference")
+			{
+				return Err(Diagnostic::error("@reference must appear at the top level").with_code("PS0106").with_label(self.peek().span, "")
+					.with_help("move it to the top of the script"));
+			}
+			if self.peek().token_type == Defi
+			{
+				return Err(Diagnostic::error("defi must appear at the top level").with_code("PS0106").with_label(self.peek().span, "")
+					.with_help("hooks cannot be nested in functions or blocks"));
+			}
+			statements.push(self.declaration()?);
+		}
+		self.closing(RightBrace, "Expected '}' after block", opener)?;
+		Ok(statements)
+	}
+
+	fn reference_declaration(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let start = self.previous().span;
+		let target = if self.match_token(&LeftParen)
+		{
+			let path = self.consume(StringLat, "Expected a file path such as \"./lib\" in @reference(...)")?;
+			// ;; takes either the name to use the module under, or a reference
+			// number as in @reference("./lib";;1), which keeps the module's own name.
+			let alias = if self.match_token(&SemicolonEqual)
+			{
+				let alias = self.peek().clone();
+				let numbered = match alias.stract
+				{
+					Some(crate::scanner::StractValue::IntValue(_)) => true,
+					Some(crate::scanner::StractValue::FloatValue(value)) => value.fract() == 0.0,
+					_ => false,
+				};
+				if alias.token_type != Identifier && !numbered
+				{
+					return Err(Diagnostic::error("Expected a module name or reference number after ';;'")
+						.with_code("PS0107")
+						.with_label(alias.span, "a name or whole number goes here")
+						.with_note("';;' takes the name to use the module under, or a whole number that keeps the module's own name")
+						.with_help("write @reference(\"./lib\";;lib) to use the module as lib, or @reference(\"./lib\";;1)"));
 				}
+				self.advance();
+				if numbered { None } else { Some(alias) }
 			}
+			else
+			{
+				None
+			};
+			self.consume(RightParen, "Expected ')' after @reference path")?;
+			ReferenceTarget::File { path, alias }
 		}
 		else
 		{
-			self.statement()
+			ReferenceTarget::Library(self.consume(Annotation, "Expected a library such as @lib_jellybeans after @reference")?)
+		};
+
+		let import = if self.match_token(&Equal)
+		{
+			self.reference_import()?
+		}
+		else
+		{
+			match target
+			{
+				ReferenceTarget::Library(_) => Import::All,
+				ReferenceTarget::File { .. } => Import::Namespace,
+			}
+		};
+		Ok(Statement::Reference { target, import, span: self.span_from(start) })
+	}
+
+	/// Parses the header after `::`: `@--I++<` for every export, or
+	/// `@--I++{@a, @b}` for the listed ones.
+	fn reference_import(&mut self) -> Result<Import, Diagnostic>
+	{
+		let header = self.consume(Annotation, "Expected @--I++< or @--I++{...} after '::'")?;
+		match header.panoll.as_str()
+		{
+			"@--I++<" => Ok(Import::All),
+			"@--I++" => {
+				self.consume(LeftBrace, "Expected '{' after @--I++")?;
+				let mut names = vec![];
+				loop
+				{
+					let name = self.advance().clone();
+					match name.token_type
+					{
+						Annotation | Identifier => names.push(name),
+						_ => return Err(Diagnostic::error("Expected a name such as @memcpy").with_code("PS0107").with_label(name.span, "")),
+					}
+					if !self.match_token(&Comma)
+					{
+						break;
+					}
+				}
+				self.consume(RightBrace, "Expected '}' after imported names")?;
+				Ok(Import::Only(names))
+			}
+			other => Err(Diagnostic::error(format!("Unsupported<j header {}", other)).with_code("PS0107")
+				.with_label(header.span, "")
+				.with_help("use @--I++< to import everything or @--I++{...} to pick names")),
 		}
 	}
 
-	fn var_declaration(&mut self) -> Result<Statement, String>
+	fn var_declaration(&mut self) -> Result<Statement, Diagnostic>
 	{
+		let start = self.previous().span;
 		let token = self.consume(Identifier, "Expected Variable name")?;
-		let initializer;
-		if self.match_token(Equal)
+		let initializer = if self.match_token(&Equal)
 		{
-			initializer = self.expression()?;
+			self.expression()?
 		}
 		else
 		{
-			initializer = Lateral { value: StractValue::Nil };
-		}
+			Lateral { value: StractValue::Nil, span: token.span }
+		};
 		self.consume(Semicolon, "Expect end of line declaration [!]")?;
 		Ok(Statement::Var {
 			name: token,
-			initializer: initializer,
+			initializer,
+			span: self.span_from(start),
 		})
 	}
 
-	fn statement(&mut self) -> Result<Statement, String>
+	fn statement(&mut self) -> Result<Statement, Diagnostic>
 	{
 		if self.match_token(&Print)
 		{
 			self.print_statement()
 		}
+		else if self.match_token(&Return)
+		{
+			self.return_statement()
+		}
+		else if self.match_token(&If)
+		{
+			self.if_statement()
+		}
+		else if self.match_token(&While)
+		{
+			self.while_statement()
+		}
+		else if self.match_token(&Try)
+		{
+			self.try_statement()
+		}
+		else if self.match_token(&Throw)
+		{
+			self.throw_statement()
+		}
+		else if self.match_token(&Break)
+		{
+			self.break_statement()
+		}
+		else if self.match_token(&LeftBrace)
+		{
+			let start = self.previous().span;
+			Ok(Statement::Block { statements: self.block()?, span: self.span_from(start) })
+		}
 		else
 		{
 			self.expression_statement()
@@ -92,46 +386,256 @@ impl Parser
 	}
 
 
-	fn print_statement(&mut self) -> Result<Statement, String>
+	fn if_statement(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let start = self.previous().span;
+		let paren = self.consume(LeftParen, "Expected '(' after 'if'")?;
+		let condition = self.expression()?;
+		self.closing(RightParen, "Expected ')' after if condition", paren.span)?;
+		let then_branch = self.statement()?;
+		let else_branch = if self.match_token(&Else)
+		{
+			Some(Box::from(self.statement()?))
+		}
+		else
+		{
+			None
+		};
+		Ok(Statement::If {
+			condition,
+			then_branch: Box::from(then_branch),
+			else_branch,
+			span: self.span_from(start),
+		})
+	}
+
+	fn while_statement(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let start = self.previous().span;
+		let paren = self.consume(LeftParen, "Expected '(' after 'while'")?;
+		let condition = self.expression()?;
+		self.closing(RightParen, "Expected ')' after while condition", paren.span)?;
+		self.loops += 1;
+		let body = self.statement();
+		self.loops -= 1;
+		Ok(Statement::While {
+			condition,
+			body: Box::from(body?),
+			span: self.span_from(start),
+		})
+	}
+
+	/// Parses `try { ... }` followed by any number of `catch (e) { ... }` or
+	/// `catch (e: Type) { ... }` clauses and an optional `finally { ... }`.
+	fn try_statement(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let keyword = self.previous().clone();
+		self.consume(LeftBrace, "Expected '{' after 'try'")?;
+		let body = self.block()?;
+		let mut handlers = vec![];
+		while self.match_token(&Catch)
+		{
+			self.consume(LeftParen, "Expected '(' after 'catch'")?;
+			let name = self.consume(Identifier, "Expected a name for the caught error")?;
+			let error_type = if self.match_token(&EqualEqual)
+			{
+				Some(self.consume(Identifier, "Expected an error type after ':'")?)
+			}
+			else
+			{
+				None
+			};
+			self.consume(RightParen, "Expected ')' after catch clause")?;
+			self.consume(LeftBrace, "Expected '{' before catch body")?;
+			handlers.push(Handler {
+				name,
+				error_type,
+				body: self.block()?,
+			});
+		}
+		let finally = if self.match_token(&Finally)
+		{
+			self.consume(LeftBrace, "Expected '{' after 'finally'")?;
+			Some(self.block()?)
+		}
+		else
+		{
+			None
+		};
+		if handlers.is_empty() && finally.is_none()
+		{
+			return Err(Diagnostic::error("Expected catch or finally after try block").with_code("PS0108").with_label(keyword.span, ""));
+		}
+		Ok(Statement::Try {
+			span: self.span_from(keyword.span),
+			keyword,
+			body,
+			handlers,
+			finally,
+		})
+	}
+
+	fn throw_statement(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let keyword = self.previous().clone();
+		let value = self.expression()?;
+		self.consume(Bang, "Expected end of line statement [!]")?;
+		Ok(Statement::Throw {
+			span: self.span_from(keyword.span),
+			keyword,
+			value,
+		})
+	}
+
+	/// `break!`, or `break?` as the example scripts spell it. A break has no
+	/// error to pass on, so `?` only ends the statement, like `!`.
+	fn break_statement(&mut self) -> Result<Statement, Diagnostic>
+	{
+		let keyword = self.previous().clone();
+		if self.loops == 0
+		{
+			return Err(Diagnostic::error("Cannot break outside a loop").with_code("PS0109").with_label(keyword.span, ""));
+		}
+		if !self.match_token(&Question)
+		{
+			self.consume(Bang, "Expected end of line statement [!]")?;
+		}
+		Ok(Statement::Break { span: self.span_from(keyword.span), keyword })
+	}
+
+	fn print_statement(&mut self) -> Result<Statement, Diagnostic>
 	{
+		let start = self.previous().span;
 		let value = self.expression()?;
 		self.consume(Bang, "Expected end of line statement [!]")?;
 		Ok(Statement::Print {
-			expression: value
+			expression: value,
+			span: self.span_from(start),
 		})
 	}
 
-	fn expression_statement(&mut self) -> Result<Statement, String>
+	fn return_statement(&mut self) -> Result<Statement, Diagnostic>
 	{
-		let expr = self.expression()?;
+		let keyword = self.previous().clone();
+		let value = if self.peek().token_type == Bang
+		{
+			Lateral { value: StractValue::Nil, span: keyword.span }
+		}
+		else
+		{
+			self.expression()?
+		};
 		self.consume(Bang, "Expected end of line statement [!]")?;
-		Ok(Statement::Expression {expression: expr})
+		Ok(Statement::Return {
+			span: self.span_from(keyword.span),
+			keyword,
+			value,
+		})
 	}
 
-	pub fn expression(&mut self)->Result<Expr, String>
+	/// The `!` may be left off the last expression of the source, so a
+	/// REPL line or an embedded snippet such as `1 + 2` parses on its own.
+	fn expression_statement(&mut self) -> Result<Statement, Diagnostic>
 	{
-		self.equality()
+		let expr = self.expression()?;
+		if !self.is_at_end()
+		{
+			self.consume(Bang, "Expected end of line statement [!]")?;
+		}
+		Ok(Statement::Expression { span: self.span_from(expr.span()), expression: expr })
+	}
+
+	pub fn expression(&mut self)->Result<Expr, Diagnostic>
+	{
+		self.assignment()
 	}
 
-	fn equality(&mut self)->Result<Expr, String>
+	fn assignment(&mut self)->Result<Expr, Diagnostic>
+	{
+		let expr = self.or()?;
+		if self.match_tokens(&[Equal, PlusEqual, MinusEqual, StarEqual, SlashEqual])
+		{
+			let equals = self.previous().clone();
+			let mut value = self.assignment()?;
+			// `x +:: y` is `x :: x + y`.
+			let operator = match equals.token_type
+			{
+				PlusEqual => Some((Plus, "+")),
+				MinusEqual => Some((Minus, "-")),
+				StarEqual => Some((Star, "*")),
+				SlashEqual => Some((Slash, "/")),
+				_ => None,
+			};
+			if let (Some((token_type, text)), Variable { .. } | Get { .. }) = (operator, &expr)
+			{
+				value = Binary {
+					left: Box::from(expr.clone()),
+					operator: Token::new(token_type, text.to_string(), None, equals.span),
+					right: Box::from(value),
+				};
+			}
+			return match expr
+			{
+				Variable { name } => Ok(Assign {
+					name,
+					value: Box::from(value),
+				}),
+				Get { object, name } => Ok(Set {
+					object,
+					name,
+					value: Box::from(value),
+				}),
+				target => Err(Diagnostic::error("Invalid assignment target").with_code("PS0102")
+					.with_label(equals.span, "")
+					.with_secondary(target.span(), "cannot be assigned to")),
+			};
+		}
+		Ok(expr)
+	}
+
+	fn or(&mut self)->Result<Expr, Diagnostic>
+	{
+		let mut expr = self.and()?;
+		while self.match_token(&Or)
+		{
+			let operator = self.previous().clone();
+			let rhs = self.and()?;
+			expr = Logical { left: Box::from(expr), operator, right: Box::from(rhs) };
+		}
+		Ok(expr)
+	}
+
+	fn and(&mut self)->Result<Expr, Diagnostic>
+	{
+		let mut expr = self.equality()?;
+		while self.match_token(&And)
+		{
+			let operator = self.previous().clone();
+			let rhs = self.equality()?;
+			expr = Logical { left: Box::from(expr), operator, right: Box::from(rhs) };
+		}
+		Ok(expr)
+	}
+
+	fn equality(&mut self)->Result<Expr, Diagnostic>
 	{
 		let mut expr = self.comparison()?;
 
 		while self.match_tokens(&[BangEqual, EqualEqual])
 		{
-			let operator = self.previous();
+			let operator = self.previous().clone();
 			let rhs = self.comparison()?;
-			expr = Binary { left: Box::from(expr), operator: operator, right: Box::from(rhs),};
+			expr = Binary { left: Box::from(expr), operator, right: Box::from(rhs),};
 		}
 		Ok(expr)
 	}
 
-	fn comparison(&mut self)->Result<Expr, String>
+	fn comparison(&mut self)->Result<Expr, Diagnostic>
 	{
 		let mut expr = self.term()?;
 		while self.match_tokens(&[Greater, GreaterEqual, Less, LessEqual])
 		{
-			let op = self.previous();
+			let op = self.previous().clone();
 			let rhs = self.term()?;
 			expr = Binary{
 				left: Box::from(expr),
@@ -142,12 +646,12 @@ impl Parser
 		Ok(expr)
 	}
 
-	fn term(&mut self)->Result<Expr, String>{
+	fn term(&mut self)->Result<Expr, Diagnostic>{
 		let mut expr = self.factor()?;
 
 		while self.match_tokens(&[Minus, Plus])
 		{
-			let op = self.previous();
+			let op = self.previous().clone();
 			let rhs = self.factor()?;
 			expr = Binary
 			{
@@ -159,12 +663,12 @@ impl Parser
 		Ok(expr)
 	}
 
-	fn factor(&mut self)->Result<Expr, String>
+	fn factor(&mut self)->Result<Expr, Diagnostic>
 	{
 		let mut expr = self.unary()?;
 		while self.match_tokens(&[Slash, Star])
 		{
-			let op = self.previous();
+			let op = self.previous().clone();
 			let rhs = self.unary()?;
 			expr = Binary{
 				left: Box::from(expr),
@@ -175,10 +679,10 @@ impl Parser
 		Ok(expr)
 	}
 
-	fn unary(&mut self)->Result<Expr, String>{
+	fn unary(&mut self)->Result<Expr, Diagnostic>{
 		if self.match_tokens(&[Bang, Minus])
 		{
-			let op = self.previous();
+			let op = self.previous().clone();
 			let rhs = self.unary()?;
 			Ok(Unary{
 				operator: op,
@@ -187,72 +691,173 @@ impl Parser
 		}
 		else
 		{
-			self.primary()
+			self.call()
+		}
+	}
+
+	fn call(&mut self)->Result<Expr, Diagnostic>
+	{
+		let mut expr = self.primary()?;
+		loop
+		{
+			if self.match_token(&LeftParen)
+			{
+				let opener = self.previous().span;
+				let arguments = self.arguments(RightParen)?;
+				let paren = self.closing(RightParen, "Expected ')' after arguments", opener)?;
+				expr = Call {
+					callee: Box::from(expr),
+					paren,
+					arguments,
+				};
+			}
+			else if self.match_token(&Dot)
+			{
+				let name = self.consume(Identifier, "Expected property name after '.'")?;
+				expr = Get {
+					object: Box::from(expr),
+					name,
+				};
+			}
+			else if self.match_token(&LeftBracket)
+			{
+				let opener = self.previous().span;
+				let index = self.expression()?;
+				let bracket = self.closing(RightBracket, "Expected ']' after index", opener)?;
+				expr = Index {
+					object: Box::from(expr),
+					bracket,
+					index: Box::from(index),
+				};
+			}
+			else if self.match_token(&Question)
+			{
+				expr = Propagate {
+					expression: Box::from(expr),
+					question: self.previous().clone(),
+				};
+			}
+			else
+			{
+				break;
+			}
+		}
+		Ok(expr)
+	}
+
+	/// Parses a comma separated list of expressions up to, but not
+	/// including, the `closing` token.
+	fn arguments(&mut self, closing: TokenType)->Result<Vec<Expr>, Diagnostic>
+	{
+		let mut arguments = vec![];
+		if self.peek().token_type != closing
+		{
+			loop
+			{
+				arguments.push(self.expression()?);
+				if !self.match_token(&Comma)
+				{
+					break;
+				}
+			}
 		}
+		Ok(arguments)
 	}
 
-	fn primary(&mut self)->Result<Expr, String>
+	fn primary(&mut self)->Result<Expr, Diagnostic>
 	{
 		let token = self.peek();
 
-		let result;
-		match token.token_type{
+		let result = match token.token_type{
 			LeftParen =>
 			{
+				let start = self.advance().span;
 				let expr = self.expression()?;
-				self.consume(RightParen, "Expected ')'")?;
-				result = Grouping {
+				self.closing(RightParen, "Expected ')'", start)?;
+				return Ok(Grouping {
 					expression: Box::from(expr),
-				}
+					span: self.span_from(start),
+				});
 			}
-			False | True | Nil | Null | Number | StringLat => { 
-				result = Lateral {
+			False | True | Nil | Null | Number | StringLat | CharLat => { 
+				Lateral {
 					value: StractValue::from_token(token.clone()),
+					span: token.span,
 				}
 			}
-			Var => {
-				self.advance();
-				result = Variable { name:self.previous() };
+			Identifier => {
+				Variable { name: token.clone() }
 			}
-			_ => return Err("Expected [decent] literal or expression".to_string()),
-		}
+			Interpolation => return self.interpolation(),
+			LeftBracket => {
+				let start = self.advance().span;
+				let items = self.arguments(RightBracket)?;
+				self.closing(RightBracket, "Expected ']' after list items", start)?;
+				return Ok(Expr::List { items, span: self.span_from(start) });
+			}
+			_ => return Err(Diagnostic::error("Expected [decent] literal or expression").with_code("PS0101").with_label(token.span, "")),
+		};
 
 		self.advance();
 		Ok(result)
+	}
 
-		//if self.match_token(LeftParen)
-		//{
-		//	let expr = self.expression()?;
-		//	self.consume(RightParen, "Expected ')'")?
-		//	Ok(Grouping {
-		//		expression: Box::from(expr),
-		//	})
-		//}
-		//else if self.match_token(false)
-		//{
-		//	let token = self.peek();
-		//	self.advance()
-		//	Ok(Lateral{
-		//		value: StractValue::from_token(token),
-		//	})
-		//}
-	}
-
-	fn consume(&mut self, token_type: TokenType, msg:&str) ->Result<Token, String>
+	fn interpolation(&mut self)->Result<Expr, Diagnostic>
 	{
-		let token = self.peek();
-		if token.token_type == token_type
+		let mut parts = vec![];
+		let start = self.peek().span;
+		loop
 		{
-			self.advance();
-			let token = self.previous();
-			Ok(token)
+			let token = self.advance().clone();
+			let is_last = token.token_type == StringLat;
+			let text = Lateral {
+				value: StractValue::StringValue(match &token.stract {
+					Some(crate::scanner::StractValue::StringValue(s)) => s.clone(),
+					_ => String::new(),
+				}),
+				span: token.span,
+			};
+			parts.push(text);
+			if is_last
+			{
+				break;
+			}
+			parts.push(self.expression()?);
+			let next = self.peek().token_type;
+			if next != Interpolation && next != StringLat
+			{
+				return Err(Diagnostic::error("Expected '}' to close interpolation").with_code("PS0110")
+					.with_label(self.peek().span, "")
+					.with_secondary(token.span, "the interpolation starts here"));
+			}
+		}
+		Ok(Expr::Interpolation { parts, span: self.span_from(start) })
+	}
+
+	fn consume(&mut self, token_type: TokenType, msg:&str) ->Result<Token, Diagnostic>
+	{
+		if self.peek().token_type == token_type
+		{
+			Ok(self.advance().clone())
 		}
 		else
 		{
-			Err(msg.to_string())
+			Err(Diagnostic::error(msg).with_code("PS0100").with_label(self.peek().span, ""))
 		}
 	}
 
+	/// Consumes the token that closes the bracket at `opener`.
+	fn closing(&mut self, token_type: TokenType, msg: &str, opener: Span) -> Result<Token, Diagnostic>
+	{
+		self.consume(token_type, msg).map_err(|error| error.with_secondary(opener, "to close this"))
+	}
+
+	/// The span from `start` to the end of the last token consumed.
+	fn span_from(&self, start: Span) -> Span
+	{
+		start.to(self.previous().span)
+	}
+
 	fn match_token(&mut self, typ: &TokenType)-> bool
 	{
 		if self.is_at_end()
@@ -285,7 +890,7 @@ impl Parser
 		false
 	}
 
-	fn advance(&mut self)-> Token
+	fn advance(&mut self)-> &Token
 	{
 		if !self.is_at_end()
 		{
@@ -294,17 +899,18 @@ impl Parser
 		self.previous()
 	}
 
-	fn peek(&mut self)-> Token
+	/// The next token. Tokens are only cloned when they go into the tree.
+	fn peek(&self)-> &Token
 	{
-		self.tokens[self.current].clone()
+		&self.tokens[self.current]
 	}
 
-	fn previous(&mut self)-> Token
+	fn previous(&self)-> &Token
 	{
-		self.tokens[self.current - 1].clone()
+		&self.tokens[self.current - 1]
 	}
 
-	fn is_at_end(&mut self)->bool
+	fn is_at_end(&self)->bool
 	{
 		self.peek().token_type == Eof
 	}
@@ -320,7 +926,7 @@ impl Parser
 			}
 			match self.peek().token_type
 			{
-				Class | Func | Var | For | If | While | Print | Return => return,
+				Class | Func | Defi | Var | For | If | While | Print | Return | Try | Throw | Break => return,
 				_ => (),
 			}
 			self.advance();
@@ -340,21 +946,27 @@ mod tests {
 			token_type: Number,
 			panoll: "1".to_string(),
 			stract: Some(IntValue(1)),
-			line_number: 0,
+			span: Span::default(),
 		};
 		let plus = Token{
 			token_type: Plus,
 			panoll: "+".to_string(),
 			stract: None,
-			line_number: 0,
+			span: Span::default(),
 		};
 		let two = Token{
 			token_type: Number,
 			panoll: "2".to_string(),
 			stract: Some(IntValue(2)),
-			line_number: 0,
+			span: Span::default(),
+		};
+		let eof = Token{
+			token_type: Eof,
+			panoll: "".to_string(),
+			stract: None,
+			span: Span::default(),
 		};
-		let tokens = vec![one, plus, two];
+		let tokens = vec![one, plus, two, eof];
 
 		let mut parser = Parser::new(tokens);
 		let parsed_expr = parser.expression().unwrap();
@@ -369,8 +981,132 @@ mod tests {
 		let mut scanner = Scanner::new(source);
 		let tokens = scanner.scan_tokens().unwrap();
 		let mut parser = Parser::new(tokens);
-		let parsed_expr = parser.parse().unwrap();
+		let parsed_expr = parser.expression().unwrap();
 		let string_expr = parsed_expr.to_string();
 		assert_eq!(string_expr, "(: (+ 1 2) (+ 5 7))");
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_interpolation()
+	{
+		let source = r#""a {1 + 2} b {"c"}""#;
+		let mut scanner = Scanner::new(source);
+		let tokens = scanner.scan_tokens().unwrap();
+		let mut parser = Parser::new(tokens);
+		let parsed_expr = parser.expression().unwrap();
+		assert_eq!(parsed_expr.to_string(), "(interpolate a  (+ 1 2)  b  c )");
+	}
+
+	#[test]
+	fn test_call_and_index()
+	{
+		let source = "f(1, [2, 3])[0]";
+		let mut scanner = Scanner::new(source);
+		let tokens = scanner.scan_tokens().unwrap();
+		let mut parser = Parser::new(tokens);
+		let parsed_expr = parser.expression().unwrap();
+		assert_eq!(parsed_expr.to_string(), "(index (call (var f) 1 [2 3]) 0)");
+	}
+
+	#[test]
+	fn test_property_assignment()
+	{
+		let source = "player.position :: a :: 1";
+		let mut scanner = Scanner::new(source);
+		let tokens = scanner.scan_tokens().unwrap();
+		let mut parser = Parser::new(tokens);
+		let parsed_expr = parser.expression().unwrap();
+		assert_eq!(parsed_expr.to_string(), "(set (var player) position (= a 1))");
+		let tokens = Scanner::new("f() :: 1").scan_tokens().unwrap();
+		assert!(Parser::new(tokens).expression().is_err());
+	}
+
+	#[test]
+	fn compound_assignment()
+	{
+		let parse = |source: &str| Parser::new(Scanner::new(source).scan_tokens().unwrap()).expression().map(|expr| expr.to_string());
+		assert_eq!(parse("speed +:: 2").unwrap(), "(= speed (+ (var speed) 2))");
+		assert_eq!(parse("velocity.x -:: speed * 2").unwrap(), "(set (var velocity) x (- (get (var velocity) x) (* (var speed) 2)))");
+		assert_eq!(parse("a /:: b *:: 3").unwrap(), "(= a (/ (var a) (= b (* (var b) 3))))");
+		assert!(parse("f() +:: 1").is_err());
+	}
+
+	#[test]
+	fn test_logical()
+	{
+		let tokens = Scanner::new("a or b and c : d").scan_tokens().unwrap();
+		let parsed_expr = Parser::new(tokens).expression().unwrap();
+		assert_eq!(parsed_expr.to_string(), "(or (var a) (and (var b) (: (var c) (var d))))");
+	}
+
+	fn parse_errors(source: &str) -> Vec<Diagnostic>
+	{
+		match Parser::new(Scanner::new(source).scan_tokens().unwrap()).parse()
+		{
+			Err(errors) => errors,
+			Ok(_) => panic!("Should have failed: {}", source),
+		}
+	}
+
+	#[test]
+	fn test_control_flow_errors()
+	{
+		let error = |source: &str| parse_errors(source)[0].to_string();
+		assert_eq!(error("if x { }"), "Expected '(' after 'if' at line 1, column 4");
+		assert_eq!(error("while (x { }"), "Expected ')' after while condition at line 1, column 10");
+		assert_eq!(error("while (x) { func f() { break! } }"), "Cannot break outside a loop at line 1, column 24");
+		assert_eq!(error("try { }\nx!"), "Expected catch or finally after try block at line 1, column 1");
+	}
+
+	#[test]
+	fn test_error_labels()
+	{
+		let source = "print (1 + [2, 3!\nvar x::1;";
+		let errors = parse_errors(source);
+		let labels: Vec<(&str, bool, &str)> = errors[0].labels.iter()
+			.map(|label| (label.span.text(source), label.primary, label.message.as_str()))
+			.collect();
+		assert_eq!(labels, vec![("!", true, ""), ("[", false, "to close this")]);
+
+		let errors = parse_errors("f() :: 1!");
+		assert_eq!(errors[0].labels[1].span.text("f() :: 1!"), "f()");
+		let errors = parse_errors("defi physics() { }");
+		assert_eq!(errors[0].to_string(), "Unknown<j define 'physics' at line 1, column 6\n  help: expected one of main, physic, proc, init, imide, notify, input, exput");
+
+		let errors = parse_errors("@reference(\"./lib_halcyon\";;\"x\")");
+		assert_eq!(errors[0].code, Some("PS0107"));
+		assert_eq!(errors[0].to_string(), "Expected a module name or reference number after ';;' at line 1, column 29\n  \
+note: ';;' takes the name to use the module under, or a whole number that keeps the module's own name\n  \
+help: write @reference(\"./lib\";;lib) to use the module as lib, or @reference(\"./lib\";;1)");
+		assert_eq!(parse_errors("@reference(\"./lib_halcyon\";;1.5)")[0].code, Some("PS0107"));
+
+		// Each bad statement is reported, not just the first.
+		assert_eq!(parse_errors("var ::1;\nvar y::(2;").len(), 2);
+	}
+
+	#[test]
+	fn test_spans()
+	{
+		let source = "var speed::(é + 1) * 2;\nif (speed) { print [speed]! }";
+		let statements = Parser::new(Scanner::new(source).scan_tokens().unwrap()).parse().unwrap();
+		let texts: Vec<&str> = statements.iter().map(|statement| statement.span().text(source)).collect();
+		assert_eq!(texts, vec!["var speed::(é + 1) * 2;", "if (speed) { print [speed]! }"]);
+		match &statements[0]
+		{
+			Statement::Var { initializer, .. } => {
+				assert_eq!(initializer.span().text(source), "(é + 1) * 2");
+				assert_eq!((initializer.span().line, initializer.span().column), (1, 12));
+			}
+			_ => panic!("Expected a var"),
+		}
+		assert_eq!(statements[1].span().line, 2);
+	}
+
+	#[test]
+	fn test_propagate()
+	{
+		let tokens = Scanner::new("load(path)?.size").scan_tokens().unwrap();
+		let parsed_expr = Parser::new(tokens).expression().unwrap();
+		assert_eq!(parsed_expr.to_string(), "(get (? (call (var load) (var path))) size)");
+	}
+}
@@ -0,0 +1,604 @@
+// The `.pyxc` file format: compiled bytecode saved so a script can start
+// without being scanned, parsed and compiled again.
+//
+// All numbers are little-endian. A file is the magic bytes `PYXC`, the
+// format version as a u16, a hash of the source it came from as a u64, and
+// then the script's top-level function. A function is written as
+//
+//     name          string (u32 length, then UTF-8)
+//     arity         u32
+//     cell count    u32
+//     captures      u32 count, then a u8 kind and a u16 index each
+//     code          u32 count, then a u8 opcode and its operand each
+//     spans         u32 count of runs, then a u32 start, end, line, column
+//                   and length each
+//     constants     u32 count, then a u8 kind and the value each
+//     functions     u32 count, then each nested function
+use crate::chunk::{Capture, Chunk, Function, Op};
+use crate::expr::StractValue;
+use crate::span::Span;
+use std::rc::Rc;
+
+pub const MAGIC: &[u8; 4] = b"PYXC";
+/// Raised whenever the layout or the meaning of an opcode changes.
+pub const FORMAT_VERSION: u16 = 3;
+
+/// A hash of the source text, stable across Rust versions and platforms
+/// (64-bit FNV-1a), to tell whether a compiled file is out of date.
+pub fn source_hash(source: &str) -> u64 {
+	source.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Writes `function`, compiled from source with the hash `source_hash`.
+pub fn serialize(function: &Function, source_hash: u64) -> Result<Vec<u8>, String> {
+	let mut writer = Writer { bytes: vec![] };
+	writer.bytes.extend_from_slice(MAGIC);
+	writer.u16(FORMAT_VERSION);
+	writer.u64(source_hash);
+	writer.function(function)?;
+	Ok(writer.bytes)
+}
+
+/// Reads a compiled file, returning the hash of its source and its code.
+pub fn deserialize(bytes: &[u8]) -> Result<(u64, Function), String> {
+	let mut reader = Reader { bytes, position: 0 };
+	if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..])
+	{
+		return Err("not a compiled PScript file".to_string());
+	}
+	let version = reader.u16()?;
+	if version != FORMAT_VERSION
+	{
+		return Err(format!("compiled with format version {}, but this cii reads version {}", version, FORMAT_VERSION));
+	}
+	let hash = reader.u64()?;
+	let function = reader.function()?;
+	if reader.position != bytes.len()
+	{
+		return Err(format!("{} unexpected bytes after the code", bytes.len() - reader.position));
+	}
+	Ok((hash, function))
+}
+
+struct Writer {
+	bytes: Vec<u8>,
+}
+
+impl Writer {
+	fn u8(&mut self, value: u8) {
+		self.bytes.push(value);
+	}
+
+	fn u16(&mut self, value: u16) {
+		self.bytes.extend_from_slice(&value.to_le_bytes());
+	}
+
+	fn u32(&mut self, value: usize) -> Result<(), String> {
+		let value = u32::try_from(value).map_err(|_| format!("{} is too large to save", value))?;
+		self.bytes.extend_from_slice(&value.to_le_bytes());
+		Ok(())
+	}
+
+	fn u64(&mut self, value: u64) {
+		self.bytes.extend_from_slice(&value.to_le_bytes());
+	}
+
+	fn string(&mut self, value: &str) -> Result<(), String> {
+		self.u32(value.len())?;
+		self.bytes.extend_from_slice(value.as_bytes());
+		Ok(())
+	}
+
+	fn function(&mut self, function: &Function) -> Result<(), String> {
+		self.string(&function.name)?;
+		self.u32(function.arity)?;
+		self.u32(function.cell_count)?;
+		self.u32(function.captures.len())?;
+		for capture in &function.captures
+		{
+			match capture
+			{
+				Capture::Cell(index) => { self.u8(0); self.u16(*index); }
+				Capture::Upvalue(index) => { self.u8(1); self.u16(*index); }
+			}
+		}
+
+		let chunk = &function.chunk;
+		self.u32(chunk.code.len())?;
+		for op in &chunk.code
+		{
+			self.op(*op);
+		}
+
+		let mut runs: Vec<(Span, usize)> = vec![];
+		for span in &chunk.spans
+		{
+			match runs.last_mut()
+			{
+				Some((last, length)) if last == span => *length += 1,
+				_ => runs.push((*span, 1)),
+			}
+		}
+		self.u32(runs.len())?;
+		for (span, length) in runs
+		{
+			self.u32(span.start)?;
+			self.u32(span.end)?;
+			self.u32(span.line)?;
+			self.u32(span.column)?;
+			self.u32(length)?;
+		}
+
+		self.u32(chunk.constants.len())?;
+		for constant in &chunk.constants
+		{
+			match constant
+			{
+				StractValue::Number(x) => { self.u8(0); self.u64(x.to_bits()); }
+				StractValue::StringValue(s) => { self.u8(1); self.string(s)?; }
+				StractValue::Char(c) => { self.u8(2); self.u32(*c as usize)?; }
+				StractValue::True => self.u8(3),
+				StractValue::False => self.u8(4),
+				StractValue::Nil => self.u8(5),
+				StractValue::Null => self.u8(6),
+				other => return Err(format!("Cannot save a {} constant", other.to_type())),
+			}
+		}
+
+		self.u32(chunk.functions.len())?;
+		for nested in &chunk.functions
+		{
+			self.function(nested)?;
+		}
+		Ok(())
+	}
+
+	fn op(&mut self, op: Op) {
+		let (code, operand) = match op
+		{
+			Op::Constant(i) => (0, Some(i)),
+			Op::Nil => (1, None),
+			Op::True => (2, None),
+			Op::False => (3, None),
+			Op::Pop => (4, None),
+			Op::GetLocal(i) => (5, Some(i)),
+			Op::SetLocal(i) => (6, Some(i)),
+			Op::GetGlobal(i) => (7, Some(i)),
+			Op::SetGlobal(i) => (8, Some(i)),
+			Op::DefineGlobal(i) => (9, Some(i)),
+			Op::NewCell(i) => (10, Some(i)),
+			Op::DefineCell(i) => (11, Some(i)),
+			Op::GetCell(i) => (12, Some(i)),
+			Op::SetCell(i) => (13, Some(i)),
+			Op::GetUpvalue(i) => (14, Some(i)),
+			Op::SetUpvalue(i) => (15, Some(i)),
+			Op::GetProperty(i) => (16, Some(i)),
+			Op::SetProperty(i) => (17, Some(i)),
+			Op::Index => (18, None),
+			Op::Add => (19, None),
+			Op::Subtract => (20, None),
+			Op::Multiply => (21, None),
+			Op::Divide => (22, None),
+			Op::Greater => (23, None),
+			Op::GreaterEqual => (24, None),
+			Op::Less => (25, None),
+			Op::LessEqual => (26, None),
+			Op::Equal => (27, None),
+			Op::NotEqual => (28, None),
+			Op::Negate => (29, None),
+			Op::Not => (30, None),
+			Op::Jump(i) => (31, Some(i)),
+			Op::JumpIfFalse(i) => (32, Some(i)),
+			Op::Call(count) => (33, Some(count as u16)),
+			Op::Closure(i) => (34, Some(i)),
+			Op::Return => (35, None),
+			Op::Print => (36, None),
+			Op::List(i) => (37, Some(i)),
+			Op::Interpolate(i) => (38, Some(i)),
+			Op::SetComponent(i) => (39, Some(i)),
+		};
+		self.u8(code);
+		if let Some(operand) = operand
+		{
+			self.u16(operand);
+		}
+	}
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	position: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+		match self.bytes.get(self.position..self.position + count)
+		{
+			Some(taken) => {
+				self.position += count;
+				Ok(taken)
+			}
+			None => Err("the file ends early".to_string()),
+		}
+	}
+
+	fn u8(&mut self) -> Result<u8, String> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, String> {
+		Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+	}
+
+	fn u32(&mut self) -> Result<usize, String> {
+		Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+	}
+
+	fn u64(&mut self) -> Result<u64, String> {
+		Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+	}
+
+	/// A count of items that each take at least one byte, checked against
+	/// what is left so a damaged count cannot allocate without bound.
+	fn count(&mut self) -> Result<usize, String> {
+		let count = self.u32()?;
+		match count <= self.bytes.len() - self.position
+		{
+			true => Ok(count),
+			false => Err("the file ends early".to_string()),
+		}
+	}
+
+	fn string(&mut self) -> Result<String, String> {
+		let length = self.u32()?;
+		let bytes = self.take(length)?;
+		String::from_utf8(bytes.to_vec()).map_err(|_| "a name is not valid UTF-8".to_string())
+	}
+
+	fn function(&mut self) -> Result<Function, String> {
+		let name = self.string()?;
+		let arity = self.u32()?;
+		let cell_count = self.u32()?;
+		let mut captures = vec![];
+		for _ in 0..self.count()?
+		{
+			captures.push(match (self.u8()?, self.u16()?)
+			{
+				(0, index) => Capture::Cell(index),
+				(1, index) => Capture::Upvalue(index),
+				(kind, _) => return Err(format!("unknown capture kind {}", kind)),
+			});
+		}
+
+		let mut chunk = Chunk::default();
+		for _ in 0..self.count()?
+		{
+			let op = self.op()?;
+			chunk.code.push(op);
+		}
+		for _ in 0..self.count()?
+		{
+			let span = Span::new(self.u32()?, self.u32()?, self.u32()?, self.u32()?);
+			let length = self.u32()?;
+			if chunk.spans.len() + length > chunk.code.len()
+			{
+				return Err(format!("the span table of {} is longer than its code", name));
+			}
+			chunk.spans.extend(std::iter::repeat_n(span, length));
+		}
+		if chunk.spans.len() != chunk.code.len()
+		{
+			return Err(format!("the span table of {} is shorter than its code", name));
+		}
+		for _ in 0..self.count()?
+		{
+			let constant = match self.u8()?
+			{
+				0 => StractValue::Number(f64::from_bits(self.u64()?)),
+				1 => StractValue::StringValue(self.string()?),
+				2 => {
+					let code = self.u32()?;
+					StractValue::Char(char::from_u32(code as u32).ok_or(format!("{} is not a valid Char", code))?)
+				}
+				3 => StractValue::True,
+				4 => StractValue::False,
+				5 => StractValue::Nil,
+				6 => StractValue::Null,
+				kind => return Err(format!("unknown constant kind {}", kind)),
+			};
+			chunk.constants.push(constant);
+		}
+		for _ in 0..self.count()?
+		{
+			let nested = self.function()?;
+			chunk.functions.push(Rc::new(nested));
+		}
+
+		let function = Function {
+			name,
+			arity,
+			chunk,
+			cell_count,
+			captures,
+		};
+		check(&function)?;
+		Ok(function)
+	}
+
+	fn op(&mut self) -> Result<Op, String> {
+		let code = self.u8()?;
+		let op = match code
+		{
+			0 => Op::Constant(self.u16()?),
+			1 => Op::Nil,
+			2 => Op::True,
+			3 => Op::False,
+			4 => Op::Pop,
+			5 => Op::GetLocal(self.u16()?),
+			6 => Op::SetLocal(self.u16()?),
+			7 => Op::GetGlobal(self.u16()?),
+			8 => Op::SetGlobal(self.u16()?),
+			9 => Op::DefineGlobal(self.u16()?),
+			10 => Op::NewCell(self.u16()?),
+			11 => Op::DefineCell(self.u16()?),
+			12 => Op::GetCell(self.u16()?),
+			13 => Op::SetCell(self.u16()?),
+			14 => Op::GetUpvalue(self.u16()?),
+			15 => Op::SetUpvalue(self.u16()?),
+			16 => Op::GetProperty(self.u16()?),
+			17 => Op::SetProperty(self.u16()?),
+			18 => Op::Index,
+			19 => Op::Add,
+			20 => Op::Subtract,
+			21 => Op::Multiply,
+			22 => Op::Divide,
+			23 => Op::Greater,
+			24 => Op::GreaterEqual,
+			25 => Op::Less,
+			26 => Op::LessEqual,
+			27 => Op::Equal,
+			28 => Op::NotEqual,
+			29 => Op::Negate,
+			30 => Op::Not,
+			31 => Op::Jump(self.u16()?),
+			32 => Op::JumpIfFalse(self.u16()?),
+			33 => {
+				let count = self.u16()?;
+				Op::Call(u8::try_from(count).map_err(|_| format!("a call with {} arguments", count))?)
+			}
+			34 => Op::Closure(self.u16()?),
+			35 => Op::Return,
+			36 => Op::Print,
+			37 => Op::List(self.u16()?),
+			38 => Op::Interpolate(self.u16()?),
+			39 => Op::SetComponent(self.u16()?),
+			other => return Err(format!("unknown opcode {}", other)),
+		};
+		Ok(op)
+	}
+}
+
+/// Checks that every operand points inside the function and that every
+/// instruction finds the values it uses on the stack, so a damaged file is
+/// rejected here rather than crashing the VM.
+fn check(function: &Function) -> Result<(), String> {
+	let chunk = &function.chunk;
+	let fail = |what: &str, index: u16| Err(format!("{} {} is out of range in {}", what, index, function.name));
+	if chunk.code.last() != Some(&Op::Return)
+	{
+		return Err(format!("{} does not end with a return", function.name));
+	}
+	for op in &chunk.code
+	{
+		match *op
+		{
+			Op::Constant(i) | Op::GetGlobal(i) | Op::SetGlobal(i) | Op::DefineGlobal(i)
+			| Op::GetProperty(i) | Op::SetProperty(i) | Op::SetComponent(i) if i as usize >= chunk.constants.len() => return fail("constant", i),
+			Op::GetGlobal(i) | Op::SetGlobal(i) | Op::DefineGlobal(i) | Op::GetProperty(i) | Op::SetProperty(i) | Op::SetComponent(i)
+				if !matches!(chunk.constants[i as usize], StractValue::StringValue(_)) => return fail("name", i),
+			Op::NewCell(i) | Op::DefineCell(i) | Op::GetCell(i) | Op::SetCell(i) if i as usize >= function.cell_count => return fail("cell", i),
+			Op::GetUpvalue(i) | Op::SetUpvalue(i) if i as usize >= function.captures.len() => return fail("upvalue", i),
+			Op::Jump(i) | Op::JumpIfFalse(i) if i as usize >= chunk.code.len() => return fail("jump target", i),
+			Op::Closure(i) if i as usize >= chunk.functions.len() => return fail("function", i),
+			_ => (),
+		}
+	}
+	for nested in &chunk.functions
+	{
+		for capture in &nested.captures
+		{
+			match *capture
+			{
+				Capture::Cell(i) if i as usize >= function.cell_count => return fail("captured cell", i),
+				Capture::Upvalue(i) if i as usize >= function.captures.len() => return fail("captured upvalue", i),
+				_ => (),
+			}
+		}
+	}
+	check_stack(function)
+}
+
+/// How many values an instruction takes off the stack and puts back.
+fn stack_effect(op: Op) -> (usize, usize) {
+	match op
+	{
+		Op::Constant(_) | Op::Nil | Op::True | Op::False | Op::GetLocal(_) | Op::GetGlobal(_)
+		| Op::GetCell(_) | Op::GetUpvalue(_) | Op::Closure(_) => (0, 1),
+		Op::NewCell(_) | Op::Jump(_) => (0, 0),
+		Op::Pop | Op::DefineGlobal(_) | Op::DefineCell(_) | Op::Print | Op::Return => (1, 0),
+		Op::SetLocal(_) | Op::SetGlobal(_) | Op::SetCell(_) | Op::SetUpvalue(_) | Op::GetProperty(_)
+		| Op::Negate | Op::Not | Op::JumpIfFalse(_) => (1, 1),
+		Op::SetProperty(_) | Op::Index | Op::Add | Op::Subtract | Op::Multiply | Op::Divide | Op::Greater
+		| Op::GreaterEqual | Op::Less | Op::LessEqual | Op::Equal | Op::NotEqual => (2, 1),
+		Op::Call(count) => (count as usize + 1, 1),
+		Op::SetComponent(_) => (2, 2),
+		Op::List(count) | Op::Interpolate(count) => (count as usize, 1),
+	}
+}
+
+/// Follows every path through the code, counting the values on the stack
+/// above the call's arguments. Each instruction must find the values it
+/// takes and the local slots it names, and be reached with the same count
+/// from every path.
+fn check_stack(function: &Function) -> Result<(), String> {
+	let code = &function.chunk.code;
+	let mut heights: Vec<Option<usize>> = vec![None; code.len()];
+	let mut pending = vec![(0, function.arity)];
+	while let Some((ip, height)) = pending.pop()
+	{
+		match heights[ip]
+		{
+			Some(known) if known == height => continue,
+			Some(known) => return Err(format!("instruction {} of {} is reached with {} and with {} values on the stack", ip, function.name, known, height)),
+			None => heights[ip] = Some(height),
+		}
+		let op = code[ip];
+		let (takes, puts) = stack_effect(op);
+		if height < takes
+		{
+			return Err(format!("instruction {} of {} takes {} values from a stack of {}", ip, function.name, takes, height));
+		}
+		if let Op::GetLocal(slot) | Op::SetLocal(slot) = op
+		{
+			if slot as usize >= height
+			{
+				return Err(format!("local {} is out of range in {}", slot, function.name));
+			}
+		}
+		let after = height - takes + puts;
+		match op
+		{
+			Op::Return => (),
+			Op::Jump(target) => pending.push((target as usize, after)),
+			Op::JumpIfFalse(target) => {
+				pending.push((target as usize, after));
+				pending.push((ip + 1, after));
+			}
+			// The code ends with a return, so ip + 1 is inside it.
+			_ => pending.push((ip + 1, after)),
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compiler::compile;
+	use crate::parser::Parser;
+	use crate::scanner::Scanner;
+
+	fn compile_source(source: &str) -> Function
+	{
+		let tokens = Scanner::new(source).scan_tokens().unwrap();
+		compile(&Parser::new(tokens).parse().unwrap()).unwrap()
+	}
+
+	const SOURCE: &str = "var greeting::\"h\\u{e9}llo\";
+func counter(step) {
+	var n::0;
+	func next() { n :: n + step! return [n, 'c', true, false, nil, null, -0.5]! }
+	return next!
+}
+var next::counter(2);
+while (next()[0] < 6) { }
+\"{greeting} {next()[0]}\"";
+
+	#[test]
+	fn round_trip()
+	{
+		let function = compile_source(SOURCE);
+		let bytes = serialize(&function, source_hash(SOURCE)).unwrap();
+		assert_eq!(&bytes[..4], b"PYXC");
+		let (hash, loaded) = deserialize(&bytes).unwrap();
+		assert_eq!(hash, source_hash(SOURCE));
+		assert_eq!(loaded.chunk.disassemble(), function.chunk.disassemble());
+		let (counter, loaded_counter) = (&function.chunk.functions[0], &loaded.chunk.functions[0]);
+		assert_eq!(loaded_counter.chunk.functions[0].chunk.disassemble(), counter.chunk.functions[0].chunk.disassemble());
+		assert_eq!(loaded_counter.chunk.functions[0].captures, counter.chunk.functions[0].captures);
+		assert_eq!(loaded.chunk.spans, function.chunk.spans);
+
+		let mut engine = crate::engine::Engine::new();
+		assert_eq!(engine.run_bytecode(&Rc::new(loaded)), Ok(StractValue::StringValue("h\u{e9}llo 8".to_string())));
+	}
+
+	#[test]
+	fn every_opcode_round_trips()
+	{
+		let ops = [Op::Constant(1), Op::Nil, Op::True, Op::False, Op::Pop, Op::GetLocal(2), Op::SetLocal(3), Op::GetGlobal(4),
+			Op::SetGlobal(5), Op::DefineGlobal(6), Op::NewCell(7), Op::DefineCell(8), Op::GetCell(9), Op::SetCell(10),
+			Op::GetUpvalue(11), Op::SetUpvalue(12), Op::GetProperty(13), Op::SetProperty(14), Op::Index, Op::Add,
+			Op::Subtract, Op::Multiply, Op::Divide, Op::Greater, Op::GreaterEqual, Op::Less, Op::LessEqual, Op::Equal,
+			Op::NotEqual, Op::Negate, Op::Not, Op::Jump(15), Op::JumpIfFalse(16), Op::Call(255), Op::Closure(17),
+			Op::Return, Op::Print, Op::List(18), Op::Interpolate(19), Op::SetComponent(20)];
+		let mut writer = Writer { bytes: vec![] };
+		for op in ops
+		{
+			writer.op(op);
+		}
+		let mut reader = Reader { bytes: &writer.bytes, position: 0 };
+		for op in ops
+		{
+			assert_eq!(reader.op(), Ok(op));
+		}
+		assert_eq!(reader.position, writer.bytes.len());
+	}
+
+	#[test]
+	fn bad_files_are_rejected()
+	{
+		let bytes = serialize(&compile_source("1 + 2"), 7).unwrap();
+		assert_eq!(deserialize(b"#!/bin/sh").err(), Some("not a compiled PScript file".to_string()));
+		let mut old = bytes.clone();
+		old[4] = 0;
+		assert_eq!(deserialize(&old).err(), Some("compiled with format version 0, but this cii reads version 3".to_string()));
+		assert_eq!(deserialize(&bytes[..bytes.len() - 3]).err(), Some("the file ends early".to_string()));
+		let mut extra = bytes.clone();
+		extra.push(0);
+		assert!(deserialize(&extra).is_err());
+
+		let mut bad = Function {
+			name: "script".to_string(),
+			arity: 0,
+			chunk: Chunk::default(),
+			cell_count: 0,
+			captures: vec![],
+		};
+		bad.chunk.write(Op::Constant(3), Span::default());
+		bad.chunk.write(Op::Return, Span::default());
+		let bytes = serialize(&bad, 0).unwrap();
+		assert_eq!(deserialize(&bytes).err(), Some("constant 3 is out of range in script".to_string()));
+
+		let mut damaged = |code: &[Op]| {
+			bad.chunk = Chunk::default();
+			code.iter().for_each(|op| { bad.chunk.write(*op, Span::default()); });
+			deserialize(&serialize(&bad, 0).unwrap()).err()
+		};
+		assert_eq!(damaged(&[Op::Nil, Op::GetLocal(100), Op::Return]), Some("local 100 is out of range in script".to_string()));
+		assert_eq!(damaged(&[Op::Pop, Op::Nil, Op::Return]), Some("instruction 0 of script takes 1 values from a stack of 0".to_string()));
+		assert_eq!(damaged(&[Op::Nil, Op::Call(2), Op::Return]), Some("instruction 1 of script takes 3 values from a stack of 1".to_string()));
+		assert_eq!(damaged(&[Op::True, Op::JumpIfFalse(3), Op::Nil, Op::Return]),
+			Some("instruction 3 of script is reached with 2 and with 1 values on the stack".to_string()));
+		assert_eq!(damaged(&[Op::Nil, Op::Jump(0)]), Some("script does not end with a return".to_string()));
+	}
+
+	#[test]
+	fn compiled_code_passes_the_checks()
+	{
+		let sources = [SOURCE, "var a::1; var b::a or 2 and 3;", "{ var i::0; while (i < 9) { var j::i; if (j : 4) break! i :: j + 1! } }",
+			"func f(a, b) { var c::a; { var d::b; return c + d! } }\nf(1, 2)", "\"{1}{[2, 3]}\" + \"x\"", "var o::[1]; o[0]! print -o[0]!"];
+		for source in sources
+		{
+			let function = compile_source(source);
+			assert!(deserialize(&serialize(&function, 0).unwrap()).is_ok(), "{}", source);
+		}
+	}
+
+	#[test]
+	fn hashes_are_stable()
+	{
+		assert_eq!(source_hash(""), 0xcbf29ce484222325);
+		assert_eq!(source_hash("a"), 0xaf63dc4c8601ec8c);
+		assert_ne!(source_hash("var x::1;"), source_hash("var x::2;"));
+	}
+}
@@ -1,8 +1,13 @@
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
 use std::string::String;
 use std::collections::HashMap;
 
+/// The largest 0x, 0b or 0o number a float holds exactly: 2^53.
+const MAX_EXACT: i64 = 1 << 53;
+
 fn is_digit(ch: char) -> bool{
-	ch.is_digit(10)
+	ch.is_ascii_digit()
 }
 
 fn is_alpha(ch: char) -> bool{
@@ -16,9 +21,13 @@ fn is_alpha_numeric(ch: char) -> bool{
 fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
 	HashMap::from([
 		("and", And),
+		("break", Break),
+		("catch", Catch),
 		("class", Class),
+		("defi", Defi),
 		("else", Else),
 		("false", False),
+		("finally", Finally),
 		("for", For),
 		("func", Func),
 		("if", If),
@@ -28,7 +37,10 @@ fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
 		("or", Or),
 		("print", Print),
 		("return", Return),
+		("signal", Signal),
+		("throw", Throw),
 		("true", True),
+		("try", Try),
 		("unif", Unif),
 		("var", Var),
 		("when", When),
@@ -37,6 +49,80 @@ fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
 	])
 }
 
+/// Resolves the escape sequences in the body of a string or character
+/// literal. Errors point at the literal, which starts at `at`.
+fn unescape(raw: &str, at: Span) -> Result<String, Diagnostic>
+{
+	let error = |detail: String| Diagnostic::error(format!("Invalid<j escape sequence {}", detail)).with_code("PS0003").with_label(at, "in this literal");
+	let mut value = String::new();
+	let mut chars = raw.chars();
+	while let Some(c) = chars.next()
+	{
+		if c != '\\'
+		{
+			value.push(c);
+			continue;
+		}
+		match chars.next()
+		{
+			Some('n') => value.push('\n'),
+			Some('t') => value.push('\t'),
+			Some('r') => value.push('\r'),
+			Some('0') => value.push('\0'),
+			Some('"') => value.push('"'),
+			Some('\'') => value.push('\''),
+			Some('\\') => value.push('\\'),
+			Some('u') => {
+				if chars.next() != Some('{')
+				{
+					return Err(error("\\u".to_string()).with_help("write a unicode escape as \\u{1F600}"));
+				}
+				let mut digits = String::new();
+				loop
+				{
+					match chars.next()
+					{
+						Some('}') => break,
+						Some(d) if d.is_ascii_hexdigit() && digits.len() < 6 => digits.push(d),
+						_ => return Err(error(format!("\\u{{{}", digits)).with_help("a unicode escape holds up to six hex digits and ends with '}'")),
+					}
+				}
+				let code = u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32);
+				match code
+				{
+					Some(ch) => value.push(ch),
+					None => return Err(error(format!("\\u{{{}}}", digits)).with_note(format!("{} is not a character", digits))),
+				}
+			}
+			Some(other) => return Err(error(format!("\\{}", other)).with_help("write '\\\\' for a backslash")),
+			None => return Err(error("lone '\\'".to_string()).with_help("write '\\\\' for a backslash")),
+		}
+	}
+	Ok(value)
+}
+
+/// Strips the layout of a `"""` block: a leading newline, a trailing
+/// whitespace-only line and the indentation common to all non-blank lines.
+fn dedent(raw: &str) -> String
+{
+	let body = raw.strip_prefix("\r\n").or_else(|| raw.strip_prefix('\n')).unwrap_or(raw);
+	let mut lines: Vec<&str> = body.split('\n').collect();
+	if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty()
+	{
+		lines.pop();
+	}
+	let indent = lines.iter()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+		.min()
+		.unwrap_or(0);
+
+	lines.iter()
+		.map(|line| if line.len() >= indent { &line[indent..] } else { line.trim_start_matches([' ', '\t']) })
+		.collect::<Vec<&str>>()
+		.join("\n")
+}
+
 pub struct Scanner
 {
 	source: String,
@@ -44,7 +130,15 @@ pub struct Scanner
 	start: usize,
 	current: usize,
 	line: usize,
-	keywords: HashMap<&'static str, TokenType>
+	/// Where the current line starts, to count columns from.
+	line_start: usize,
+	/// The line and column of `start`.
+	start_line: usize,
+	start_column: usize,
+	keywords: HashMap<&'static str, TokenType>,
+	interpolations: Vec<usize>,
+	/// The file the source came from, for the spans of its tokens.
+	file: usize,
 }
 
 impl Scanner {
@@ -56,48 +150,90 @@ impl Scanner {
 			start: 0,
 			current: 0,
 			line: 1,
-			keywords: get_keywords_hashmap()
+			line_start: 0,
+			start_line: 1,
+			start_column: 1,
+			keywords: get_keywords_hashmap(),
+			interpolations: vec![],
+			file: 0,
 		}
 	}
 
-	pub fn scan_tokens(self: &mut Self) -> Result<Vec<Token>, String>
+	/// Scans the source as the file numbered `file` by [`crate::sources`].
+	pub fn in_file(mut self, file: usize) -> Self {
+		self.file = file;
+		self
+	}
+
+	/// The tokens of the source, or everything wrong with it.
+	pub fn scan_tokens(&mut self) -> Result<Vec<Token>, Vec<Diagnostic>>
 	{
 		let mut errors = vec![];
 		while !self.is_at_end()
 		{
-			self.start = self.current;
+			self.begin_token();
 			match self.scan_token()
 			{
 				Ok(_) => (),
 				Err(msg) => errors.push(msg),
 			}
 		}
+		self.begin_token();
+		if !self.interpolations.is_empty()
+		{
+			errors.push(Diagnostic::error("Unterminated<j interpolation in ATString").with_code("PS0002")
+				.with_label(self.here(), "expected '}' before the end of the script"));
+		}
 		self.tokens.push(Token {
 			token_type:Eof, 
 			panoll:"".to_string(), 
 			stract:None, 
-			line_number:self.line, 
+			span:self.here(), 
 		});
 
-		if errors.len() > 0
+		if !errors.is_empty()
 		{
-			let mut joined = "".to_string();
-			for error in errors
-			{
-				joined.push_str(&error);
-				joined.push_str("\n");
-			};
-			return Err(joined);
+			return Err(errors);
 		}
 		Ok(self.tokens.clone())
 	}
 
-	fn is_at_end(self: &Self)->bool
+	fn is_at_end(&self)->bool
+	{
+		self.current >= self.source.len()
+	}
+
+	fn begin_token(&mut self)
 	{
-		self.current >= self.source.len() as usize
+		self.start = self.current;
+		self.start_line = self.line;
+		self.start_column = self.source[self.line_start..self.start].chars().count() + 1;
 	}
 
-	fn scan_token(self: &mut Self)->Result<(), String>
+	/// The span of the token scanned so far.
+	fn here(&self)->Span
+	{
+		Span::new(self.start, self.current, self.start_line, self.start_column).in_file(self.file)
+	}
+
+	/// An arithmetic operator, or the assignment made of it and `::`, such
+	/// as `+::`.
+	fn operator(&mut self, plain: TokenType, compound: TokenType)
+	{
+		let token = if self.peek() == ':' && self.peek_next() == ':'
+		{
+			self.advance();
+			self.advance();
+			compound
+		}
+		else
+		{
+			plain
+		};
+		self.add_token(token);
+	}
+
+	fn scan_token(&mut self)->Result<(), Diagnostic>
 	{
 		let c = self.advance();
 		
@@ -105,14 +241,17 @@ impl Scanner {
 		{
 			'(' => self.add_token(LeftParen),
 			')' => self.add_token(RightParen),
-			'{' => self.add_token(LeftBrace),
-			'}' => self.add_token(RightBrace),
+			'{' => self.left_brace(),
+			'}' => self.right_brace()?,
+			'@' => self.annotation()?,
+			'[' => self.add_token(LeftBracket),
+			']' => self.add_token(RightBracket),
 			',' => self.add_token(Comma),
 			'.' => self.add_token(Dot),
-			'-' => self.add_token(Minus),
-			'+' => self.add_token(Plus),
-			';' => self.add_token(Semicolon),
-			'*' => self.add_token(Star),
+			'-' => self.operator(Minus, MinusEqual),
+			'+' => self.operator(Plus, PlusEqual),
+			'*' => self.operator(Star, StarEqual),
+			'?' => self.add_token(Question),
 			'!' => {
 				let token = if self.do_match(':')
 				{
@@ -147,7 +286,7 @@ impl Scanner {
 				self.add_token(token);
 			}
 			'>' => {
-				let token = if self.do_match(';')
+				let token = if self.do_match(';') || self.do_match(':')
 				{
 					GreaterEqual
 				}
@@ -181,103 +320,423 @@ impl Scanner {
 				}
 				else
 				{
-					self.add_token(Slash);
+					self.operator(Slash, SlashEqual);
 				}
 			}
-			' ' | '\r' | '\t' => {},
-			'\n' => self.line += 1,
-			'"' => self.string()?,
+			' ' | '\r' | '\t' | '\n' => {},
+			'"' => {
+				if self.peek() == '"' && self.peek_next() == '"'
+				{
+					self.advance();
+					self.advance();
+					self.multiline_string()?;
+				}
+				else
+				{
+					self.string()?;
+				}
+			}
+			'\'' => self.character()?,
 			c =>{
 				if is_digit(c)
 				{
 					self.number()?;
 				}
+				else if c == 'r' && (self.peek() == '"' || self.peek() == '#')
+				{
+					self.raw_string()?;
+				}
 				else if is_alpha(c)
 				{
 					self.identifier();
 				}
 				else
 				{
-					return Err(format!("Unrecognized<j CHAR at line {}: {}", self.line, c));
+					return Err(Diagnostic::error(format!("Unrecognized<j CHAR {:?}", c)).with_code("PS0009").with_label(self.here(), ""));
 				}
 			}
 		}
 		Ok(())
 	}
 
-	fn peek(self: &Self)->char{
-		if self.is_at_end()
+	// `current` is a byte offset, so these look at the characters from
+	// there rather than counting characters from the start.
+	fn peek(&self)->char{
+		self.peek_at(0)
+	}
+
+	fn peek_next(&self)->char
+	{
+		self.peek_at(1)
+	}
+
+	fn peek_at(&self, offset: usize)->char
+	{
+		self.source[self.current..].chars().nth(offset).unwrap_or('\0')
+	}
+
+	fn do_match(&mut self, ch: char)->bool{
+		if self.is_at_end() || self.peek() != ch
 		{
-			return '\0';
+			return false;
 		}
-		self.source.chars().nth(self.current).unwrap()
+		self.advance();
+		true
 	}
 
-	fn peek_next(self: &Self)->char
+	/// Scans the body of a `"` string up to the closing quote, or up to a
+	/// `{` that opens an interpolated expression. In the latter case the text
+	/// so far becomes an `Interpolation` token and scanning carries on with
+	/// ordinary tokens until the matching `}` resumes the string.
+	fn string(&mut self) -> Result<(), Diagnostic>
 	{
-		if self.current + 1 >= self.source.len()
+		let at = self.here();
+		let mut raw = String::new();
+		let mut error = None;
+		loop
 		{
-			return '\0';
+			if self.is_at_end()
+			{
+				return Err(Diagnostic::error("Unterminated NONULL ATString <j").with_code("PS0001")
+					.with_label(at, "the string starts here")
+					.with_help("close it with '\"'"));
+			}
+			let c = self.advance();
+			match c
+			{
+				'"' => break,
+				'{' => {
+					if self.do_match('{')
+					{
+						raw.push('{');
+					}
+					else
+					{
+						let value = unescape(&raw, at)?;
+						self.add_token_lateral(Interpolation, Some(StringValue(value)));
+						self.interpolations.push(0);
+						return match error { Some(msg) => Err(msg), None => Ok(()) };
+					}
+				}
+				'}' => {
+					if self.do_match('}')
+					{
+						raw.push('}');
+					}
+					else
+					{
+						let column = self.source[self.line_start..self.current].chars().count();
+						let brace = Span::new(self.current - 1, self.current, self.line, column).in_file(self.file);
+						error = Some(Diagnostic::error("Single '}' in ATString").with_code("PS0004")
+							.with_label(brace, "")
+							.with_help("write '}}' for a literal brace"));
+					}
+				}
+				'\\' => {
+					raw.push(c);
+					if self.is_at_end()
+					{
+						continue;
+					}
+					let escaped = self.advance();
+					raw.push(escaped);
+					if escaped == 'u' && self.peek() == '{'
+					{
+						// the braces of \u{...} belong to the escape, not to an interpolation
+						while self.peek() != '}' && self.peek() != '"' && !self.is_at_end()
+						{
+							raw.push(self.advance());
+						}
+						if self.peek() == '}'
+						{
+							raw.push(self.advance());
+						}
+					}
+				}
+				_ => raw.push(c),
+			}
 		}
-		return self.source.chars().nth(self.current + 1).unwrap()
+		if let Some(msg) = error
+		{
+			return Err(msg);
+		}
+		let value = unescape(&raw, at)?;
+
+		self.add_token_lateral(StringLat, Some(StringValue(value)));
+		Ok(())
 	}
 
-	fn do_match(self: &mut Self, ch: char)->bool{
-		if self.is_at_end()
+	fn left_brace(&mut self)
+	{
+		if let Some(depth) = self.interpolations.last_mut()
 		{
-			return false;
+			*depth += 1;
 		}
-		if self.source.chars().nth(self.current).unwrap() != ch{
-			return false;
+		self.add_token(LeftBrace);
+	}
+
+	fn right_brace(&mut self) -> Result<(), Diagnostic>
+	{
+		match self.interpolations.last_mut()
+		{
+			Some(0) => {
+				self.interpolations.pop();
+				self.string()
+			}
+			Some(depth) => {
+				*depth -= 1;
+				self.add_token(RightBrace);
+				Ok(())
+			}
+			None => {
+				self.add_token(RightBrace);
+				Ok(())
+			}
 		}
-		else
+	}
+
+	/// Scans a `"""` block. The newline after the opening quotes and the
+	/// whitespace-only line before the closing quotes are dropped, then the
+	/// indentation shared by every non-blank line is stripped.
+	fn multiline_string(&mut self) -> Result<(), Diagnostic>
+	{
+		let at = self.here();
+		let mut raw = String::new();
+		loop
 		{
-			self.current += 1;
-			return true;
+			if self.is_at_end()
+			{
+				return Err(Diagnostic::error("Unterminated<j multi-line ATString").with_code("PS0001")
+					.with_label(at, "the string starts here")
+					.with_help("close it with '\"\"\"'"));
+			}
+			if self.peek() == '"' && self.peek_next() == '"' && self.peek_at(2) == '"'
+			{
+				break;
+			}
+			let c = self.advance();
+			raw.push(c);
+			if c == '\\' && !self.is_at_end()
+			{
+				raw.push(self.advance());
+			}
 		}
+		self.advance();
+		self.advance();
+		self.advance();
+		let value = unescape(&dedent(&raw), at)?;
+
+		self.add_token_lateral(StringLat, Some(StringValue(value)));
+		Ok(())
 	}
 
-	fn string(self: &mut Self) -> Result<(), String>
+	/// Scans `r"..."` or `r#"..."#`, taking every character literally.
+	/// The number of `#` after the closing quote must match the opening.
+	fn raw_string(&mut self) -> Result<(), Diagnostic>
 	{
-		while self.peek() != '"' && !self.is_at_end()
+		let at = self.here();
+		let mut hashes = 0;
+		while self.do_match('#')
 		{
-			if self.peek() == '\n'
+			hashes += 1;
+		}
+		if !self.do_match('"')
+		{
+			return Err(Diagnostic::error("Expected '\"' to open raw ATString").with_code("PS0011").with_label(self.here(), ""));
+		}
+		let mut value = String::new();
+		loop
+		{
+			if self.is_at_end()
 			{
-				self.line += 1;
+				return Err(Diagnostic::error("Unterminated<j raw ATString").with_code("PS0001")
+					.with_label(at, "the string starts here")
+					.with_help(format!("close it with '\"{}'", "#".repeat(hashes))));
+			}
+			let c = self.advance();
+			if c == '"' && (0..hashes).all(|i| self.peek_at(i) == '#')
+			{
+				for _ in 0..hashes
+				{
+					self.advance();
+				}
+				break;
+			}
+			value.push(c);
+		}
+
+		self.add_token_lateral(StringLat, Some(StringValue(value)));
+		Ok(())
+	}
+
+	fn character(&mut self) -> Result<(), Diagnostic>
+	{
+		let at = self.here();
+		let mut raw = String::new();
+		while self.peek() != '\'' && self.peek() != '\n' && !self.is_at_end()
+		{
+			let c = self.advance();
+			raw.push(c);
+			if c == '\\' && !self.is_at_end()
+			{
+				raw.push(self.advance());
 			}
-			self.advance();
 		}
-		if self.is_at_end()
+		if self.peek() != '\''
 		{
-			return Err("Unterminated NONULL ATString <j".to_string());
+			return Err(Diagnostic::error("Unterminated<j CHAR").with_code("PS0005").with_label(self.here(), "").with_help("close it with '\\''"));
 		}
 		self.advance();
-		let value = &self.source[self.start + 1..self.current - 1];
+		let value = unescape(&raw, at)?;
 
-		self.add_token_lateral(StringLat, Some(StringValue(value.to_string())));
+		let mut chars = value.chars();
+		match (chars.next(), chars.next())
+		{
+			(Some(c), None) => self.add_token_lateral(CharLat, Some(CharValue(c))),
+			(None, _) => return Err(Diagnostic::error("Empty<j CHAR").with_code("PS0006").with_label(self.here(), "")),
+			_ => return Err(Diagnostic::error("CHAR holds more than one character").with_code("PS0006")
+				.with_label(self.here(), "")
+				.with_help(format!("use a string for more: \"{}\"", raw))),
+		}
 		Ok(())
 	}
 
-	fn number(self: &mut Self)-> Result <(), String>
+	fn number_error(&self, code: &'static str, msg: &str)->Diagnostic
 	{
-		while is_digit(self.peek())
+		Diagnostic::error(format!("{}: {}", msg, &self.source[self.start..self.current]))
+			.with_code(code)
+			.with_label(self.here(), "")
+	}
+
+	/// Consumes digits accepted by `is_valid` along with `_` separators and
+	/// returns them with the separators removed.
+	fn digits(&mut self, is_valid: fn(char) -> bool)->String
+	{
+		let mut digits = String::new();
+		while is_valid(self.peek()) || self.peek() == '_'
 		{
-			self.advance();
+			let c = self.advance();
+			if c != '_'
+			{
+				digits.push(c);
+			}
 		}
+		digits
+	}
+
+	fn number(&mut self)-> Result <(), Diagnostic>
+	{
+		let first = self.source[self.start..].chars().next().unwrap();
+		let radix = match (first, self.peek())
+		{
+			('0', 'x' | 'X') => 16,
+			('0', 'b' | 'B') => 2,
+			('0', 'o' | 'O') => 8,
+			_ => 10,
+		};
+		if radix != 10
+		{
+			return self.radix_number(radix);
+		}
+
+		let mut literal = first.to_string();
+		literal.push_str(&self.digits(is_digit));
 		if self.peek() == '.' && is_digit(self.peek_next())
 		{
 			self.advance();
-			while is_digit(self.peek())
+			literal.push('.');
+			literal.push_str(&self.digits(is_digit));
+		}
+		let sign = self.peek_next();
+		let exponent_digit = if sign == '+' || sign == '-' { self.peek_at(2) } else { sign };
+		if (self.peek() == 'e' || self.peek() == 'E') && is_digit(exponent_digit)
+		{
+			self.advance();
+			literal.push('e');
+			if sign == '+' || sign == '-'
+			{
+				literal.push(self.advance());
+			}
+			literal.push_str(&self.digits(is_digit));
+		}
+		if self.source[self.start..self.current].ends_with('_')
+		{
+			return Err(self.number_error("PS0007", "Trailing '_' in NUM"));
+		}
+		if is_alpha(self.peek())
+		{
+			self.advance();
+			return Err(self.number_error("PS0007", "Invalid<j digit in NUM"));
+		}
+
+		match literal.parse::<f64>() {
+			Ok(value) if value.is_finite() => self.add_token_lateral(Number, Some(FloatValue(value))),
+			Ok(_) => return Err(self.number_error("PS0008", "NUM out of range")),
+			Err(_) => return Err(self.number_error("PS0007", "Cannot parse NUM")),
+		}
+		Ok(())
+	}
+
+	/// Scans the digits after a `0x`, `0b` or `0o` prefix into an integer.
+	fn radix_number(&mut self, radix: u32)-> Result <(), Diagnostic>
+	{
+		self.advance();
+		let digits = match radix
+		{
+			16 => self.digits(|c| c.is_ascii_hexdigit()),
+			8 => self.digits(|c| c.is_digit(8)),
+			_ => self.digits(|c| c == '0' || c == '1'),
+		};
+		if is_alpha_numeric(self.peek())
+		{
+			self.advance();
+			return Err(self.number_error("PS0007", "Invalid<j digit in NUM"));
+		}
+		if digits.is_empty()
+		{
+			return Err(self.number_error("PS0007", "Missing digits in NUM"));
+		}
+		if self.source[self.start..self.current].ends_with('_')
+		{
+			return Err(self.number_error("PS0007", "Trailing '_' in NUM"));
+		}
+
+		match i64::from_str_radix(&digits, radix) {
+			Ok(value) if value <= MAX_EXACT => self.add_token_lateral(Number, Some(IntValue(value))),
+			_ => return Err(self.number_error("PS0008", "NUM out of range")
+				.with_help("0x, 0b and 0o numbers can be at most 2^53, which is 0x20_0000_0000_0000")),
+		}
+		Ok(())
+	}
+
+	/// Scans `@name`, or a header such as `@--I++<` that says what an
+	/// `@reference` brings into scope.
+	fn annotation(&mut self)->Result<(), Diagnostic>
+	{
+		if self.peek() == '-' && self.peek_next() == '-'
+		{
+			self.advance();
+			self.advance();
+			while is_alpha(self.peek())
 			{
 				self.advance();
 			}
+			while matches!(self.peek(), '+' | '-' | '<' | '>')
+			{
+				self.advance();
+			}
+			self.add_token(Annotation);
+			return Ok(());
 		}
-		let substring = &self.source[self.start..self.current];
-		let value = substring.parse::<f64>();
-		match value {
-			Ok(value) => self.add_token_lateral(Number, Some(FloatValue(value))),
-			Err(_) => return Err(format!("Cannot parse NUM: {}", substring)),
+		if !is_alpha(self.peek())
+		{
+			return Err(Diagnostic::error("Expected annotation name after '@'").with_code("PS0010").with_label(self.here(), ""));
+		}
+		while is_alpha_numeric(self.peek())
+		{
+			self.advance();
 		}
+		self.add_token(Annotation);
 		Ok(())
 	}
 
@@ -298,26 +757,31 @@ impl Scanner {
 		}
 	}
 
-	fn advance(self: &mut Self)->char
+	fn advance(&mut self)->char
 	{
-		let c = self.source.chars().nth(self.current).unwrap();
-		self.current += 1;
+		let c = self.source[self.current..].chars().next().unwrap();
+		self.current += c.len_utf8();
+		if c == '\n'
+		{
+			self.line += 1;
+			self.line_start = self.current;
+		}
 		c
 	}
 
-	fn add_token(self: &mut Self, token_type: TokenType)
+	fn add_token(&mut self, token_type: TokenType)
 	{
 		self.add_token_lateral(token_type, None);
 	}
 
-	fn add_token_lateral(self: &mut Self, token_type: TokenType, stract: Option<StractValue>)
+	fn add_token_lateral(&mut self, token_type: TokenType, stract: Option<StractValue>)
 	{
-		let mut text = self.source[self.start..self.current].to_string();
+		let text = self.source[self.start..self.current].to_string();
 		self.tokens.push(Token{
-			token_type: token_type,
+			token_type,
 			panoll: text,
-			stract: stract,
-			line_number: self.line,
+			stract,
+			span: self.here(),
 		});
 	}
 }
@@ -328,6 +792,8 @@ pub enum TokenType {
 	RightParen,
 	LeftBrace,
 	RightBrace,
+	LeftBracket,
+	RightBracket,
 	Comma,
 	Dot,
 	Minus,
@@ -335,9 +801,15 @@ pub enum TokenType {
 	Slash,
 	Start,
 	Star,
+	Question,
 
 	Equal,
 	EqualEqual,
+	/// `+::`, `-::`, `*::` and `/::`.
+	PlusEqual,
+	MinusEqual,
+	StarEqual,
+	SlashEqual,
 	Bang,
 	BangEqual,
 	Semicolon,
@@ -349,12 +821,19 @@ pub enum TokenType {
 
 	Identifier,
 	StringLat,
+	CharLat,
+	Interpolation,
 	Number,
+	Annotation,
 
 	And,
+	Break,
+	Catch,
 	Class,
+	Defi,
 	Else,
 	False,
+	Finally,
 	Func,
 	For,
 	If,
@@ -364,7 +843,10 @@ pub enum TokenType {
 	Or,
 	Print,
 	Return,
+	Signal,
+	Throw,
 	True,
+	Try,
 	Unif,
 	Var,
 	When,
@@ -388,6 +870,7 @@ pub enum StractValue
 	IntValue(i64),
 	FloatValue(f64),
 	StringValue(String),
+	CharValue(char),
 	IdentifierValue(String)
 }
 use StractValue::*;
@@ -398,20 +881,23 @@ pub struct Token
 	pub token_type: TokenType,
 	pub panoll: String,
 	pub stract: Option<StractValue>,
-	pub line_number: usize,
+	pub span: Span,
 }
 
 impl Token {
-	pub fn new(token_type: TokenType, panoll: String, stract:Option<StractValue>, line_number:usize)->Self{
+	pub fn new(token_type: TokenType, panoll: String, stract:Option<StractValue>, span:Span)->Self{
 		Self {
 			token_type,
 			panoll,
 			stract,
-			line_number,
+			span,
 		}
 	}
-	pub fn to_string(self: &Self)->String{
-		format!("{} {} {:?}",self.token_type, self.panoll, self.stract)
+}
+
+impl std::fmt::Display for Token {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{} {} {:?}", self.token_type, self.panoll, self.stract)
 	}
 }
 
@@ -450,6 +936,15 @@ mod tests {
 		assert_eq!(scanner.tokens[4].token_type, Eof);
 	}
 
+	#[test]
+	fn compound_assignment_tokens()
+	{
+		let mut scanner = Scanner::new("+:: -:: *:: /:: +: -::: x//::");
+		scanner.scan_tokens().unwrap();
+		let types: Vec<TokenType> = scanner.tokens.iter().map(|token| token.token_type).collect();
+		assert_eq!(types, vec![PlusEqual, MinusEqual, StarEqual, SlashEqual, Plus, EqualEqual, MinusEqual, EqualEqual, Identifier, Eof]);
+	}
+
 	#[test]
 	fn handle_string_lat()
 	{
@@ -493,6 +988,142 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn handle_string_escapes()
+	{
+		let source = r#""a\tb\n\"c\" \\ \u{1F600}""#;
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens.len(), 2);
+		match scanner.tokens[0].stract.as_ref().unwrap()
+		{
+			StringValue(value) => assert_eq!(value, "a\tb\n\"c\" \\ \u{1F600}"),
+			_=>panic!("Incorrect Lateral Value"),
+		}
+	}
+
+	#[test]
+	fn handle_invalid_escapes()
+	{
+		for source in [r#""\q""#, r#""\u{110000}""#, r#""\u{zz}""#, r#""\u41""#]
+		{
+			let mut scanner = Scanner::new(source);
+			match scanner.scan_tokens()
+			{
+				Err(errors) => assert!(errors[0].to_string().contains("line 1"), "{:?}", errors),
+				_=>panic!("Should have failed: {}", source),
+			}
+		}
+	}
+
+	#[test]
+	fn handle_char_lat()
+	{
+		let source = r"'Z' '\n' '\''";
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens.len(), 4);
+		let expected = ['Z', '\n', '\''];
+		for (token, expected) in scanner.tokens.iter().zip(expected)
+		{
+			assert_eq!(token.token_type, CharLat);
+			match token.stract
+			{
+				Some(CharValue(c)) => assert_eq!(c, expected),
+				_=>panic!("Incorrect Lateral Value"),
+			}
+		}
+	}
+
+	#[test]
+	fn handle_invalid_char_lat()
+	{
+		for source in ["''", "'ab'", "'a"]
+		{
+			let mut scanner = Scanner::new(source);
+			assert!(scanner.scan_tokens().is_err(), "Should have failed: {}", source);
+		}
+	}
+
+	#[test]
+	fn handle_raw_string_lat()
+	{
+		let source = r####"r"C:\new\{x}" r#"say "hi""#"####;
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens.len(), 3);
+		match (scanner.tokens[0].stract.as_ref().unwrap(), scanner.tokens[1].stract.as_ref().unwrap())
+		{
+			(StringValue(first), StringValue(second)) => {
+				assert_eq!(first, r"C:\new\{x}");
+				assert_eq!(second, r#"say "hi""#);
+			}
+			_=>panic!("Incorrect Lateral Value"),
+		}
+	}
+
+	#[test]
+	fn handle_triple_quoted_string_lat()
+	{
+		let source = "var s::\"\"\"\n\t\tfirst\n\t\t  second\\t!\n\n\t\tthird\n\t\"\"\";\nprint s!";
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens[3].token_type, StringLat);
+		match scanner.tokens[3].stract.as_ref().unwrap()
+		{
+			StringValue(value) => assert_eq!(value, "first\n  second\t!\n\nthird"),
+			_=>panic!("Incorrect Lateral Value"),
+		}
+		assert_eq!(scanner.tokens[4].token_type, Semicolon);
+		assert_eq!(scanner.tokens[5].span.line, 7);
+	}
+
+	#[test]
+	fn handle_interpolated_string_lat()
+	{
+		let source = r#""Score: {score} of {max({a}, "b")}!""#;
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		let types: Vec<TokenType> = scanner.tokens.iter().map(|t| t.token_type).collect();
+		assert_eq!(types, vec![
+			Interpolation, Identifier,
+			Interpolation, Identifier, LeftParen, LeftBrace, Identifier, RightBrace, Comma, StringLat, RightParen,
+			StringLat, Eof,
+		]);
+		match (scanner.tokens[0].stract.as_ref().unwrap(), scanner.tokens[11].stract.as_ref().unwrap())
+		{
+			(StringValue(head), StringValue(tail)) => {
+				assert_eq!(head, "Score: ");
+				assert_eq!(tail, "!");
+			}
+			_=>panic!("Incorrect Lateral Value"),
+		}
+	}
+
+	#[test]
+	fn handle_escaped_braces()
+	{
+		let source = r#""{{x}} }}""#;
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens.len(), 2);
+		match scanner.tokens[0].stract.as_ref().unwrap()
+		{
+			StringValue(value) => assert_eq!(value, "{x} }"),
+			_=>panic!("Incorrect Lateral Value"),
+		}
+	}
+
+	#[test]
+	fn handle_bad_interpolation()
+	{
+		for source in [r#""a } b""#, r#""a {b""#, r#""a {b"#]
+		{
+			let mut scanner = Scanner::new(source);
+			assert!(scanner.scan_tokens().is_err(), "Should have failed: {}", source);
+		}
+	}
+
 	#[test]
 	fn number_laterals()
 	{
@@ -562,4 +1193,116 @@ mod tests {
 		assert_eq!(scanner.tokens[11].token_type, Semicolon);
 		assert_eq!(scanner.tokens[12].token_type, Eof);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn token_spans()
+	{
+		let source = "var é::\"né\";\n\tx + é";
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		let spans: Vec<(&str, usize, usize)> = scanner.tokens.iter()
+			.map(|token| (token.span.text(source), token.span.line, token.span.column))
+			.collect();
+		assert_eq!(spans, vec![
+			("var", 1, 1), ("é", 1, 5), ("::", 1, 6), ("\"né\"", 1, 8), (";", 1, 12),
+			("x", 2, 2), ("+", 2, 4), ("é", 2, 6), ("", 2, 7),
+		]);
+		assert_eq!(scanner.tokens[3].span, Span::new(8, 13, 1, 8));
+	}
+
+	#[test]
+	fn radix_number_laterals()
+	{
+		let source = "0xFF_88_00 0b1010 0o17 0XdeadBEEF 0x20_0000_0000_0000";
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens.len(), 6);
+		let expected = [0xFF8800, 0b1010, 0o17, 0xDEADBEEF, 1 << 53];
+		for (token, expected) in scanner.tokens.iter().zip(expected)
+		{
+			assert_eq!(token.token_type, Number);
+			match token.stract
+			{
+				Some(IntValue(value)) => assert_eq!(value, expected),
+				_=>panic!("Incorrect Lateral Value"),
+			}
+		}
+	}
+
+	#[test]
+	fn separated_and_exponent_laterals()
+	{
+		let source = "1_000_000 1e-3 2.5E+2 6e2 3_1.4_1";
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens.len(), 6);
+		let expected = [1_000_000.0, 1e-3, 250.0, 600.0, 31.41];
+		for (token, expected) in scanner.tokens.iter().zip(expected)
+		{
+			match token.stract
+			{
+				Some(FloatValue(value)) => assert_eq!(value, expected),
+				_=>panic!("Incorrect Lateral Value"),
+			}
+		}
+	}
+
+	#[test]
+	fn exponent_needs_digits()
+	{
+		let source = "2.max";
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens[0].token_type, Number);
+		assert_eq!(scanner.tokens[1].token_type, Dot);
+		assert_eq!(scanner.tokens[2].token_type, Identifier);
+	}
+
+	#[test]
+	fn invalid_number_laterals()
+	{
+		let cases = [
+			("var x::0x1_0000_0000_0000_0000;", "out of range", 1, 8),
+			("var x::0x7FFF_FFFF_FFFF_FFFF;", "out of range", 1, 8),
+			("0x20_0000_0000_0001", "out of range", 1, 1),
+			("\n  1e999", "out of range", 2, 3),
+			("0b102", "digit", 1, 1),
+			("0x", "Missing digits", 1, 1),
+			("x::1_;", "Trailing '_'", 1, 4),
+			("12abc", "digit", 1, 1),
+		];
+		for (source, fragment, line, column) in cases
+		{
+			let mut scanner = Scanner::new(source);
+			match scanner.scan_tokens()
+			{
+				Err(errors) => {
+					let msg = crate::diagnostic::join(&errors);
+					assert!(msg.contains(fragment), "{}", msg);
+					assert!(msg.contains(&format!("line {}, column {}", line, column)), "{}", msg);
+				}
+				_=>panic!("Should have failed: {}", source),
+			}
+		}
+	}
+
+	#[test]
+	fn annotations()
+	{
+		let source = "@reference @lib_jellybeans";
+		let mut scanner = Scanner::new(source);
+		scanner.scan_tokens().unwrap();
+		assert_eq!(scanner.tokens.len(), 3);
+		assert_eq!(scanner.tokens[0].token_type, Annotation);
+		assert_eq!(scanner.tokens[0].panoll, "@reference");
+		assert_eq!(scanner.tokens[1].panoll, "@lib_jellybeans");
+		assert!(Scanner::new("@ x").scan_tokens().is_err());
+
+		let mut scanner = Scanner::new("@reference @lib_halcyon::@--I++{@memcpy} @--I++<");
+		scanner.scan_tokens().unwrap();
+		let kinds: Vec<TokenType> = scanner.tokens.iter().map(|token| token.token_type).collect();
+		assert_eq!(kinds, vec![Annotation, Annotation, Equal, Annotation, LeftBrace, Annotation, RightBrace, Annotation, Eof]);
+		assert_eq!(scanner.tokens[3].panoll, "@--I++");
+		assert_eq!(scanner.tokens[7].panoll, "@--I++<");
+	}
+}
@@ -0,0 +1,248 @@
+// Drives the lifecycle hooks of many scripts from a host's game loop. The
+// host calls `tick` once per rendered frame with the time that passed; each
+// script gets `init` once, `physic(step)` at a fixed timestep and `proc()`
+// every frame. Each tick ends with the frame's garbage collection.
+use crate::convert::IntoArgs;
+use crate::engine::{Engine, EngineError};
+use crate::expr::StractValue;
+use crate::gc;
+use crate::hooks::Hook;
+
+/// Refers to a script added to a [`Scheduler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptId(usize);
+
+/// A hook that failed during a tick. The other scripts still ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+	pub script: String,
+	pub hook: Hook,
+	pub error: EngineError,
+}
+
+impl std::fmt::Display for ScriptError {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "{} failed in {}: {}", self.script, self.hook, self.error)
+	}
+}
+
+/// What happened during one [`Scheduler::tick`].
+#[derive(Debug, Default, PartialEq)]
+pub struct TickReport {
+	/// How many fixed `physic` steps ran.
+	pub physics_steps: usize,
+	pub errors: Vec<ScriptError>,
+}
+
+struct Script {
+	name: String,
+	engine: Engine,
+	enabled: bool,
+	initialized: bool,
+}
+
+/// Runs the hooks of every enabled script, frame by frame.
+///
+/// ```
+/// use cii::{Engine, Scheduler};
+///
+/// let mut engine = Engine::new();
+/// engine.eval("var steps::0;\ndefi physic(delta) { steps :: steps + 1! }").unwrap();
+/// let mut scheduler = Scheduler::new(0.5);
+/// let player = scheduler.add("player", engine);
+/// scheduler.tick(1.25);
+/// assert_eq!(scheduler.engine(player).get_global("steps"), Some(cii::StractValue::Number(2.0)));
+/// ```
+pub struct Scheduler {
+	scripts: Vec<Script>,
+	fixed_step: f64,
+	accumulator: f64,
+	max_steps: usize,
+}
+
+impl Scheduler {
+	/// A scheduler running physics every `fixed_step` seconds.
+	pub fn new(fixed_step: f64) -> Self {
+		assert!(fixed_step > 0.0, "the fixed step must be positive");
+		Self {
+			scripts: vec![],
+			fixed_step,
+			accumulator: 0.0,
+			max_steps: 8,
+		}
+	}
+
+	/// Caps the physics steps one tick may run, so a long stall does not
+	/// make the next frames slower still. Time beyond the cap is dropped.
+	pub fn set_max_steps(&mut self, max_steps: usize) {
+		self.max_steps = max_steps.max(1);
+	}
+
+	pub fn fixed_step(&self) -> f64 {
+		self.fixed_step
+	}
+
+	/// How far the simulation is between two physics steps, from 0 to 1,
+	/// for hosts that interpolate what they draw.
+	pub fn alpha(&self) -> f64 {
+		self.accumulator / self.fixed_step
+	}
+
+	/// Adds a script whose top-level code has already run. Its `init` runs
+	/// at the start of the first tick it is enabled for.
+	pub fn add(&mut self, name: &str, engine: Engine) -> ScriptId {
+		self.scripts.push(Script {
+			name: name.to_string(),
+			engine,
+			enabled: true,
+			initialized: false,
+		});
+		ScriptId(self.scripts.len() - 1)
+	}
+
+	/// Disabled scripts keep their state but none of their hooks run.
+	pub fn set_enabled(&mut self, id: ScriptId, enabled: bool) {
+		self.scripts[id.0].enabled = enabled;
+	}
+
+	pub fn is_enabled(&self, id: ScriptId) -> bool {
+		self.scripts[id.0].enabled
+	}
+
+	pub fn engine(&self, id: ScriptId) -> &Engine {
+		&self.scripts[id.0].engine
+	}
+
+	pub fn engine_mut(&mut self, id: ScriptId) -> &mut Engine {
+		&mut self.scripts[id.0].engine
+	}
+
+	/// Advances every enabled script by `delta` seconds of frame time.
+	pub fn tick(&mut self, delta: f64) -> TickReport {
+		let mut report = TickReport::default();
+
+		for script in self.scripts.iter_mut().filter(|script| script.enabled && !script.initialized) {
+			script.initialized = true;
+			run(script, Hook::Init, (), &mut report);
+		}
+
+		self.accumulator += delta.max(0.0);
+		while self.accumulator >= self.fixed_step {
+			if report.physics_steps == self.max_steps {
+				self.accumulator %= self.fixed_step;
+				break;
+			}
+			self.accumulator -= self.fixed_step;
+			report.physics_steps += 1;
+			for script in self.scripts.iter_mut().filter(|script| script.enabled) {
+				run(script, Hook::Physic, (self.fixed_step,), &mut report);
+			}
+		}
+
+		for script in self.scripts.iter_mut().filter(|script| script.enabled) {
+			run(script, Hook::Proc, (), &mut report);
+		}
+		gc::frame();
+		report
+	}
+}
+
+/// Calls `hook` if the script declares it, recording a failure instead of
+/// passing it on.
+fn run(script: &mut Script, hook: Hook, args: impl IntoArgs, report: &mut TickReport) {
+	if !script.engine.has_hook(hook) {
+		return;
+	}
+	if let Err(error) = script.engine.call_hook::<StractValue>(hook, args) {
+		report.errors.push(ScriptError {
+			script: script.name.clone(),
+			hook,
+			error,
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::expr::StractValue::*;
+
+	const COUNTER: &str = "var inits::0;\nvar frames::0;\nvar steps::0;\nvar time::0;
+defi init() { inits :: inits + 1! }
+defi proc() { frames :: frames + 1! }
+defi physic(delta) { steps :: steps + 1! time :: time + delta! }";
+
+	fn script(source: &str) -> Engine
+	{
+		let mut engine = Engine::new();
+		engine.eval(source).unwrap();
+		engine
+	}
+
+	fn global(scheduler: &Scheduler, id: ScriptId, name: &str) -> StractValue
+	{
+		scheduler.engine(id).get_global(name).unwrap()
+	}
+
+	#[test]
+	fn fixed_steps_accumulate_across_frames()
+	{
+		let mut scheduler = Scheduler::new(0.25);
+		let id = scheduler.add("counter", script(COUNTER));
+		assert_eq!(scheduler.tick(0.1).physics_steps, 0);
+		assert_eq!(scheduler.tick(0.1).physics_steps, 0);
+		assert_eq!(scheduler.tick(0.1).physics_steps, 1);
+		assert_eq!(scheduler.tick(0.6).physics_steps, 2);
+		assert!((scheduler.alpha() - 0.6).abs() < 1e-9);
+		assert_eq!(global(&scheduler, id, "inits"), Number(1.0));
+		assert_eq!(global(&scheduler, id, "frames"), Number(4.0));
+		assert_eq!(global(&scheduler, id, "steps"), Number(3.0));
+		assert_eq!(global(&scheduler, id, "time"), Number(0.75));
+	}
+
+	#[test]
+	fn long_frames_are_capped()
+	{
+		let mut scheduler = Scheduler::new(0.1);
+		scheduler.set_max_steps(3);
+		let id = scheduler.add("counter", script(COUNTER));
+		assert_eq!(scheduler.tick(10.05).physics_steps, 3);
+		assert!(scheduler.alpha() < 1.0);
+		assert_eq!(scheduler.tick(0.0).physics_steps, 0);
+		assert_eq!(global(&scheduler, id, "steps"), Number(3.0));
+	}
+
+	#[test]
+	fn disabled_scripts_pause()
+	{
+		let mut scheduler = Scheduler::new(1.0);
+		let a = scheduler.add("a", script(COUNTER));
+		let b = scheduler.add("b", script(COUNTER));
+		scheduler.set_enabled(b, false);
+		scheduler.tick(1.0);
+		assert!(!scheduler.is_enabled(b));
+		assert_eq!(global(&scheduler, b, "inits"), Number(0.0));
+		scheduler.set_enabled(b, true);
+		scheduler.tick(1.0);
+		assert_eq!(global(&scheduler, a, "frames"), Number(2.0));
+		assert_eq!(global(&scheduler, b, "inits"), Number(1.0));
+		assert_eq!(global(&scheduler, b, "steps"), Number(1.0));
+	}
+
+	#[test]
+	fn errors_are_isolated()
+	{
+		let mut scheduler = Scheduler::new(1.0);
+		let broken = scheduler.add("broken", script("defi proc() { missing()! }\ndefi init() { return 1 + nil! }"));
+		let healthy = scheduler.add("healthy", script(COUNTER));
+		let report = scheduler.tick(1.0);
+		assert_eq!(report.errors.len(), 2);
+		assert_eq!(report.errors[0].hook, Hook::Init);
+		assert_eq!(report.errors[1].script, "broken");
+		assert!(report.errors[1].to_string().starts_with("broken failed in proc: Undefined<j variable 'missing'"), "{}", report.errors[1]);
+		assert_eq!(scheduler.tick(1.0).errors.len(), 1);
+		assert!(scheduler.is_enabled(broken));
+		assert_eq!(global(&scheduler, healthy, "frames"), Number(2.0));
+		assert_eq!(global(&scheduler, healthy, "steps"), Number(2.0));
+	}
+}
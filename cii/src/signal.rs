@@ -0,0 +1,322 @@
+// Signals: named events an object emits and other code listens to.
+//
+//     player.died.connect(hud.show_game_over)!
+//     player.died.emit("lava")!
+//
+// Listeners run synchronously, in the order they connected. A connected
+// method only holds its object weakly: once the object is destroyed, the
+// connection is dropped instead of keeping the object alive.
+use crate::class::{InstanceImpl, MethodImpl};
+use crate::error::RuntimeError;
+use crate::expr::{CallableImpl, NativeFunctionImpl, StractFunctionImpl, StractValue};
+use crate::gc::{self, Collectable};
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+
+enum Listener {
+	Callable(CallableImpl),
+	Method { receiver: Weak<InstanceImpl>, function: StractFunctionImpl },
+}
+
+impl Listener {
+	fn new(callable: CallableImpl) -> Self {
+		match callable {
+			CallableImpl::Method(method) => Listener::Method {
+				receiver: Rc::downgrade(&method.receiver),
+				function: method.function,
+			},
+			other => Listener::Callable(other),
+		}
+	}
+
+	/// The callable to run, or `None` once a method's object is gone.
+	fn resolve(&self) -> Option<CallableImpl> {
+		match self {
+			Listener::Callable(callable) => Some(callable.clone()),
+			Listener::Method { receiver, function } => receiver.upgrade().map(|receiver| CallableImpl::Method(MethodImpl {
+				receiver,
+				function: function.clone(),
+			})),
+		}
+	}
+
+	fn is_alive(&self) -> bool {
+		match self {
+			Listener::Callable(_) => true,
+			Listener::Method { receiver, .. } => receiver.strong_count() > 0,
+		}
+	}
+}
+
+struct Connection {
+	id: usize,
+	listener: Listener,
+}
+
+pub struct SignalImpl {
+	pub name: String,
+	/// How many arguments every `emit` passes.
+	pub arity: usize,
+	connections: RefCell<Vec<Connection>>,
+	next_id: Cell<usize>,
+}
+
+impl std::fmt::Debug for SignalImpl {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result {
+		write!(f, "<signal {}>", self.name)
+	}
+}
+
+impl PartialEq for SignalImpl {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+impl SignalImpl {
+	/// A signal with no listeners. Hosts can make their own and hand them
+	/// to scripts, for instance from a `HostObject` property.
+	pub fn new(name: &str, arity: usize) -> Self {
+		Self {
+			name: name.to_string(),
+			arity,
+			connections: RefCell::new(vec![]),
+			next_id: Cell::new(1),
+		}
+	}
+
+	/// Adds a listener and returns an id that `disconnect_id` accepts.
+	pub fn connect(&self, callable: CallableImpl) -> usize {
+		let id = self.next_id.get();
+		self.next_id.set(id + 1);
+		self.connections.borrow_mut().push(Connection {
+			id,
+			listener: Listener::new(callable),
+		});
+		id
+	}
+
+	/// Removes every connection of `callable`. Returns whether there was one.
+	pub fn disconnect(&self, callable: &CallableImpl) -> bool {
+		let before = self.connections.borrow().len();
+		self.connections.borrow_mut().retain(|connection| connection.listener.resolve().as_ref() != Some(callable));
+		self.prune();
+		self.connections.borrow().len() != before
+	}
+
+	pub fn disconnect_id(&self, id: usize) -> bool {
+		let before = self.connections.borrow().len();
+		self.connections.borrow_mut().retain(|connection| connection.id != id);
+		self.connections.borrow().len() != before
+	}
+
+	/// The number of live connections.
+	pub fn connection_count(&self) -> usize {
+		self.prune();
+		self.connections.borrow().len()
+	}
+
+	/// Calls every listener with `arguments`, in connection order. Listeners
+	/// connected while emitting wait for the next emit; the first error stops
+	/// the rest.
+	pub fn emit(&self, arguments: &[StractValue]) -> Result<(), RuntimeError> {
+		if arguments.len() != self.arity
+		{
+			return Err(format!("Signal '{}' is emitted with {} arguments but got {}", self.name, self.arity, arguments.len()).into());
+		}
+		let listeners: Vec<(usize, Option<CallableImpl>)> = self.connections.borrow().iter()
+			.map(|connection| (connection.id, connection.listener.resolve()))
+			.collect();
+		for (id, listener) in listeners
+		{
+			let still_connected = self.connections.borrow().iter().any(|connection| connection.id == id);
+			if let (true, Some(callable)) = (still_connected, listener)
+			{
+				callable.call(arguments).map_err(|e| e.traced(&format!("{}.emit", self.name)))?;
+			}
+		}
+		self.prune();
+		Ok(())
+	}
+
+	/// Drops the connections of destroyed objects.
+	fn prune(&self) {
+		self.connections.borrow_mut().retain(|connection| connection.listener.is_alive());
+	}
+}
+
+/// A connected method's object is weak, so only its function's scope is
+/// traced.
+impl Collectable for SignalImpl {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+		match self.connections.try_borrow()
+		{
+			Ok(connections) => {
+				for connection in connections.iter()
+				{
+					match &connection.listener
+					{
+						Listener::Callable(callable) => gc::trace_callable(callable, visit),
+						Listener::Method { function, .. } => visit(gc::address(&function.closure)),
+					}
+				}
+				true
+			}
+			Err(_) => false,
+		}
+	}
+
+	fn clear(&self) {
+		self.connections.borrow_mut().clear();
+	}
+}
+
+/// The members scripts use on a signal: `connect(f)`, `disconnect(f or id)`,
+/// `emit(...)` and `connections`.
+pub fn get(signal: &Rc<SignalImpl>, name: &str) -> Option<StractValue>
+{
+	let this = signal.clone();
+	let label = format!("{}.{}", signal.name, name);
+	let native = match name
+	{
+		"connect" => NativeFunctionImpl::new(&label, 1, Rc::new(move |args: &[StractValue]| match &args[0]
+		{
+			StractValue::Callable(callable) => Ok(StractValue::Number(this.connect(callable.clone()) as f64)),
+			other => Err(RuntimeError::type_mismatch(format!("Cannot connect {} to signal '{}', expected a function", other.to_type(), this.name))),
+		})),
+		"disconnect" => NativeFunctionImpl::new(&label, 1, Rc::new(move |args: &[StractValue]| match &args[0]
+		{
+			StractValue::Callable(callable) => Ok(StractValue::from_bool(this.disconnect(callable))),
+			StractValue::Number(id) if *id >= 0.0 && id.fract() == 0.0 => Ok(StractValue::from_bool(this.disconnect_id(*id as usize))),
+			other => Err(RuntimeError::type_mismatch(format!("Cannot disconnect {} from signal '{}'", other.to_type(), this.name))),
+		})),
+		"emit" => NativeFunctionImpl::variadic(&label, Rc::new(move |args: &[StractValue]| {
+			this.emit(args)?;
+			Ok(StractValue::Nil)
+		})),
+		"connections" => return Some(StractValue::Number(signal.connection_count() as f64)),
+		_ => return None,
+	};
+	Some(StractValue::Callable(CallableImpl::NativeFunction(native)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::engine::Engine;
+	use crate::expr::StractValue::*;
+
+	const GAME: &str = "var log::\"\";
+class Player {
+	var health::3;
+	signal hurt(amount, left);
+	signal died();
+	func damage(amount) {
+		self.health :: self.health - amount!
+		self.hurt.emit(amount, self.health)!
+	}
+}
+class Hud {
+	var name::\"hud\";
+	func on_hurt(amount, left) { log :: log + self.name + \" saw \" + \"{amount}/{left} \"! }
+}
+func first(amount, left) { log :: log + \"first \"! }
+func second(amount, left) { log :: log + \"second \"! }
+var player::Player();";
+
+	fn logged(engine: &mut Engine) -> String
+	{
+		engine.eval("log").unwrap().to_string()
+	}
+
+	#[test]
+	fn listeners_run_in_connection_order()
+	{
+		let mut engine = Engine::new();
+		engine.eval(GAME).unwrap();
+		engine.eval("player.hurt.connect(second)!\nplayer.hurt.connect(first)!\nplayer.damage(1)!").unwrap();
+		assert_eq!(logged(&mut engine), "second first ");
+		assert_eq!(engine.eval("player.hurt.connections").unwrap(), Number(2.0));
+	}
+
+	#[test]
+	fn listeners_get_the_emitted_arguments()
+	{
+		let mut engine = Engine::new();
+		engine.eval(GAME).unwrap();
+		engine.eval("var hud::Hud();\nplayer.hurt.connect(hud.on_hurt)!\nplayer.damage(2)!").unwrap();
+		assert_eq!(logged(&mut engine), "hud saw 2/1 ");
+	}
+
+	#[test]
+	fn destroyed_listeners_are_disconnected()
+	{
+		let mut engine = Engine::new();
+		engine.eval(GAME).unwrap();
+		engine.eval("var hud::Hud();\nplayer.hurt.connect(hud.on_hurt)!\nhud :: nil!").unwrap();
+		assert_eq!(engine.eval("player.hurt.connections").unwrap(), Number(0.0));
+		engine.eval("player.damage(1)!").unwrap();
+		assert_eq!(logged(&mut engine), "");
+	}
+
+	#[test]
+	fn disconnect_by_function_or_id()
+	{
+		let mut engine = Engine::new();
+		engine.eval(GAME).unwrap();
+		engine.eval("var id::player.hurt.connect(first);\nplayer.hurt.connect(second)!").unwrap();
+		assert_eq!(engine.eval("player.hurt.disconnect(second)").unwrap(), True);
+		assert_eq!(engine.eval("player.hurt.disconnect(second)").unwrap(), False);
+		assert_eq!(engine.eval("player.hurt.disconnect(id)").unwrap(), True);
+		engine.eval("player.damage(1)!").unwrap();
+		assert_eq!(logged(&mut engine), "");
+	}
+
+	#[test]
+	fn connecting_while_emitting_waits_for_the_next_emit()
+	{
+		let mut engine = Engine::new();
+		engine.eval(GAME).unwrap();
+		engine.eval("func late() { log :: log + \"late \"! }
+func chain() { log :: log + \"chain \"! player.died.connect(late)! }
+player.died.connect(chain)!\nplayer.died.emit()!").unwrap();
+		assert_eq!(logged(&mut engine), "chain ");
+		engine.eval("player.died.emit()!").unwrap();
+		assert_eq!(logged(&mut engine), "chain chain late ");
+	}
+
+	#[test]
+	fn emit_errors()
+	{
+		let mut engine = Engine::new();
+		engine.eval(GAME).unwrap();
+		let err = engine.eval("player.hurt.emit(1)!").unwrap_err().to_string();
+		assert_eq!(err, "Signal 'hurt' is emitted with 2 arguments but got 1 at line 1, column 1");
+		let err = engine.eval("player.died.connect(1)!").unwrap_err().to_string();
+		assert_eq!(err, "Cannot connect Number to signal 'died', expected a function at line 1, column 1");
+		engine.eval("func broken() { return missing! }\nplayer.died.connect(broken)!").unwrap();
+		let err = engine.eval("player.died.emit()!").unwrap_err().to_string();
+		assert!(err.ends_with("in broken() at line 1\n  in died.emit()\n  in script at line 1"), "{}", err);
+		assert!(engine.eval("player.hurt :: 1!").unwrap_err().to_string().contains("Cannot assign to signal 'hurt'"));
+	}
+
+	#[test]
+	fn the_host_can_emit()
+	{
+		let mut engine = Engine::new();
+		engine.eval(GAME).unwrap();
+		engine.eval("player.hurt.connect(first)!").unwrap();
+		let player = engine.get_global("player").unwrap();
+		engine.emit_signal(&player, "hurt", (1, 2)).unwrap();
+		assert_eq!(logged(&mut engine), "first ");
+		assert!(engine.emit_signal(&player, "jumped", ()).unwrap_err().to_string().contains("Player has no signal 'jumped'"));
+
+		let pressed = Rc::new(SignalImpl::new("pressed", 1));
+		engine.set_global("pressed", Signal(pressed.clone()));
+		engine.eval("var last::nil;\nfunc remember(key) { last :: key! }\npressed.connect(remember)!").unwrap();
+		pressed.emit(&[StringValue("a".to_string())]).unwrap();
+		assert_eq!(engine.get_global("last"), Some(StringValue("a".to_string())));
+		engine.emit_signal(&Signal(pressed), "pressed", ("b",)).unwrap();
+		assert_eq!(engine.get_global("last"), Some(StringValue("b".to_string())));
+	}
+}
@@ -0,0 +1,73 @@
+// The files scripts were read from, so a span can say which file it is in
+// and a diagnostic can draw it over that file's text, even when the error
+// travelled out of a module into the script that referenced it.
+//
+// Files are numbered from 1 as they are added; a span in file 0 is in the
+// source the host passed in directly, such as to `Engine::eval`. The list
+// belongs to the thread, like the heap, and is shared by every engine on it.
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq)]
+pub struct SourceFile {
+	pub path: PathBuf,
+	pub text: String,
+}
+
+impl SourceFile {
+	/// The path to show, relative to the working directory when it is
+	/// inside it.
+	pub fn name(&self) -> String {
+		let relative = std::env::current_dir().ok()
+			.and_then(|directory| self.path.strip_prefix(directory).ok().map(Path::to_path_buf));
+		relative.unwrap_or_else(|| self.path.clone()).display().to_string()
+	}
+}
+
+thread_local! {
+	static FILES: RefCell<Vec<Rc<SourceFile>>> = const { RefCell::new(vec![]) };
+}
+
+/// The number of the file at `path` with `text`, adding it unless the same
+/// text was added for that path before.
+pub fn add(path: &Path, text: &str) -> usize
+{
+	FILES.with(|files|
+	{
+		let mut files = files.borrow_mut();
+		if let Some(index) = files.iter().rposition(|file| file.path == path && file.text == text)
+		{
+			return index + 1;
+		}
+		files.push(Rc::new(SourceFile {
+			path: path.to_path_buf(),
+			text: text.to_string(),
+		}));
+		files.len()
+	})
+}
+
+/// The file numbered `file`, or `None` for 0 and numbers never given out.
+pub fn get(file: usize) -> Option<Rc<SourceFile>>
+{
+	FILES.with(|files| file.checked_sub(1).and_then(|index| files.borrow().get(index).cloned()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn files_are_numbered_once_per_text()
+	{
+		let first = add(Path::new("numbered.pyx"), "print 1!");
+		assert!(first > 0);
+		assert_eq!(add(Path::new("numbered.pyx"), "print 1!"), first);
+		let edited = add(Path::new("numbered.pyx"), "print 2!");
+		assert_ne!(edited, first);
+		assert_eq!(get(edited).unwrap().text, "print 2!");
+		assert_eq!(get(first).unwrap().name(), "numbered.pyx");
+		assert!(get(0).is_none());
+	}
+}
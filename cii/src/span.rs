@@ -0,0 +1,79 @@
+// Positions in source text, carried by tokens, syntax tree nodes and errors
+// so anything that reports on the source can point at the exact text.
+use std::fmt;
+
+/// The source bytes `start..end`, with the line and column where they
+/// start. Columns count characters from 1, so a tab or an `é` is one
+/// column. Line 0 means the position is unknown, as for values and errors
+/// that did not come from a script.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+	pub start: usize,
+	pub end: usize,
+	pub line: usize,
+	pub column: usize,
+	/// The file the text is in, numbered by [`crate::sources`].
+	pub file: usize,
+}
+
+impl Span {
+	pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+		Self {
+			start,
+			end,
+			line,
+			column,
+			file: 0,
+		}
+	}
+
+	/// The same text, in the file numbered `file`.
+	pub fn in_file(mut self, file: usize) -> Self {
+		self.file = file;
+		self
+	}
+
+	pub fn is_known(&self) -> bool {
+		self.line > 0
+	}
+
+	/// The span from the start of `self` to the end of `other`. An unknown
+	/// span gives way to the other one.
+	pub fn to(self, other: Span) -> Span {
+		match (self.is_known(), other.is_known())
+		{
+			(false, _) => other,
+			(_, false) => self,
+			_ => Span::new(self.start, other.end.max(self.end), self.line, self.column).in_file(self.file),
+		}
+	}
+
+	/// The text the span covers, or `""` when it is not inside `source`.
+	pub fn text(self, source: &str) -> &str {
+		source.get(self.start..self.end).unwrap_or("")
+	}
+}
+
+impl fmt::Display for Span {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}, column {}", self.line, self.column)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn joining_spans()
+	{
+		let left = Span::new(4, 7, 1, 5);
+		let right = Span::new(10, 12, 2, 3);
+		assert_eq!(left.to(right), Span::new(4, 12, 1, 5));
+		assert_eq!(Span::default().to(right), right);
+		assert_eq!(left.to(Span::default()), left);
+		assert_eq!(left.text("var abc::1;"), "abc");
+		assert_eq!(left.to_string(), "line 1, column 5");
+		assert_eq!(left.in_file(2).to(right.in_file(2)).file, 2);
+	}
+}
@@ -1,7 +1,61 @@
 use crate::expr::Expr;
+use crate::hooks::Hook;
+use crate::scanner::Token;
+use crate::span::Span;
+use std::rc::Rc;
 
+/// A statement, with the span of source it was parsed from.
 pub enum Statement {
-	Expression { expression: Expr },
-	Print { expression:Expr },
-	Var { name: Token, initializer: Expr },
-}
\ No newline at end of file
+	Block { statements: Vec<Statement>, span: Span },
+	Expression { expression: Expr, span: Span },
+	Function { name: Token, params: Vec<Token>, body: Rc<Vec<Statement>>, span: Span },
+	Define { hook: Hook, name: Token, params: Vec<Token>, body: Rc<Vec<Statement>>, span: Span },
+	If { condition: Expr, then_branch: Box<Statement>, else_branch: Option<Box<Statement>>, span: Span },
+	Print { expression:Expr, span: Span },
+	Return { keyword: Token, value: Expr, span: Span },
+	Var { name: Token, initializer: Expr, span: Span },
+	While { condition: Expr, body: Box<Statement>, span: Span },
+	Reference { target: ReferenceTarget, import: Import, span: Span },
+	Class { name: Token, body: Rc<Vec<Statement>>, span: Span },
+	Signal { name: Token, params: Vec<Token>, span: Span },
+	Throw { keyword: Token, value: Expr, span: Span },
+	Try { keyword: Token, body: Vec<Statement>, handlers: Vec<Handler>, finally: Option<Vec<Statement>>, span: Span },
+	Break { keyword: Token, span: Span },
+}
+
+impl Statement {
+	pub fn span(&self) -> Span {
+		match self {
+			Statement::Block { span, .. } | Statement::Expression { span, .. } | Statement::Function { span, .. }
+			| Statement::Define { span, .. } | Statement::If { span, .. } | Statement::Print { span, .. }
+			| Statement::Return { span, .. } | Statement::Var { span, .. } | Statement::While { span, .. }
+			| Statement::Reference { span, .. } | Statement::Class { span, .. } | Statement::Signal { span, .. }
+			| Statement::Throw { span, .. } | Statement::Try { span, .. } | Statement::Break { span, .. } => *span,
+		}
+	}
+}
+
+/// A `catch (name)` clause, or `catch (name: Type)` to handle only errors of
+/// that type.
+pub struct Handler {
+	pub name: Token,
+	pub error_type: Option<Token>,
+	pub body: Vec<Statement>,
+}
+
+/// What an `@reference` loads: a built-in library such as `@lib_jellybeans`,
+/// or another script file, optionally renamed with `;;alias`.
+pub enum ReferenceTarget {
+	Library(Token),
+	File { path: Token, alias: Option<Token> },
+}
+
+/// Which names an `@reference` brings into scope.
+pub enum Import {
+	/// The module itself, bound to its name: `@reference("./math")`.
+	Namespace,
+	/// Every export: `::@--I++<`, and the default for libraries.
+	All,
+	/// Only the listed exports: `::@--I++{@memcpy}`.
+	Only(Vec<Token>),
+}
@@ -0,0 +1,445 @@
+// VECTOR-TYPES and SPES-TYPES: Vector2, Vector3, To_Basis and Color.
+//
+// These are plain values: assigning one copies it. Arithmetic is
+// component-wise, and a Number on either side of `*` or `/` scales every
+// component. A Basis is three Vector3 axes; multiplying a Vector3 by it
+// rotates and scales the vector, and multiplying two chains them.
+use crate::environment::Environment;
+use crate::expr::{CallableImpl, NativeFunctionImpl, StractValue, StractValue::*};
+use crate::scanner::TokenType;
+use crate::error::RuntimeError;
+use std::rc::Rc;
+
+type Native = fn(&[StractValue]) -> Result<StractValue, String>;
+
+fn define(environment: &mut Environment, name: &str, arity: usize, fun: Native)
+{
+	environment.define(name.to_string(), Callable(CallableImpl::NativeFunction(NativeFunctionImpl::new(name, arity, Rc::new(move |args: &[StractValue]| fun(args).map_err(RuntimeError::from))))));
+}
+
+pub fn define_vectors(environment: &mut Environment)
+{
+	define(environment, "Vector2", 2, vector2);
+	define(environment, "Vector3", 3, vector3);
+	environment.define("To_Basis".to_string(), Callable(CallableImpl::NativeFunction(NativeFunctionImpl::variadic("To_Basis", Rc::new(|args: &[StractValue]| to_basis(args).map_err(RuntimeError::from))))));
+	define(environment, "Color", 4, color);
+	define(environment, "Color_hex", 1, color_hex);
+}
+
+fn expect_number(function: &str, value: &StractValue) -> Result<f64, String>
+{
+	match value
+	{
+		Number(x) => Ok(*x),
+		other => Err(format!("{}() expects a Number, got {}", function, other.to_type())),
+	}
+}
+
+fn vector2(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Vector2(expect_number("Vector2", &args[0])?, expect_number("Vector2", &args[1])?))
+}
+
+fn vector3(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Vector3(
+		expect_number("Vector3", &args[0])?,
+		expect_number("Vector3", &args[1])?,
+		expect_number("Vector3", &args[2])?,
+	))
+}
+
+type Axes = [[f64; 3]; 3];
+
+/// A Basis from its three axes, `To_Basis(x, y, z)`, or from rotations in
+/// radians about each axis, `To_Basis(Vector3(pitch, yaw, roll))`, applied
+/// as roll about z, then pitch about x, then yaw about y.
+fn to_basis(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let axis = |value: &StractValue| match value
+	{
+		Vector3(x, y, z) => Ok([*x, *y, *z]),
+		other => Err(format!("To_Basis() expects a Vector3, got {}", other.to_type())),
+	};
+	match args
+	{
+		[x, y, z] => Ok(Basis(Rc::new([axis(x)?, axis(y)?, axis(z)?]))),
+		[euler] => {
+			let [pitch, yaw, roll] = axis(euler)?;
+			let (sin, cos) = (f64::sin, f64::cos);
+			let about_x = [[1.0, 0.0, 0.0], [0.0, cos(pitch), sin(pitch)], [0.0, -sin(pitch), cos(pitch)]];
+			let about_y = [[cos(yaw), 0.0, -sin(yaw)], [0.0, 1.0, 0.0], [sin(yaw), 0.0, cos(yaw)]];
+			let about_z = [[cos(roll), sin(roll), 0.0], [-sin(roll), cos(roll), 0.0], [0.0, 0.0, 1.0]];
+			Ok(Basis(Rc::new(compose(&compose(&about_y, &about_x), &about_z))))
+		}
+		_ => Err(format!("To_Basis() expects 1 or 3 arguments but got {}", args.len())),
+	}
+}
+
+/// The vector `v` in the space of `axes`.
+fn transform(axes: &Axes, v: [f64; 3]) -> [f64; 3]
+{
+	let mut out = [0.0; 3];
+	for (axis, k) in axes.iter().zip(v)
+	{
+		for (total, component) in out.iter_mut().zip(axis)
+		{
+			*total += component * k;
+		}
+	}
+	out
+}
+
+/// `outer * inner`: what `inner` does, then what `outer` does.
+fn compose(outer: &Axes, inner: &Axes) -> Axes
+{
+	inner.map(|axis| transform(outer, axis))
+}
+
+fn transposed(axes: &Axes) -> Axes
+{
+	[0, 1, 2].map(|row| [axes[0][row], axes[1][row], axes[2][row]])
+}
+
+fn determinant(axes: &Axes) -> f64
+{
+	let [x, y, z] = axes;
+	x[0] * (y[1] * z[2] - z[1] * y[2]) - y[0] * (x[1] * z[2] - z[1] * x[2]) + z[0] * (x[1] * y[2] - y[1] * x[2])
+}
+
+fn color(args: &[StractValue]) -> Result<StractValue, String>
+{
+	Ok(Color(
+		expect_number("Color", &args[0])?,
+		expect_number("Color", &args[1])?,
+		expect_number("Color", &args[2])?,
+		expect_number("Color", &args[3])?,
+	))
+}
+
+/// Builds a Color from `0xRRGGBBAA` or from `"#RRGGBB"` / `"#RRGGBBAA"`,
+/// with or without the leading `#`.
+fn color_hex(args: &[StractValue]) -> Result<StractValue, String>
+{
+	let rgba = match &args[0]
+	{
+		Number(x) if *x >= 0.0 && *x <= u32::MAX as f64 && x.fract() == 0.0 => *x as u32,
+		StringValue(s) => {
+			let digits = s.strip_prefix('#').unwrap_or(s);
+			let parsed = u32::from_str_radix(digits, 16).ok().filter(|_| digits.is_ascii());
+			match (digits.len(), parsed)
+			{
+				(6, Some(rgb)) => rgb << 8 | 0xFF,
+				(8, Some(rgba)) => rgba,
+				_ => return Err(format!("Color_hex() cannot read \"{}\" as #RRGGBB or #RRGGBBAA", s)),
+			}
+		}
+		other => return Err(format!("Color_hex() expects a 32-bit Number or a String, got {}", other)),
+	};
+	let channel = |shift: u32| ((rgba >> shift) & 0xFF) as f64 / 255.0;
+	Ok(Color(channel(24), channel(16), channel(8), channel(0)))
+}
+
+fn components(value: &StractValue) -> Option<Vec<f64>>
+{
+	match value
+	{
+		Vector2(x, y) => Some(vec![*x, *y]),
+		Vector3(x, y, z) => Some(vec![*x, *y, *z]),
+		Color(r, g, b, a) => Some(vec![*r, *g, *b, *a]),
+		_ => None,
+	}
+}
+
+/// Rebuilds a value of the same kind as `like` from its components.
+fn rebuild(like: &StractValue, c: &[f64]) -> StractValue
+{
+	match like
+	{
+		Vector2(..) => Vector2(c[0], c[1]),
+		Vector3(..) => Vector3(c[0], c[1], c[2]),
+		_ => Color(c[0], c[1], c[2], c[3]),
+	}
+}
+
+fn combine(left: &[f64], right: &[f64], op: fn(f64, f64) -> f64) -> Vec<f64>
+{
+	left.iter().zip(right).map(|(a, b)| op(*a, *b)).collect()
+}
+
+/// Applies a binary operator when either operand is a vector or color.
+/// Returns `None` when the operator is not defined for the operands, so the
+/// caller can report it.
+pub fn arithmetic(left: &StractValue, operator: TokenType, right: &StractValue) -> Option<StractValue>
+{
+	match (left, operator, right)
+	{
+		(Basis(axes), TokenType::Star, Vector3(x, y, z)) => {
+			let [x, y, z] = transform(axes, [*x, *y, *z]);
+			return Some(Vector3(x, y, z));
+		}
+		(Basis(outer), TokenType::Star, Basis(inner)) => return Some(Basis(Rc::new(compose(outer, inner)))),
+		_ => (),
+	}
+	let op: fn(f64, f64) -> f64 = match operator
+	{
+		TokenType::Plus => |a, b| a + b,
+		TokenType::Minus => |a, b| a - b,
+		TokenType::Star => |a, b| a * b,
+		TokenType::Slash => |a, b| a / b,
+		_ => return None,
+	};
+	match (components(left), components(right))
+	{
+		(Some(l), Some(r)) if std::mem::discriminant(left) == std::mem::discriminant(right) => {
+			Some(rebuild(left, &combine(&l, &r, op)))
+		}
+		(Some(l), None) => match (right, operator)
+		{
+			(Number(k), TokenType::Star | TokenType::Slash) => Some(rebuild(left, &combine(&l, &vec![*k; l.len()], op))),
+			_ => None,
+		},
+		(None, Some(r)) => match (left, operator)
+		{
+			(Number(k), TokenType::Star) => Some(rebuild(right, &combine(&vec![*k; r.len()], &r, op))),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+pub fn negate(value: &StractValue) -> Option<StractValue>
+{
+	components(value).map(|c| rebuild(value, &c.iter().map(|x| -x).collect::<Vec<f64>>()))
+}
+
+fn length_of(c: &[f64]) -> f64
+{
+	c.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+fn method(name: &str, arity: usize, fun: impl Fn(&[StractValue]) -> Result<StractValue, String> + 'static) -> StractValue
+{
+	Callable(CallableImpl::NativeFunction(NativeFunctionImpl::new(name, arity, Rc::new(move |args: &[StractValue]| fun(args).map_err(RuntimeError::from)))))
+}
+
+/// The value with one component replaced, for `velocity.x :: 10`, or
+/// `None` when the value is not a vector or color. Vectors are values, so
+/// the caller stores the result back where the value came from.
+pub fn with_component(value: &StractValue, name: &str, component: &StractValue) -> Option<Result<StractValue, String>>
+{
+	if let Basis(axes) = value
+	{
+		let mut axes = **axes;
+		let index = match name
+		{
+			"x" => 0,
+			"y" => 1,
+			"z" => 2,
+			_ => return Some(Err(format!("Basis has no axis '{}'", name))),
+		};
+		match component
+		{
+			Vector3(x, y, z) => axes[index] = [*x, *y, *z],
+			other => return Some(Err(format!("Basis.{} must be a Vector3, got {}", name, other.to_type()))),
+		}
+		return Some(Ok(Basis(Rc::new(axes))));
+	}
+	let mut own = components(value)?;
+	let index = match (value, name)
+	{
+		(Vector2(..) | Vector3(..), "x") | (Color(..), "r") => 0,
+		(Vector2(..) | Vector3(..), "y") | (Color(..), "g") => 1,
+		(Vector3(..), "z") | (Color(..), "b") => 2,
+		(Color(..), "a") => 3,
+		_ => return Some(Err(format!("{} has no component '{}'", value.to_type(), name))),
+	};
+	match component
+	{
+		Number(x) => own[index] = *x,
+		other => return Some(Err(format!("{}.{} must be a Number, got {}", value.to_type(), name, other.to_type()))),
+	}
+	Some(Ok(rebuild(value, &own)))
+}
+
+/// Looks up a component such as `velocity.x` or a method such as
+/// `velocity.normalized`. Returns `None` for names the value lacks.
+pub fn get(value: &StractValue, name: &str) -> Option<StractValue>
+{
+	let component = match (value, name)
+	{
+		(Vector2(x, _) | Vector3(x, _, _), "x") => Some(*x),
+		(Vector2(_, y) | Vector3(_, y, _), "y") => Some(*y),
+		(Vector3(_, _, z), "z") => Some(*z),
+		(Color(r, _, _, _), "r") => Some(*r),
+		(Color(_, g, _, _), "g") => Some(*g),
+		(Color(_, _, b, _), "b") => Some(*b),
+		(Color(_, _, _, a), "a") => Some(*a),
+		_ => None,
+	};
+	if let Some(component) = component
+	{
+		return Some(Number(component));
+	}
+	if let Basis(axes) = value
+	{
+		let axes = axes.clone();
+		return match name
+		{
+			"x" | "y" | "z" => {
+				let [x, y, z] = axes[(name.as_bytes()[0] - b'x') as usize];
+				Some(Vector3(x, y, z))
+			}
+			"transposed" => Some(method("transposed", 0, move |_| Ok(Basis(Rc::new(transposed(&axes)))))),
+			"determinant" => Some(method("determinant", 0, move |_| Ok(Number(determinant(&axes))))),
+			_ => None,
+		};
+	}
+
+	let own = components(value)?;
+	if matches!(value, Color(..))
+	{
+		return None;
+	}
+	let this = value.clone();
+	match name
+	{
+		"length" => Some(method("length", 0, move |_| Ok(Number(length_of(&own))))),
+		"normalized" => Some(method("normalized", 0, move |_| {
+			let length = length_of(&own);
+			if length == 0.0
+			{
+				return Ok(this.clone());
+			}
+			Ok(rebuild(&this, &own.iter().map(|x| x / length).collect::<Vec<f64>>()))
+		})),
+		"dot" => Some(method("dot", 1, move |args| {
+			match components(&args[0])
+			{
+				Some(other) if std::mem::discriminant(&this) == std::mem::discriminant(&args[0]) => {
+					Ok(Number(combine(&own, &other, |a, b| a * b).iter().sum()))
+				}
+				_ => Err(format!("dot() expects a {}, got {}", this.to_type(), args[0].to_type())),
+			}
+		})),
+		"cross" => match value
+		{
+			Vector3(..) => Some(method("cross", 1, move |args| {
+				match &args[0]
+				{
+					Vector3(x, y, z) => Ok(Vector3(
+						own[1] * z - own[2] * y,
+						own[2] * x - own[0] * z,
+						own[0] * y - own[1] * x,
+					)),
+					other => Err(format!("cross() expects a Vector3, got {}", other.to_type())),
+				}
+			})),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::engine::Engine;
+	use crate::interpreter::Interpreter;
+	use crate::parser::Parser;
+	use crate::scanner::Scanner;
+
+	fn evaluate(source: &str) -> Result<StractValue, String>
+	{
+		let mut scanner = Scanner::new(source);
+		let tokens = scanner.scan_tokens().map_err(|errors| crate::diagnostic::join(&errors))?;
+		let expr = Parser::new(tokens).expression().map_err(|error| error.to_string())?;
+		Interpreter::new().interpret_expr(expr).map_err(|e| e.to_string())
+	}
+
+	#[test]
+	fn construction_and_components()
+	{
+		assert_eq!(evaluate("Vector2(1, 2)").unwrap(), Vector2(1.0, 2.0));
+		assert_eq!(evaluate("Vector3(1, 2, 3).z").unwrap(), Number(3.0));
+		assert_eq!(evaluate("Vector2(1, 2).y").unwrap(), Number(2.0));
+		assert!(evaluate("Vector2(1, 2).z").is_err());
+		assert!(evaluate("Vector2(1, \"2\")").is_err());
+		assert_eq!(evaluate("Vector3(1, 2, 3)").unwrap().to_string(), "(1, 2, 3)");
+	}
+
+	#[test]
+	fn component_wise_arithmetic()
+	{
+		assert_eq!(evaluate("Vector2(1, 2) + Vector2(3, 4)").unwrap(), Vector2(4.0, 6.0));
+		assert_eq!(evaluate("Vector3(1, 2, 3) - Vector3(1, 1, 1)").unwrap(), Vector3(0.0, 1.0, 2.0));
+		assert_eq!(evaluate("Vector2(2, 3) * Vector2(4, 5)").unwrap(), Vector2(8.0, 15.0));
+		assert_eq!(evaluate("Vector2(2, 3) * 2").unwrap(), Vector2(4.0, 6.0));
+		assert_eq!(evaluate("0.5 * Vector3(2, 4, 6)").unwrap(), Vector3(1.0, 2.0, 3.0));
+		assert_eq!(evaluate("Vector2(2, 4) / 2").unwrap(), Vector2(1.0, 2.0));
+		assert_eq!(evaluate("-Vector2(1, -2)").unwrap(), Vector2(-1.0, 2.0));
+		assert!(evaluate("Vector2(1, 2) + Vector3(1, 2, 3)").is_err());
+		assert!(evaluate("Vector2(1, 2) + 1").is_err());
+		assert!(evaluate("2 / Vector2(1, 2)").is_err());
+		assert_eq!(evaluate("Vector2(1, 2) : Vector2(1, 2)").unwrap(), True);
+	}
+
+	#[test]
+	fn vector_methods()
+	{
+		assert_eq!(evaluate("Vector2(3, 4).length()").unwrap(), Number(5.0));
+		assert_eq!(evaluate("Vector2(0, 5).normalized()").unwrap(), Vector2(0.0, 1.0));
+		assert_eq!(evaluate("Vector2(0, 0).normalized()").unwrap(), Vector2(0.0, 0.0));
+		assert_eq!(evaluate("Vector3(1, 2, 3).dot(Vector3(4, 5, 6))").unwrap(), Number(32.0));
+		assert_eq!(evaluate("Vector3(1, 0, 0).cross(Vector3(0, 1, 0))").unwrap(), Vector3(0.0, 0.0, 1.0));
+		assert!(evaluate("Vector2(1, 0).cross(Vector2(0, 1))").is_err());
+		assert!(evaluate("Vector2(1, 0).dot(Vector3(0, 1, 0))").is_err());
+	}
+
+	#[test]
+	fn colors()
+	{
+		assert_eq!(evaluate("Color(1, 0.5, 0, 1).g").unwrap(), Number(0.5));
+		assert_eq!(evaluate("Color_hex(0xFF0000FF)").unwrap(), Color(1.0, 0.0, 0.0, 1.0));
+		assert_eq!(evaluate("Color_hex(\"#00ff00\")").unwrap(), Color(0.0, 1.0, 0.0, 1.0));
+		assert_eq!(evaluate("Color_hex(\"0000FF00\")").unwrap(), Color(0.0, 0.0, 1.0, 0.0));
+		assert_eq!(evaluate("Color(0.5, 0.5, 0.5, 1) * 2").unwrap(), Color(1.0, 1.0, 1.0, 2.0));
+		assert!(evaluate("Color_hex(\"#12345\")").is_err());
+		assert!(evaluate("Color_hex(-1)").is_err());
+		assert!(evaluate("Color(1, 1, 1, 1).length()").is_err());
+	}
+
+	#[test]
+	fn components_can_be_assigned()
+	{
+		let script = "var velocity::Vector3(1, 2, 3);\nvelocity.y :: 10!\nvelocity.x -:: 4!\nvelocity.z *:: 2!\nvelocity";
+		assert_eq!(Engine::new().eval(script).unwrap(), Vector3(-3.0, 10.0, 6.0));
+		assert_eq!(Engine::new().eval_bytecode(script).unwrap(), Vector3(-3.0, 10.0, 6.0));
+		let body = "class Body {\n\tvar tint::Color(1, 1, 1, 1);\n\tfunc fade() { self.tint.a /:: 4! }\n}\nvar body::Body();\nbody.fade()!\nbody.tint";
+		assert_eq!(Engine::new().eval(body).unwrap(), Color(1.0, 1.0, 1.0, 0.25));
+
+		assert!(Engine::new().eval("var v::Vector2(1, 2);\nv.z :: 1!").unwrap_err().to_string().starts_with("Vector2 has no component 'z'"));
+		assert!(Engine::new().eval_bytecode("var v::Vector2(1, 2);\nv.x :: \"far\"!").unwrap_err().to_string().starts_with("Vector2.x must be a Number"));
+		assert!(Engine::new().eval("Vector2(1, 2).x :: 1").unwrap_err().to_string().starts_with("Cannot set a component of a Vector2"));
+	}
+
+	#[test]
+	fn bases()
+	{
+		assert_eq!(evaluate("To_Basis(Vector3(2, 0, 0), Vector3(0, 3, 0), Vector3(0, 0, 4)) * Vector3(1, 1, 1)").unwrap(), Vector3(2.0, 3.0, 4.0));
+		assert_eq!(evaluate("To_Basis(Vector3(1, 0, 0), Vector3(0, 1, 0), Vector3(0, 0, 1)).y").unwrap(), Vector3(0.0, 1.0, 0.0));
+		let Vector3(x, y, z) = evaluate("To_Basis(Vector3(0, 1.5707963267948966, 0)) * Vector3(1, 0, 0)").unwrap() else { panic!() };
+		assert!((x.abs(), y.abs(), (z + 1.0).abs()) < (1e-12, 1e-12, 1e-12), "({}, {}, {})", x, y, z);
+		let turn = "To_Basis(Vector3(0.3, 0.5, 0.7))";
+		let Number(determinant) = evaluate(&format!("{}.determinant()", turn)).unwrap() else { panic!() };
+		assert!((determinant - 1.0).abs() < 1e-12);
+		let Vector3(x, y, z) = evaluate(&format!("{}.transposed() * {} * Vector3(1, 2, 3)", turn, turn)).unwrap() else { panic!() };
+		assert!(((x - 1.0).abs(), (y - 2.0).abs(), (z - 3.0).abs()) < (1e-12, 1e-12, 1e-12), "({}, {}, {})", x, y, z);
+		assert_eq!(evaluate("To_Basis(Vector3(1, 0, 0), Vector3(0, 1, 0), Vector3(0, 0, 1))").unwrap().to_string(),
+			"[X: (1, 0, 0), Y: (0, 1, 0), Z: (0, 0, 1)]");
+		assert!(evaluate("To_Basis(Vector3(1, 0, 0), Vector3(0, 1, 0))").is_err());
+		assert!(evaluate("To_Basis(Vector2(1, 0))").is_err());
+		let mut engine = Engine::new();
+		assert_eq!(engine.eval("var basis::To_Basis(Vector3(0, 0, 0));\nbasis.x :: Vector3(2, 0, 0)!\nbasis * Vector3(1, 1, 0)").unwrap(), Vector3(2.0, 1.0, 0.0));
+	}
+}
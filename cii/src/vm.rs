@@ -0,0 +1,514 @@
+// A stack machine that runs the bytecode from `compiler.rs`.
+//
+// Each call gets a frame whose locals are a window of the shared value
+// stack, starting with the arguments. Values, globals and natives are the
+// same as the tree-walker's, and operators, properties and calls go through
+// the same functions in `expr.rs`, so both give the same results and errors.
+use crate::chunk::{Capture, Function, Op};
+use crate::environment::Environment;
+use crate::error::{ErrorKind, RuntimeError};
+use crate::expr::{self, CallableImpl, StractValue, StractValue::*};
+use crate::gc::{self, Collectable};
+use crate::scanner::TokenType;
+use crate::span::Span;
+use crate::vector;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Calls nested deeper than this fail instead of growing without bound.
+const MAX_FRAMES: usize = 10_000;
+
+/// A compiled function with the variables it captured.
+pub struct Closure {
+	pub function: Rc<Function>,
+	upvalues: Vec<Rc<RefCell<StractValue>>>,
+	globals: Rc<RefCell<Environment>>,
+}
+
+impl std::fmt::Debug for Closure {
+	fn fmt(&self, f: &mut std::fmt::Formatter)->std::fmt::Result
+	{
+		write!(f, "<func {}>", self.function.name)
+	}
+}
+
+impl PartialEq for Closure {
+	fn eq(&self, other: &Self) -> bool
+	{
+		std::ptr::eq(self, other)
+	}
+}
+
+/// The captured cells are heap objects of their own and are cleared
+/// instead, so there is nothing to clear here.
+impl Collectable for Closure {
+	fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool
+	{
+		self.upvalues.iter().for_each(|cell| visit(gc::address(cell)));
+		visit(gc::address(&self.globals));
+		true
+	}
+
+	fn clear(&self) {}
+}
+
+impl Closure {
+	/// Calls the closure from outside the VM, as natives and the host do.
+	pub fn call(self: &Rc<Self>, arguments: &[StractValue]) -> Result<StractValue, RuntimeError>
+	{
+		Vm::new(self.globals.clone()).call(self.clone(), arguments)
+	}
+}
+
+struct Frame {
+	closure: Rc<Closure>,
+	ip: usize,
+	/// Where the call's first argument sits on the stack.
+	base: usize,
+	cell_base: usize,
+}
+
+impl Frame {
+	fn constant(&self, index: u16) -> &StractValue
+	{
+		&self.closure.function.chunk.constants[index as usize]
+	}
+
+	fn name(&self, index: u16) -> &str
+	{
+		match self.constant(index)
+		{
+			StringValue(name) => name,
+			_ => "",
+		}
+	}
+
+	fn span(&self) -> Span
+	{
+		self.closure.function.chunk.spans[self.ip - 1]
+	}
+}
+
+pub struct Vm {
+	stack: Vec<StractValue>,
+	/// The callers of the running frame.
+	frames: Vec<Frame>,
+	cells: Vec<Rc<RefCell<StractValue>>>,
+	globals: Rc<RefCell<Environment>>,
+}
+
+impl Vm {
+	/// A VM whose globals are `globals`, usually those of an interpreter
+	/// so scripts see the same natives and host values.
+	pub fn new(globals: Rc<RefCell<Environment>>) -> Self
+	{
+		Self {
+			stack: Vec::with_capacity(256),
+			frames: vec![],
+			cells: vec![],
+			globals,
+		}
+	}
+
+	/// Runs a compiled script and returns the value it ends with.
+	pub fn run(&mut self, function: Rc<Function>) -> Result<StractValue, RuntimeError>
+	{
+		let closure = Rc::new(Closure {
+			function,
+			upvalues: vec![],
+			globals: self.globals.clone(),
+		});
+		// The script is a frame here but top-level code to the tree-walker.
+		self.call(closure, &[]).map_err(|e| e.untraced().in_script())
+	}
+
+	fn call(&mut self, closure: Rc<Closure>, arguments: &[StractValue]) -> Result<StractValue, RuntimeError>
+	{
+		let (stack, frames, cells) = (self.stack.len(), self.frames.len(), self.cells.len());
+		self.stack.push(Callable(CallableImpl::Compiled(closure.clone())));
+		self.stack.extend_from_slice(arguments);
+		let result = self.frame(closure, arguments.len()).and_then(|frame| self.execute(frame, frames));
+		if result.is_err()
+		{
+			self.stack.truncate(stack);
+			self.frames.truncate(frames);
+			self.cells.truncate(cells);
+		}
+		result
+	}
+
+	/// A frame for a call whose `count` arguments are on top of the stack.
+	fn frame(&mut self, closure: Rc<Closure>, count: usize) -> Result<Frame, RuntimeError>
+	{
+		let function = &closure.function;
+		if count != function.arity
+		{
+			return Err(RuntimeError::arity(&function.name, function.arity, count));
+		}
+		if self.frames.len() == MAX_FRAMES
+		{
+			return Err(RuntimeError::new(ErrorKind::StackOverflow(function.name.clone())));
+		}
+		let cell_base = self.cells.len();
+		self.cells.resize_with(cell_base + function.cell_count, || Rc::new(RefCell::new(Nil)));
+		Ok(Frame {
+			base: self.stack.len() - count,
+			cell_base,
+			closure,
+			ip: 0,
+		})
+	}
+
+	fn pop(&mut self) -> StractValue
+	{
+		self.stack.pop().expect("the compiler keeps the stack balanced")
+	}
+
+	fn top(&self) -> &StractValue
+	{
+		self.stack.last().expect("the compiler keeps the stack balanced")
+	}
+
+	/// Runs `frame` and the calls it makes until it returns to a caller
+	/// outside the VM, with `depth` frames below it. An error leaving any
+	/// of those frames records each one in its trace.
+	fn execute(&mut self, mut frame: Frame, depth: usize) -> Result<StractValue, RuntimeError>
+	{
+		let mut error = match self.dispatch(&mut frame, depth)
+		{
+			Ok(value) => return Ok(value),
+			Err(error) => error,
+		};
+		loop
+		{
+			error = error.at(frame.span()).traced(&frame.closure.function.name);
+			if self.frames.len() == depth
+			{
+				return Err(error);
+			}
+			frame = self.frames.pop().unwrap();
+		}
+	}
+
+	fn dispatch(&mut self, frame: &mut Frame, depth: usize) -> Result<StractValue, RuntimeError>
+	{
+		loop
+		{
+			let op = frame.closure.function.chunk.code[frame.ip];
+			frame.ip += 1;
+			match op
+			{
+				Op::Constant(index) => {
+					let value = frame.constant(index).clone();
+					self.stack.push(value);
+				}
+				Op::Nil => self.stack.push(Nil),
+				Op::True => self.stack.push(True),
+				Op::False => self.stack.push(False),
+				Op::Pop => { self.pop(); }
+				Op::GetLocal(slot) => {
+					let value = self.stack[frame.base + slot as usize].clone();
+					self.stack.push(value);
+				}
+				Op::SetLocal(slot) => {
+					let value = self.top().clone();
+					self.stack[frame.base + slot as usize] = value;
+				}
+				Op::GetGlobal(index) => {
+					let name = frame.name(index);
+					let value = self.globals.borrow().get(name);
+					match value
+					{
+						Some(value) => self.stack.push(value),
+						None => return Err(RuntimeError::new(ErrorKind::UndefinedVariable(name.to_string()))),
+					}
+				}
+				Op::SetGlobal(index) => {
+					let value = self.top().clone();
+					if !self.globals.borrow_mut().assign(frame.name(index), value)
+					{
+						return Err(RuntimeError::new(ErrorKind::UndefinedVariable(frame.name(index).to_string())));
+					}
+				}
+				Op::DefineGlobal(index) => {
+					let value = self.pop();
+					self.globals.borrow_mut().define(frame.name(index).to_string(), value);
+				}
+				Op::NewCell(cell) => self.cells[frame.cell_base + cell as usize] = gc::alloc(RefCell::new(Nil)),
+				Op::DefineCell(cell) => {
+					let value = self.pop();
+					self.cells[frame.cell_base + cell as usize] = gc::alloc(RefCell::new(value));
+				}
+				Op::GetCell(cell) => {
+					let value = self.cells[frame.cell_base + cell as usize].borrow().clone();
+					self.stack.push(value);
+				}
+				Op::SetCell(cell) => *self.cells[frame.cell_base + cell as usize].borrow_mut() = self.top().clone(),
+				Op::GetUpvalue(upvalue) => {
+					let value = frame.closure.upvalues[upvalue as usize].borrow().clone();
+					self.stack.push(value);
+				}
+				Op::SetUpvalue(upvalue) => *frame.closure.upvalues[upvalue as usize].borrow_mut() = self.top().clone(),
+				Op::GetProperty(index) => {
+					let object = self.pop();
+					let value = expr::get_property(&object, frame.name(index))?;
+					self.stack.push(value);
+				}
+				Op::SetProperty(index) => {
+					let value = self.pop();
+					let object = self.pop();
+					expr::set_property(&object, frame.name(index), value.clone())?;
+					self.stack.push(value);
+				}
+				Op::SetComponent(index) => {
+					let value = self.pop();
+					let object = self.pop();
+					let object = match vector::with_component(&object, frame.name(index), &value)
+					{
+						Some(rebuilt) => rebuilt.map_err(RuntimeError::type_mismatch)?,
+						None => {
+							expr::set_property(&object, frame.name(index), value.clone())?;
+							object
+						}
+					};
+					self.stack.push(value);
+					self.stack.push(object);
+				}
+				Op::Index => {
+					let index = self.pop();
+					let object = self.pop();
+					let value = expr::index_value(&object, &index)?;
+					self.stack.push(value);
+				}
+				Op::Add => self.binary(TokenType::Plus, |x, y| Number(x + y))?,
+				Op::Subtract => self.binary(TokenType::Minus, |x, y| Number(x - y))?,
+				Op::Multiply => self.binary(TokenType::Star, |x, y| Number(x * y))?,
+				Op::Divide => {
+					let right = self.pop();
+					let left = self.pop();
+					let value = match (&left, &right)
+					{
+						(Number(x), Number(y)) => expr::divide(*x, *y)?,
+						_ => expr::binary(&left, TokenType::Slash, &right)?,
+					};
+					self.stack.push(value);
+				}
+				Op::Greater => self.binary(TokenType::Greater, |x, y| StractValue::from_bool(x > y))?,
+				Op::GreaterEqual => self.binary(TokenType::GreaterEqual, |x, y| StractValue::from_bool(x >= y))?,
+				Op::Less => self.binary(TokenType::Less, |x, y| StractValue::from_bool(x < y))?,
+				Op::LessEqual => self.binary(TokenType::LessEqual, |x, y| StractValue::from_bool(x <= y))?,
+				Op::Equal => self.binary(TokenType::EqualEqual, |x, y| StractValue::from_bool(x == y))?,
+				Op::NotEqual => self.binary(TokenType::BangEqual, |x, y| StractValue::from_bool(x != y))?,
+				Op::Negate => {
+					let value = self.pop();
+					self.stack.push(expr::unary(TokenType::Minus, &value)?);
+				}
+				Op::Not => {
+					let value = self.pop();
+					self.stack.push(value.is_falsy());
+				}
+				Op::Jump(target) => frame.ip = target as usize,
+				Op::JumpIfFalse(target) => {
+					if self.top().is_falsy() == True
+					{
+						frame.ip = target as usize;
+					}
+				}
+				Op::Call(count) => {
+					let count = count as usize;
+					let callee = self.stack.len() - count - 1;
+					if let Callable(CallableImpl::Compiled(closure)) = &self.stack[callee]
+					{
+						let closure = closure.clone();
+						let callee = self.frame(closure, count)?;
+						self.frames.push(std::mem::replace(frame, callee));
+						continue;
+					}
+					let arguments = self.stack.split_off(callee + 1);
+					let callee = self.pop();
+					let value = expr::call_value(callee, &arguments)?;
+					self.stack.push(value);
+				}
+				Op::Closure(index) => {
+					let function = frame.closure.function.chunk.functions[index as usize].clone();
+					let upvalues = function.captures.iter().map(|capture| match capture
+					{
+						Capture::Cell(cell) => self.cells[frame.cell_base + *cell as usize].clone(),
+						Capture::Upvalue(upvalue) => frame.closure.upvalues[*upvalue as usize].clone(),
+					}).collect();
+					let closure = Closure {
+						function,
+						upvalues,
+						globals: self.globals.clone(),
+					};
+					self.stack.push(Callable(CallableImpl::Compiled(gc::alloc(closure))));
+				}
+				Op::Return => {
+					let value = self.pop();
+					self.stack.truncate(frame.base - 1);
+					self.cells.truncate(frame.cell_base);
+					if self.frames.len() == depth
+					{
+						return Ok(value);
+					}
+					*frame = self.frames.pop().unwrap();
+					self.stack.push(value);
+				}
+				Op::Print => println!("{}", self.pop()),
+				Op::List(count) => {
+					let items = self.stack.split_off(self.stack.len() - count as usize);
+					self.stack.push(List(gc::alloc(RefCell::new(items))));
+				}
+				Op::Interpolate(count) => {
+					let parts = self.stack.split_off(self.stack.len() - count as usize);
+					let text: String = parts.iter().map(|part| part.to_string()).collect();
+					self.stack.push(StringValue(text));
+				}
+			}
+		}
+	}
+
+	/// Applies an operator, with a fast path when both sides are numbers.
+	fn binary(&mut self, operator: TokenType, numbers: fn(f64, f64) -> StractValue) -> Result<(), RuntimeError>
+	{
+		let right = self.pop();
+		let left = self.pop();
+		let value = match (&left, &right)
+		{
+			(Number(x), Number(y)) => numbers(*x, *y),
+			_ => expr::binary(&left, operator, &right)?,
+		};
+		self.stack.push(value);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::MAX_FRAMES;
+	use crate::engine::{Engine, EngineError};
+	use crate::error::RuntimeError;
+	use crate::expr::StractValue::{self, *};
+
+	/// Runs `source` on both backends, checking they agree.
+	fn both(source: &str) -> Result<StractValue, EngineError>
+	{
+		let walked = Engine::new().eval(source);
+		let compiled = Engine::new().eval_bytecode(source);
+		assert_eq!(walked, compiled, "the backends disagree on {}", source);
+		compiled
+	}
+
+	#[test]
+	fn arithmetic_and_strings()
+	{
+		assert_eq!(both("1 + 2 * 3 - -4 / 2"), Ok(Number(9.0)));
+		assert_eq!(both("\"a\" + 'b' + \"{1 + 1}\""), Ok(StringValue("ab2".to_string())));
+		assert_eq!(both("!nil : true"), Ok(True));
+		assert_eq!(both("[1, \"x\", [2]][2][0]"), Ok(Number(2.0)));
+		assert_eq!(both("(1, 2) + (3, 4)"), both("(4, 6)"));
+	}
+
+	#[test]
+	fn control_flow()
+	{
+		assert_eq!(both("var i::0;\nvar sum::0;\nwhile (i < 10) { if (i : 3 or i : 5) sum :: sum + 100! else sum :: sum + i! i :: i + 1! }\nsum"), Ok(Number(237.0)));
+		assert_eq!(both("var x::nil and missing;\nvar y::0 or \"fallback\";\n\"{x} {y}\""), Ok(StringValue("nil fallback".to_string())));
+		assert_eq!(both("var out::0;\n{ var a::1; { var a::2; out :: a! } out :: out * 10 + a! }\nout"), Ok(Number(21.0)));
+		assert_eq!(both("var n::0;\nwhile (true) { n :: n + 1! if (n : 4) break! }\nn"), Ok(Number(4.0)));
+		assert_eq!(both("var n::0;\nwhile (true) { n :: n + 1! if (n : 4) break? }\nn"), Ok(Number(4.0)));
+		assert_eq!(both("func first(limit) {
+	var i::0;
+	while (true)
+	{
+		var square::i * i;
+		{ var big::square > limit; if (big) break! }
+		i :: i + 1!
+	}
+	return i!
+}
+first(50)"), Ok(Number(8.0)));
+	}
+
+	#[test]
+	fn functions_and_recursion()
+	{
+		assert_eq!(both("func fib(n) { if (n < 2) return n! return fib(n - 1) + fib(n - 2)! }\nfib(15)"), Ok(Number(610.0)));
+		assert_eq!(both("func f(a, b) { var c::a * b; return c + a! }\nf(3, 4)"), Ok(Number(15.0)));
+		assert_eq!(both("func none() { }\nnone()"), Ok(Nil));
+		assert_eq!(both("caseupper(\"shout\")"), Ok(StringValue("SHOUT".to_string())));
+	}
+
+	#[test]
+	fn closures_share_their_variables()
+	{
+		let source = "func counter() {
+	var n::0;
+	func next() { n :: n + 1! return n! }
+	func peek() { return n! }
+	return [next, peek]!
+}
+var pair::counter();
+pair[0]()!
+pair[0]()!
+var other::counter();
+other[0]()!
+pair[1]() * 10 + other[1]()";
+		assert_eq!(both(source), Ok(Number(21.0)));
+		let source = "func outer(x) {
+	func middle() {
+		func inner() { x :: x + 1! return x! }
+		return inner!
+	}
+	return middle()!
+}
+var f::outer(5);
+f()!
+f()";
+		assert_eq!(both(source), Ok(Number(7.0)));
+		assert_eq!(both("func loop() { func again(n) { if (n : 0) return \"done\"! return again(n - 1)! } return again(3)! }\nloop()"), Ok(StringValue("done".to_string())));
+	}
+
+	#[test]
+	fn runtime_errors_match()
+	{
+		let error = |source: &str| both(source).unwrap_err().to_string();
+		assert_eq!(error("missing + 1"), "Undefined<j variable 'missing' at line 1, column 1");
+		assert_eq!(error("var n::1;\nn()"), "Number is not callable at line 2, column 1");
+		assert_eq!(error("func f(a) { }\nf()"), "f() expects 1 arguments but got 0 at line 2, column 1");
+		assert_eq!(error("1 + nil"), "Plus isn't implemented for operands Number(1.0) and Nil at line 1, column 1");
+		assert_eq!(error("\n\nnope :: 1"), "Undefined<j variable 'nope' at line 3, column 1");
+		assert_eq!(error("[1][4]"), "Index 4 out of range for length 1 at line 1, column 1");
+		assert_eq!(error("var x::0;\n2 / x"), "Division by zero at line 2, column 1");
+		assert_eq!(error("func get(list) {\n\treturn list[3]!\n}\nfunc outer() { return get([1])! }\nouter()"),
+			"Index 3 out of range for length 1 at line 2, column 9\n  in get() at line 2\n  in outer() at line 4\n  in script at line 5");
+		let err = Engine::new().eval_bytecode("func down(n) { return down(n + 1)! }\ndown(0)").unwrap_err();
+		match err
+		{
+			EngineError::Runtime(error) => {
+				assert_eq!(error.message(), "Stack overflow in down()");
+				assert_eq!(error.trace.len(), MAX_FRAMES + 1);
+			}
+			other => panic!("expected a runtime error, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn compiled_functions_are_ordinary_values()
+	{
+		let mut engine = Engine::new();
+		engine.eval_bytecode("var total::0;\nfunc add(x) { total :: total + x! return total! }").unwrap();
+		assert_eq!(engine.call_function::<f64>("add", (2,)), Ok(2.0));
+		engine.eval("add(3)!").unwrap();
+		assert_eq!(engine.get_global("total"), Some(Number(5.0)));
+		assert_eq!(engine.eval("\"{add}\"").unwrap(), StringValue("<func add>".to_string()));
+		engine.register_fn("twice", |f: StractValue| -> Result<StractValue, RuntimeError> {
+			match f
+			{
+				Callable(f) => { f.call(&[Number(1.0)])?; f.call(&[Number(1.0)]) }
+				_ => Err("expected a function".into()),
+			}
+		});
+		assert_eq!(engine.eval_bytecode("twice(add)"), Ok(Number(7.0)));
+	}
+}